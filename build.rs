@@ -15,6 +15,10 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 
+// Shared with host-tests/build.rs.
+#[path = "build/tables.rs"]
+mod tables;
+
 fn download_proprietary_firmware() {
     let status = Command::new("bash")
         .arg("./scripts/download_firmware.sh")
@@ -67,6 +71,8 @@ fn main() {
         .write_all(include_bytes!("memory.x"))
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
+    tables::compile_vulns(Path::new("data"), out);
+    tables::compile_services(Path::new("data"), out);
 
     println!("cargo:rerun-if-changed=memory.x");
 
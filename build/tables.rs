@@ -0,0 +1,134 @@
+// Tables compiled from data/ for src/vulns.rs and src/services.rs.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+// Compiles data/vulns.txt into the `SIGNATURES` table in src/vulns.rs.
+// Bad lines fail the build rather than silently dropping a signature.
+pub fn compile_vulns(data: &Path, out: &Path) {
+    let path = data.join("vulns.txt");
+    println!("cargo:rerun-if-changed={}", path.display());
+    let src = fs::read_to_string(&path).unwrap();
+    let mut rows = Vec::new();
+
+    for (n, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let bad = |why: &str| -> ! { panic!("{}:{}: {}", path.display(), n + 1, why) };
+        let [product, introduced, fixed, severity, cve] = fields[..] else {
+            bad("expected: product introduced fixed severity cve");
+        };
+        let severity = match severity {
+            "low" => "Low",
+            "medium" => "Medium",
+            "high" => "High",
+            "critical" => "Critical",
+            _ => bad("severity must be low, medium, high or critical"),
+        };
+        let Some((year, id)) = cve
+            .strip_prefix("CVE-")
+            .and_then(|rest| rest.split_once('-'))
+            .and_then(|(y, i)| Some((y.parse::<u16>().ok()?, i.parse::<u32>().ok()?)))
+        else {
+            bad("CVE should look like CVE-2016-6210");
+        };
+        let introduced = if introduced == "-" { "" } else { introduced };
+        rows.push((
+            product.to_ascii_lowercase(),
+            introduced.to_string(),
+            fixed.to_string(),
+            severity,
+            year,
+            id,
+        ));
+    }
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut f = File::create(out.join("vulns.rs")).unwrap();
+    writeln!(f, "pub static SIGNATURES: [Signature; {}] = [", rows.len()).unwrap();
+    for (product, introduced, fixed, severity, year, id) in rows {
+        writeln!(
+            f,
+            "    Signature {{ product: {:?}, introduced: {:?}, fixed: {:?}, \
+             severity: Severity::{}, cve_year: {}, cve_id: {} }},",
+            product, introduced, fixed, severity, year, id
+        )
+        .unwrap();
+    }
+    writeln!(f, "];").unwrap();
+}
+
+// Compiles data/services.txt into the tables in src/services.rs: each
+// protocol sorted by port for lookups, plus its ports in rank order.
+pub fn compile_services(data: &Path, out: &Path) {
+    let path = data.join("services.txt");
+    println!("cargo:rerun-if-changed={}", path.display());
+    let src = fs::read_to_string(&path).unwrap();
+    let mut tcp = Vec::new();
+    let mut udp = Vec::new();
+
+    for (n, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = |why: &str| -> ! { panic!("{}:{}: {}", path.display(), n + 1, why) };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, port_proto, freq] = fields[..] else {
+            bad("expected: name port/proto frequency");
+        };
+        let Some((port, proto)) = port_proto.split_once('/') else {
+            bad("expected port/proto, like 22/tcp");
+        };
+        let Ok(port) = port.parse::<u16>() else {
+            bad("bad port");
+        };
+        let Some(freq) = freq.parse::<f64>().ok().filter(|f| (0.0..=1.0).contains(f)) else {
+            bad("frequency should be between 0 and 1");
+        };
+        let table = match proto {
+            "tcp" => &mut tcp,
+            "udp" => &mut udp,
+            _ => bad("proto must be tcp or udp"),
+        };
+        if table.iter().any(|&(p, _, _)| p == port) {
+            bad("port listed twice");
+        }
+        // Parts per million, so the table stays integer.
+        table.push((port, name.to_string(), (freq * 1_000_000.0).round() as u32));
+    }
+
+    let mut f = File::create(out.join("services.rs")).unwrap();
+    for (label, table) in [("TCP", &mut tcp), ("UDP", &mut udp)] {
+        table.sort_by_key(|&(port, _, _)| port);
+        writeln!(f, "pub static {}: [Service; {}] = [", label, table.len()).unwrap();
+        for (port, name, freq) in table.iter() {
+            writeln!(
+                f,
+                "    Service {{ port: {}, name: {:?}, freq: {} }},",
+                port, name, freq
+            )
+            .unwrap();
+        }
+        writeln!(f, "];").unwrap();
+
+        let mut ranked: Vec<_> = table.iter().collect();
+        // Ties go to the lower port.
+        ranked.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        write!(
+            f,
+            "pub static {}_RANKED: [u16; {}] = [",
+            label,
+            ranked.len()
+        )
+        .unwrap();
+        for (port, _, _) in ranked {
+            write!(f, "{}, ", port).unwrap();
+        }
+        writeln!(f, "];").unwrap();
+    }
+}
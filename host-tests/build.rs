@@ -0,0 +1,11 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[path = "../build/tables.rs"]
+mod tables;
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    tables::compile_vulns(Path::new("../data"), out);
+    tables::compile_services(Path::new("../data"), out);
+}
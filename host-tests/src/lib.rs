@@ -0,0 +1,85 @@
+//! The firmware modules that don't touch hardware, compiled for the host
+//! straight from ../src so `cargo test` can run their tests. The SD card
+//! is replaced by an in-memory `SdStorage`; everything else is the
+//! firmware's own code.
+//!
+//! A module that starts needing a peripheral has to come out of this list
+//! (or have that part moved out of it).
+
+#[path = "../../src/arp_guard.rs"]
+pub mod arp_guard;
+#[path = "../../src/arp_tap.rs"]
+pub mod arp_tap;
+#[path = "../../src/ble_adv.rs"]
+pub mod ble_adv;
+#[path = "../../src/camera.rs"]
+pub mod camera;
+#[path = "../../src/channels.rs"]
+pub mod channels;
+#[path = "../../src/clock.rs"]
+pub mod clock;
+#[path = "../../src/dns_audit.rs"]
+pub mod dns_audit;
+#[path = "../../src/dns_wire.rs"]
+pub mod dns_wire;
+#[path = "../../src/exposure.rs"]
+pub mod exposure;
+#[path = "../../src/health.rs"]
+pub mod health;
+#[path = "../../src/industrial.rs"]
+pub mod industrial;
+#[path = "../../src/inventory.rs"]
+pub mod inventory;
+#[path = "../../src/ndp.rs"]
+pub mod ndp;
+#[path = "../../src/oui.rs"]
+pub mod oui;
+#[path = "../../src/perf.rs"]
+pub mod perf;
+#[path = "../../src/pmtu.rs"]
+pub mod pmtu;
+#[path = "../../src/portmap.rs"]
+pub mod portmap;
+#[path = "../../src/rogue_ap.rs"]
+pub mod rogue_ap;
+#[path = "../../src/scan_diff.rs"]
+pub mod scan_diff;
+pub mod sd_spi;
+pub mod sd_storage;
+#[path = "../../src/services.rs"]
+pub mod services;
+#[path = "../../src/session.rs"]
+pub mod session;
+#[path = "../../src/sha256.rs"]
+pub mod sha256;
+#[path = "../../src/smb.rs"]
+pub mod smb;
+#[path = "../../src/sntp.rs"]
+pub mod sntp;
+#[path = "../../src/ssh.rs"]
+pub mod ssh;
+#[path = "../../src/syslog.rs"]
+pub mod syslog;
+#[path = "../../src/uptime.rs"]
+pub mod uptime;
+#[path = "../../src/util.rs"]
+pub mod util;
+#[path = "../../src/vulns.rs"]
+pub mod vulns;
+#[path = "../../src/wol.rs"]
+pub mod wol;
+
+// defmt output goes nowhere on the host.
+#[defmt::global_logger]
+struct Discard;
+
+unsafe impl defmt::Logger for Discard {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+// The firmware's own timestamp (in clock.rs) is left out of test builds.
+#[cfg(test)]
+defmt::timestamp!("");
@@ -0,0 +1,9 @@
+//! sd_spi
+//! ------
+//!
+//! Host stand-in for the SD card's SPI error type.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdSpiError {
+    Unavailable,
+}
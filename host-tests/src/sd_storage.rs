@@ -0,0 +1,168 @@
+//! sd_storage
+//! ----------
+//!
+//! Host stand-in for the SD card: the same file calls as the real
+//! `SdStorage`, backed by a map of file names to bytes, so code that keeps
+//! records on the card can be tested end to end.
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::sd_spi::SdSpiError;
+
+type Result<T> = core::result::Result<T, embedded_sdmmc::Error<SdSpiError>>;
+
+pub struct SdStorage<'d, S: embassy_rp::spi::Instance> {
+    pub files: BTreeMap<String, Vec<u8>>,
+    _spi: PhantomData<&'d S>,
+}
+
+// What the tests plug into SdStorage's SPI bound.
+pub struct NoSpi;
+
+impl embassy_rp::spi::Instance for NoSpi {}
+
+impl Default for SdStorage<'_, NoSpi> {
+    fn default() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            _spi: PhantomData,
+        }
+    }
+}
+
+impl SdStorage<'_, NoSpi> {
+    // Contents of `name` as text, empty if it doesn't exist.
+    pub fn text(&self, name: &str) -> &str {
+        self.files
+            .get(&name.to_ascii_uppercase())
+            .map_or("", |f| core::str::from_utf8(f).unwrap())
+    }
+}
+
+impl<S: embassy_rp::spi::Instance> SdStorage<'_, S> {
+    fn file(&mut self, name: &str) -> &mut Vec<u8> {
+        self.files.entry(name.to_ascii_uppercase()).or_default()
+    }
+
+    pub fn log_ip(&mut self, ip: &str) -> Result<()> {
+        self.log_event(ip)
+    }
+
+    pub fn log_event(&mut self, event: &str) -> Result<()> {
+        self.append_line("NETWORK.LOG", event)
+    }
+
+    pub fn append_line(&mut self, name: &str, line: &str) -> Result<()> {
+        self.append_lines(name, core::iter::once(line))
+    }
+
+    pub fn append_lines<I, L>(&mut self, name: &str, lines: I) -> Result<()>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
+        let file = self.file(name);
+        for line in lines {
+            file.extend_from_slice(line.as_ref().as_bytes());
+            file.extend_from_slice(b"\n");
+        }
+        Ok(())
+    }
+
+    pub fn write_lines<I, L>(&mut self, name: &str, lines: I) -> Result<()>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
+        self.file(name).clear();
+        self.append_lines(name, lines)
+    }
+
+    pub fn write_at(&mut self, name: &str, offset: u32, data: &[u8]) -> Result<()> {
+        let file = self.file(name);
+        let offset = offset as usize;
+        if offset > file.len() {
+            return Err(embedded_sdmmc::Error::InvalidOffset);
+        }
+        let end = offset + data.len();
+        if end > file.len() {
+            file.resize(end, 0);
+        }
+        file[offset..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn read_at(&mut self, name: &str, offset: u32, buf: &mut [u8]) -> Result<usize> {
+        let file = self
+            .files
+            .get(&name.to_ascii_uppercase())
+            .ok_or(embedded_sdmmc::Error::NotFound)?;
+        let rest = file.get(offset as usize..).unwrap_or(&[]);
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    pub fn read_records<const N: usize>(
+        &mut self,
+        name: &str,
+        mut f: impl FnMut(u32, &[u8; N]),
+    ) -> Result<()> {
+        if let Some(file) = self.files.get(&name.to_ascii_uppercase()) {
+            for (index, record) in file.chunks_exact(N).enumerate() {
+                f(index as u32, record.try_into().unwrap());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list_files(&mut self, mut f: impl FnMut(&str, u32)) -> Result<()> {
+        for (name, file) in &self.files {
+            f(name, file.len() as u32);
+        }
+        Ok(())
+    }
+}
+
+// Walks a file a line at a time, like the card's LineReader; lines too
+// long for the string are cut short.
+pub struct LineReader {
+    name: String,
+    offset: usize,
+}
+
+impl LineReader {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_ascii_uppercase(),
+            offset: 0,
+        }
+    }
+
+    pub fn next_line<S: embassy_rp::spi::Instance, const N: usize>(
+        &mut self,
+        storage: &mut SdStorage<'_, S>,
+    ) -> Result<Option<heapless::String<N>>> {
+        let file = storage
+            .files
+            .get(&self.name)
+            .ok_or(embedded_sdmmc::Error::NotFound)?;
+        let rest = file.get(self.offset..).unwrap_or(&[]);
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        self.offset += (end + 1).min(rest.len());
+        let bytes = &rest[..end];
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        let mut line = heapless::String::new();
+        for c in core::str::from_utf8(bytes).unwrap_or("").chars() {
+            if line.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(Some(line))
+    }
+}
@@ -0,0 +1,25 @@
+//! Stand-in for embassy-rp on the host. The modules under test only name
+//! `spi::Instance` as a bound on `SdStorage`, and take a `gpio::Input` for
+//! buttons that are never pressed here.
+#![no_std]
+
+pub mod spi {
+    pub trait Instance {}
+}
+
+pub mod gpio {
+    use core::future::pending;
+    use core::marker::PhantomData;
+
+    pub struct Input<'d>(PhantomData<&'d ()>);
+
+    impl Input<'_> {
+        pub async fn wait_for_falling_edge(&mut self) {
+            pending().await
+        }
+
+        pub async fn wait_for_high(&mut self) {
+            pending().await
+        }
+    }
+}
@@ -6,10 +6,21 @@ pushd $SCRIPTDIR/..
 REPODIR=$(pwd)
 FWDIR="$REPODIR/cyw43-firmware"
 
-if [[ ! -d "$FWDIR" ]] ; then
-    mkdir -p "$FWDIR"
-    wget https://github.com/embassy-rs/embassy/raw/4f7ac1946a43379306aa432961fb97bba1139a6e/cyw43-firmware/43439A0.bin -O "$FWDIR/43439A0.bin"
-    wget https://github.com/embassy-rs/embassy/raw/4f7ac1946a43379306aa432961fb97bba1139a6e/cyw43-firmware/43439A0_clm.bin -O "$FWDIR/43439A0_clm.bin"
+BASEURL=https://github.com/embassy-rs/embassy/raw/4f7ac1946a43379306aa432961fb97bba1139a6e/cyw43-firmware
+
+# Each blob on its own, so a checkout from before one was added still gets it.
+mkdir -p "$FWDIR"
+FETCHED=0
+for BLOB in 43439A0.bin 43439A0_clm.bin 43439A0_btfw.bin ; do
+    if [[ ! -f "$FWDIR/$BLOB" ]] ; then
+        # Via a .part file, so a failed download isn't taken as done next time.
+        wget "$BASEURL/$BLOB" -O "$FWDIR/$BLOB.part"
+        mv "$FWDIR/$BLOB.part" "$FWDIR/$BLOB"
+        FETCHED=1
+    fi
+done
+
+if [[ $FETCHED == 1 ]] ; then
     sha256sum -c cyw43-firmware.sha256
 else
     echo "Firmware already here, skipping."
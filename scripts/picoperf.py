@@ -0,0 +1,211 @@
+#!/usr/bin/env python3
+"""Host-side peer for the picomap throughput tester (src/perf.rs).
+
+Against a Pico built with PERF_MODE="server":
+
+    ./picoperf.py 192.168.1.77                 # TCP, laptop -> Pico
+    ./picoperf.py 192.168.1.77 --down          # TCP, Pico -> laptop
+    ./picoperf.py 192.168.1.77 --udp --rate 8000
+
+As the server for a Pico built with PERF_MODE="client":
+
+    ./picoperf.py -s
+"""
+import argparse
+import socket
+import struct
+import time
+
+PORT = 5301
+REQUEST = struct.Struct(">4sBBBBHHHH")
+REPORT = struct.Struct(">4sQIIII")
+DATAGRAM = struct.Struct(">II")
+END_SEQ = 0xFFFFFFFF
+UDP_IDLE = 2.0
+
+
+def recv_exact(sock, n):
+    buf = b""
+    while len(buf) < n:
+        chunk = sock.recv(n - len(buf))
+        if not chunk:
+            raise ConnectionError("connection closed")
+        buf += chunk
+    return buf
+
+
+def now_us():
+    return int(time.monotonic() * 1_000_000) & 0xFFFFFFFF
+
+
+def tcp_send(sock, secs):
+    chunk = b"\x5a" * 8192
+    sent = 0
+    deadline = time.monotonic() + secs
+    while time.monotonic() < deadline:
+        sock.sendall(chunk)
+        sent += len(chunk)
+    sock.shutdown(socket.SHUT_WR)
+    return sent
+
+
+def tcp_receive(sock):
+    total = 0
+    start = None
+    while True:
+        chunk = sock.recv(65536)
+        if not chunk:
+            break
+        if start is None:
+            start = time.monotonic()
+        total += len(chunk)
+    elapsed_ms = int((time.monotonic() - start) * 1000) if start else 0
+    return dict(bytes=total, elapsed_ms=elapsed_ms)
+
+
+def udp_send(udp, remote, secs, payload_len, rate_kbps):
+    packet = bytearray(payload_len)
+    start = time.monotonic()
+    sent = 0
+    seq = 0
+    while time.monotonic() - start < secs:
+        DATAGRAM.pack_into(packet, 0, seq, now_us())
+        udp.sendto(packet, remote)
+        sent += payload_len
+        seq += 1
+        target = start + sent * 8 / (rate_kbps * 1000)
+        delay = target - time.monotonic()
+        if delay > 0:
+            time.sleep(delay)
+    for _ in range(3):
+        udp.sendto(DATAGRAM.pack(END_SEQ, 0), remote)
+        time.sleep(0.01)
+    return sent
+
+
+def udp_receive(udp, first_timeout):
+    udp.settimeout(first_timeout)
+    received = 0
+    total = 0
+    highest = -1
+    first = last = None
+    prev_transit = None
+    jitter = 0.0
+    while True:
+        try:
+            data, _ = udp.recvfrom(2048)
+        except socket.timeout:
+            break
+        udp.settimeout(UDP_IDLE)
+        if len(data) < 8:
+            continue
+        seq, sent_us = DATAGRAM.unpack_from(data)
+        if seq == END_SEQ:
+            break
+        now = time.monotonic()
+        first = first or now
+        last = now
+        received += 1
+        total += len(data)
+        highest = max(highest, seq)
+        transit = ((now_us() - sent_us) & 0xFFFFFFFF)
+        if transit >= 0x80000000:
+            transit -= 0x100000000
+        if prev_transit is not None:
+            jitter += (abs(transit - prev_transit) - jitter) / 16
+        prev_transit = transit
+    lost = max(0, highest + 1 - received)
+    elapsed_ms = int((last - first) * 1000) if first else 0
+    return dict(bytes=total, elapsed_ms=elapsed_ms, received=received, lost=lost, jitter_us=int(jitter))
+
+
+def show(label, r):
+    mbps = r["bytes"] * 8 / max(r["elapsed_ms"], 1) / 1000
+    line = f"{label}: {mbps:.2f} Mbit/s, {r['bytes']} bytes in {r['elapsed_ms']} ms"
+    if "received" in r:
+        total = r["received"] + r["lost"]
+        loss = 100 * r["lost"] / total if total else 0
+        line += f", loss {r['lost']}/{total} ({loss:.1f}%), jitter {r['jitter_us'] / 1000:.2f} ms"
+    print(line)
+
+
+def client(args):
+    sock = socket.create_connection((args.host, PORT), timeout=args.time + 10)
+    udp = None
+    udp_port = 0
+    if args.udp:
+        udp = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        udp.bind(("", 0))
+        udp_port = udp.getsockname()[1]
+    sock.sendall(REQUEST.pack(b"PMPF", 1, int(args.udp), int(args.down), 0,
+                              args.time, args.length, args.rate, udp_port))
+    if recv_exact(sock, 4) != b"PMPA":
+        raise SystemExit("server did not accept the request")
+
+    if not args.udp and not args.down:
+        tcp_send(sock, args.time)
+    elif not args.udp:
+        show("received", tcp_receive(sock))
+        return
+    elif not args.down:
+        udp_send(udp, (args.host, PORT), args.time, args.length, args.rate)
+    else:
+        show("received", udp_receive(udp, args.time + 10))
+        return
+
+    magic, total, elapsed_ms, received, lost, jitter_us = REPORT.unpack(recv_exact(sock, REPORT.size))
+    r = dict(bytes=total, elapsed_ms=elapsed_ms)
+    if args.udp:
+        r.update(received=received, lost=lost, jitter_us=jitter_us)
+    show("pico received", r)
+
+
+def serve():
+    listener = socket.create_server(("", PORT))
+    udp = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    udp.bind(("", PORT))
+    print(f"listening on {PORT}")
+    while True:
+        sock, peer = listener.accept()
+        with sock:
+            magic, ver, proto, down, _, secs, length, rate, udp_port = REQUEST.unpack(
+                recv_exact(sock, REQUEST.size))
+            if magic != b"PMPF" or ver != 1:
+                continue
+            print(f"{peer[0]}: {'udp' if proto else 'tcp'} {'down' if down else 'up'} for {secs}s")
+            sock.sendall(b"PMPA")
+            if proto == 0 and down == 0:
+                r = tcp_receive(sock)
+            elif proto == 0:
+                tcp_send(sock, secs)
+                continue
+            elif down == 0:
+                r = udp_receive(udp, secs + 10)
+            else:
+                udp_send(udp, (peer[0], udp_port), secs, max(length, 8), max(rate, 1))
+                continue
+            show("received", r)
+            sock.sendall(REPORT.pack(b"PMPR", r["bytes"], r["elapsed_ms"], r.get("received", 0),
+                                     r.get("lost", 0), r.get("jitter_us", 0)))
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("host", nargs="?", help="Pico to test against")
+    parser.add_argument("-s", "--server", action="store_true", help="be the server for a Pico client")
+    parser.add_argument("-u", "--udp", action="store_true", help="UDP instead of TCP")
+    parser.add_argument("-d", "--down", action="store_true", help="Pico sends, we receive")
+    parser.add_argument("-t", "--time", type=int, default=10, help="seconds (default 10)")
+    parser.add_argument("-r", "--rate", type=int, default=5000, help="UDP rate in kbit/s (default 5000)")
+    parser.add_argument("-l", "--length", type=int, default=1024, help="UDP payload bytes (default 1024)")
+    args = parser.parse_args()
+    if args.server:
+        serve()
+    elif args.host:
+        client(args)
+    else:
+        parser.error("need a host, or -s to serve")
+
+
+if __name__ == "__main__":
+    main()
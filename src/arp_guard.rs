@@ -0,0 +1,269 @@
+//! arp_guard
+//! ---------
+//!
+//! Duplicate IP detection, fed by `arp_tap`.
+//!
+//! On join we ARP-probe our own DHCP address RFC 5227 style: three probes
+//! from 0.0.0.0, and anyone else answering for (or probing for) that
+//! address is a conflict. Unlike RFC 5227 this happens after the address
+//! is in use: embassy-net applies the lease as soon as DHCP acks it and
+//! has no way to decline one, so the probe can only report a conflict, not
+//! avoid it. After that `watch` keeps an IP -> MAC table from
+//! every ARP packet it sees, and flags an IP that two MACs keep claiming.
+//!
+//! Conflicts go out on `CONFLICTS` for main to put on the OLED and SD log,
+//! and newly seen hosts on `SIGHTINGS` for the inventory.
+use core::fmt::{self, Write};
+use defmt::{info, warn};
+use embassy_net::{IpAddress, Ipv4Address};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use heapless::Vec;
+
+use crate::arp_tap::{ArpPacket, ArpTapState};
+use crate::oui;
+
+const PROBE_NUM: usize = 3;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+// Two MACs answering for the same IP this close together is a conflict,
+// further apart is more likely a DHCP lease moving.
+const CONFLICT_WINDOW: Duration = Duration::from_secs(300);
+const MAX_BINDINGS: usize = 64;
+
+pub static CONFLICTS: Channel<CriticalSectionRawMutex, Conflict, 4> = Channel::new();
+
+// New IP/MAC pairs as the watcher sees them, for the inventory. `ndp`
+// sends IPv6 ones here too. Dropped if nobody's keeping up.
+pub static SIGHTINGS: Channel<CriticalSectionRawMutex, (IpAddress, [u8; 6]), 8> = Channel::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Conflict {
+    pub ip: Ipv4Address,
+    pub mac_a: [u8; 6],
+    pub mac_b: [u8; 6],
+    // Found by probing our own address, rather than watching.
+    pub ours: bool,
+}
+
+impl Conflict {
+    pub fn vendor_a(&self) -> &'static str {
+        oui::vendor(&self.mac_a)
+    }
+
+    pub fn vendor_b(&self) -> &'static str {
+        oui::vendor(&self.mac_b)
+    }
+}
+
+pub struct Mac<'a>(pub &'a [u8; 6]);
+
+impl fmt::Display for Mac<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            m[0], m[1], m[2], m[3], m[4], m[5]
+        )
+    }
+}
+
+// "00:11:22:aa:bb:cc" or "00-11-22-AA-BB-CC"
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let s = s.trim();
+    let sep = if s.contains('-') { '-' } else { ':' };
+    let mut out = [0u8; 6];
+    let mut parts = s.split(sep);
+    for byte in out.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(out)
+}
+
+// "ARP conflict 192.168.1.20 00:11:22:33:44:55 (Dell) vs ..."
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ARP conflict {}{} {} ({}) vs {} ({})",
+            self.ip,
+            if self.ours { " (ours)" } else { "" },
+            Mac(&self.mac_a),
+            self.vendor_a(),
+            Mac(&self.mac_b),
+            self.vendor_b()
+        )
+    }
+}
+
+fn report(conflict: Conflict) {
+    let mut line: heapless::String<128> = heapless::String::new();
+    write!(&mut line, "{}", conflict).ok();
+    warn!("arp_guard: {}", line.as_str());
+    CONFLICTS.try_send(conflict).ok();
+}
+
+// Someone other than us claiming `ip`, or probing for it.
+fn claims(packet: &ArpPacket, ip: Ipv4Address, our_mac: [u8; 6]) -> bool {
+    packet.sender_mac != our_mac
+        && (packet.sender_ip == ip || (packet.is_probe() && packet.target_ip == ip))
+}
+
+// Runs once the stack already has `ip`, so it may have answered ARP for
+// it by now; a conflict found here is reported, the lease is kept.
+pub async fn probe_own(tap: &ArpTapState, ip: Ipv4Address) -> Option<Conflict> {
+    let our_mac = tap.mac();
+    tap.clear();
+    info!("arp_guard: probing {}", ip);
+
+    let listen = async {
+        loop {
+            let packet = tap.receive().await;
+            if claims(&packet, ip, our_mac) {
+                return packet.sender_mac;
+            }
+        }
+    };
+    let probe = async {
+        for _ in 0..PROBE_NUM {
+            tap.send(ArpPacket::probe(our_mac, ip));
+            Timer::after(PROBE_INTERVAL).await;
+        }
+        Timer::after(ANNOUNCE_WAIT).await;
+    };
+
+    let other = match embassy_futures::select::select(listen, probe).await {
+        embassy_futures::select::Either::First(mac) => mac,
+        embassy_futures::select::Either::Second(()) => return None,
+    };
+    let conflict = Conflict {
+        ip,
+        mac_a: our_mac,
+        mac_b: other,
+        ours: true,
+    };
+    report(conflict);
+    Some(conflict)
+}
+
+struct Binding {
+    ip: Ipv4Address,
+    mac: [u8; 6],
+    last_seen: Instant,
+    // The other MAC we already reported, so a pair flapping back and forth
+    // only gets reported once.
+    reported: Option<[u8; 6]>,
+}
+
+// Watches ARP on this interface forever. Spawn it from a task.
+pub async fn watch(tap: &ArpTapState, own_ip: Ipv4Address) -> ! {
+    let our_mac = tap.mac();
+    let mut table: Vec<Binding, MAX_BINDINGS> = Vec::new();
+    let mut reported_ours = false;
+    tap.clear();
+
+    loop {
+        let packet = tap.receive().await;
+
+        if claims(&packet, own_ip, our_mac) {
+            if !reported_ours {
+                reported_ours = true;
+                report(Conflict {
+                    ip: own_ip,
+                    mac_a: our_mac,
+                    mac_b: packet.sender_mac,
+                    ours: true,
+                });
+            }
+            continue;
+        }
+        if packet.sender_ip.is_unspecified() || packet.sender_mac == our_mac {
+            continue;
+        }
+
+        let now = Instant::now();
+        match table.iter_mut().find(|b| b.ip == packet.sender_ip) {
+            Some(b) if b.mac == packet.sender_mac => b.last_seen = now,
+            Some(b) => {
+                if now - b.last_seen < CONFLICT_WINDOW
+                    && b.reported != Some(packet.sender_mac)
+                    && b.reported != Some(b.mac)
+                {
+                    report(Conflict {
+                        ip: b.ip,
+                        mac_a: b.mac,
+                        mac_b: packet.sender_mac,
+                        ours: false,
+                    });
+                    b.reported = Some(b.mac);
+                }
+                SIGHTINGS
+                    .try_send((packet.sender_ip.into(), packet.sender_mac))
+                    .ok();
+                b.mac = packet.sender_mac;
+                b.last_seen = now;
+            }
+            None => {
+                SIGHTINGS
+                    .try_send((packet.sender_ip.into(), packet.sender_mac))
+                    .ok();
+                if table.is_full()
+                    && let Some(oldest) = table
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, b)| b.last_seen)
+                        .map(|(i, _)| i)
+                {
+                    table.swap_remove(oldest);
+                }
+                table
+                    .push(Binding {
+                        ip: packet.sender_ip,
+                        mac: packet.sender_mac,
+                        last_seen: now,
+                        reported: None,
+                    })
+                    .ok();
+            }
+        }
+    }
+}
+
+// Waits up to `timeout` for a conflict from the watcher.
+pub async fn next_conflict(timeout: Duration) -> Option<Conflict> {
+    with_timeout(timeout, CONFLICTS.receive()).await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC];
+
+    #[test]
+    fn parses_macs() {
+        assert_eq!(parse_mac("00:11:22:aa:bb:cc"), Some(MAC));
+        assert_eq!(parse_mac(" 00-11-22-AA-BB-CC "), Some(MAC));
+        assert_eq!(parse_mac("00:11:22:aa:bb"), None);
+        assert_eq!(parse_mac("00:11:22:aa:bb:cc:dd"), None);
+        assert_eq!(parse_mac("00:11:22:aa:bb:zz"), None);
+        assert_eq!(parse_mac("0:11:22:aa:bb:cc"), None);
+    }
+
+    #[test]
+    fn mac_round_trip() {
+        let mut s: heapless::String<17> = heapless::String::new();
+        write!(&mut s, "{}", Mac(&MAC)).unwrap();
+        assert_eq!(s, "00:11:22:aa:bb:cc");
+        assert_eq!(parse_mac(&s), Some(MAC));
+    }
+}
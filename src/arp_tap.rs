@@ -0,0 +1,244 @@
+//! arp_tap
+//! -------
+//!
+//! embassy-net's raw sockets sit at the IP layer, so ARP never reaches
+//! them. This wraps the network driver instead: every received ARP frame
+//! is copied to a channel for whoever is listening (smoltcp still gets the
+//! frame as normal), and ARP packets we queue get sent out ahead of the
+//! stack's own traffic. IPv6 neighbor discovery, ARP's replacement, gets
+//! the same treatment on a channel of its own (see `ndp`).
+//!
+//! One `ArpTapState` per interface, living in a static.
+use core::cell::Cell;
+use core::task::Context;
+use embassy_net::Ipv4Address;
+use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::ndp::Neighbor;
+
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+
+const ETH_HEADER_LEN: usize = 14;
+const ARP_LEN: usize = 28;
+// Minimum Ethernet frame without the FCS.
+const MIN_FRAME_LEN: usize = 60;
+
+pub const BROADCAST_MAC: [u8; 6] = [0xFF; 6];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: [u8; 6],
+    pub sender_ip: Ipv4Address,
+    pub target_mac: [u8; 6],
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    // Ethernet/IPv4 ARP only, which is all we'll ever see.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN + ARP_LEN
+            || u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_ARP
+        {
+            return None;
+        }
+        let arp = &frame[ETH_HEADER_LEN..ETH_HEADER_LEN + ARP_LEN];
+        if arp[..6] != [0x00, 0x01, 0x08, 0x00, 6, 4] {
+            return None;
+        }
+        let mac = |at: usize| -> [u8; 6] { arp[at..at + 6].try_into().unwrap() };
+        let ip = |at: usize| Ipv4Address::new(arp[at], arp[at + 1], arp[at + 2], arp[at + 3]);
+        Some(Self {
+            op: u16::from_be_bytes([arp[6], arp[7]]),
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    // RFC 5227 probe: "who has `ip`?" from 0.0.0.0, so nobody's ARP cache
+    // gets updated by it.
+    pub fn probe(our_mac: [u8; 6], ip: Ipv4Address) -> Self {
+        Self {
+            op: OP_REQUEST,
+            sender_mac: our_mac,
+            sender_ip: Ipv4Address::UNSPECIFIED,
+            target_mac: [0; 6],
+            target_ip: ip,
+        }
+    }
+
+    pub fn is_probe(&self) -> bool {
+        self.op == OP_REQUEST && self.sender_ip.is_unspecified()
+    }
+
+    // Writes the whole Ethernet frame, padded to the minimum length.
+    pub fn write_frame(&self, buf: &mut [u8]) -> usize {
+        let dst = if self.op == OP_REQUEST {
+            BROADCAST_MAC
+        } else {
+            self.target_mac
+        };
+        buf[..MIN_FRAME_LEN].fill(0);
+        buf[0..6].copy_from_slice(&dst);
+        buf[6..12].copy_from_slice(&self.sender_mac);
+        buf[12..14].copy_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        let arp = &mut buf[ETH_HEADER_LEN..ETH_HEADER_LEN + ARP_LEN];
+        arp[..6].copy_from_slice(&[0x00, 0x01, 0x08, 0x00, 6, 4]);
+        arp[6..8].copy_from_slice(&self.op.to_be_bytes());
+        arp[8..14].copy_from_slice(&self.sender_mac);
+        arp[14..18].copy_from_slice(&self.sender_ip.octets());
+        arp[18..24].copy_from_slice(&self.target_mac);
+        arp[24..28].copy_from_slice(&self.target_ip.octets());
+        MIN_FRAME_LEN
+    }
+}
+
+pub struct ArpTapState {
+    mac: Mutex<CriticalSectionRawMutex, Cell<[u8; 6]>>,
+    seen: Channel<CriticalSectionRawMutex, ArpPacket, 16>,
+    neighbors: Channel<CriticalSectionRawMutex, Neighbor, 8>,
+    outbox: Channel<CriticalSectionRawMutex, ArpPacket, 4>,
+    waker: AtomicWaker,
+}
+
+impl ArpTapState {
+    pub const fn new() -> Self {
+        Self {
+            mac: Mutex::new(Cell::new([0; 6])),
+            seen: Channel::new(),
+            neighbors: Channel::new(),
+            outbox: Channel::new(),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    // Our own MAC on this interface.
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac.lock(|m| m.get())
+    }
+
+    // Next ARP packet off the wire. If nobody's reading, new packets are
+    // dropped once the channel fills, so call `clear` before listening.
+    pub async fn receive(&self) -> ArpPacket {
+        self.seen.receive().await
+    }
+
+    pub fn clear(&self) {
+        self.seen.clear();
+    }
+
+    // Next IPv6 neighbor off the wire, same rules as `receive`.
+    pub async fn receive_neighbor(&self) -> Neighbor {
+        self.neighbors.receive().await
+    }
+
+    pub fn clear_neighbors(&self) {
+        self.neighbors.clear();
+    }
+
+    // Queues `packet` to go out. Returns false if the queue is full.
+    pub fn send(&self, packet: ArpPacket) -> bool {
+        let queued = self.outbox.try_send(packet).is_ok();
+        self.waker.wake();
+        queued
+    }
+}
+
+impl Default for ArpTapState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ArpTap<D> {
+    inner: D,
+    state: &'static ArpTapState,
+}
+
+impl<D: Driver> ArpTap<D> {
+    pub fn new(inner: D, state: &'static ArpTapState) -> Self {
+        if let HardwareAddress::Ethernet(mac) = inner.hardware_address() {
+            state.mac.lock(|m| m.set(mac));
+        }
+        Self { inner, state }
+    }
+
+    fn send_queued(&mut self, cx: &mut Context) {
+        self.state.waker.register(cx.waker());
+        while !self.state.outbox.is_empty() {
+            let Some(tx) = self.inner.transmit(cx) else {
+                return;
+            };
+            let Ok(packet) = self.state.outbox.try_receive() else {
+                return;
+            };
+            tx.consume(MIN_FRAME_LEN, |buf| packet.write_frame(buf));
+        }
+    }
+}
+
+impl<D: Driver> Driver for ArpTap<D> {
+    type RxToken<'a>
+        = TapRx<D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.send_queued(cx);
+        let state = self.state;
+        self.inner
+            .receive(cx)
+            .map(|(rx, tx)| (TapRx { inner: rx, state }, tx))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.send_queued(cx);
+        self.inner.transmit(cx)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+pub struct TapRx<T> {
+    inner: T,
+    state: &'static ArpTapState,
+}
+
+impl<T: RxToken> RxToken for TapRx<T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let state = self.state;
+        self.inner.consume(|frame| {
+            if let Some(packet) = ArpPacket::parse(frame) {
+                state.seen.try_send(packet).ok();
+            } else if let Some(neighbor) = Neighbor::parse(frame) {
+                state.neighbors.try_send(neighbor).ok();
+            }
+            f(frame)
+        })
+    }
+}
@@ -6,7 +6,8 @@ use embassy_executor::Spawner;
 use embassy_rp::gpio::{Level, Output};
 use embedded_sdmmc::VolumeManager;
 
-use picomap::sd_spi::{DummyTime, EmbassySpiDevice};
+use picomap::clock::WallClock;
+use picomap::sd_spi::EmbassySpiDevice;
 use picomap::fat_utils::{append_line, sfn_to_str, str_to_sfn};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -30,7 +31,7 @@ async fn main(_spawner: Spawner) {
     let blockdev = EmbassySpiDevice::new(spi_dev, cs);
     blockdev.init().unwrap();
 
-    let ts = DummyTime;
+    let ts = WallClock;
     let mut volman = VolumeManager::new(blockdev, ts);
 
     let mut filenames: heapless::Vec<heapless::String<13>, 64> = heapless::Vec::new();
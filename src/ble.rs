@@ -0,0 +1,143 @@
+//! ble
+//! ---
+//!
+//! BLE advertisement scanning on the CYW43439's Bluetooth side.
+//!
+//! This is a tiny HCI host: just enough commands to put the controller into
+//! active LE scanning, and a reader that feeds every LE Advertising Report
+//! into a `BleInventory`. Parsing lives in `ble_adv` so it can be tested on
+//! the host.
+use bt_hci::ControllerToHostPacket;
+use bt_hci::cmd::controller_baseband::{Reset, SetEventMask};
+use bt_hci::cmd::le::{LeSetScanEnable, LeSetScanParams};
+use bt_hci::controller::{Controller, ControllerCmdSync, ExternalController};
+use bt_hci::event::Event;
+use bt_hci::event::le::LeEvent;
+use bt_hci::param::{
+    AddrKind, Duration as HciDuration, EventMask, LeScanKind, ScanningFilterPolicy,
+};
+use cyw43::bluetooth::BtDriver;
+use defmt::{debug, info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Timer};
+
+use heapless::String;
+
+use crate::ble_adv::{BleInventory, CSV_HEADER};
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+
+pub type BleController = ExternalController<BtDriver<'static>, 4>;
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub enum BleError {
+    // Controller rejected a command.
+    Hci,
+    // Transport failed talking to the controller.
+    Io,
+}
+
+impl<E> From<bt_hci::cmd::Error<E>> for BleError {
+    fn from(err: bt_hci::cmd::Error<E>) -> Self {
+        match err {
+            bt_hci::cmd::Error::Hci(_) => BleError::Hci,
+            bt_hci::cmd::Error::Io(_) => BleError::Io,
+        }
+    }
+}
+
+// Scans for `duration`, folding every advertisement into `inventory`.
+// Active scanning, so we also get scan responses (that's where most
+// devices put their name).
+pub async fn scan(
+    controller: &BleController,
+    duration: Duration,
+    inventory: &mut BleInventory,
+) -> Result<(), BleError> {
+    info!("ble: scanning for {}s", duration.as_secs());
+
+    // Command completions only arrive through `read`, so the reader has to
+    // be running while the commands are executing.
+    let commands = async {
+        controller.exec(&Reset::new()).await?;
+        controller
+            .exec(&SetEventMask::new(
+                EventMask::new()
+                    .enable_le_meta(true)
+                    .enable_hardware_error(true),
+            ))
+            .await?;
+        controller
+            .exec(&LeSetScanParams::new(
+                LeScanKind::Active,
+                // Raw units of 0.625ms: 100ms interval, 50ms window. bt-hci
+                // types these with the wrong timebase, so from_millis is off.
+                HciDuration::from_u16(0x00A0),
+                HciDuration::from_u16(0x0050),
+                AddrKind::PUBLIC,
+                ScanningFilterPolicy::BasicUnfiltered,
+            ))
+            .await?;
+        controller.exec(&LeSetScanEnable::new(true, false)).await?;
+        Timer::after(duration).await;
+        controller.exec(&LeSetScanEnable::new(false, false)).await?;
+        Ok::<(), BleError>(())
+    };
+
+    let reader = async {
+        let mut buf = [0u8; 259];
+        loop {
+            match controller.read(&mut buf).await {
+                Ok(ControllerToHostPacket::Event(Event::Le(LeEvent::LeAdvertisingReport(ev)))) => {
+                    for report in ev.reports.iter() {
+                        let Ok(report) = report else {
+                            warn!("ble: malformed advertising report");
+                            continue;
+                        };
+                        // HCI hands addresses over LSB first.
+                        let mut addr = [0u8; 6];
+                        addr.copy_from_slice(report.addr.raw());
+                        addr.reverse();
+                        let random = report.addr_kind != AddrKind::PUBLIC;
+                        inventory.observe(addr, random, report.rssi, report.data);
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("ble: HCI read failed");
+                    Timer::after_millis(10).await;
+                }
+            }
+        }
+    };
+
+    let res = match select(commands, reader).await {
+        Either::First(res) => res,
+        Either::Second(_) => unreachable!(),
+    };
+
+    debug!(
+        "ble: {} devices ({} dropped)",
+        inventory.len(),
+        inventory.dropped
+    );
+    res
+}
+
+pub const EXPORT_FILE: &str = "BLE.CSV";
+
+// Writes the whole inventory out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    inventory: &BleInventory,
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    let rows = inventory.devices.iter().map(|dev| {
+        let mut row: String<192> = String::new();
+        dev.write_csv(&mut row).ok();
+        row
+    });
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::try_from(CSV_HEADER).unwrap()).chain(rows),
+    )
+}
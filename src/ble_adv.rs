@@ -0,0 +1,413 @@
+//! ble_adv
+//! -------
+//!
+//! BLE advertising data parser and the in-RAM list of devices we've heard.
+//!
+//! No hardware in here, so it's unit tested on the host. The HCI side that
+//! feeds it reports lives in `ble`.
+//!
+//! These devices stay out of `inventory` on purpose. Most advertise from a
+//! random address that changes every few minutes, and the inventory never
+//! forgets a host, so each boot would add a fresh batch until it's full
+//! and stops taking LAN hosts. They also have no IP for the checks to
+//! scan and nothing for Wake-on-LAN, and would show up in the session
+//! diff as new hosts on every boot.
+use heapless::{String, Vec};
+
+// AD types we care about, from the Bluetooth "Assigned Numbers" doc.
+const AD_FLAGS: u8 = 0x01;
+const AD_UUID16_PARTIAL: u8 = 0x02;
+const AD_UUID16_COMPLETE: u8 = 0x03;
+const AD_UUID32_PARTIAL: u8 = 0x04;
+const AD_UUID32_COMPLETE: u8 = 0x05;
+const AD_UUID128_PARTIAL: u8 = 0x06;
+const AD_UUID128_COMPLETE: u8 = 0x07;
+const AD_NAME_SHORT: u8 = 0x08;
+const AD_NAME_COMPLETE: u8 = 0x09;
+const AD_TX_POWER: u8 = 0x0A;
+const AD_MANUFACTURER: u8 = 0xFF;
+
+pub const MAX_NAME: usize = 32;
+pub const MAX_MFG_DATA: usize = 24;
+pub const MAX_UUIDS: usize = 8;
+pub const MAX_DEVICES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceUuid {
+    U16(u16),
+    U32(u32),
+    U128([u8; 16]),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AdvData {
+    pub flags: Option<u8>,
+    pub name: Option<String<MAX_NAME>>,
+    pub name_complete: bool,
+    pub tx_power: Option<i8>,
+    pub company_id: Option<u16>,
+    pub mfg_data: Vec<u8, MAX_MFG_DATA>,
+    pub uuids: Vec<ServiceUuid, MAX_UUIDS>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvError {
+    // An AD structure's length runs off the end of the payload.
+    Truncated,
+}
+
+// Parses a 0-31 byte advertising (or scan response) payload.
+// Fields we don't understand are skipped. Anything that doesn't fit the
+// fixed-size buffers is dropped rather than failing the whole report.
+pub fn parse(data: &[u8]) -> Result<AdvData, AdvError> {
+    let mut out = AdvData::default();
+    let mut rest = data;
+
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = len as usize;
+        if len == 0 {
+            // Zero length marks early end of significant data.
+            break;
+        }
+        if tail.len() < len {
+            return Err(AdvError::Truncated);
+        }
+        let (field, next) = tail.split_at(len);
+        rest = next;
+
+        let (ad_type, value) = (field[0], &field[1..]);
+        match ad_type {
+            AD_FLAGS => out.flags = value.first().copied(),
+            AD_UUID16_PARTIAL | AD_UUID16_COMPLETE => {
+                for c in value.chunks_exact(2) {
+                    push_uuid(&mut out, ServiceUuid::U16(u16::from_le_bytes([c[0], c[1]])));
+                }
+            }
+            AD_UUID32_PARTIAL | AD_UUID32_COMPLETE => {
+                for c in value.chunks_exact(4) {
+                    push_uuid(
+                        &mut out,
+                        ServiceUuid::U32(u32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+                    );
+                }
+            }
+            AD_UUID128_PARTIAL | AD_UUID128_COMPLETE => {
+                for c in value.chunks_exact(16) {
+                    let mut uuid = [0u8; 16];
+                    uuid.copy_from_slice(c);
+                    push_uuid(&mut out, ServiceUuid::U128(uuid));
+                }
+            }
+            AD_NAME_SHORT | AD_NAME_COMPLETE => {
+                // A short name never replaces a complete one.
+                if ad_type == AD_NAME_SHORT && out.name_complete {
+                    continue;
+                }
+                out.name = Some(lossy_name(value));
+                out.name_complete = ad_type == AD_NAME_COMPLETE;
+            }
+            AD_TX_POWER => out.tx_power = value.first().map(|&b| b as i8),
+            AD_MANUFACTURER if value.len() >= 2 => {
+                out.company_id = Some(u16::from_le_bytes([value[0], value[1]]));
+                out.mfg_data.clear();
+                let n = (value.len() - 2).min(MAX_MFG_DATA);
+                out.mfg_data.extend_from_slice(&value[2..2 + n]).ok();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+fn push_uuid(out: &mut AdvData, uuid: ServiceUuid) {
+    if !out.uuids.contains(&uuid) {
+        out.uuids.push(uuid).ok();
+    }
+}
+
+// Names are supposed to be UTF-8 but plenty of gadgets disagree.
+fn lossy_name(bytes: &[u8]) -> String<MAX_NAME> {
+    let mut name = String::new();
+    for &b in bytes {
+        let c = if b.is_ascii_graphic() || b == b' ' {
+            b as char
+        } else {
+            '?'
+        };
+        if name.push(c).is_err() {
+            break;
+        }
+    }
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BleDevice {
+    pub addr: [u8; 6],
+    pub random_addr: bool,
+    pub rssi: i8,
+    pub best_rssi: i8,
+    pub seen: u16,
+    pub adv: AdvData,
+}
+
+impl BleDevice {
+    // Writes one CSV row (without newline):
+    // addr,addr_type,rssi,best_rssi,seen,name,company_id,mfg_data,uuids
+    pub fn write_csv<W: core::fmt::Write>(&self, w: &mut W) -> core::fmt::Result {
+        let a = self.addr;
+        write!(
+            w,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X},{},{},{},{},",
+            a[0],
+            a[1],
+            a[2],
+            a[3],
+            a[4],
+            a[5],
+            if self.random_addr { "random" } else { "public" },
+            self.rssi,
+            self.best_rssi,
+            self.seen
+        )?;
+        if let Some(name) = &self.adv.name {
+            // Keep the CSV parseable.
+            for c in name.chars() {
+                w.write_char(if c == ',' || c == '"' { '_' } else { c })?;
+            }
+        }
+        w.write_char(',')?;
+        if let Some(id) = self.adv.company_id {
+            write!(w, "{:04X}", id)?;
+        }
+        w.write_char(',')?;
+        for b in &self.adv.mfg_data {
+            write!(w, "{:02X}", b)?;
+        }
+        w.write_char(',')?;
+        for (i, uuid) in self.adv.uuids.iter().enumerate() {
+            if i > 0 {
+                w.write_char(' ')?;
+            }
+            match uuid {
+                ServiceUuid::U16(u) => write!(w, "{:04X}", u)?,
+                ServiceUuid::U32(u) => write!(w, "{:08X}", u)?,
+                // 128-bit UUIDs are little-endian on the air.
+                ServiceUuid::U128(u) => {
+                    for (j, b) in u.iter().rev().enumerate() {
+                        if matches!(j, 4 | 6 | 8 | 10) {
+                            w.write_char('-')?;
+                        }
+                        write!(w, "{:02X}", b)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const CSV_HEADER: &str = "addr,addr_type,rssi,best_rssi,seen,name,company_id,mfg_data,uuids";
+
+#[derive(Default)]
+pub struct BleInventory {
+    pub devices: Vec<BleDevice, MAX_DEVICES>,
+    // Reports we couldn't store because the list was full.
+    pub dropped: u32,
+}
+
+impl BleInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Merges one advertising report. Advertisements and scan responses for
+    // the same address get folded together, so a name from the scan
+    // response isn't lost when the next plain advertisement comes in.
+    pub fn observe(&mut self, addr: [u8; 6], random_addr: bool, rssi: i8, data: &[u8]) {
+        let Ok(adv) = parse(data) else {
+            return;
+        };
+
+        if let Some(dev) = self.devices.iter_mut().find(|d| d.addr == addr) {
+            dev.rssi = rssi;
+            dev.best_rssi = dev.best_rssi.max(rssi);
+            dev.seen = dev.seen.saturating_add(1);
+            merge(&mut dev.adv, adv);
+            return;
+        }
+
+        let dev = BleDevice {
+            addr,
+            random_addr,
+            rssi,
+            best_rssi: rssi,
+            seen: 1,
+            adv,
+        };
+        if self.devices.push(dev).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
+
+fn merge(into: &mut AdvData, from: AdvData) {
+    if from.flags.is_some() {
+        into.flags = from.flags;
+    }
+    if let Some(name) = from.name
+        && (from.name_complete || !into.name_complete)
+    {
+        into.name = Some(name);
+        into.name_complete = from.name_complete;
+    }
+    if from.tx_power.is_some() {
+        into.tx_power = from.tx_power;
+    }
+    if from.company_id.is_some() {
+        into.company_id = from.company_id;
+        into.mfg_data = from.mfg_data;
+    }
+    for uuid in from.uuids {
+        if !into.uuids.contains(&uuid) {
+            into.uuids.push(uuid).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: [u8; 6] = [0xC0, 0xFF, 0xEE, 0x00, 0x11, 0x22];
+
+    #[test]
+    fn parses_flags_name_and_uuid16() {
+        let data = [
+            0x02, 0x01, 0x06, // flags
+            0x05, 0x09, b'T', b'a', b'g', b'1', // complete name
+            0x05, 0x03, 0x0F, 0x18, 0x0A, 0x18, // battery + device info services
+        ];
+        let adv = parse(&data).unwrap();
+        assert_eq!(adv.flags, Some(0x06));
+        assert_eq!(adv.name.as_deref(), Some("Tag1"));
+        assert!(adv.name_complete);
+        assert_eq!(
+            adv.uuids.as_slice(),
+            &[ServiceUuid::U16(0x180F), ServiceUuid::U16(0x180A)]
+        );
+    }
+
+    #[test]
+    fn parses_manufacturer_data() {
+        // Apple (0x004C) iBeacon prefix
+        let data = [0x07, 0xFF, 0x4C, 0x00, 0x02, 0x15, 0xAA, 0xBB];
+        let adv = parse(&data).unwrap();
+        assert_eq!(adv.company_id, Some(0x004C));
+        assert_eq!(adv.mfg_data.as_slice(), &[0x02, 0x15, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn parses_uuid128_and_tx_power() {
+        let mut data = [0u8; 21];
+        data[0] = 0x11;
+        data[1] = AD_UUID128_COMPLETE;
+        for (i, b) in data[2..18].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        data[18] = 0x02;
+        data[19] = AD_TX_POWER;
+        data[20] = 0xF4; // -12 dBm
+        let adv = parse(&data).unwrap();
+        let mut expected = [0u8; 16];
+        for (i, b) in expected.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(adv.uuids.as_slice(), &[ServiceUuid::U128(expected)]);
+        assert_eq!(adv.tx_power, Some(-12));
+    }
+
+    #[test]
+    fn stops_at_zero_length_padding() {
+        let data = [0x02, 0x01, 0x1A, 0x00, 0x00, 0x00];
+        let adv = parse(&data).unwrap();
+        assert_eq!(adv.flags, Some(0x1A));
+    }
+
+    #[test]
+    fn rejects_truncated_field() {
+        let data = [0x05, 0x09, b'a', b'b'];
+        assert_eq!(parse(&data), Err(AdvError::Truncated));
+    }
+
+    #[test]
+    fn non_ascii_name_is_replaced() {
+        let data = [0x04, 0x08, b'h', 0xC3, b'i'];
+        let adv = parse(&data).unwrap();
+        assert_eq!(adv.name.as_deref(), Some("h?i"));
+        assert!(!adv.name_complete);
+    }
+
+    #[test]
+    fn inventory_merges_adv_and_scan_response() {
+        let mut inv = BleInventory::new();
+        inv.observe(ADDR, true, -70, &[0x02, 0x01, 0x06, 0x03, 0x03, 0x0F, 0x18]);
+        inv.observe(ADDR, true, -60, &[0x05, 0x09, b'B', b'e', b'a', b'n']);
+        inv.observe(ADDR, true, -75, &[0x02, 0x01, 0x06]);
+
+        assert_eq!(inv.len(), 1);
+        let dev = &inv.devices[0];
+        assert_eq!(dev.seen, 3);
+        assert_eq!(dev.rssi, -75);
+        assert_eq!(dev.best_rssi, -60);
+        assert_eq!(dev.adv.name.as_deref(), Some("Bean"));
+        assert_eq!(dev.adv.uuids.as_slice(), &[ServiceUuid::U16(0x180F)]);
+    }
+
+    #[test]
+    fn short_name_does_not_replace_complete_name() {
+        let mut inv = BleInventory::new();
+        inv.observe(ADDR, false, -50, &[0x05, 0x09, b'L', b'o', b'n', b'g']);
+        inv.observe(ADDR, false, -50, &[0x02, 0x08, b'L']);
+        assert_eq!(inv.devices[0].adv.name.as_deref(), Some("Long"));
+    }
+
+    #[test]
+    fn inventory_counts_dropped_when_full() {
+        let mut inv = BleInventory::new();
+        for i in 0..(MAX_DEVICES + 3) {
+            inv.observe([0, 0, 0, 0, (i >> 8) as u8, i as u8], true, -80, &[]);
+        }
+        assert_eq!(inv.len(), MAX_DEVICES);
+        assert_eq!(inv.dropped, 3);
+    }
+
+    #[test]
+    fn csv_row() {
+        let mut inv = BleInventory::new();
+        inv.observe(
+            ADDR,
+            true,
+            -61,
+            &[
+                0x04, 0x09, b'a', b',', b'b', 0x05, 0xFF, 0x59, 0x00, 0x01, 0x02, 0x03, 0x03, 0x0F,
+                0x18,
+            ],
+        );
+        let mut row: String<128> = String::new();
+        inv.devices[0].write_csv(&mut row).unwrap();
+        assert_eq!(
+            row.as_str(),
+            "C0:FF:EE:00:11:22,random,-61,-61,1,a_b,0059,0102,180F"
+        );
+    }
+}
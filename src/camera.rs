@@ -0,0 +1,477 @@
+//! camera
+//! ------
+//!
+//! IP camera discovery, since cameras are most of the unmanaged devices we
+//! turn up.
+//!
+//! Two ways in:
+//!   - ONVIF WS-Discovery: a SOAP Probe for NetworkVideoTransmitter sent
+//!     to 239.255.255.250:3702. Cameras answer with a ProbeMatch giving
+//!     their device service URL (XAddrs) and scopes, which usually include
+//!     a name and hardware model. We join the group while listening, so a
+//!     Hello from a camera that boots meanwhile counts too.
+//!   - RTSP on 554 and 8554 of every host seen: `OPTIONS` for the `Server`
+//!     header, then `DESCRIBE` of the root URL. A 200 there means the
+//!     stream is open without a login, which is worth knowing.
+//!
+//! Replies are parsed in place, straight out of the socket buffer. The
+//! stack doesn't reassemble IP fragments, so a ProbeMatch too big for one
+//! packet never arrives; RTSP still finds those cameras.
+//!
+//! Cameras get tagged `camera` in the inventory, and their endpoints go in
+//! `CAMERAS.CSV`.
+use core::fmt::{self, Write};
+use defmt::{debug, info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use heapless::{String, Vec};
+
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::util::{element, find, write_all};
+
+pub const WSD_GROUP: Ipv4Address = Ipv4Address::new(239, 255, 255, 250);
+// 01:00:5e plus the low 23 bits of the group, for the WiFi chip's filter.
+pub const WSD_MAC: [u8; 6] = [0x01, 0x00, 0x5e, 0x7f, 0xff, 0xfa];
+pub const WSD_PORT: u16 = 3702;
+pub const RTSP_PORTS: [u16; 2] = [554, 8554];
+pub const MAX_CAMERAS: usize = 16;
+pub const EXPORT_FILE: &str = "CAMERAS.CSV";
+pub const CSV_HEADER: &str = "host,name,hardware,onvif,rtsp,rtsp_server,stream";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+const WSD_WAIT: Duration = Duration::from_secs(3);
+
+const PROBE_HEAD: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<e:Envelope xmlns:e="http://www.w3.org/2003/05/soap-envelope""#,
+    r#" xmlns:w="http://schemas.xmlsoap.org/ws/2004/08/addressing""#,
+    r#" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery""#,
+    r#" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">"#,
+    "<e:Header><w:MessageID>uuid:",
+);
+const PROBE_TAIL: &str = concat!(
+    "</w:MessageID>",
+    r#"<w:To e:mustUnderstand="true">urn:schemas-xmlsoap-org:ws:2005:04:discovery</w:To>"#,
+    r#"<w:Action e:mustUnderstand="true">"#,
+    "http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</w:Action>",
+    "</e:Header><e:Body><d:Probe><d:Types>dn:NetworkVideoTransmitter</d:Types>",
+    "</d:Probe></e:Body></e:Envelope>",
+);
+
+// What a camera said about itself over WS-Discovery.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Onvif {
+    // First of its XAddrs, the ONVIF device service.
+    pub xaddr: String<64>,
+    pub name: String<32>,
+    pub hardware: String<32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rtsp {
+    pub port: u16,
+    pub server: String<48>,
+    // DESCRIBE of the root URL worked without credentials.
+    pub open: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Camera {
+    pub host: IpAddress,
+    pub onvif: Option<Onvif>,
+    pub rtsp: Option<Rtsp>,
+}
+
+// "192.168.1.40 AXIS M3045 M3045-V rtsp:554 AXIS Media Server open"
+impl fmt::Display for Camera {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.host)?;
+        if let Some(onvif) = &self.onvif {
+            for s in [&onvif.name[..], &onvif.hardware] {
+                if !s.is_empty() {
+                    write!(f, " {}", s)?;
+                }
+            }
+        }
+        if let Some(rtsp) = &self.rtsp {
+            write!(f, " rtsp:{}", rtsp.port)?;
+            if !rtsp.server.is_empty() {
+                write!(f, " {}", rtsp.server)?;
+            }
+            if rtsp.open {
+                f.write_str(" open")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Camera {
+    pub fn csv_row(&self) -> String<256> {
+        let mut row = String::new();
+        let onvif = self.onvif.clone().unwrap_or_default();
+        write!(
+            row,
+            "{},{},{},{},",
+            self.host, onvif.name, onvif.hardware, onvif.xaddr
+        )
+        .ok();
+        if let Some(rtsp) = &self.rtsp {
+            write!(
+                row,
+                "rtsp://{}:{}/,{},{}",
+                Bracketed(self.host),
+                rtsp.port,
+                rtsp.server,
+                if rtsp.open { "open" } else { "auth" }
+            )
+            .ok();
+        } else {
+            row.push_str(",,").ok();
+        }
+        row
+    }
+}
+
+// An address the way it goes in a URL, IPv6 in brackets.
+struct Bracketed(IpAddress);
+
+impl fmt::Display for Bracketed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            IpAddress::Ipv4(ip) => write!(f, "{}", ip),
+            IpAddress::Ipv6(ip) => write!(f, "[{}]", ip),
+        }
+    }
+}
+
+// The Probe, with a MessageID made unique by `id`.
+pub fn probe_message(id: u64) -> String<768> {
+    let mut msg = String::new();
+    msg.push_str(PROBE_HEAD).ok();
+    write!(
+        msg,
+        "7069636f-6d61-7000-8000-{:012x}",
+        id & 0xffff_ffff_ffff
+    )
+    .ok();
+    msg.push_str(PROBE_TAIL).ok();
+    msg
+}
+
+// %20 and friends, as they appear in ONVIF scopes.
+fn percent_decode<const N: usize>(s: &str) -> String<N> {
+    let mut out = String::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        let c = if b == b'%' {
+            let hex = [bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+            core::str::from_utf8(&hex)
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .unwrap_or(b'?')
+        } else {
+            b
+        };
+        let c = if c == b',' || !c.is_ascii() || c.is_ascii_control() {
+            ' '
+        } else {
+            c as char
+        };
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+// A ProbeMatch or Hello. None for anything else, including our own Probe
+// if it loops back.
+pub fn parse_discovery(xml: &[u8]) -> Option<Onvif> {
+    let xml = core::str::from_utf8(xml).ok()?;
+    element(xml, "ProbeMatch").or_else(|| element(xml, "Hello"))?;
+    let mut onvif = Onvif::default();
+    let xaddr = element(xml, "XAddrs")?.split_whitespace().next()?;
+    onvif.xaddr = percent_decode(xaddr);
+    for scope in element(xml, "Scopes").unwrap_or("").split_whitespace() {
+        let Some(rest) = scope.strip_prefix("onvif://www.onvif.org/") else {
+            continue;
+        };
+        if let Some(name) = rest.strip_prefix("name/") {
+            onvif.name = percent_decode(name);
+        } else if let Some(hardware) = rest.strip_prefix("hardware/") {
+            onvif.hardware = percent_decode(hardware);
+        }
+    }
+    Some(onvif)
+}
+
+pub fn rtsp_request(method: &str, host: IpAddress, port: u16, cseq: u8) -> String<160> {
+    let mut req = String::new();
+    write!(
+        req,
+        "{} rtsp://{}:{}/ RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: picomap\r\n",
+        method,
+        Bracketed(host),
+        port,
+        cseq
+    )
+    .ok();
+    if method == "DESCRIBE" {
+        req.push_str("Accept: application/sdp\r\n").ok();
+    }
+    req.push_str("\r\n").ok();
+    req
+}
+
+// Status code and Server header, once the headers are all in.
+pub fn parse_rtsp(resp: &[u8]) -> Option<(u16, &str)> {
+    let end = find(resp, b"\r\n\r\n")?;
+    let head = core::str::from_utf8(&resp[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()?
+        .strip_prefix("RTSP/1.")?
+        .split(' ')
+        .nth(1)?
+        .parse()
+        .ok()?;
+    let server = lines
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("server"))
+        .map_or("", |(_, v)| v.trim());
+    Some((status, server))
+}
+
+// WS-Discovery, gathering ProbeMatches until WSD_WAIT runs out.
+async fn discover(stack: Stack<'_>) -> Vec<(IpAddress, Onvif), MAX_CAMERAS> {
+    let mut found = Vec::new();
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buf = [0u8; 3072];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 1024];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if socket.bind(0).is_err() {
+        return found;
+    }
+    let joined = stack.join_multicast_group(WSD_GROUP).is_ok();
+    let msg = probe_message(Instant::now().as_ticks());
+    if let Err(e) = socket
+        .send_to(msg.as_bytes(), (IpAddress::Ipv4(WSD_GROUP), WSD_PORT))
+        .await
+    {
+        warn!("camera: probe not sent: {:?}", e);
+    } else {
+        let deadline = Instant::now() + WSD_WAIT;
+        while let Ok((from, onvif)) = with_deadline(
+            deadline,
+            socket.recv_from_with(|xml, meta| (meta.endpoint.addr, parse_discovery(xml))),
+        )
+        .await
+        {
+            if let Some(onvif) = onvif
+                && !found.iter().any(|(host, _)| *host == from)
+            {
+                debug!("camera: ONVIF at {}", from);
+                found.push((from, onvif)).ok();
+            }
+        }
+    }
+    if joined {
+        stack.leave_multicast_group(WSD_GROUP).ok();
+    }
+    found
+}
+
+// OPTIONS then DESCRIBE on one connection. None if nothing RTSP answers.
+async fn rtsp(stack: Stack<'_>, host: IpAddress, port: u16) -> Option<Rtsp> {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, port))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    let mut buf = [0u8; 1024];
+    let mut result: Option<Rtsp> = None;
+    for (cseq, method) in [(1, "OPTIONS"), (2, "DESCRIBE")] {
+        let req = rtsp_request(method, host, port, cseq);
+        if write_all(&mut socket, req.as_bytes()).await.is_err() {
+            break;
+        }
+        let mut got = 0;
+        let read = async {
+            while got < buf.len() && parse_rtsp(&buf[..got]).is_none() {
+                match socket.read(&mut buf[got..]).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => got += n,
+                }
+            }
+        };
+        with_timeout(READ_TIMEOUT, read).await.ok();
+        let Some((status, server)) = parse_rtsp(&buf[..got]) else {
+            break;
+        };
+        let rtsp = result.get_or_insert_with(|| Rtsp {
+            port,
+            ..Default::default()
+        });
+        if rtsp.server.is_empty() {
+            rtsp.server = String::try_from(server.get(..48).unwrap_or(server)).unwrap_or_default();
+        }
+        rtsp.open = method == "DESCRIBE" && status == 200;
+    }
+    socket.abort();
+    result
+}
+
+// ONVIF discovery, then RTSP on every host and every ONVIF camera.
+pub async fn run(stack: Stack<'_>, hosts: &[IpAddress]) -> Vec<Camera, MAX_CAMERAS> {
+    let mut cameras: Vec<Camera, MAX_CAMERAS> = discover(stack)
+        .await
+        .into_iter()
+        .map(|(host, onvif)| Camera {
+            host,
+            onvif: Some(onvif),
+            rtsp: None,
+        })
+        .collect();
+    let onvif_only = cameras
+        .iter()
+        .map(|c| c.host)
+        .filter(|h| !hosts.contains(h));
+    let targets: Vec<IpAddress, MAX_CAMERAS> = onvif_only.collect();
+    for &host in hosts.iter().chain(&targets) {
+        let mut found = None;
+        for port in RTSP_PORTS {
+            found = rtsp(stack, host, port).await;
+            if found.is_some() {
+                break;
+            }
+        }
+        let Some(found) = found else {
+            continue;
+        };
+        match cameras.iter_mut().find(|c| c.host == host) {
+            Some(camera) => camera.rtsp = Some(found),
+            None => {
+                let camera = Camera {
+                    host,
+                    onvif: None,
+                    rtsp: Some(found),
+                };
+                if cameras.push(camera).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    info!("camera: {} hosts, {} cameras", hosts.len(), cameras.len());
+    cameras
+}
+
+// Writes the cameras out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    cameras: &[Camera],
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::<256>::try_from(CSV_HEADER).unwrap())
+            .chain(cameras.iter().map(Camera::csv_row)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE_MATCH: &str = concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope">"#,
+        "<SOAP-ENV:Header><wsa:RelatesTo>uuid:7069636f</wsa:RelatesTo></SOAP-ENV:Header>",
+        "<SOAP-ENV:Body><d:ProbeMatches><d:ProbeMatch>",
+        "<wsa:EndpointReference><wsa:Address>urn:uuid:1</wsa:Address></wsa:EndpointReference>",
+        "<d:Types>dn:NetworkVideoTransmitter tds:Device</d:Types>",
+        "<d:Scopes>onvif://www.onvif.org/type/video_encoder ",
+        "onvif://www.onvif.org/name/AXIS%20M3045 onvif://www.onvif.org/hardware/M3045-V ",
+        "onvif://www.onvif.org/location/</d:Scopes>",
+        r#"<d:XAddrs>http://192.168.1.40/onvif/device_service "#,
+        "http://[fe80::1]/onvif/device_service</d:XAddrs>",
+        "<d:MetadataVersion>1</d:MetadataVersion>",
+        "</d:ProbeMatch></d:ProbeMatches></SOAP-ENV:Body></SOAP-ENV:Envelope>",
+    );
+
+    #[test]
+    fn probe() {
+        let msg = probe_message(0x1234);
+        assert!(msg.len() < msg.capacity());
+        assert!(msg.contains("<w:MessageID>uuid:7069636f-6d61-7000-8000-000000001234<"));
+        assert!(msg.ends_with("</e:Envelope>"));
+        // Our own Probe isn't a camera.
+        assert_eq!(parse_discovery(msg.as_bytes()), None);
+    }
+
+    #[test]
+    fn probe_match() {
+        let onvif = parse_discovery(PROBE_MATCH.as_bytes()).unwrap();
+        assert_eq!(onvif.xaddr, "http://192.168.1.40/onvif/device_service");
+        assert_eq!(onvif.name, "AXIS M3045");
+        assert_eq!(onvif.hardware, "M3045-V");
+        assert_eq!(
+            element(PROBE_MATCH, "Types"),
+            Some("dn:NetworkVideoTransmitter tds:Device")
+        );
+    }
+
+    #[test]
+    fn rtsp_replies() {
+        let req = rtsp_request("DESCRIBE", IpAddress::v4(192, 168, 1, 40), 554, 2);
+        assert_eq!(
+            req,
+            "DESCRIBE rtsp://192.168.1.40:554/ RTSP/1.0\r\nCSeq: 2\r\n\
+             User-Agent: picomap\r\nAccept: application/sdp\r\n\r\n"
+        );
+        let v6 = rtsp_request("OPTIONS", "fd00::40".parse().unwrap(), 8554, 1);
+        assert!(v6.starts_with("OPTIONS rtsp://[fd00::40]:8554/ RTSP/1.0\r\n"));
+
+        let reply = b"RTSP/1.0 200 OK\r\nCSeq: 1\r\nPublic: OPTIONS, DESCRIBE\r\n\
+                      server: GStreamer RTSP server\r\n\r\n";
+        assert_eq!(parse_rtsp(reply), Some((200, "GStreamer RTSP server")));
+        let reply = b"RTSP/1.0 401 Unauthorized\r\nCSeq: 2\r\n\r\n";
+        assert_eq!(parse_rtsp(reply), Some((401, "")));
+        assert_eq!(parse_rtsp(b"RTSP/1.0 200 OK\r\nCSeq: 1\r\n"), None);
+        assert_eq!(parse_rtsp(b"HTTP/1.1 400 Bad Request\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let camera = Camera {
+            host: IpAddress::v4(192, 168, 1, 40),
+            onvif: parse_discovery(PROBE_MATCH.as_bytes()),
+            rtsp: Some(Rtsp {
+                port: 554,
+                server: String::try_from("AXIS Media Server").unwrap(),
+                open: false,
+            }),
+        };
+        assert_eq!(
+            camera.csv_row(),
+            "192.168.1.40,AXIS M3045,M3045-V,http://192.168.1.40/onvif/device_service,\
+             rtsp://192.168.1.40:554/,AXIS Media Server,auth"
+        );
+        let camera = Camera {
+            host: IpAddress::v4(192, 168, 1, 41),
+            onvif: None,
+            rtsp: None,
+        };
+        assert_eq!(camera.csv_row(), "192.168.1.41,,,,,,");
+    }
+}
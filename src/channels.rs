@@ -0,0 +1,188 @@
+//! channels
+//! --------
+//!
+//! 2.4 GHz channel utilization from one WiFi scan.
+//!
+//! 20 MHz channels are 5 MHz apart, so an AP interferes with anything
+//! within four channels of its own, less the further away it is. Each
+//! channel gets a congestion score: every AP on or near it counts for how
+//! strong it is, scaled down by how far away its channel is. We recommend
+//! whichever of 1, 6 and 11 scores lowest, since those are the only ones
+//! that don't overlap each other.
+use core::fmt::{self, Write};
+use heapless::String;
+
+pub const CHANNELS: usize = 13;
+pub const NON_OVERLAPPING: [u8; 3] = [1, 6, 11];
+// Channels this far apart or more don't overlap.
+const OVERLAP_SPAN: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ChannelStats {
+    pub channel: u8,
+    // APs on this exact channel.
+    pub aps: u8,
+    // Strongest of those, dBm. None with no APs.
+    pub best_rssi: Option<i16>,
+    // APs on other channels close enough to overlap this one.
+    pub overlapping: u8,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Survey {
+    pub channels: [ChannelStats; CHANNELS],
+}
+
+impl Default for Survey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How much an AP at `rssi` dBm counts, 1 for barely there up to 70 for
+// right next to us.
+fn weight(rssi: i16) -> u32 {
+    (rssi + 100).clamp(1, 70) as u32
+}
+
+impl Survey {
+    pub fn new() -> Self {
+        Self {
+            channels: core::array::from_fn(|i| ChannelStats {
+                channel: i as u8 + 1,
+                aps: 0,
+                best_rssi: None,
+                overlapping: 0,
+                score: 0,
+            }),
+        }
+    }
+
+    // One AP heard on `channel`. Anything outside 1-13 (5 GHz, or 14) is
+    // ignored.
+    pub fn add(&mut self, channel: u8, rssi: i16) {
+        if !(1..=CHANNELS as u8).contains(&channel) {
+            return;
+        }
+        for stats in self.channels.iter_mut() {
+            let distance = stats.channel.abs_diff(channel);
+            if distance >= OVERLAP_SPAN {
+                continue;
+            }
+            if distance == 0 {
+                stats.aps = stats.aps.saturating_add(1);
+                stats.best_rssi = Some(stats.best_rssi.map_or(rssi, |best| best.max(rssi)));
+            } else {
+                stats.overlapping = stats.overlapping.saturating_add(1);
+            }
+            stats.score += weight(rssi) * (OVERLAP_SPAN - distance) as u32;
+        }
+    }
+
+    pub fn total_aps(&self) -> u32 {
+        self.channels.iter().map(|c| c.aps as u32).sum()
+    }
+
+    pub fn get(&self, channel: u8) -> Option<&ChannelStats> {
+        self.channels.get((channel as usize).checked_sub(1)?)
+    }
+
+    // Least congested of 1, 6 and 11, the lowest channel on a tie.
+    pub fn recommend(&self) -> u8 {
+        NON_OVERLAPPING
+            .into_iter()
+            .min_by_key(|&ch| self.get(ch).map_or(u32::MAX, |c| c.score))
+            .unwrap()
+    }
+
+    // Each channel's score as a percentage of the worst, for a bar chart.
+    pub fn bars(&self) -> [u8; CHANNELS] {
+        let worst = self.channels.iter().map(|c| c.score).max().unwrap_or(0);
+        core::array::from_fn(|i| {
+            (self.channels[i].score * 100)
+                .checked_div(worst)
+                .unwrap_or(0) as u8
+        })
+    }
+
+    // One CSV row per channel, each starting with `time`.
+    pub fn csv_rows<'a>(
+        &'a self,
+        time: &'a dyn fmt::Display,
+    ) -> impl Iterator<Item = String<64>> + 'a {
+        let best = self.recommend();
+        self.channels.iter().map(move |c| {
+            let mut row = String::new();
+            write!(&mut row, "{},{},{},", time, c.channel, c.aps).ok();
+            if let Some(rssi) = c.best_rssi {
+                write!(&mut row, "{}", rssi).ok();
+            }
+            write!(
+                &mut row,
+                ",{},{},{}",
+                c.overlapping,
+                c.score,
+                if c.channel == best { "yes" } else { "no" }
+            )
+            .ok();
+            row
+        })
+    }
+}
+
+pub const CSV_HEADER: &str = "time,channel,aps,best_rssi,overlapping,score,recommended";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_overlap() {
+        let mut s = Survey::new();
+        s.add(6, -40);
+        s.add(6, -70);
+        s.add(8, -50);
+        s.add(36, -30);
+
+        let six = s.get(6).unwrap();
+        assert_eq!((six.aps, six.best_rssi, six.overlapping), (2, Some(-40), 1));
+        assert_eq!(six.score, 60 * 5 + 30 * 5 + 50 * 3);
+        let eleven = s.get(11).unwrap();
+        assert_eq!((eleven.aps, eleven.overlapping), (0, 1));
+        assert_eq!(eleven.score, 100);
+        assert_eq!(s.get(1).unwrap().score, 0);
+        assert_eq!(s.total_aps(), 3);
+    }
+
+    #[test]
+    fn recommends_quietest() {
+        let mut s = Survey::new();
+        assert_eq!(s.recommend(), 1);
+        s.add(1, -45);
+        s.add(3, -60);
+        s.add(11, -80);
+        assert_eq!(s.recommend(), 6);
+        s.add(6, -30);
+        assert_eq!(s.recommend(), 11);
+    }
+
+    #[test]
+    fn scales_bars() {
+        let mut s = Survey::new();
+        assert_eq!(s.bars(), [0; CHANNELS]);
+        s.add(1, -50);
+        let bars = s.bars();
+        assert_eq!(&bars[..6], &[100, 80, 60, 40, 20, 0]);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let mut s = Survey::new();
+        s.add(6, -40);
+        let rows: Vec<_> = s.csv_rows(&"T").collect();
+        assert_eq!(rows.len(), CHANNELS);
+        assert_eq!(rows[0].as_str(), "T,1,0,,0,0,yes");
+        assert_eq!(rows[5].as_str(), "T,6,1,-40,0,300,no");
+    }
+}
@@ -0,0 +1,224 @@
+//! clock
+//! -----
+//!
+//! Wall-clock time on top of embassy_time's uptime. SNTP (see `sntp`) hands
+//! us a unix timestamp, we remember which uptime it went with, and work out
+//! "now" from there. Until the first sync, time reads as uptime counted from
+//! 1970-01-01, so logs and FAT stamps still go in the right order.
+//!
+//! UTC is kept internally. Local time is a fixed offset from `.env`, there's
+//! no DST handling:
+//!
+//!     TZ_OFFSET_MINUTES="-300"
+use core::cell::Cell;
+use core::fmt;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_sdmmc::{TimeSource, Timestamp};
+
+#[derive(Clone, Copy)]
+struct Anchor {
+    unix_micros: u64,
+    uptime_micros: u64,
+}
+
+static ANCHOR: Mutex<CriticalSectionRawMutex, Cell<Option<Anchor>>> = Mutex::new(Cell::new(None));
+
+// Every log line gets the wall-clock time, in UTC.
+#[cfg(not(test))]
+defmt::timestamp!("{=u64:iso8601ms}", now_unix_micros() / 1000);
+
+// Called by SNTP with the current time. Returns how far off we were, in
+// microseconds (positive means our clock was behind).
+pub fn set_unix_micros(unix_micros: u64) -> i64 {
+    let before = now_unix_micros();
+    let anchor = Anchor {
+        unix_micros,
+        uptime_micros: Instant::now().as_micros(),
+    };
+    ANCHOR.lock(|a| a.set(Some(anchor)));
+    unix_micros as i64 - before as i64
+}
+
+pub fn is_synced() -> bool {
+    ANCHOR.lock(|a| a.get().is_some())
+}
+
+// Waits up to `timeout` for the first SNTP sync.
+pub async fn wait_synced(timeout: Duration) -> bool {
+    with_timeout(timeout, async {
+        while !is_synced() {
+            Timer::after_millis(100).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+pub fn now_unix_micros() -> u64 {
+    let uptime = Instant::now().as_micros();
+    match ANCHOR.lock(|a| a.get()) {
+        Some(anchor) => anchor.unix_micros + (uptime - anchor.uptime_micros),
+        None => uptime,
+    }
+}
+
+pub fn now_unix() -> u64 {
+    now_unix_micros() / 1_000_000
+}
+
+pub fn tz_offset_minutes() -> i32 {
+    option_env!("TZ_OFFSET_MINUTES")
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn now_utc() -> DateTime {
+    DateTime::from_unix(now_unix() as i64)
+}
+
+pub fn now_local() -> DateTime {
+    DateTime::from_unix(now_unix() as i64 + tz_offset_minutes() as i64 * 60)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    // 1-12
+    pub month: u8,
+    // 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    // Howard Hinnant's days-to-civil, good for anything after 1970.
+    pub fn from_unix(secs: i64) -> Self {
+        let secs = secs.max(0);
+        let days = secs / 86_400;
+        let rem = secs % 86_400;
+
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+}
+
+// 2025-06-01T14:03:09
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl defmt::Format for DateTime {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{=u16}-{=u8:02}-{=u8:02}T{=u8:02}:{=u8:02}:{=u8:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+// FAT timestamps are local time, so files show up right on a PC.
+pub struct WallClock;
+
+impl TimeSource for WallClock {
+    fn get_timestamp(&self) -> Timestamp {
+        now_local().into()
+    }
+}
+
+// FAT can't go before 1980; embedded-sdmmc writes earlier years as 1980.
+impl From<DateTime> for Timestamp {
+    fn from(t: DateTime) -> Self {
+        Timestamp {
+            year_since_1970: t.year.saturating_sub(1970).min(255) as u8,
+            zero_indexed_month: t.month - 1,
+            zero_indexed_day: t.day - 1,
+            hours: t.hour,
+            minutes: t.minute,
+            seconds: t.second,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Round trip through the on-disk FAT date and time fields.
+    fn fat(unix: i64) -> Timestamp {
+        let raw = Timestamp::from(DateTime::from_unix(unix)).serialize_to_fat();
+        Timestamp::from_fat(
+            u16::from_le_bytes([raw[2], raw[3]]),
+            u16::from_le_bytes([raw[0], raw[1]]),
+        )
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(DateTime::from_unix(0).to_string(), "1970-01-01T00:00:00");
+        assert_eq!(
+            DateTime::from_unix(1_748_786_589).to_string(),
+            "2025-06-01T14:03:09"
+        );
+        // Leap day.
+        assert_eq!(
+            DateTime::from_unix(1_709_210_096).to_string(),
+            "2024-02-29T12:34:56"
+        );
+    }
+
+    #[test]
+    fn fat_timestamps() {
+        // FAT keeps seconds in twos.
+        let t = fat(1_748_786_589);
+        assert_eq!(
+            (t.year_since_1970, t.zero_indexed_month, t.zero_indexed_day),
+            (55, 5, 0)
+        );
+        assert_eq!((t.hours, t.minutes, t.seconds), (14, 3, 8));
+
+        // The FAT epoch.
+        let t = fat(315_532_800);
+        assert_eq!(
+            (t.year_since_1970, t.zero_indexed_month, t.zero_indexed_day),
+            (10, 0, 0)
+        );
+
+        // Before the first sync the clock is uptime from 1970, which FAT
+        // can only show as 1980.
+        let t = fat(90);
+        assert_eq!(t.year_since_1970, 10);
+        assert_eq!((t.hours, t.minutes, t.seconds), (0, 1, 30));
+    }
+}
@@ -0,0 +1,647 @@
+//! dns_audit
+//! ---------
+//!
+//! DNS server audit, for authorized assessments only.
+//!
+//! For each host answering on UDP or TCP 53 we check:
+//!   - does it recurse for an outside name? (open resolver)
+//!   - does it answer `version.bind` in the CHAOS class?
+//!   - will it hand over the search domain with AXFR?
+//!
+//! Targets are the DHCP-provided DNS servers and the gateway (and the IPv6
+//! ones if configured), plus anything listed in `DNS_AUDIT_HOSTS` (comma
+//! separated IPv4 or IPv6 addresses). Once the inventory has had time to
+//! fill, `run_hosts` tries every host it has seen too, keeping the ones
+//! that answer on 53.
+//!
+//! embassy-net doesn't give us DHCP option 15, so the search domain comes
+//! from `DNS_AUDIT_DOMAIN` if set, otherwise we PTR our own IPv4 address
+//! and take everything after the first label.
+use core::fmt::Write;
+use defmt::{debug, info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_time::{Duration, with_timeout};
+use heapless::{String, Vec};
+
+use crate::dns_wire::{self, Header, Records};
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::util::write_all;
+use crate::vulns;
+
+const UDP_TIMEOUT: Duration = Duration::from_secs(2);
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+// Stop reading a zone transfer after this many messages, we only need to
+// know that it's allowed and roughly how big it is.
+const AXFR_MAX_MESSAGES: usize = 32;
+
+pub const MAX_TARGETS: usize = 16;
+pub const EXPORT_FILE: &str = "DNSAUDIT.CSV";
+pub const CSV_HEADER: &str =
+    "host,udp53,tcp53,lan_resolver,recursion,version_bind,domain,axfr,axfr_records,vulns";
+
+pub struct DnsAuditConfig {
+    pub probe_name: &'static str,
+    pub domain: Option<&'static str>,
+    pub extra_hosts: &'static str,
+}
+
+impl DnsAuditConfig {
+    pub fn from_env() -> Self {
+        Self {
+            probe_name: option_env!("DNS_AUDIT_NAME").unwrap_or("example.com"),
+            domain: option_env!("DNS_AUDIT_DOMAIN"),
+            extra_hosts: option_env!("DNS_AUDIT_HOSTS").unwrap_or(""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Recursion {
+    Unknown,
+    Refused,
+    Open,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Axfr {
+    NotTried,
+    Refused,
+    Allowed { records: u32 },
+}
+
+pub struct DnsAudit {
+    pub host: IpAddress,
+    pub udp_open: bool,
+    pub tcp_open: bool,
+    // One of our own DHCP resolvers; recursion is expected there.
+    pub lan_resolver: bool,
+    pub recursion: Recursion,
+    pub version: Option<String<64>>,
+    pub domain: Option<String<64>>,
+    pub axfr: Axfr,
+}
+
+impl DnsAudit {
+    fn new(host: IpAddress, lan_resolver: bool) -> Self {
+        Self {
+            host,
+            udp_open: false,
+            tcp_open: false,
+            lan_resolver,
+            recursion: Recursion::Unknown,
+            version: None,
+            domain: None,
+            axfr: Axfr::NotTried,
+        }
+    }
+
+    pub fn is_dns_server(&self) -> bool {
+        self.udp_open || self.tcp_open
+    }
+
+    // Known CVEs for the version.bind answer, for servers that give a
+    // product name with it (dnsmasq does, BIND doesn't).
+    pub fn vulns(&self) -> Option<vulns::Hint> {
+        vulns::check_banner(self.version.as_ref()?)
+    }
+
+    // Something worth a human's attention.
+    pub fn has_findings(&self) -> bool {
+        (self.recursion == Recursion::Open && !self.lan_resolver)
+            || self.version.is_some()
+            || matches!(self.axfr, Axfr::Allowed { .. })
+    }
+
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let yn = |b: bool| if b { "yes" } else { "no" };
+        write!(
+            w,
+            "{},{},{},{},",
+            self.host,
+            yn(self.udp_open),
+            yn(self.tcp_open),
+            yn(self.lan_resolver)
+        )?;
+        w.write_str(match self.recursion {
+            Recursion::Unknown => "unknown",
+            Recursion::Refused => "refused",
+            Recursion::Open => "open",
+        })?;
+        w.write_char(',')?;
+        if let Some(v) = &self.version {
+            for c in v.chars() {
+                w.write_char(if c == ',' { ' ' } else { c })?;
+            }
+        }
+        w.write_char(',')?;
+        if let Some(d) = &self.domain {
+            w.write_str(d)?;
+        }
+        match self.axfr {
+            Axfr::NotTried => w.write_str(",not_tried,")?,
+            Axfr::Refused => w.write_str(",refused,")?,
+            Axfr::Allowed { records } => write!(w, ",allowed,{}", records)?,
+        }
+        w.write_char(',')?;
+        if let Some(hint) = self.vulns() {
+            write!(w, "{}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+pub type DnsAuditResults = Vec<DnsAudit, MAX_TARGETS>;
+
+pub async fn run(stack: Stack<'_>, cfg: &DnsAuditConfig) -> DnsAuditResults {
+    let mut results = DnsAuditResults::new();
+    let v4 = stack.config_v4();
+    let v6 = stack.config_v6();
+
+    let mut targets: Vec<(IpAddress, bool), MAX_TARGETS> = Vec::new();
+    if let Some(v4) = &v4 {
+        for dns in &v4.dns_servers {
+            add_target(&mut targets, (*dns).into(), true);
+        }
+        if let Some(gw) = v4.gateway {
+            add_target(&mut targets, gw.into(), false);
+        }
+    }
+    if let Some(v6) = &v6 {
+        for dns in &v6.dns_servers {
+            add_target(&mut targets, (*dns).into(), true);
+        }
+        if let Some(gw) = v6.gateway {
+            add_target(&mut targets, gw.into(), false);
+        }
+    }
+    for host in cfg.extra_hosts.split(',') {
+        match host.trim().parse() {
+            Ok(addr) => add_target(&mut targets, addr, false),
+            Err(_) if host.trim().is_empty() => {}
+            Err(_) => warn!("dns_audit: bad address in DNS_AUDIT_HOSTS: {}", host),
+        }
+    }
+
+    let own = v4.map(|v4| v4.address.address());
+    for (host, lan_resolver) in targets {
+        let mut audit = DnsAudit::new(host, lan_resolver);
+        audit_host(stack, &mut audit, own, cfg).await;
+        info!(
+            "dns_audit: {} udp={} tcp={} recursion={} axfr={}",
+            host, audit.udp_open, audit.tcp_open, audit.recursion, audit.axfr
+        );
+        results.push(audit).ok();
+    }
+    results
+}
+
+// Audits the hosts in `hosts` that aren't in `results` yet, adding the
+// ones that turn out to speak DNS. Returns how many were added.
+pub async fn run_hosts(
+    stack: Stack<'_>,
+    cfg: &DnsAuditConfig,
+    hosts: &[IpAddress],
+    results: &mut DnsAuditResults,
+) -> usize {
+    let own = stack.config_v4().map(|v4| v4.address.address());
+    let mut added = 0;
+    for &host in hosts {
+        if results.is_full() {
+            warn!("dns_audit: target list full, skipping the rest");
+            break;
+        }
+        if results.iter().any(|a| a.host == host) {
+            continue;
+        }
+        let mut audit = DnsAudit::new(host, false);
+        audit_host(stack, &mut audit, own, cfg).await;
+        if audit.is_dns_server() {
+            info!(
+                "dns_audit: {} udp={} tcp={} recursion={} axfr={}",
+                host, audit.udp_open, audit.tcp_open, audit.recursion, audit.axfr
+            );
+            results.push(audit).ok();
+            added += 1;
+        }
+    }
+    added
+}
+
+fn add_target(targets: &mut Vec<(IpAddress, bool), MAX_TARGETS>, addr: IpAddress, lan: bool) {
+    if let Some(t) = targets.iter_mut().find(|(a, _)| *a == addr) {
+        t.1 |= lan;
+    } else {
+        targets.push((addr, lan)).ok();
+    }
+}
+
+pub async fn audit_host(
+    stack: Stack<'_>,
+    audit: &mut DnsAudit,
+    own: Option<Ipv4Address>,
+    cfg: &DnsAuditConfig,
+) {
+    let mut query = [0u8; 128];
+    let mut resp = [0u8; 512];
+    let host = audit.host;
+
+    // Open resolver: ask for an outside name with RD set.
+    if let Ok(len) = dns_wire::build_query(
+        &mut query,
+        0x5043,
+        cfg.probe_name,
+        dns_wire::TYPE_A,
+        dns_wire::CLASS_IN,
+        true,
+    ) && let Some(n) = udp_query(stack, host, &query[..len], &mut resp).await
+        && let Ok(h) = Header::parse(&resp[..n])
+    {
+        audit.udp_open = true;
+        audit.recursion =
+            if h.rcode() == dns_wire::RCODE_NOERROR && h.recursion_available() && h.ancount > 0 {
+                Recursion::Open
+            } else {
+                Recursion::Refused
+            };
+    }
+
+    // version.bind CH TXT. Not worth another wait if UDP said nothing.
+    if audit.udp_open
+        && let Ok(len) = dns_wire::build_query(
+            &mut query,
+            0x5044,
+            "version.bind",
+            dns_wire::TYPE_TXT,
+            dns_wire::CLASS_CH,
+            false,
+        )
+        && let Some(n) = udp_query(stack, host, &query[..len], &mut resp).await
+    {
+        audit.udp_open = true;
+        audit.version = first_txt(&resp[..n]);
+    }
+
+    audit.tcp_open = tcp_port_open(stack, host).await;
+    if !audit.is_dns_server() {
+        debug!("dns_audit: {} doesn't speak DNS", host);
+        return;
+    }
+
+    audit.domain = match (cfg.domain, own) {
+        (Some(d), _) => String::try_from(d).ok(),
+        (None, Some(own)) => search_domain(stack, host, own).await,
+        (None, None) => None,
+    };
+    if let Some(domain) = audit.domain.clone()
+        && audit.tcp_open
+    {
+        audit.axfr = try_axfr(stack, host, &domain).await;
+    }
+}
+
+pub async fn udp_query(
+    stack: Stack<'_>,
+    server: IpAddress,
+    query: &[u8],
+    resp: &mut [u8],
+) -> Option<usize> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buf = [0u8; 768];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    socket.bind(0).ok()?;
+
+    let remote = (server, dns_wire::PORT);
+    socket.send_to(query, remote).await.ok()?;
+
+    let id = &query[..2];
+    let recv = async {
+        loop {
+            let Ok((n, meta)) = socket.recv_from(resp).await else {
+                return None;
+            };
+            if meta.endpoint.addr == server && n >= 2 && resp[..2] == *id {
+                return Some(n);
+            }
+        }
+    };
+    with_timeout(UDP_TIMEOUT, recv).await.ok().flatten()
+}
+
+async fn tcp_port_open(stack: Stack<'_>, host: IpAddress) -> bool {
+    let mut rx_buf = [0u8; 64];
+    let mut tx_buf = [0u8; 64];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    let open = matches!(
+        with_timeout(TCP_TIMEOUT, socket.connect((host, dns_wire::PORT))).await,
+        Ok(Ok(()))
+    );
+    socket.abort();
+    open
+}
+
+fn first_txt(msg: &[u8]) -> Option<String<64>> {
+    let header = Header::parse(msg).ok()?;
+    if header.rcode() != dns_wire::RCODE_NOERROR || header.ancount == 0 {
+        return None;
+    }
+    Records::new(msg)
+        .ok()?
+        .flatten()
+        .find(|r| r.rtype == dns_wire::TYPE_TXT)
+        .map(|r| dns_wire::txt_to_string(r.rdata))
+}
+
+// PTR our own address at `server`, "pico.corp.example" -> "corp.example".
+async fn search_domain(
+    stack: Stack<'_>,
+    server: IpAddress,
+    own: Ipv4Address,
+) -> Option<String<64>> {
+    let mut query = [0u8; 64];
+    let mut resp = [0u8; 512];
+    let name = dns_wire::reverse_name(own.octets());
+    let len = dns_wire::build_query(
+        &mut query,
+        0x5045,
+        &name,
+        dns_wire::TYPE_PTR,
+        dns_wire::CLASS_IN,
+        true,
+    )
+    .ok()?;
+    let n = udp_query(stack, server, &query[..len], &mut resp).await?;
+    let msg = &resp[..n];
+    let ptr = Records::new(msg)
+        .ok()?
+        .flatten()
+        .find(|r| r.rtype == dns_wire::TYPE_PTR)?;
+    let (fqdn, _) = dns_wire::read_name(msg, ptr.rdata_at).ok()?;
+    let (_, domain) = fqdn.split_once('.')?;
+    if domain.is_empty() {
+        return None;
+    }
+    debug!(
+        "dns_audit: search domain {} (from PTR {})",
+        domain,
+        fqdn.as_str()
+    );
+    String::try_from(domain).ok()
+}
+
+async fn try_axfr(stack: Stack<'_>, host: IpAddress, domain: &str) -> Axfr {
+    let mut rx_buf = [0u8; 2048];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    socket.set_timeout(Some(TCP_TIMEOUT));
+
+    if !matches!(
+        with_timeout(TCP_TIMEOUT, socket.connect((host, dns_wire::PORT))).await,
+        Ok(Ok(()))
+    ) {
+        return Axfr::NotTried;
+    }
+
+    // TCP DNS messages get a two byte length prefix.
+    let mut query = [0u8; 130];
+    let Ok(len) = dns_wire::build_query(
+        &mut query[2..],
+        0x5046,
+        domain,
+        dns_wire::TYPE_AXFR,
+        dns_wire::CLASS_IN,
+        false,
+    ) else {
+        socket.abort();
+        return Axfr::NotTried;
+    };
+    query[..2].copy_from_slice(&(len as u16).to_be_bytes());
+    if write_all(&mut socket, &query[..len + 2]).await.is_err() {
+        socket.abort();
+        return Axfr::Refused;
+    }
+
+    let mut msg = [0u8; 1024];
+    let mut records = 0u32;
+    let mut soa_seen = 0;
+    let mut result = Axfr::Refused;
+
+    for _ in 0..AXFR_MAX_MESSAGES {
+        let mut len_buf = [0u8; 2];
+        if read_exact(&mut socket, &mut len_buf).await.is_err() {
+            break;
+        }
+        let len = u16::from_be_bytes(len_buf) as usize;
+        if len > msg.len() {
+            // Too big to look at, but it's still zone data.
+            if skip_exact(&mut socket, len).await.is_err() {
+                break;
+            }
+            continue;
+        }
+        if read_exact(&mut socket, &mut msg[..len]).await.is_err() {
+            break;
+        }
+        let Ok(header) = Header::parse(&msg[..len]) else {
+            break;
+        };
+        if header.rcode() != dns_wire::RCODE_NOERROR || header.ancount == 0 {
+            break;
+        }
+        let Ok(iter) = Records::new(&msg[..len]) else {
+            break;
+        };
+        for record in iter.flatten() {
+            records += 1;
+            if record.rtype == dns_wire::TYPE_SOA {
+                soa_seen += 1;
+            }
+        }
+        result = Axfr::Allowed { records };
+        // A complete transfer starts and ends with the SOA.
+        if soa_seen >= 2 {
+            break;
+        }
+    }
+
+    socket.abort();
+    if matches!(result, Axfr::Allowed { .. }) {
+        warn!("dns_audit: {} allowed AXFR of {}", host, domain);
+    }
+    result
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Result<(), ()> {
+    let mut got = 0;
+    while got < buf.len() {
+        match socket.read(&mut buf[got..]).await {
+            Ok(0) | Err(_) => return Err(()),
+            Ok(n) => got += n,
+        }
+    }
+    Ok(())
+}
+
+async fn skip_exact(socket: &mut TcpSocket<'_>, mut len: usize) -> Result<(), ()> {
+    let mut scratch = [0u8; 256];
+    while len > 0 {
+        let want = len.min(scratch.len());
+        read_exact(socket, &mut scratch[..want]).await?;
+        len -= want;
+    }
+    Ok(())
+}
+
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    results: &DnsAuditResults,
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    let rows = results.iter().filter(|a| a.is_dns_server()).map(|audit| {
+        let mut row: String<256> = String::new();
+        audit.write_csv(&mut row).ok();
+        row
+    });
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::try_from(CSV_HEADER).unwrap()).chain(rows),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_net::Ipv6Address;
+
+    fn audit(last: u8) -> DnsAudit {
+        let mut audit = DnsAudit::new(Ipv4Address::new(192, 168, 1, last).into(), false);
+        audit.udp_open = true;
+        audit
+    }
+
+    fn csv(audit: &DnsAudit) -> String<256> {
+        let mut row = String::new();
+        audit.write_csv(&mut row).unwrap();
+        row
+    }
+
+    // A version.bind reply with one TXT answer pointing back at the question.
+    fn txt_reply(rcode: u8, txt: &[u8]) -> ([u8; 64], usize) {
+        let mut msg = [0u8; 64];
+        let n = dns_wire::build_query(
+            &mut msg,
+            0x5642,
+            "version.bind",
+            dns_wire::TYPE_TXT,
+            dns_wire::CLASS_CH,
+            false,
+        )
+        .unwrap();
+        msg[2] = 0x84; // QR, AA
+        msg[3] = rcode;
+        msg[7] = 1; // ancount
+        let answer = [0xc0, 12, 0, 16, 0, 3, 0, 0, 0, 0, 0, txt.len() as u8 + 1];
+        msg[n..n + answer.len()].copy_from_slice(&answer);
+        let rdata = n + answer.len();
+        msg[rdata] = txt.len() as u8;
+        msg[rdata + 1..rdata + 1 + txt.len()].copy_from_slice(txt);
+        (msg, rdata + 1 + txt.len())
+    }
+
+    #[test]
+    fn csv_rows() {
+        assert_eq!(CSV_HEADER.split(',').count(), 10);
+
+        let mut resolver = audit(1);
+        resolver.recursion = Recursion::Open;
+        assert_eq!(csv(&resolver), "192.168.1.1,yes,no,no,open,,,not_tried,,");
+
+        let mut bind = audit(2);
+        bind.tcp_open = true;
+        bind.recursion = Recursion::Refused;
+        bind.version = Some(String::try_from("9.18.1, patched").unwrap());
+        assert_eq!(
+            csv(&bind),
+            "192.168.1.2,yes,yes,no,refused,9.18.1  patched,,not_tried,,"
+        );
+
+        let mut axfr = audit(3);
+        axfr.tcp_open = true;
+        axfr.domain = Some(String::try_from("corp.example").unwrap());
+        axfr.axfr = Axfr::Allowed { records: 42 };
+        assert_eq!(
+            csv(&axfr),
+            "192.168.1.3,yes,yes,no,unknown,,corp.example,allowed,42,"
+        );
+        axfr.axfr = Axfr::Refused;
+        assert_eq!(
+            csv(&axfr),
+            "192.168.1.3,yes,yes,no,unknown,,corp.example,refused,,"
+        );
+
+        for row in [csv(&resolver), csv(&bind), csv(&axfr)] {
+            assert_eq!(row.split(',').count(), 10, "{}", row);
+        }
+
+        let mut v6 = DnsAudit::new(
+            Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53).into(),
+            true,
+        );
+        v6.udp_open = true;
+        assert!(csv(&v6).starts_with("2001:db8::53,yes,no,yes,unknown,"));
+    }
+
+    #[test]
+    fn csv_row_with_vulns() {
+        let mut audit = audit(4);
+        audit.version = Some(String::try_from("dnsmasq-2.76").unwrap());
+        assert_eq!(
+            csv(&audit),
+            "192.168.1.4,yes,no,no,unknown,dnsmasq-2.76,,not_tried,,\
+             outdated; known CVEs: CVE-2017-14491 (critical) +1 more"
+        );
+    }
+
+    #[test]
+    fn findings() {
+        let quiet = audit(1);
+        assert!(!quiet.has_findings());
+
+        let mut refused = audit(1);
+        refused.recursion = Recursion::Refused;
+        refused.axfr = Axfr::Refused;
+        assert!(!refused.has_findings());
+
+        let mut open = audit(1);
+        open.recursion = Recursion::Open;
+        assert!(open.has_findings());
+        // Our own resolver is supposed to recurse for us.
+        open.lan_resolver = true;
+        assert!(!open.has_findings());
+
+        let mut version = audit(1);
+        version.version = Some(String::try_from("9.18.1").unwrap());
+        assert!(version.has_findings());
+
+        let mut axfr = audit(1);
+        axfr.axfr = Axfr::Allowed { records: 0 };
+        assert!(axfr.has_findings());
+    }
+
+    #[test]
+    fn first_txt_answer() {
+        let (msg, n) = txt_reply(dns_wire::RCODE_NOERROR, b"9.18.1");
+        assert_eq!(first_txt(&msg[..n]).unwrap(), "9.18.1");
+
+        // Refused, no answers, and junk all count as no version.
+        let (msg, n) = txt_reply(dns_wire::RCODE_REFUSED, b"9.18.1");
+        assert_eq!(first_txt(&msg[..n]), None);
+        let (mut msg, n) = txt_reply(dns_wire::RCODE_NOERROR, b"9.18.1");
+        msg[7] = 0;
+        assert_eq!(first_txt(&msg[..n]), None);
+        assert_eq!(first_txt(&msg[..5]), None);
+    }
+}
@@ -0,0 +1,487 @@
+//! dns_wire
+//! --------
+//!
+//! DNS message encoding and decoding (RFC 1035), for when we need to ask a
+//! specific server something odd. `Stack::dns_query` covers normal lookups
+//! through the DHCP resolvers; this is for CHAOS class queries, AXFR, and
+//! asking arbitrary hosts whether they recurse.
+use heapless::String;
+
+pub const PORT: u16 = 53;
+pub const HEADER_LEN: usize = 12;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AXFR: u16 = 252;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_NXDOMAIN: u8 = 3;
+pub const RCODE_REFUSED: u8 = 5;
+
+// Longest name we'll bother decoding into a String.
+pub const MAX_NAME: usize = 128;
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_AA: u16 = 0x0400;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+const FLAG_RA: u16 = 0x0080;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WireError {
+    BufferTooSmall,
+    BadName,
+    Truncated,
+    PointerLoop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub qdcount: u16,
+    pub ancount: u16,
+    pub nscount: u16,
+    pub arcount: u16,
+}
+
+impl Header {
+    pub fn parse(msg: &[u8]) -> Result<Self, WireError> {
+        if msg.len() < HEADER_LEN {
+            return Err(WireError::Truncated);
+        }
+        let word = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]);
+        Ok(Self {
+            id: word(0),
+            flags: word(2),
+            qdcount: word(4),
+            ancount: word(6),
+            nscount: word(8),
+            arcount: word(10),
+        })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_QR != 0
+    }
+
+    pub fn authoritative(&self) -> bool {
+        self.flags & FLAG_AA != 0
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.flags & FLAG_TC != 0
+    }
+
+    pub fn recursion_available(&self) -> bool {
+        self.flags & FLAG_RA != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000F) as u8
+    }
+}
+
+// Writes a single-question query into `buf` and returns its length.
+pub fn build_query(
+    buf: &mut [u8],
+    id: u16,
+    name: &str,
+    qtype: u16,
+    qclass: u16,
+    recursion_desired: bool,
+) -> Result<usize, WireError> {
+    if buf.len() < HEADER_LEN {
+        return Err(WireError::BufferTooSmall);
+    }
+    let flags = if recursion_desired { FLAG_RD } else { 0 };
+    buf[..HEADER_LEN].copy_from_slice(&[
+        (id >> 8) as u8,
+        id as u8,
+        (flags >> 8) as u8,
+        flags as u8,
+        0,
+        1, // qdcount
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ]);
+    let mut pos = HEADER_LEN;
+    pos += encode_name(&mut buf[pos..], name)?;
+    if buf.len() < pos + 4 {
+        return Err(WireError::BufferTooSmall);
+    }
+    buf[pos..pos + 2].copy_from_slice(&qtype.to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&qclass.to_be_bytes());
+    Ok(pos + 4)
+}
+
+// "example.com" -> 7example3com0
+pub fn encode_name(buf: &mut [u8], name: &str) -> Result<usize, WireError> {
+    let name = name.trim_end_matches('.');
+    let mut pos = 0;
+    if !name.is_empty() {
+        for label in name.split('.') {
+            let len = label.len();
+            if len == 0 || len > 63 {
+                return Err(WireError::BadName);
+            }
+            if buf.len() < pos + 1 + len {
+                return Err(WireError::BufferTooSmall);
+            }
+            buf[pos] = len as u8;
+            buf[pos + 1..pos + 1 + len].copy_from_slice(label.as_bytes());
+            pos += 1 + len;
+        }
+    }
+    if buf.len() <= pos {
+        return Err(WireError::BufferTooSmall);
+    }
+    buf[pos] = 0;
+    Ok(pos + 1)
+}
+
+// Reads a possibly-compressed name starting at `pos`. Returns the dotted
+// name and the offset just past it in the original message.
+pub fn read_name(msg: &[u8], mut pos: usize) -> Result<(String<MAX_NAME>, usize), WireError> {
+    let mut out: String<MAX_NAME> = String::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *msg.get(pos).ok_or(WireError::Truncated)? as usize;
+        if len & 0xC0 == 0xC0 {
+            let lo = *msg.get(pos + 1).ok_or(WireError::Truncated)? as usize;
+            if end.is_none() {
+                end = Some(pos + 2);
+            }
+            jumps += 1;
+            if jumps > 16 {
+                return Err(WireError::PointerLoop);
+            }
+            pos = ((len & 0x3F) << 8) | lo;
+            continue;
+        }
+        // 0x40 and 0x80 are reserved label types.
+        if len > 63 {
+            return Err(WireError::BadName);
+        }
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        let label = msg
+            .get(pos + 1..pos + 1 + len)
+            .ok_or(WireError::Truncated)?;
+        if !out.is_empty() {
+            out.push('.').ok();
+        }
+        for &b in label {
+            out.push(if b.is_ascii_graphic() { b as char } else { '?' })
+                .ok();
+        }
+        pos += 1 + len;
+    }
+
+    Ok((out, end.unwrap_or(pos)))
+}
+
+pub fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize, WireError> {
+    loop {
+        let len = *msg.get(pos).ok_or(WireError::Truncated)? as usize;
+        if len & 0xC0 == 0xC0 {
+            return Ok(pos + 2);
+        }
+        if len > 63 {
+            return Err(WireError::BadName);
+        }
+        pos += 1 + len;
+        if len == 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    // Offset of the owner name, pass to read_name if you need it.
+    pub name_at: usize,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: &'a [u8],
+    // Offset of rdata in the message, for names compressed against it.
+    pub rdata_at: usize,
+}
+
+// Walks the answer, authority and additional sections.
+pub struct Records<'a> {
+    msg: &'a [u8],
+    pos: usize,
+    remaining: u16,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(msg: &'a [u8]) -> Result<Self, WireError> {
+        let header = Header::parse(msg)?;
+        let mut pos = HEADER_LEN;
+        for _ in 0..header.qdcount {
+            pos = skip_name(msg, pos)? + 4;
+        }
+        Ok(Self {
+            msg,
+            pos,
+            remaining: header
+                .ancount
+                .saturating_add(header.nscount)
+                .saturating_add(header.arcount),
+        })
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, WireError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let name_at = self.pos;
+        let parsed = (|| {
+            let pos = skip_name(self.msg, name_at)?;
+            let fixed = self.msg.get(pos..pos + 10).ok_or(WireError::Truncated)?;
+            let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+            let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+            let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            let rdata_at = pos + 10;
+            let rdata = self
+                .msg
+                .get(rdata_at..rdata_at + rdlen)
+                .ok_or(WireError::Truncated)?;
+            Ok((
+                Record {
+                    name_at,
+                    rtype,
+                    class,
+                    ttl,
+                    rdata,
+                    rdata_at,
+                },
+                rdata_at + rdlen,
+            ))
+        })();
+
+        match parsed {
+            Ok((record, next)) => {
+                self.pos = next;
+                Some(Ok(record))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// TXT rdata is a run of <len><bytes> strings; this joins them.
+pub fn txt_to_string<const N: usize>(rdata: &[u8]) -> String<N> {
+    let mut out = String::new();
+    let mut rest = rdata;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = (len as usize).min(tail.len());
+        for &b in &tail[..len] {
+            if out
+                .push(if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '?'
+                })
+                .is_err()
+            {
+                return out;
+            }
+        }
+        rest = &tail[len..];
+    }
+    out
+}
+
+// 192.168.1.10 -> 10.1.168.192.in-addr.arpa
+pub fn reverse_name(addr: [u8; 4]) -> String<32> {
+    use core::fmt::Write;
+    let mut out = String::new();
+    write!(
+        out,
+        "{}.{}.{}.{}.in-addr.arpa",
+        addr[3], addr[2], addr[1], addr[0]
+    )
+    .ok();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A response to `build_query(.., 0x1234, "version.bind", TXT, CH, ..)`
+    // with one TXT answer whose name points back at the question.
+    fn version_reply() -> ([u8; 64], usize) {
+        let mut msg = [0u8; 64];
+        let n = build_query(&mut msg, 0x1234, "version.bind", TYPE_TXT, CLASS_CH, false).unwrap();
+        msg[2] = 0x84; // QR, AA
+        msg[7] = 1; // ancount
+        let answer = [
+            0xc0, 12, 0, 16, 0, 3, 0, 0, 0, 0, 0, 7, 6, b'9', b'.', b'1', b'8', b'.', b'1',
+        ];
+        msg[n..n + answer.len()].copy_from_slice(&answer);
+        (msg, n + answer.len())
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let mut buf = [0u8; 64];
+        let n = build_query(&mut buf, 0xbeef, "example.com.", TYPE_A, CLASS_IN, true).unwrap();
+        assert_eq!(n, HEADER_LEN + 13 + 4);
+        let header = Header::parse(&buf[..n]).unwrap();
+        assert_eq!((header.id, header.qdcount, header.ancount), (0xbeef, 1, 0));
+        assert!(!header.is_response());
+        assert_eq!(header.flags, FLAG_RD);
+        assert_eq!(&buf[HEADER_LEN..HEADER_LEN + 13], b"\x07example\x03com\x00");
+        assert_eq!(&buf[n - 4..n], &[0, 1, 0, 1]);
+        let (name, end) = read_name(&buf[..n], HEADER_LEN).unwrap();
+        assert_eq!((name.as_str(), end), ("example.com", n - 4));
+        assert_eq!(skip_name(&buf[..n], HEADER_LEN), Ok(n - 4));
+        assert_eq!(Records::new(&buf[..n]).unwrap().count(), 0);
+
+        // The root name is just the terminator.
+        assert_eq!(encode_name(&mut buf, "."), Ok(1));
+        assert_eq!(read_name(&buf[..1], 0).unwrap().0, "");
+    }
+
+    #[test]
+    fn encode_errors() {
+        let mut buf = [0u8; 80];
+        assert_eq!(encode_name(&mut buf, "a..b"), Err(WireError::BadName));
+        let long = [b'a'; 64];
+        let long = core::str::from_utf8(&long).unwrap();
+        assert_eq!(encode_name(&mut buf, long), Err(WireError::BadName));
+        assert_eq!(
+            encode_name(&mut buf[..11], "example.com"),
+            Err(WireError::BufferTooSmall)
+        );
+        // Room for the labels but not the terminator.
+        assert_eq!(
+            encode_name(&mut buf[..12], "example.com"),
+            Err(WireError::BufferTooSmall)
+        );
+        assert_eq!(
+            build_query(
+                &mut buf[..HEADER_LEN + 13 + 3],
+                1,
+                "example.com",
+                TYPE_A,
+                CLASS_IN,
+                false
+            ),
+            Err(WireError::BufferTooSmall)
+        );
+        assert_eq!(
+            build_query(&mut buf[..4], 1, "x", TYPE_A, CLASS_IN, false),
+            Err(WireError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn compressed_answer() {
+        let (msg, n) = version_reply();
+        let msg = &msg[..n];
+        let header = Header::parse(msg).unwrap();
+        assert!(header.is_response() && header.authoritative() && !header.truncated());
+        assert_eq!(header.rcode(), RCODE_NOERROR);
+
+        let records: heapless::Vec<_, 4> = Records::new(msg).unwrap().collect();
+        assert_eq!(records.len(), 1);
+        let record = records[0].unwrap();
+        assert_eq!(
+            (record.rtype, record.class, record.ttl),
+            (TYPE_TXT, CLASS_CH, 0)
+        );
+        assert_eq!(txt_to_string::<16>(record.rdata), "9.18.1");
+        assert_eq!(record.rdata_at, n - 7);
+        // The pointer is followed for the name, but the end is just past it.
+        let (name, end) = read_name(msg, record.name_at).unwrap();
+        assert_eq!((name.as_str(), end), ("version.bind", record.name_at + 2));
+        assert_eq!(skip_name(msg, record.name_at), Ok(record.name_at + 2));
+    }
+
+    #[test]
+    fn pointer_loops() {
+        // A name pointing at itself, and two pointing at each other.
+        let mut msg = [0u8; 16];
+        msg[12..14].copy_from_slice(&[0xc0, 12]);
+        assert_eq!(read_name(&msg, 12), Err(WireError::PointerLoop));
+        msg[12..16].copy_from_slice(&[0xc0, 14, 0xc0, 12]);
+        assert_eq!(read_name(&msg, 12), Err(WireError::PointerLoop));
+        // Label, then a pointer back to the start of the same name.
+        msg[12..16].copy_from_slice(&[1, b'a', 0xc0, 12]);
+        assert_eq!(read_name(&msg, 12), Err(WireError::PointerLoop));
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(Header::parse(&[0; 11]), Err(WireError::Truncated));
+        // Label running off the end, pointer missing its second byte,
+        // pointer past the end, no terminator.
+        assert_eq!(read_name(b"\x05ab", 0), Err(WireError::Truncated));
+        assert_eq!(read_name(b"\x01a\xc0", 0), Err(WireError::Truncated));
+        assert_eq!(read_name(b"\xc0\x20", 0), Err(WireError::Truncated));
+        assert_eq!(read_name(b"\x01a", 0), Err(WireError::Truncated));
+        assert_eq!(skip_name(b"\x01a", 0), Err(WireError::Truncated));
+        // Reserved label types.
+        assert_eq!(read_name(b"\x41a\x00", 0), Err(WireError::BadName));
+        assert_eq!(skip_name(b"\x80\x00", 0), Err(WireError::BadName));
+
+        let (msg, n) = version_reply();
+        // Cut inside the rdata, then inside the fixed part of the record.
+        for cut in [n - 1, n - 12] {
+            let mut records = Records::new(&msg[..cut]).unwrap();
+            assert!(matches!(records.next(), Some(Err(WireError::Truncated))));
+            assert!(records.next().is_none());
+        }
+        // Cut inside the question.
+        assert!(Records::new(&msg[..HEADER_LEN + 4]).is_err());
+
+        // More records claimed than present: the first parses, then an
+        // error, then nothing.
+        let mut msg = msg;
+        msg[7] = 200;
+        let mut records = Records::new(&msg[..n]).unwrap();
+        assert!(matches!(records.next(), Some(Ok(_))));
+        assert!(matches!(records.next(), Some(Err(WireError::Truncated))));
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn txt_strings() {
+        assert_eq!(txt_to_string::<32>(b"\x03abc\x02de"), "abcde");
+        // A length past the end takes what's there.
+        assert_eq!(txt_to_string::<32>(b"\x09ab"), "ab");
+        assert_eq!(txt_to_string::<32>(b"\x02a\n"), "a?");
+        assert_eq!(txt_to_string::<4>(b"\x06abcdef"), "abcd");
+        assert_eq!(txt_to_string::<4>(b""), "");
+        assert_eq!(reverse_name([192, 168, 1, 10]), "10.1.168.192.in-addr.arpa");
+    }
+}
@@ -0,0 +1,785 @@
+//! exposure
+//! --------
+//!
+//! Risky exposed services, for authorized assessments only.
+//!
+//! Every host seen since boot gets a TCP connect on the most often open
+//! ports from `services` (`TOP_PORTS` in `.env`, 32 by default), plus a
+//! list of ports that shouldn't be open on most LANs. Every open port goes
+//! into the inventory. The risky ones get a quick look at what answers:
+//!   - Telnet, RDP, database ports: being open is the finding
+//!   - FTP: cleartext, and worse if `USER anonymous` logs in
+//!   - SMB: does it still negotiate SMBv1 (`NT LM 0.12`)?
+//!   - VNC: does the security-types handshake offer "None"?
+//!   - Redis `PING`, Memcached `stats`, MongoDB `listDatabases`: do they
+//!     answer without auth?
+//!
+//! Banners read along the way (the FTP greeting) are checked against
+//! `vulns`, and a known-vulnerable version is a finding of its own. Main
+//! adds the same for SSH versions (`ssh`) and RTSP servers (`camera`).
+//!
+//! Each finding has a severity, worst first in `FINDINGS.CSV` and on the
+//! OLED. Nothing past the handshake is read or changed.
+use core::fmt::{self, Write};
+use defmt::{debug, info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, with_timeout};
+use heapless::{String, Vec};
+
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::services::{self, Port, Proto};
+use crate::util::write_all;
+use crate::vulns::{self, Hint, Severity};
+
+// Most hosts on a LAN refuse right away; this only bites on firewalls that
+// drop.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub const MAX_FINDINGS: usize = 32;
+pub const DEFAULT_TOP_PORTS: usize = 32;
+pub const MAX_TOP_PORTS: usize = 100;
+pub const EXPORT_FILE: &str = "FINDINGS.CSV";
+pub const CSV_HEADER: &str = "host,port,service,severity,finding";
+
+// What a port said about itself, for the inventory.
+pub type Banner = String<64>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Risk {
+    Telnet,
+    Ftp,
+    FtpAnonymous,
+    SmbV1,
+    Rdp,
+    Vnc,
+    VncNoAuth,
+    Database,
+    RedisNoAuth,
+    MongoNoAuth,
+    MemcachedNoAuth,
+    // The banner names a version with known CVEs.
+    Outdated(Hint),
+}
+
+impl Risk {
+    pub fn severity(self) -> Severity {
+        match self {
+            Risk::Vnc => Severity::Low,
+            Risk::Ftp | Risk::Rdp | Risk::Database => Severity::Medium,
+            Risk::Telnet | Risk::FtpAnonymous | Risk::SmbV1 => Severity::High,
+            Risk::VncNoAuth | Risk::RedisNoAuth | Risk::MongoNoAuth | Risk::MemcachedNoAuth => {
+                Severity::Critical
+            }
+            Risk::Outdated(hint) => hint.severity(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Risk::Telnet => "cleartext Telnet",
+            Risk::Ftp => "cleartext FTP",
+            Risk::FtpAnonymous => "anonymous FTP login",
+            Risk::SmbV1 => "SMBv1 enabled",
+            Risk::Rdp => "RDP exposed",
+            Risk::Vnc => "VNC exposed",
+            Risk::VncNoAuth => "VNC without auth",
+            Risk::Database => "database port exposed",
+            Risk::RedisNoAuth => "Redis without auth",
+            Risk::MongoNoAuth => "MongoDB without auth",
+            Risk::MemcachedNoAuth => "Memcached without auth",
+            Risk::Outdated(_) => "outdated version",
+        }
+    }
+}
+
+// Outdated ones name the CVEs.
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Risk::Outdated(hint) => write!(f, "{}", hint),
+            risk => f.write_str(risk.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Finding {
+    pub host: IpAddress,
+    pub port: u16,
+    pub risk: Risk,
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        self.risk.severity()
+    }
+}
+
+// A finding if `banner` names a version with known CVEs.
+pub fn outdated(host: IpAddress, port: u16, banner: &str) -> Option<Finding> {
+    Some(Finding {
+        host,
+        port,
+        risk: Risk::Outdated(vulns::check_banner(banner)?),
+    })
+}
+
+// "192.168.1.20 6379/redis critical: Redis without auth"
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {}",
+            self.host,
+            Port(Proto::Tcp, self.port),
+            self.severity().as_str(),
+            self.risk
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    // Only goes in the inventory.
+    Connect,
+    // Open is all we need to know.
+    Open(Risk),
+    Ftp,
+    Smb,
+    Vnc,
+    Redis,
+    Memcached,
+    Mongo,
+}
+
+const PROBES: [(u16, Probe); 13] = [
+    (21, Probe::Ftp),
+    (23, Probe::Open(Risk::Telnet)),
+    (445, Probe::Smb),
+    (1433, Probe::Open(Risk::Database)),
+    (1521, Probe::Open(Risk::Database)),
+    (3306, Probe::Open(Risk::Database)),
+    (3389, Probe::Open(Risk::Rdp)),
+    (5432, Probe::Open(Risk::Database)),
+    (5900, Probe::Vnc),
+    (5901, Probe::Vnc),
+    (6379, Probe::Redis),
+    (11211, Probe::Memcached),
+    (27017, Probe::Mongo),
+];
+
+// How many of the most often open TCP ports to try, from TOP_PORTS.
+pub fn top_ports_from_env() -> usize {
+    option_env!("TOP_PORTS")
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(DEFAULT_TOP_PORTS)
+        .min(MAX_TOP_PORTS)
+}
+
+// The `top` most often open ports, most likely first, then the risky ones
+// that aren't among them. Each with how to check it.
+pub fn ports(top: usize) -> Vec<(u16, Probe), { MAX_TOP_PORTS + PROBES.len() }> {
+    let mut ports = Vec::new();
+    for &port in services::top_ports(Proto::Tcp, top.min(MAX_TOP_PORTS)) {
+        let probe = PROBES.iter().find(|(p, _)| *p == port);
+        ports
+            .push((port, probe.map_or(Probe::Connect, |&(_, probe)| probe)))
+            .ok();
+    }
+    for &(port, probe) in &PROBES {
+        if !ports.iter().any(|(p, _)| *p == port) {
+            ports.push((port, probe)).ok();
+        }
+    }
+    ports
+}
+
+// FTP reply code, once the reply is complete. Multi-line replies are
+// "220-..." lines ending with a "220 ..." one.
+pub fn ftp_reply(buf: &[u8]) -> Option<u16> {
+    buf.split_inclusive(|&b| b == b'\n')
+        .filter(|line| line.ends_with(b"\n"))
+        .find(|line| line.len() >= 4 && line[3] == b' ')
+        .and_then(|line| core::str::from_utf8(&line[..3]).ok()?.parse().ok())
+}
+
+// Text of the FTP server's greeting, "(vsFTPd 3.0.3)" from
+// "220 (vsFTPd 3.0.3)". First line only for multi-line greetings.
+pub fn ftp_greeting(buf: &[u8]) -> Option<&str> {
+    let line = buf.split(|&b| b == b'\n').next()?;
+    let line = core::str::from_utf8(line).ok()?;
+    let text = line.strip_prefix("220")?;
+    Some(text.get(1..).unwrap_or("").trim())
+}
+
+// SMB1 negotiate offering only "NT LM 0.12", behind a 4 byte NetBIOS
+// session header. Anything that accepts it speaks SMBv1.
+pub const SMB1_NEGOTIATE: [u8; 51] = [
+    0x00, 0x00, 0x00, 0x2f, // NetBIOS session message, 47 bytes
+    0xff, b'S', b'M', b'B', 0x72, // SMB_COM_NEGOTIATE
+    0x00, 0x00, 0x00, 0x00, // status
+    0x18, 0x01, 0x28, // flags, flags2
+    0x00, 0x00, // PID high
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // signature
+    0x00, 0x00, // reserved
+    0x00, 0x00, 0x2f, 0x4b, 0x00, 0x00, 0xc5, 0x5e, // TID, PID, UID, MID
+    0x00, // word count
+    0x0c, 0x00, // byte count
+    0x02, b'N', b'T', b' ', b'L', b'M', b' ', b'0', b'.', b'1', b'2', 0x00,
+];
+
+// A successful SMB1 negotiate response that picked our dialect.
+pub fn smb1_accepted(resp: &[u8]) -> bool {
+    resp.len() >= 39
+        && resp[4..8] == *b"\xffSMB"
+        && resp[8] == 0x72
+        && resp[9..13] == [0; 4]
+        && resp[36] >= 1
+        && resp[37..39] != [0xff, 0xff]
+}
+
+// "RFB 003.008\n" -> 8. Versions in between are treated as 3.3, as the
+// spec says.
+pub fn vnc_version(banner: &[u8]) -> Option<u8> {
+    let banner = banner.get(..12)?;
+    if !banner.starts_with(b"RFB 003.") || banner[11] != b'\n' {
+        return None;
+    }
+    let minor: u16 = core::str::from_utf8(&banner[8..11]).ok()?.parse().ok()?;
+    Some(match minor {
+        8.. => 8,
+        7 => 7,
+        _ => 3,
+    })
+}
+
+pub fn vnc_hello(minor: u8) -> &'static [u8; 12] {
+    match minor {
+        8 => b"RFB 003.008\n",
+        7 => b"RFB 003.007\n",
+        _ => b"RFB 003.003\n",
+    }
+}
+
+// Whether the server offers security type 1, None. 3.3 servers pick one
+// type and send it as a u32; later ones send a count and a list. Either
+// way 0 means it refused us.
+pub fn vnc_no_auth(minor: u8, security: &[u8]) -> Option<bool> {
+    if minor == 3 {
+        let kind = u32::from_be_bytes(security.get(..4)?.try_into().ok()?);
+        return (kind != 0).then_some(kind == 1);
+    }
+    let (&count, types) = security.split_first()?;
+    let types = types.get(..count as usize)?;
+    (count != 0).then(|| types.contains(&1))
+}
+
+// OP_MSG {listDatabases: 1, $db: "admin"}, which needs auth if the
+// server has it turned on.
+pub fn mongo_list_databases() -> Vec<u8, 64> {
+    let mut doc: Vec<u8, 48> = Vec::new();
+    doc.extend_from_slice(&[0; 4]).ok();
+    doc.push(0x10).ok(); // int32
+    doc.extend_from_slice(b"listDatabases\0").ok();
+    doc.extend_from_slice(&1i32.to_le_bytes()).ok();
+    doc.push(0x02).ok(); // string
+    doc.extend_from_slice(b"$db\0").ok();
+    doc.extend_from_slice(&6i32.to_le_bytes()).ok();
+    doc.extend_from_slice(b"admin\0").ok();
+    doc.push(0).ok();
+    let doc_len = doc.len() as i32;
+    doc[..4].copy_from_slice(&doc_len.to_le_bytes());
+
+    let mut msg = Vec::new();
+    let msg_len = 16 + 4 + 1 + doc.len() as i32;
+    msg.extend_from_slice(&msg_len.to_le_bytes()).ok();
+    msg.extend_from_slice(&0x7069_6361i32.to_le_bytes()).ok(); // request id
+    msg.extend_from_slice(&0i32.to_le_bytes()).ok(); // response to
+    msg.extend_from_slice(&2013i32.to_le_bytes()).ok(); // OP_MSG
+    msg.extend_from_slice(&0u32.to_le_bytes()).ok(); // flags
+    msg.push(0).ok(); // body section
+    msg.extend_from_slice(&doc).ok();
+    msg
+}
+
+// The reply's "ok" field, as a double or an int32.
+pub fn mongo_ok(resp: &[u8]) -> Option<bool> {
+    let at = resp.windows(4).position(|w| w[1..] == *b"ok\0")?;
+    let value = &resp[at + 4..];
+    match resp[at] {
+        0x01 => Some(f64::from_le_bytes(value.get(..8)?.try_into().ok()?) == 1.0),
+        0x10 => Some(i32::from_le_bytes(value.get(..4)?.try_into().ok()?) == 1),
+        _ => None,
+    }
+}
+
+pub struct Findings {
+    // Worst first, then in the order found.
+    pub list: Vec<Finding, MAX_FINDINGS>,
+    // Found after the list was full.
+    pub dropped: usize,
+}
+
+impl Default for Findings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Findings {
+    pub fn new() -> Self {
+        Self {
+            list: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn add(&mut self, finding: Finding) {
+        // After everything as bad or worse, so hosts stay in scan order.
+        let at = self
+            .list
+            .iter()
+            .position(|f| f.severity() < finding.severity())
+            .unwrap_or(self.list.len());
+        if self.list.is_full() {
+            // Make room if this one's worse than the least bad kept.
+            if at == self.list.len() {
+                self.dropped += 1;
+                return;
+            }
+            self.list.pop();
+            self.dropped += 1;
+        }
+        self.list.insert(at, finding).ok();
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.list
+            .iter()
+            .filter(|f| f.severity() == severity)
+            .count()
+    }
+
+    // Summary and the worst few, sized for the OLED:
+    //
+    //     Exposed: 4
+    //     C1 H2 M1 L0
+    //     C 10.0.0.5:6379
+    pub fn lines(&self) -> Vec<String<21>, 6> {
+        let mut lines = Vec::new();
+        let mut head = String::new();
+        write!(head, "Exposed: {}", self.list.len() + self.dropped).ok();
+        lines.push(head).ok();
+        let mut counts = String::new();
+        write!(
+            counts,
+            "C{} H{} M{} L{}",
+            self.count(Severity::Critical),
+            self.count(Severity::High),
+            self.count(Severity::Medium),
+            self.count(Severity::Low)
+        )
+        .ok();
+        lines.push(counts).ok();
+        for f in &self.list {
+            let mut line = String::new();
+            let sev = f.severity().as_str().as_bytes()[0].to_ascii_uppercase() as char;
+            match f.host {
+                IpAddress::Ipv4(ip) => write!(line, "{} {}", sev, ip).ok(),
+                IpAddress::Ipv6(ip) => write!(line, "{} [{}]", sev, ip).ok(),
+            };
+            // Doesn't always fit after a long address.
+            write!(line, ":{}", f.port).ok();
+            if lines.push(line).is_err() {
+                break;
+            }
+        }
+        lines
+    }
+
+    pub fn csv_rows(&self) -> impl Iterator<Item = String<128>> + '_ {
+        self.list.iter().map(|f| {
+            let mut row = String::new();
+            write!(
+                row,
+                "{},{},{},{},{}",
+                f.host,
+                f.port,
+                services::name(Proto::Tcp, f.port).unwrap_or(""),
+                f.severity().as_str(),
+                f.risk
+            )
+            .ok();
+            row
+        })
+    }
+}
+
+// Reads until `done` says the reply is complete, the buffer's full, the
+// peer closes or READ_TIMEOUT runs out. Returns how much was read.
+async fn read_reply(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    done: impl Fn(&[u8]) -> bool,
+) -> usize {
+    let mut got = 0;
+    let read = async {
+        while got < buf.len() && !done(&buf[..got]) {
+            match socket.read(&mut buf[got..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => got += n,
+            }
+        }
+    };
+    with_timeout(READ_TIMEOUT, read).await.ok();
+    got
+}
+
+async fn ftp_command(socket: &mut TcpSocket<'_>, buf: &mut [u8], cmd: &[u8]) -> Option<u16> {
+    write_all(socket, cmd).await.ok()?;
+    let n = read_reply(socket, buf, |b| ftp_reply(b).is_some()).await;
+    ftp_reply(&buf[..n])
+}
+
+async fn probe_ftp(socket: &mut TcpSocket<'_>, buf: &mut [u8], banner: &mut Banner) -> Risk {
+    let n = read_reply(socket, buf, |b| ftp_reply(b).is_some()).await;
+    if ftp_reply(&buf[..n]) != Some(220) {
+        return Risk::Ftp;
+    }
+    if let Some(greeting) = ftp_greeting(&buf[..n]) {
+        *banner = truncated(greeting);
+    }
+    let anonymous = match ftp_command(socket, buf, b"USER anonymous\r\n").await {
+        Some(230) => true,
+        Some(331) => ftp_command(socket, buf, b"PASS picomap@\r\n").await == Some(230),
+        _ => false,
+    };
+    write_all(socket, b"QUIT\r\n").await.ok();
+    if anonymous {
+        Risk::FtpAnonymous
+    } else {
+        Risk::Ftp
+    }
+}
+
+// None if it doesn't take SMBv1; plenty of hosts just drop the connection.
+async fn probe_smb(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Option<Risk> {
+    write_all(socket, &SMB1_NEGOTIATE).await.ok()?;
+    let n = read_reply(socket, buf, |b| b.len() >= 39).await;
+    smb1_accepted(&buf[..n]).then_some(Risk::SmbV1)
+}
+
+async fn probe_vnc(socket: &mut TcpSocket<'_>, buf: &mut [u8], banner: &mut Banner) -> Risk {
+    let n = read_reply(socket, buf, |b| b.len() >= 12).await;
+    let Some(minor) = vnc_version(&buf[..n]) else {
+        return Risk::Vnc;
+    };
+    // "RFB 003.008", as the server sent it.
+    *banner = truncated(core::str::from_utf8(&buf[..11]).unwrap_or(""));
+    if write_all(socket, vnc_hello(minor)).await.is_err() {
+        return Risk::Vnc;
+    }
+    let n = read_reply(socket, buf, |b| vnc_no_auth(minor, b).is_some()).await;
+    match vnc_no_auth(minor, &buf[..n]) {
+        Some(true) => Risk::VncNoAuth,
+        _ => Risk::Vnc,
+    }
+}
+
+async fn probe_redis(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Risk {
+    if write_all(socket, b"PING\r\n").await.is_ok() {
+        let n = read_reply(socket, buf, |b| b.ends_with(b"\r\n")).await;
+        if buf[..n].starts_with(b"+PONG") {
+            return Risk::RedisNoAuth;
+        }
+    }
+    Risk::Database
+}
+
+async fn probe_memcached(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Risk {
+    if write_all(socket, b"stats\r\n").await.is_ok() {
+        let n = read_reply(socket, buf, |b| b.ends_with(b"\r\n")).await;
+        if buf[..n].starts_with(b"STAT ") {
+            return Risk::MemcachedNoAuth;
+        }
+    }
+    Risk::Database
+}
+
+async fn probe_mongo(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Risk {
+    if write_all(socket, &mongo_list_databases()).await.is_ok() {
+        let n = read_reply(socket, buf, |b| {
+            b.len() >= 4 && b.len() >= i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize
+        })
+        .await;
+        if mongo_ok(&buf[..n]) == Some(true) {
+            return Risk::MongoNoAuth;
+        }
+    }
+    Risk::Database
+}
+
+// None if the port's closed, otherwise what the probe found, if anything.
+async fn check(
+    stack: Stack<'_>,
+    host: IpAddress,
+    port: u16,
+    probe: Probe,
+    banner: &mut Banner,
+) -> Option<Option<Risk>> {
+    let mut rx_buf = [0u8; 512];
+    let mut tx_buf = [0u8; 128];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, port))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    debug!("exposure: {}:{} open", host, port);
+
+    let mut buf = [0u8; 512];
+    let risk = match probe {
+        Probe::Connect => None,
+        Probe::Open(risk) => Some(risk),
+        Probe::Ftp => Some(probe_ftp(&mut socket, &mut buf, banner).await),
+        Probe::Smb => probe_smb(&mut socket, &mut buf).await,
+        Probe::Vnc => Some(probe_vnc(&mut socket, &mut buf, banner).await),
+        Probe::Redis => Some(probe_redis(&mut socket, &mut buf).await),
+        Probe::Memcached => Some(probe_memcached(&mut socket, &mut buf).await),
+        Probe::Mongo => Some(probe_mongo(&mut socket, &mut buf).await),
+    };
+    socket.abort();
+    Some(risk)
+}
+
+// Checks the `top` most common ports and the risky ones on every host.
+// `open` hears about each open port, with or without a finding, and its
+// banner if it sent one (empty if not), so it can go into the inventory.
+pub async fn run(
+    stack: Stack<'_>,
+    hosts: &[IpAddress],
+    top: usize,
+    mut open: impl FnMut(IpAddress, u16, &str),
+) -> Findings {
+    let ports = ports(top);
+    let mut findings = Findings::new();
+    for &host in hosts {
+        for &(port, probe) in &ports {
+            let mut banner = Banner::new();
+            let Some(risk) = check(stack, host, port, probe, &mut banner).await else {
+                continue;
+            };
+            open(host, port, &banner);
+            let risk = risk.map(|risk| Finding { host, port, risk });
+            for finding in [risk, outdated(host, port, &banner)].into_iter().flatten() {
+                if finding.severity() >= Severity::High {
+                    warn!("exposure: {}", defmt::Display2Format(&finding));
+                }
+                findings.add(finding);
+            }
+        }
+    }
+    info!(
+        "exposure: {} hosts, {} ports, {} findings",
+        hosts.len(),
+        ports.len(),
+        findings.list.len() + findings.dropped
+    );
+    findings
+}
+
+// As much of `s` as fits.
+fn truncated(s: &str) -> Banner {
+    let mut out = Banner::new();
+    for c in s.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+// Writes the findings out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    findings: &Findings,
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::try_from(CSV_HEADER).unwrap()).chain(findings.csv_rows()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ftp_replies() {
+        assert_eq!(ftp_reply(b"220 (vsFTPd 3.0.3)\r\n"), Some(220));
+        assert_eq!(ftp_reply(b"220-Welcome\r\n"), None);
+        assert_eq!(ftp_reply(b"220-Welcome\r\n220 Ready\r\n"), Some(220));
+        assert_eq!(
+            ftp_reply(b"331 Please specify the password.\r\n"),
+            Some(331)
+        );
+        assert_eq!(ftp_reply(b"23"), None);
+    }
+
+    #[test]
+    fn port_list() {
+        let list = ports(5);
+        let numbers: Vec<u16, 20> = list.iter().map(|&(p, _)| p).collect();
+        // Top five, then the risky ports not already in it.
+        assert_eq!(&numbers[..5], services::top_ports(Proto::Tcp, 5));
+        assert_eq!(
+            &numbers[5..],
+            &[
+                445, 1433, 1521, 3306, 3389, 5432, 5900, 5901, 6379, 11211, 27017
+            ]
+        );
+        assert_eq!(list[0], (80, Probe::Connect));
+        assert_eq!(list[1], (23, Probe::Open(Risk::Telnet)));
+        assert_eq!(list[3], (21, Probe::Ftp));
+
+        let all = ports(MAX_TOP_PORTS);
+        assert!(all.iter().all(|&(p, probe)| {
+            PROBES
+                .iter()
+                .find(|(q, _)| *q == p)
+                .map_or(Probe::Connect, |&(_, q)| q)
+                == probe
+        }));
+        assert_eq!(
+            all.iter().filter(|&&(_, p)| p != Probe::Connect).count(),
+            PROBES.len()
+        );
+    }
+
+    #[test]
+    fn ftp_greetings() {
+        assert_eq!(
+            ftp_greeting(b"220 (vsFTPd 3.0.3)\r\n"),
+            Some("(vsFTPd 3.0.3)")
+        );
+        assert_eq!(
+            ftp_greeting(b"220-ProFTPD Server\r\n220 Ready\r\n"),
+            Some("ProFTPD Server")
+        );
+        assert_eq!(ftp_greeting(b"220\r\n"), Some(""));
+        assert_eq!(ftp_greeting(b"421 Too many users\r\n"), None);
+    }
+
+    #[test]
+    fn smb1() {
+        assert_eq!(SMB1_NEGOTIATE[3] as usize, SMB1_NEGOTIATE.len() - 4);
+        let mut resp = [0u8; 40];
+        resp[4..9].copy_from_slice(b"\xffSMB\x72");
+        resp[36] = 17;
+        assert!(smb1_accepted(&resp));
+        resp[37..39].copy_from_slice(&[0xff, 0xff]);
+        assert!(!smb1_accepted(&resp));
+        assert!(!smb1_accepted(b"\x00\x00\x00\x40\xfeSMB"));
+    }
+
+    #[test]
+    fn vnc_handshake() {
+        assert_eq!(vnc_version(b"RFB 003.008\n"), Some(8));
+        assert_eq!(vnc_version(b"RFB 003.889\n"), Some(8));
+        assert_eq!(vnc_version(b"RFB 003.005\n"), Some(3));
+        assert_eq!(vnc_version(b"SSH-2.0-x\r\n\0"), None);
+        assert_eq!(vnc_no_auth(8, &[2, 2, 1]), Some(true));
+        assert_eq!(vnc_no_auth(8, &[1, 2]), Some(false));
+        assert_eq!(vnc_no_auth(8, &[2, 2]), None);
+        assert_eq!(vnc_no_auth(8, &[0]), None);
+        assert_eq!(vnc_no_auth(3, &[0, 0, 0, 1]), Some(true));
+        assert_eq!(vnc_no_auth(3, &[0, 0, 0, 2]), Some(false));
+    }
+
+    #[test]
+    fn mongo() {
+        let msg = mongo_list_databases();
+        assert_eq!(
+            msg.len(),
+            i32::from_le_bytes(msg[..4].try_into().unwrap()) as usize
+        );
+        assert_eq!(msg.len(), 60);
+        let mut ok = b"....\x01ok\x00".to_vec();
+        ok.extend_from_slice(&1.0f64.to_le_bytes());
+        assert_eq!(mongo_ok(&ok), Some(true));
+        let mut denied = b"....\x01ok\x00".to_vec();
+        denied.extend_from_slice(&0.0f64.to_le_bytes());
+        assert_eq!(mongo_ok(&denied), Some(false));
+        assert_eq!(mongo_ok(b"nothing"), None);
+    }
+
+    #[test]
+    fn outdated_banners() {
+        let host = IpAddress::v4(10, 0, 0, 3);
+        let finding = outdated(host, 21, "(vsFTPd 2.3.4)").unwrap();
+        assert_eq!(finding.severity(), Severity::Critical);
+        assert_eq!(
+            finding.to_string(),
+            "10.0.0.3 21/ftp critical: outdated; known CVEs: CVE-2011-2523 (critical)"
+        );
+        assert!(outdated(host, 21, "(vsFTPd 3.0.3)").is_none());
+        assert!(outdated(host, 554, "GStreamer RTSP server").is_none());
+
+        let mut findings = Findings::new();
+        findings.add(outdated(host, 22, "SSH-2.0-OpenSSH_7.2p2 Ubuntu-4ubuntu2.10").unwrap());
+        let rows: Vec<String<128>, 1> = findings.csv_rows().collect();
+        assert!(rows[0].starts_with("10.0.0.3,22,ssh,high,outdated; known CVEs: CVE-"));
+    }
+
+    #[test]
+    fn orders_findings() {
+        let host = |n| IpAddress::v4(10, 0, 0, n);
+        let mut findings = Findings::new();
+        findings.add(Finding {
+            host: host(9),
+            port: 3389,
+            risk: Risk::Rdp,
+        });
+        findings.add(Finding {
+            host: host(5),
+            port: 6379,
+            risk: Risk::RedisNoAuth,
+        });
+        findings.add(Finding {
+            host: host(7),
+            port: 23,
+            risk: Risk::Telnet,
+        });
+        let risks: Vec<Risk, 3> = findings.list.iter().map(|f| f.risk).collect();
+        assert_eq!(&risks, &[Risk::RedisNoAuth, Risk::Telnet, Risk::Rdp]);
+        assert_eq!(
+            findings.list[0].to_string(),
+            "10.0.0.5 6379/redis critical: Redis without auth"
+        );
+        let lines = findings.lines();
+        assert_eq!(lines[0].as_str(), "Exposed: 3");
+        assert_eq!(lines[1].as_str(), "C1 H1 M1 L0");
+        assert_eq!(lines[2].as_str(), "C 10.0.0.5:6379");
+        assert_eq!(lines.len(), 5);
+        let rows: Vec<String<128>, 3> = findings.csv_rows().collect();
+        assert_eq!(rows[1].as_str(), "10.0.0.7,23,telnet,high,cleartext Telnet");
+
+        let mut findings = Findings::new();
+        findings.add(Finding {
+            host: IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7),
+            port: 23,
+            risk: Risk::Telnet,
+        });
+        assert_eq!(findings.lines()[2].as_str(), "H [2001:db8::7]:23");
+        let rows: Vec<String<128>, 1> = findings.csv_rows().collect();
+        assert_eq!(
+            rows[0].as_str(),
+            "2001:db8::7,23,telnet,high,cleartext Telnet"
+        );
+    }
+}
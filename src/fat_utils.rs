@@ -3,7 +3,8 @@
 //!
 //! Just some helpers to read and write out to files, assuming we are
 //! using SD+SPI.
-use crate::sd_spi::{DummyTime, EmbassySpiDevice};
+use crate::clock::WallClock;
+use crate::sd_spi::EmbassySpiDevice;
 use embedded_sdmmc::{ShortFileName, VolumeManager};
 
 pub fn str_to_sfn(name: &str) -> ShortFileName {
@@ -29,7 +30,7 @@ pub fn sfn_to_str(sfn: &ShortFileName) -> heapless::String<13> {
 // Seeks to the end, then writes the bytes.
 // Caller should flush and close.
 pub fn append_line<S>(
-    volman: &mut VolumeManager<EmbassySpiDevice<S>, DummyTime>,
+    volman: &mut VolumeManager<EmbassySpiDevice<S>, WallClock>,
     handle: embedded_sdmmc::RawFile,
     line: &heapless::String<128>,
 ) where
@@ -0,0 +1,376 @@
+//! health
+//! ------
+//!
+//! "Does the internet work?" checklist, run right after joining a network.
+//!
+//! Checks, in order:
+//!   1. DHCP lease (we have an address and a gateway)
+//!   2. Gateway answers ICMP echo
+//!   3. DNS name resolves through the DHCP-provided servers
+//!   4. TCP connect to the check URL
+//!   5. HTTP request to the check URL returns a response
+//!   6. Response matches what we expect (no captive portal in the way)
+//!
+//! Then the path MTU to the gateway and to the check URL's host (see
+//! `pmtu`), and to the IPv6 gateway if `IPV6_GATEWAY` is set, which is
+//! reported on its own line rather than as a check.
+//!
+//! The targets are baked in at build time from `.env` like the WiFi creds,
+//! so you can point them at a local stand-in server:
+//!
+//!     HEALTH_DNS_NAME="example.com"
+//!     HEALTH_URL="http://192.168.1.50:8080/success.txt"
+//!     HEALTH_EXPECT="success"
+use core::fmt::Write;
+use defmt::{debug, info, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::icmp::PacketMetadata;
+use embassy_net::icmp::ping::{PingManager, PingParams};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, with_timeout};
+use heapless::String;
+
+use crate::pmtu::{self, PmtuResult};
+use crate::util::{contains, http_body, http_status_code, write_all};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl Status {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Status::Pass => "[OK]",
+            Status::Fail => "[!!]",
+            Status::Skip => "[--]",
+        }
+    }
+}
+
+pub struct HealthConfig {
+    pub dns_name: &'static str,
+    pub url: &'static str,
+    pub expect: &'static str,
+}
+
+impl HealthConfig {
+    pub fn from_env() -> Self {
+        Self {
+            dns_name: option_env!("HEALTH_DNS_NAME").unwrap_or("example.com"),
+            url: option_env!("HEALTH_URL").unwrap_or("http://detectportal.firefox.com/success.txt"),
+            expect: option_env!("HEALTH_EXPECT").unwrap_or("success"),
+        }
+    }
+}
+
+// Just enough of a URL for plain http://host[:port]/path
+pub struct HttpUrl<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+pub fn parse_http_url(url: &str) -> Option<HttpUrl<'_>> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((h, p)) => (h, p.parse().ok()?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some(HttpUrl { host, port, path })
+}
+
+pub struct HealthReport {
+    pub dhcp: Status,
+    pub gateway: Status,
+    pub dns: Status,
+    pub tcp: Status,
+    pub http: Status,
+    pub portal: Status,
+    pub gateway_rtt_ms: Option<u64>,
+    pub http_status: Option<u16>,
+    pub gateway_pmtu: Option<PmtuResult>,
+    pub remote_pmtu: Option<PmtuResult>,
+    pub gateway6_pmtu: Option<PmtuResult>,
+}
+
+impl HealthReport {
+    fn new() -> Self {
+        Self {
+            dhcp: Status::Skip,
+            gateway: Status::Skip,
+            dns: Status::Skip,
+            tcp: Status::Skip,
+            http: Status::Skip,
+            portal: Status::Skip,
+            gateway_rtt_ms: None,
+            http_status: None,
+            gateway_pmtu: None,
+            remote_pmtu: None,
+            gateway6_pmtu: None,
+        }
+    }
+
+    pub fn checks(&self) -> [(&'static str, Status); 6] {
+        [
+            ("DHCP lease", self.dhcp),
+            ("Gateway ping", self.gateway),
+            ("DNS resolve", self.dns),
+            ("TCP connect", self.tcp),
+            ("HTTP request", self.http),
+            ("No captive portal", self.portal),
+        ]
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.checks().iter().all(|(_, s)| *s == Status::Pass) && self.pmtu() != Status::Fail
+    }
+
+    // Fail on an MTU black hole, Skip if neither end answered pings. A
+    // small path MTU is fine as long as routers say so.
+    pub fn pmtu(&self) -> Status {
+        let results = [&self.gateway_pmtu, &self.remote_pmtu, &self.gateway6_pmtu];
+        let known = || {
+            results
+                .iter()
+                .filter_map(|r| r.as_ref())
+                .filter(|r| r.mtu.is_some())
+        };
+        if known().any(|r| r.black_hole()) {
+            Status::Fail
+        } else if known().next().is_some() {
+            Status::Pass
+        } else {
+            Status::Skip
+        }
+    }
+
+    // "[OK] MTU 1500/1400", gateway then remote, "-" where unknown. The
+    // IPv6 gateway only counts towards the tag, there's no room for it.
+    pub fn pmtu_line(&self) -> String<21> {
+        let mut line = String::new();
+        write!(line, "{} MTU", self.pmtu().tag()).ok();
+        for (i, result) in [&self.gateway_pmtu, &self.remote_pmtu].iter().enumerate() {
+            line.push(if i == 0 { ' ' } else { '/' }).ok();
+            match result.as_ref().and_then(|r| r.mtu) {
+                Some(mtu) => write!(line, "{}", mtu).ok(),
+                None => line.push('-').ok(),
+            };
+        }
+        line
+    }
+
+    // One "[OK] DHCP lease" style line per check, sized for the OLED.
+    pub fn lines(&self) -> [String<21>; 6] {
+        let mut out: [String<21>; 6] = Default::default();
+        for (line, (name, status)) in out.iter_mut().zip(self.checks().iter()) {
+            write!(line, "{} {}", status.tag(), name).ok();
+        }
+        out
+    }
+}
+
+pub async fn run(stack: Stack<'_>, cfg: &HealthConfig) -> HealthReport {
+    let mut report = HealthReport::new();
+
+    let Some(v4) = stack.config_v4() else {
+        warn!("health: no IPv4 config, everything else skipped");
+        report.dhcp = Status::Fail;
+        return report;
+    };
+    report.dhcp = if stack.is_config_up() && !v4.address.address().is_unspecified() {
+        Status::Pass
+    } else {
+        Status::Fail
+    };
+    info!("health: dhcp {} ({})", report.dhcp, v4.address);
+
+    if let Some(gw) = v4.gateway {
+        match ping(stack, gw.into()).await {
+            Some(ms) => {
+                report.gateway = Status::Pass;
+                report.gateway_rtt_ms = Some(ms);
+                info!("health: gateway {} answered in {}ms", gw, ms);
+                report.gateway_pmtu = Some(pmtu::probe(stack, gw.into()).await);
+            }
+            None => {
+                report.gateway = Status::Fail;
+                warn!("health: gateway {} did not answer", gw);
+            }
+        }
+    } else {
+        report.gateway = Status::Fail;
+    }
+    if let Some(gw) = stack.config_v6().and_then(|c| c.gateway) {
+        report.gateway6_pmtu = Some(pmtu::probe(stack, gw.into()).await);
+    }
+
+    report.dns = match stack.dns_query(cfg.dns_name, DnsQueryType::A).await {
+        Ok(addrs) if !addrs.is_empty() => {
+            info!("health: {} -> {}", cfg.dns_name, addrs[0]);
+            Status::Pass
+        }
+        Ok(_) => Status::Fail,
+        Err(e) => {
+            warn!("health: dns lookup of {} failed: {:?}", cfg.dns_name, e);
+            Status::Fail
+        }
+    };
+
+    let Some(url) = parse_http_url(cfg.url) else {
+        warn!("health: could not parse HEALTH_URL {}", cfg.url);
+        report.tcp = Status::Fail;
+        return report;
+    };
+
+    let addr = match stack.dns_query(url.host, DnsQueryType::A).await {
+        Ok(addrs) if !addrs.is_empty() => addrs[0],
+        _ => {
+            warn!("health: could not resolve {}", url.host);
+            report.tcp = Status::Fail;
+            return report;
+        }
+    };
+
+    http_check(stack, &url, addr, cfg.expect, &mut report).await;
+    report.remote_pmtu = Some(pmtu::probe(stack, addr).await);
+    report
+}
+
+pub async fn ping(stack: Stack<'_>, target: IpAddress) -> Option<u64> {
+    let mut rx_meta = [PacketMetadata::EMPTY];
+    let mut rx_buf = [0u8; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY];
+    let mut tx_buf = [0u8; 256];
+    let mut pinger = PingManager::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+
+    let mut params = PingParams::new(target);
+    params.set_count(3);
+    params.set_timeout(Duration::from_secs(2));
+    pinger.ping(&params).await.ok().map(|d| d.as_millis())
+}
+
+async fn http_check(
+    stack: Stack<'_>,
+    url: &HttpUrl<'_>,
+    addr: IpAddress,
+    expect: &str,
+    report: &mut HealthReport,
+) {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 512];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    socket.set_timeout(Some(HTTP_TIMEOUT));
+
+    match with_timeout(CONNECT_TIMEOUT, socket.connect((addr, url.port))).await {
+        Ok(Ok(())) => report.tcp = Status::Pass,
+        Ok(Err(e)) => {
+            warn!("health: connect to {}:{} failed: {:?}", addr, url.port, e);
+            report.tcp = Status::Fail;
+            return;
+        }
+        Err(_) => {
+            warn!("health: connect to {}:{} timed out", addr, url.port);
+            report.tcp = Status::Fail;
+            return;
+        }
+    }
+
+    let mut req: String<256> = String::new();
+    write!(
+        &mut req,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: picomap\r\nConnection: close\r\n\r\n",
+        url.path, url.host
+    )
+    .ok();
+
+    if write_all(&mut socket, req.as_bytes()).await.is_err() {
+        report.http = Status::Fail;
+        socket.abort();
+        return;
+    }
+
+    let mut resp = [0u8; 1024];
+    let mut len = 0;
+    while len < resp.len() {
+        match socket.read(&mut resp[len..]).await {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(_) => break,
+        }
+    }
+    socket.close();
+
+    let resp = &resp[..len];
+    let Some(code) = http_status_code(resp) else {
+        warn!("health: no HTTP status line in {} bytes", len);
+        report.http = Status::Fail;
+        return;
+    };
+    report.http = Status::Pass;
+    report.http_status = Some(code);
+    debug!("health: HTTP {} ({} bytes)", code, len);
+
+    let body = http_body(resp);
+    report.portal = if code == 200 && contains(body, expect.as_bytes()) {
+        Status::Pass
+    } else {
+        warn!(
+            "health: unexpected response (HTTP {}), captive portal?",
+            code
+        );
+        Status::Fail
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(url: &str) -> Option<(&str, u16, &str)> {
+        parse_http_url(url).map(|u| (u.host, u.port, u.path))
+    }
+
+    #[test]
+    fn http_urls() {
+        assert_eq!(
+            parts("http://detectportal.firefox.com/success.txt"),
+            Some(("detectportal.firefox.com", 80, "/success.txt"))
+        );
+        assert_eq!(
+            parts("http://192.168.1.1:8080/status?x=1"),
+            Some(("192.168.1.1", 8080, "/status?x=1"))
+        );
+        // No path means the root.
+        assert_eq!(parts("http://example.com"), Some(("example.com", 80, "/")));
+        assert_eq!(
+            parts("http://example.com:81"),
+            Some(("example.com", 81, "/"))
+        );
+    }
+
+    #[test]
+    fn bad_http_urls() {
+        // Only plain http, with the scheme spelled out.
+        assert!(parts("example.com/success.txt").is_none());
+        assert!(parts("https://example.com/").is_none());
+        assert!(parts("http://example.com:http/").is_none());
+        assert!(parts("http://example.com:65536/").is_none());
+        assert!(parts("http://example.com:/").is_none());
+        assert!(parts("http:///success.txt").is_none());
+        assert!(parts("http://:8080/").is_none());
+    }
+}
@@ -0,0 +1,733 @@
+//! industrial
+//! ----------
+//!
+//! Read-only identification of OT gear: PLCs, building controllers and
+//! the gateways in front of them. Off unless `SCAN_PROFILE` in `.env`
+//! includes `ot`, since some of this kit falls over when poked.
+//!
+//! Each probe only asks the device who it is:
+//!   - Modbus/TCP on 502: Read Device Identification (function 43, MEI
+//!     14), basic objects: vendor, product code, revision
+//!   - EtherNet/IP on TCP 44818: ListIdentity, which gives the vendor ID,
+//!     product name and revision
+//!   - BACnet/IP on UDP 47808: a Who-Is broadcast, then ReadProperty of
+//!     vendor-name, model-name and firmware-revision on each device that
+//!     answers with an I-Am, through its router if it's on another BACnet
+//!     network
+//!
+//! No writes, no connected sessions, and nothing that changes device
+//! state. Results go in `OT.CSV` and the inventory gets the protocol as a
+//! service.
+use core::fmt::{self, Write};
+use defmt::{debug, info};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use heapless::{String, Vec};
+
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::util::{text, write_all};
+
+pub const MODBUS_PORT: u16 = 502;
+pub const ENIP_PORT: u16 = 44818;
+pub const BACNET_PORT: u16 = 47808;
+pub const MAX_DEVICES: usize = 32;
+pub const EXPORT_FILE: &str = "OT.CSV";
+pub const CSV_HEADER: &str = "host,protocol,vendor,product,firmware";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+// How long to listen for I-Am after the Who-Is.
+const I_AM_WAIT: Duration = Duration::from_secs(3);
+const MAX_BACNET: usize = 16;
+
+// Modbus: function 43, MEI type 14, basic device identification.
+const MODBUS_READ_ID: [u8; 4] = [0x2b, 0x0e, 0x01, 0x00];
+const MODBUS_UNIT: u8 = 1;
+
+const ENIP_LIST_IDENTITY: u16 = 0x0063;
+const ENIP_HEADER: usize = 24;
+const CIP_IDENTITY_ITEM: u16 = 0x000c;
+
+const BVLC: u8 = 0x81;
+const BVLC_UNICAST: u8 = 0x0a;
+const BVLC_BROADCAST: u8 = 0x0b;
+const OBJECT_DEVICE: u32 = 8;
+const SERVICE_READ_PROPERTY: u8 = 12;
+const PROP_FIRMWARE_REVISION: u8 = 44;
+const PROP_MODEL_NAME: u8 = 70;
+const PROP_VENDOR_NAME: u8 = 121;
+
+// Global broadcast Who-Is, no range, so every device answers.
+pub const WHO_IS: [u8; 12] = [
+    BVLC,
+    BVLC_BROADCAST,
+    0x00,
+    0x0c,
+    0x01, // NPDU version
+    0x20, // destination present
+    0xff,
+    0xff, // DNET: all networks
+    0x00, // DLEN
+    0xff, // hop count
+    0x10, // unconfirmed request
+    0x08, // Who-Is
+];
+
+pub fn enabled() -> bool {
+    option_env!("SCAN_PROFILE")
+        .unwrap_or("")
+        .split(',')
+        .any(|p| p.trim() == "ot")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Protocol {
+    Modbus,
+    Enip,
+    Bacnet,
+}
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Modbus => "modbus",
+            Protocol::Enip => "enip",
+            Protocol::Bacnet => "bacnet",
+        }
+    }
+
+    // None for BACnet, which is UDP.
+    pub fn tcp_port(self) -> Option<u16> {
+        match self {
+            Protocol::Modbus => Some(MODBUS_PORT),
+            Protocol::Enip => Some(ENIP_PORT),
+            Protocol::Bacnet => None,
+        }
+    }
+}
+
+// Empty strings are things the device didn't say.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub vendor: String<32>,
+    pub product: String<32>,
+    pub firmware: String<16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub host: IpAddress,
+    pub protocol: Protocol,
+    pub id: Identity,
+}
+
+// "Schneider Electric BMX P34 2020 fw v2.80", leaving out what's empty.
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        for s in [&self.vendor[..], &self.product] {
+            if !s.is_empty() {
+                write!(f, "{}{}", sep, s)?;
+                sep = " ";
+            }
+        }
+        if !self.firmware.is_empty() {
+            write!(f, "{}fw {}", sep, self.firmware)?;
+        }
+        Ok(())
+    }
+}
+
+// "10.1.2.30 modbus: Schneider Electric BMX P34 2020 fw v2.80"
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}:", self.host, self.protocol.as_str())?;
+        if self.id != Identity::default() {
+            write!(f, " {}", self.id)?;
+        }
+        Ok(())
+    }
+}
+
+impl Device {
+    pub fn csv_row(&self) -> String<128> {
+        let mut row = String::new();
+        write!(
+            row,
+            "{},{},{},{},{}",
+            self.host,
+            self.protocol.as_str(),
+            self.id.vendor,
+            self.id.product,
+            self.id.firmware
+        )
+        .ok();
+        row
+    }
+}
+
+pub fn modbus_request(transaction: u16) -> [u8; 11] {
+    let mut req = [0u8; 11];
+    req[..2].copy_from_slice(&transaction.to_be_bytes());
+    // Protocol 0, then the length of unit ID and PDU.
+    req[4..6].copy_from_slice(&(1 + MODBUS_READ_ID.len() as u16).to_be_bytes());
+    req[6] = MODBUS_UNIT;
+    req[7..].copy_from_slice(&MODBUS_READ_ID);
+    req
+}
+
+// Length of the whole MBAP frame, once the header's in.
+pub fn modbus_frame_len(buf: &[u8]) -> Option<usize> {
+    Some(6 + u16::from_be_bytes([*buf.get(4)?, *buf.get(5)?]) as usize)
+}
+
+// Some even for an exception reply; it's still Modbus, just not telling.
+pub fn parse_modbus(resp: &[u8], transaction: u16) -> Option<Identity> {
+    if resp.len() < 9 || resp[..2] != transaction.to_be_bytes() || resp[2..4] != [0, 0] {
+        return None;
+    }
+    let pdu = &resp[7..];
+    if pdu[0] == MODBUS_READ_ID[0] | 0x80 {
+        return Some(Identity::default());
+    }
+    if pdu.len() < 7 || pdu[..2] != MODBUS_READ_ID[..2] {
+        return None;
+    }
+    let mut id = Identity::default();
+    let mut objects = &pdu[7..];
+    for _ in 0..pdu[6] {
+        let [obj, len, ref rest @ ..] = *objects else {
+            break;
+        };
+        let Some(value) = rest.get(..len as usize) else {
+            break;
+        };
+        match obj {
+            0 => id.vendor = text(value),
+            1 => id.product = text(value),
+            2 => id.firmware = text(value),
+            _ => {}
+        }
+        objects = &rest[len as usize..];
+    }
+    Some(id)
+}
+
+pub fn list_identity() -> [u8; ENIP_HEADER] {
+    let mut req = [0u8; ENIP_HEADER];
+    req[..2].copy_from_slice(&ENIP_LIST_IDENTITY.to_le_bytes());
+    // No data, no session; sender context comes back as is.
+    req[12..20].copy_from_slice(b"picomap\0");
+    req
+}
+
+pub fn enip_frame_len(buf: &[u8]) -> Option<usize> {
+    Some(ENIP_HEADER + u16::from_le_bytes([*buf.get(2)?, *buf.get(3)?]) as usize)
+}
+
+pub fn parse_list_identity(resp: &[u8]) -> Option<Identity> {
+    let le16 = |at: usize| Some(u16::from_le_bytes([*resp.get(at)?, *resp.get(at + 1)?]));
+    if le16(0)? != ENIP_LIST_IDENTITY || resp.get(8..12)? != [0; 4] || le16(24)? == 0 {
+        return None;
+    }
+    if le16(26)? != CIP_IDENTITY_ITEM {
+        return None;
+    }
+    // Item: version, socket address, then the identity object.
+    let item = resp.get(30..30 + le16(28)? as usize)?;
+    let vendor = u16::from_le_bytes([*item.get(18)?, item[19]]);
+    let (major, minor) = (*item.get(24)?, *item.get(25)?);
+    let name_len = *item.get(32)? as usize;
+    let mut id = Identity {
+        product: text(item.get(33..33 + name_len)?),
+        ..Default::default()
+    };
+    // Only the ODVA vendor ID is on the wire, not a name.
+    write!(id.vendor, "vendor {}", vendor).ok();
+    write!(id.firmware, "{}.{}", major, minor).ok();
+    Some(id)
+}
+
+// Where a device sits behind a BACnet router, for reaching it through one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub net: u16,
+    pub addr: Vec<u8, 8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IAm {
+    pub instance: u32,
+    pub vendor_id: u16,
+    pub route: Option<Route>,
+}
+
+// The APDU of a BVLC/NPDU packet, and the source network if it came
+// through a router. None for network layer messages.
+fn npdu(packet: &[u8]) -> Option<(Option<Route>, &[u8])> {
+    if packet.len() < 6 || packet[0] != BVLC || !matches!(packet[1], BVLC_UNICAST | BVLC_BROADCAST)
+    {
+        return None;
+    }
+    let len = (u16::from_be_bytes([packet[2], packet[3]]) as usize).min(packet.len());
+    let npdu = packet.get(4..len)?;
+    let control = *npdu.get(1)?;
+    if npdu[0] != 0x01 || control & 0x80 != 0 {
+        return None;
+    }
+    let mut at = 2;
+    if control & 0x20 != 0 {
+        at += 3 + *npdu.get(at + 2)? as usize;
+    }
+    let mut route = None;
+    if control & 0x08 != 0 {
+        let net = u16::from_be_bytes([*npdu.get(at)?, *npdu.get(at + 1)?]);
+        let slen = *npdu.get(at + 2)? as usize;
+        let addr = Vec::from_slice(npdu.get(at + 3..at + 3 + slen)?).ok()?;
+        route = Some(Route { net, addr });
+        at += 3 + slen;
+    }
+    if control & 0x20 != 0 {
+        at += 1; // hop count
+    }
+    Some((route, npdu.get(at..)?))
+}
+
+// One BACnet tag: its number, whether it's context specific, and its
+// value. Opening and closing tags have no value.
+fn tag(buf: &[u8]) -> Option<(u8, bool, &[u8], &[u8])> {
+    let (&first, mut rest) = buf.split_first()?;
+    let (number, context) = (first >> 4, first & 0x08 != 0);
+    let len = match first & 0x07 {
+        6 | 7 if context => 0,
+        5 => {
+            let (&n, tail) = rest.split_first()?;
+            rest = tail;
+            match n {
+                254 => {
+                    let n = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+                    rest = &rest[2..];
+                    n as usize
+                }
+                255 => return None,
+                n => n as usize,
+            }
+        }
+        n => n as usize,
+    };
+    Some((number, context, rest.get(..len)?, &rest[len..]))
+}
+
+fn unsigned(value: &[u8]) -> u32 {
+    value.iter().fold(0, |n, &b| n << 8 | b as u32)
+}
+
+pub fn parse_i_am(packet: &[u8]) -> Option<IAm> {
+    let (route, apdu) = npdu(packet)?;
+    // Unconfirmed request, I-Am.
+    if apdu.get(..2)? != [0x10, 0x00] {
+        return None;
+    }
+    let (12, false, object, rest) = tag(&apdu[2..])? else {
+        return None;
+    };
+    let object = unsigned(object);
+    if object >> 22 != OBJECT_DEVICE {
+        return None;
+    }
+    let (_, _, _, rest) = tag(rest)?; // max APDU
+    let (_, _, _, rest) = tag(rest)?; // segmentation
+    let (2, false, vendor, _) = tag(rest)? else {
+        return None;
+    };
+    Some(IAm {
+        instance: object & 0x3f_ffff,
+        vendor_id: unsigned(vendor) as u16,
+        route,
+    })
+}
+
+pub fn read_property(device: &IAm, invoke: u8, property: u8) -> Vec<u8, 40> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&[BVLC, BVLC_UNICAST, 0, 0, 0x01])
+        .ok();
+    match &device.route {
+        Some(route) => {
+            // Expecting reply, destination present.
+            out.push(0x24).ok();
+            out.extend_from_slice(&route.net.to_be_bytes()).ok();
+            out.push(route.addr.len() as u8).ok();
+            out.extend_from_slice(&route.addr).ok();
+            out.push(0xff).ok();
+        }
+        None => {
+            out.push(0x04).ok();
+        }
+    }
+    let object = OBJECT_DEVICE << 22 | device.instance;
+    // Confirmed request, max 1476 byte APDU, ReadProperty.
+    out.extend_from_slice(&[0x00, 0x05, invoke, SERVICE_READ_PROPERTY, 0x0c])
+        .ok();
+    out.extend_from_slice(&object.to_be_bytes()).ok();
+    out.extend_from_slice(&[0x19, property]).ok();
+    let len = out.len() as u16;
+    out[2..4].copy_from_slice(&len.to_be_bytes());
+    out
+}
+
+// The CharacterString value of a ReadProperty ComplexACK for `invoke`.
+pub fn parse_read_property<const N: usize>(packet: &[u8], invoke: u8) -> Option<String<N>> {
+    let (_, apdu) = npdu(packet)?;
+    if apdu.len() < 3 || apdu[0] >> 4 != 3 || apdu[1] != invoke || apdu[2] != SERVICE_READ_PROPERTY
+    {
+        return None;
+    }
+    let mut rest = &apdu[3..];
+    // Object, property and maybe an array index, then the opening tag.
+    loop {
+        let (number, context, _, tail) = tag(rest)?;
+        rest = tail;
+        if context && number == 3 {
+            break;
+        }
+    }
+    let (7, false, value, _) = tag(rest)? else {
+        return None;
+    };
+    // First byte is the character set; 0 is UTF-8, and everything else
+    // we'd see in practice is ASCII-compatible anyway.
+    Some(text(value.get(1..)?))
+}
+
+// Sends `req` and reads one reply framed by `frame_len`.
+async fn tcp_query(
+    stack: Stack<'_>,
+    host: IpAddress,
+    port: u16,
+    req: &[u8],
+    resp: &mut [u8],
+    frame_len: fn(&[u8]) -> Option<usize>,
+) -> Option<usize> {
+    let mut rx_buf = [0u8; 512];
+    let mut tx_buf = [0u8; 64];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, port))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    let mut got = 0;
+    let read = async {
+        write_all(&mut socket, req).await.ok()?;
+        while got < resp.len() && frame_len(&resp[..got]).is_none_or(|n| got < n) {
+            match socket.read(&mut resp[got..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => got += n,
+            }
+        }
+        Some(())
+    };
+    with_timeout(READ_TIMEOUT, read).await.ok();
+    socket.abort();
+    (got > 0).then_some(got)
+}
+
+async fn modbus(stack: Stack<'_>, host: IpAddress, transaction: u16) -> Option<Identity> {
+    let mut resp = [0u8; 260];
+    let req = modbus_request(transaction);
+    let n = tcp_query(stack, host, MODBUS_PORT, &req, &mut resp, modbus_frame_len).await?;
+    parse_modbus(&resp[..n], transaction)
+}
+
+async fn enip(stack: Stack<'_>, host: IpAddress) -> Option<Identity> {
+    let mut resp = [0u8; 256];
+    let req = list_identity();
+    let n = tcp_query(stack, host, ENIP_PORT, &req, &mut resp, enip_frame_len).await?;
+    parse_list_identity(&resp[..n])
+}
+
+// Who-Is, then asks every device that answered for its strings.
+async fn bacnet(stack: Stack<'_>, devices: &mut Vec<Device, MAX_DEVICES>) {
+    let Some(v4) = stack.config_v4() else {
+        return;
+    };
+    let target = v4.address.broadcast().unwrap_or(Ipv4Address::BROADCAST);
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buf = [0u8; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    // I-Am usually comes back as a broadcast to the BACnet port, not to
+    // whatever port asked.
+    if socket.bind(BACNET_PORT).is_err() {
+        return;
+    }
+    if socket
+        .send_to(&WHO_IS, (IpAddress::Ipv4(target), BACNET_PORT))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let mut found: Vec<(IpAddress, IAm), MAX_BACNET> = Vec::new();
+    let mut buf = [0u8; 256];
+    let deadline = Instant::now() + I_AM_WAIT;
+    while let Ok(Ok((n, meta))) = with_deadline(deadline, socket.recv_from(&mut buf)).await {
+        if let Some(i_am) = parse_i_am(&buf[..n])
+            && !found.iter().any(|(_, d)| d.instance == i_am.instance)
+        {
+            debug!("bacnet: device {} at {}", i_am.instance, meta.endpoint.addr);
+            found.push((meta.endpoint.addr, i_am)).ok();
+        }
+    }
+
+    let mut invoke: u8 = 0;
+    for (host, i_am) in &found {
+        let mut id = Identity::default();
+        for property in [PROP_VENDOR_NAME, PROP_MODEL_NAME, PROP_FIRMWARE_REVISION] {
+            invoke = invoke.wrapping_add(1);
+            let req = read_property(i_am, invoke, property);
+            if socket.send_to(&req, (*host, BACNET_PORT)).await.is_err() {
+                continue;
+            }
+            let reply = async {
+                loop {
+                    let (n, meta) = socket.recv_from(&mut buf).await.ok()?;
+                    if meta.endpoint.addr == *host
+                        && let Some(s) = parse_read_property::<32>(&buf[..n], invoke)
+                    {
+                        return Some(s);
+                    }
+                }
+            };
+            let Ok(Some(value)) = with_timeout(READ_TIMEOUT, reply).await else {
+                continue;
+            };
+            match property {
+                PROP_VENDOR_NAME => id.vendor = value,
+                PROP_MODEL_NAME => id.product = value,
+                _ => id.firmware = text(value.as_bytes()),
+            }
+        }
+        if id.vendor.is_empty() {
+            write!(id.vendor, "vendor {}", i_am.vendor_id).ok();
+        }
+        devices
+            .push(Device {
+                host: *host,
+                protocol: Protocol::Bacnet,
+                id,
+            })
+            .ok();
+    }
+}
+
+// Modbus and EtherNet/IP on every host, then BACnet by broadcast.
+pub async fn run(stack: Stack<'_>, hosts: &[IpAddress]) -> Vec<Device, MAX_DEVICES> {
+    let mut devices = Vec::new();
+    for (i, &host) in hosts.iter().enumerate() {
+        if let Some(id) = modbus(stack, host, i as u16).await {
+            devices
+                .push(Device {
+                    host,
+                    protocol: Protocol::Modbus,
+                    id,
+                })
+                .ok();
+        }
+        if let Some(id) = enip(stack, host).await {
+            devices
+                .push(Device {
+                    host,
+                    protocol: Protocol::Enip,
+                    id,
+                })
+                .ok();
+        }
+    }
+    bacnet(stack, &mut devices).await;
+    info!(
+        "industrial: {} hosts, {} devices",
+        hosts.len(),
+        devices.len()
+    );
+    devices
+}
+
+// Writes the devices out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    devices: &[Device],
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::<128>::try_from(CSV_HEADER).unwrap())
+            .chain(devices.iter().map(Device::csv_row)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modbus_device_id() {
+        let req = modbus_request(0x1234);
+        assert_eq!(req, [0x12, 0x34, 0, 0, 0, 5, 1, 0x2b, 0x0e, 0x01, 0x00]);
+        assert_eq!(modbus_frame_len(&req), Some(req.len()));
+
+        let mut resp: Vec<u8, 128> = Vec::from_slice(&[
+            0x12, 0x34, 0, 0, 0, 0, 1, 0x2b, 0x0e, 0x01, 0x01, 0x00, 0x00, 3,
+        ])
+        .unwrap();
+        for (obj, value) in [
+            (0u8, &b"Schneider Electric"[..]),
+            (1, b"BMX P34 2020"),
+            (2, b"v2.80"),
+        ] {
+            resp.push(obj).unwrap();
+            resp.push(value.len() as u8).unwrap();
+            resp.extend_from_slice(value).unwrap();
+        }
+        let len = resp.len() as u16 - 6;
+        resp[4..6].copy_from_slice(&len.to_be_bytes());
+        assert_eq!(modbus_frame_len(&resp), Some(resp.len()));
+
+        let id = parse_modbus(&resp, 0x1234).unwrap();
+        assert_eq!(id.vendor, "Schneider Electric");
+        assert_eq!(id.product, "BMX P34 2020");
+        assert_eq!(id.firmware, "v2.80");
+        assert_eq!(parse_modbus(&resp, 0x1235), None);
+        // Truncated objects keep what was whole.
+        let id = parse_modbus(&resp[..resp.len() - 3], 0x1234).unwrap();
+        assert_eq!(id.product, "BMX P34 2020");
+        assert_eq!(id.firmware, "");
+        // Illegal function exception.
+        let exception = [0x12, 0x34, 0, 0, 0, 3, 1, 0xab, 0x01];
+        assert_eq!(parse_modbus(&exception, 0x1234), Some(Identity::default()));
+    }
+
+    #[test]
+    fn enip_list_identity() {
+        let req = list_identity();
+        assert_eq!(req[..2], [0x63, 0x00]);
+        assert_eq!(enip_frame_len(&req), Some(ENIP_HEADER));
+
+        let name = b"1769-L33ER/A LOGIX5333ER";
+        let mut item = [0u8; 33];
+        item[0] = 1; // protocol version
+        item[18..20].copy_from_slice(&1u16.to_le_bytes());
+        item[20] = 0x0e; // PLC
+        item[24] = 30;
+        item[25] = 11;
+        item[32] = name.len() as u8;
+        let mut resp: Vec<u8, 128> = Vec::from_slice(&req).unwrap();
+        resp.extend_from_slice(&1u16.to_le_bytes()).unwrap();
+        resp.extend_from_slice(&CIP_IDENTITY_ITEM.to_le_bytes())
+            .unwrap();
+        resp.extend_from_slice(&((item.len() + name.len() + 1) as u16).to_le_bytes())
+            .unwrap();
+        resp.extend_from_slice(&item).unwrap();
+        resp.extend_from_slice(name).unwrap();
+        resp.push(3).unwrap(); // state
+        let len = (resp.len() - ENIP_HEADER) as u16;
+        resp[2..4].copy_from_slice(&len.to_le_bytes());
+        assert_eq!(enip_frame_len(&resp), Some(resp.len()));
+
+        let id = parse_list_identity(&resp).unwrap();
+        assert_eq!(id.vendor, "vendor 1");
+        assert_eq!(id.product, "1769-L33ER/A LOGIX5333ER");
+        assert_eq!(id.firmware, "30.11");
+        assert_eq!(parse_list_identity(&resp[..40]), None);
+        resp[8] = 1; // invalid command status
+        assert_eq!(parse_list_identity(&resp), None);
+    }
+
+    #[test]
+    fn bacnet_who_is_i_am() {
+        assert_eq!(WHO_IS[3] as usize, WHO_IS.len());
+        let (route, apdu) = npdu(&WHO_IS).unwrap();
+        assert_eq!(route, None);
+        assert_eq!(apdu, [0x10, 0x08]);
+
+        // Device 1234, max APDU 1476, no segmentation, vendor 5, routed
+        // from network 5 MAC 0x21.
+        let i_am = [
+            0x81, 0x0b, 0x00, 0x18, 0x01, 0x08, 0x00, 0x05, 0x01, 0x21, 0x10, 0x00, 0xc4, 0x02,
+            0x00, 0x04, 0xd2, 0x22, 0x05, 0xc4, 0x91, 0x03, 0x21, 0x05,
+        ];
+        let device = parse_i_am(&i_am).unwrap();
+        assert_eq!(device.instance, 1234);
+        assert_eq!(device.vendor_id, 5);
+        let route = device.route.clone().unwrap();
+        assert_eq!(route.net, 5);
+        assert_eq!(route.addr, [0x21]);
+        assert_eq!(parse_i_am(&WHO_IS), None);
+
+        let req = read_property(&device, 7, PROP_MODEL_NAME);
+        assert_eq!(req[2..4], (req.len() as u16).to_be_bytes());
+        assert_eq!(req[4..11], [0x01, 0x24, 0x00, 0x05, 0x01, 0x21, 0xff]);
+        assert_eq!(
+            req[11..],
+            [0x00, 0x05, 7, 12, 0x0c, 0x02, 0x00, 0x04, 0xd2, 0x19, 70]
+        );
+        let direct = IAm {
+            route: None,
+            ..device
+        };
+        assert_eq!(
+            read_property(&direct, 7, PROP_MODEL_NAME)[4..6],
+            [0x01, 0x04]
+        );
+    }
+
+    #[test]
+    fn bacnet_read_property() {
+        // ComplexACK, invoke 7, ReadProperty of device 1234 model-name,
+        // "DXR2.E18" as a UTF-8 CharacterString.
+        let mut ack: Vec<u8, 64> = Vec::from_slice(&[
+            0x81, 0x0a, 0x00, 0x00, 0x01, 0x00, 0x30, 7, 0x0c, 0x0c, 0x02, 0x00, 0x04, 0xd2, 0x19,
+            70, 0x3e, 0x75, 9, 0x00,
+        ])
+        .unwrap();
+        ack.extend_from_slice(b"DXR2.E18").unwrap();
+        ack.push(0x3f).unwrap();
+        let len = ack.len() as u16;
+        ack[2..4].copy_from_slice(&len.to_be_bytes());
+
+        assert_eq!(
+            parse_read_property::<32>(&ack, 7).as_deref(),
+            Some("DXR2.E18")
+        );
+        assert_eq!(parse_read_property::<32>(&ack, 8), None);
+        // Short strings have the length in the tag itself.
+        let short = [
+            0x81, 0x0a, 0x00, 0x17, 0x01, 0x00, 0x30, 1, 0x0c, 0x0c, 0x02, 0x00, 0x04, 0xd2, 0x19,
+            44, 0x3e, 0x74, 0x00, b'1', b'.', b'2', 0x3f,
+        ];
+        assert_eq!(parse_read_property::<16>(&short, 1).as_deref(), Some("1.2"));
+    }
+
+    #[test]
+    fn csv_rows() {
+        let device = Device {
+            host: IpAddress::v4(10, 1, 2, 30),
+            protocol: Protocol::Modbus,
+            id: Identity {
+                vendor: text(b"Acme, Inc."),
+                product: text(b" PLC-1\0"),
+                firmware: text(b"2.80"),
+            },
+        };
+        assert_eq!(device.csv_row(), "10.1.2.30,modbus,Acme  Inc.,PLC-1,2.80");
+        let mut line: String<64> = String::new();
+        write!(line, "{}", device).unwrap();
+        assert_eq!(line, "10.1.2.30 modbus: Acme  Inc. PLC-1 fw 2.80");
+    }
+}
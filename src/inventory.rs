@@ -0,0 +1,1098 @@
+//! inventory
+//! ---------
+//!
+//! Every host we've ever seen, kept on the SD card in `INVENTRY.DB`.
+//!
+//! The file is a flat array of fixed 128 byte records, so a host can be
+//! updated in place with one seek and write. Only a small index (MAC and
+//! IPs -> record number, ~20 bytes each) lives in RAM. It's rebuilt from
+//! the file at boot, so a few hundred hosts cost under 24 KB.
+//!
+//! Hosts are keyed by MAC. Hosts we only know by IP (behind a router, or
+//! found before we heard their ARP) are keyed by IP until a MAC shows up.
+//! A host can have an IPv4 and an IPv6 address; ARP and neighbor discovery
+//! both report the MAC, which is what ties the two together.
+//!
+//! Record layout, integers big-endian:
+//!
+//!     0    0xA6 if the slot is in use
+//!     1    flags: 1 = has MAC, 2 = has IPv4, 4 = has IPv6, 8 = SMBv1
+//!          enabled, 16 = SMB signing not required (see `smb`)
+//!     2    MAC
+//!     8    IPv4 address
+//!     12   first seen, unix seconds u32
+//!     16   last seen, unix seconds u32
+//!     20   times seen, u32
+//!     24   open TCP ports, 12 x u16, 0 = unused
+//!     48   hostname, 32 bytes, NUL padded
+//!     80   IPv6 address
+//!     96   services, 24 bytes, comma separated, NUL padded
+//!     120  last boot, unix seconds u32, 0 = unknown (see `uptime`)
+//!     124  TCP timestamp rate, Hz u16, 0 = unknown
+//!     126  OS build from SMB, u16, 0 = unknown
+//!
+//! The vendor isn't stored, it's looked up from the MAC on load.
+//!
+//! SSH host keys (see `ssh`) don't fit in there, so they're in
+//! `HOSTKEYS.DB`, 64 byte records at the same index as the host's record:
+//!
+//!     0    0xA1 if there's a key
+//!     1    reserved, 0
+//!     2    port it was found on, u16
+//!     4    key type, 24 bytes, NUL padded
+//!     28   SHA256 of the key blob
+//!     60   reserved, 0
+//!
+//! What answered on each open port (SSH version line, FTP greeting, RTSP
+//! server and so on) is in `BANNERS.DB`, also at the host's index. Each
+//! record is 12 entries of 48 bytes:
+//!
+//!     0    port, u16, 0 = unused
+//!     2    banner, 46 bytes, NUL padded
+use core::fmt;
+use defmt::{debug, info, warn};
+use embassy_net::{IpAddress, Ipv4Address, Ipv6Address};
+use heapless::{String, Vec};
+
+use crate::arp_guard::Mac;
+use crate::clock;
+use crate::oui;
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::smb::SmbInfo;
+use crate::ssh::SshKey;
+use crate::uptime::Uptime;
+
+pub const DB_FILE: &str = "INVENTRY.DB";
+pub const RECORD_LEN: usize = 128;
+pub const MAX_HOSTS: usize = 384;
+pub const MAX_PORTS: usize = 12;
+pub const KEYS_FILE: &str = "HOSTKEYS.DB";
+pub const KEY_RECORD_LEN: usize = 64;
+pub const BANNERS_FILE: &str = "BANNERS.DB";
+pub const BANNER_LEN: usize = 46;
+pub const BANNER_RECORD_LEN: usize = MAX_PORTS * (2 + BANNER_LEN);
+
+const MAGIC: u8 = 0xA6;
+const MAGIC_KEY: u8 = 0xA1;
+const FLAG_MAC: u8 = 1;
+const FLAG_IP: u8 = 2;
+const FLAG_IP6: u8 = 4;
+const FLAG_SMB1: u8 = 8;
+const FLAG_SMB_UNSIGNED: u8 = 16;
+
+type SdError = embedded_sdmmc::Error<SdSpiError>;
+
+// Port and what answered on it.
+pub type Banners = Vec<(u16, String<BANNER_LEN>), MAX_PORTS>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum HostKey {
+    Mac([u8; 6]),
+    Ip(IpAddress),
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKey::Mac(mac) => write!(f, "{}", Mac(mac)),
+            HostKey::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    pub mac: Option<[u8; 6]>,
+    pub ip: Option<Ipv4Address>,
+    pub ip6: Option<Ipv6Address>,
+    pub first_seen: u32,
+    pub last_seen: u32,
+    pub times_seen: u32,
+    pub ports: Vec<u16, MAX_PORTS>,
+    pub hostname: String<32>,
+    pub vendor: String<16>,
+    pub services: String<24>,
+    // Estimated from TCP timestamps, both as of the last estimate.
+    pub last_boot: Option<u32>,
+    pub tick_hz: u16,
+    // As of the last SMB probe.
+    pub smb1: bool,
+    pub smb_unsigned: bool,
+    pub os_build: u16,
+}
+
+// Something a scanner learned about a host. Anything left as None/empty
+// doesn't touch what's already stored.
+#[derive(Default)]
+pub struct Observation<'a> {
+    pub mac: Option<[u8; 6]>,
+    pub ip: Option<IpAddress>,
+    pub hostname: Option<&'a str>,
+    // Open TCP port, and what we think is behind it.
+    pub ports: &'a [(u16, Option<&'a str>)],
+    // Services found some other way (UDP, broadcast discovery).
+    pub services: &'a [&'a str],
+    // What a TCP port said about itself: banner, version, server header.
+    pub banners: &'a [(u16, &'a str)],
+    pub uptime: Option<Uptime>,
+    pub smb: Option<&'a SmbInfo>,
+}
+
+impl Host {
+    fn new(now: u32) -> Self {
+        Self {
+            mac: None,
+            ip: None,
+            ip6: None,
+            first_seen: now,
+            last_seen: now,
+            times_seen: 0,
+            ports: Vec::new(),
+            hostname: String::new(),
+            vendor: String::new(),
+            services: String::new(),
+            last_boot: None,
+            tick_hz: 0,
+            smb1: false,
+            smb_unsigned: false,
+            os_build: 0,
+        }
+    }
+
+    pub fn key(&self) -> Option<HostKey> {
+        match self.mac {
+            Some(mac) => Some(HostKey::Mac(mac)),
+            None => self.addrs().next().map(HostKey::Ip),
+        }
+    }
+
+    // IPv4 first, then IPv6.
+    pub fn addrs(&self) -> impl Iterator<Item = IpAddress> + Clone {
+        let v4 = self.ip.map(IpAddress::Ipv4);
+        let v6 = self.ip6.map(IpAddress::Ipv6);
+        v4.into_iter().chain(v6)
+    }
+
+    pub fn has_service(&self, name: &str) -> bool {
+        self.services.split(',').any(|s| s == name)
+    }
+
+    fn add_service(&mut self, service: &str) {
+        if service.is_empty() || self.has_service(service) {
+            return;
+        }
+        let sep = if self.services.is_empty() { "" } else { "," };
+        if self.services.len() + sep.len() + service.len() <= self.services.capacity() {
+            self.services.push_str(sep).ok();
+            self.services.push_str(service).ok();
+        }
+    }
+
+    fn merge(&mut self, obs: &Observation<'_>, now: u32) {
+        if let Some(mac) = obs.mac {
+            self.mac = Some(mac);
+            self.vendor = truncated(oui::vendor(&mac));
+        }
+        match obs.ip {
+            Some(IpAddress::Ipv4(ip)) => self.ip = Some(ip),
+            Some(IpAddress::Ipv6(ip)) => self.ip6 = Some(ip),
+            None => {}
+        }
+        if let Some(name) = obs.hostname
+            && !name.is_empty()
+        {
+            self.hostname = truncated(name);
+        }
+        for &(port, service) in obs.ports {
+            if !self.ports.contains(&port) {
+                // Full means we keep the first twelve we found.
+                self.ports.push(port).ok();
+            }
+            if let Some(service) = service {
+                self.add_service(service);
+            }
+        }
+        for service in obs.services {
+            self.add_service(service);
+        }
+        if let Some(uptime) = obs.uptime {
+            self.last_boot = Some(uptime.boot(now));
+            self.tick_hz = uptime.hz;
+        }
+        if let Some(smb) = obs.smb {
+            self.smb1 = smb.smb1;
+            self.smb_unsigned = smb.signing_required == Some(false);
+            if let Some(os) = smb.ntlm.os {
+                self.os_build = os.build;
+            }
+            // Names from DHCP or mDNS are usually what people call it.
+            if self.hostname.is_empty() {
+                self.hostname = truncated(smb.name());
+            }
+            self.add_service("smb");
+        }
+        self.last_seen = now;
+        self.times_seen = self.times_seen.saturating_add(1);
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0] = MAGIC;
+        if let Some(mac) = self.mac {
+            buf[1] |= FLAG_MAC;
+            buf[2..8].copy_from_slice(&mac);
+        }
+        if let Some(ip) = self.ip {
+            buf[1] |= FLAG_IP;
+            buf[8..12].copy_from_slice(&ip.octets());
+        }
+        buf[12..16].copy_from_slice(&self.first_seen.to_be_bytes());
+        buf[16..20].copy_from_slice(&self.last_seen.to_be_bytes());
+        buf[20..24].copy_from_slice(&self.times_seen.to_be_bytes());
+        for (i, port) in self.ports.iter().enumerate() {
+            buf[24 + i * 2..26 + i * 2].copy_from_slice(&port.to_be_bytes());
+        }
+        put_str(&mut buf[48..80], &self.hostname);
+        if let Some(ip) = self.ip6 {
+            buf[1] |= FLAG_IP6;
+            buf[80..96].copy_from_slice(&ip.octets());
+        }
+        put_str(&mut buf[96..120], &self.services);
+        buf[120..124].copy_from_slice(&self.last_boot.unwrap_or(0).to_be_bytes());
+        buf[124..126].copy_from_slice(&self.tick_hz.to_be_bytes());
+        buf[126..128].copy_from_slice(&self.os_build.to_be_bytes());
+        if self.smb1 {
+            buf[1] |= FLAG_SMB1;
+        }
+        if self.smb_unsigned {
+            buf[1] |= FLAG_SMB_UNSIGNED;
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8; RECORD_LEN]) -> Option<Self> {
+        if buf[0] != MAGIC {
+            return None;
+        }
+        let word = |i: usize| u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        let mut host = Host::new(word(12));
+        if buf[1] & FLAG_MAC != 0 {
+            let mac = buf[2..8].try_into().unwrap();
+            host.mac = Some(mac);
+            host.vendor = truncated(oui::vendor(&mac));
+        }
+        if buf[1] & FLAG_IP != 0 {
+            host.ip = Some(Ipv4Address::new(buf[8], buf[9], buf[10], buf[11]));
+        }
+        host.last_seen = word(16);
+        host.times_seen = word(20);
+        for pair in buf[24..48].chunks_exact(2) {
+            let port = u16::from_be_bytes([pair[0], pair[1]]);
+            if port != 0 {
+                host.ports.push(port).ok();
+            }
+        }
+        host.hostname = get_str(&buf[48..80]);
+        if buf[1] & FLAG_IP6 != 0 {
+            let ip: [u8; 16] = buf[80..96].try_into().unwrap();
+            host.ip6 = Some(ip.into());
+        }
+        host.services = get_str(&buf[96..120]);
+        host.last_boot = Some(word(120)).filter(|&t| t != 0);
+        host.tick_hz = u16::from_be_bytes([buf[124], buf[125]]);
+        host.os_build = u16::from_be_bytes([buf[126], buf[127]]);
+        host.smb1 = buf[1] & FLAG_SMB1 != 0;
+        host.smb_unsigned = buf[1] & FLAG_SMB_UNSIGNED != 0;
+        Some(host)
+    }
+}
+
+// `SCAN_HOSTS` from `.env`, comma separated IPv4 or IPv6 addresses to
+// scan even if we never see them on the wire.
+pub fn configured_hosts() -> impl Iterator<Item = IpAddress> {
+    parse_hosts(option_env!("SCAN_HOSTS").unwrap_or(""))
+}
+
+// Bad entries are logged and skipped.
+pub fn parse_hosts(list: &str) -> impl Iterator<Item = IpAddress> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match s.parse() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                warn!("inventory: bad address in SCAN_HOSTS: {}", s);
+                None
+            }
+        })
+}
+
+// Records in HOSTKEYS.DB and BANNERS.DB sit at the host's slot. False if
+// the host doesn't have one yet.
+fn read_side<S: embassy_rp::spi::Instance, const N: usize>(
+    storage: &mut SdStorage<'_, S>,
+    file: &str,
+    slot: u16,
+    record: &mut [u8; N],
+) -> Result<bool, SdError> {
+    match storage.read_at(file, slot as u32 * N as u32, record) {
+        Ok(n) => Ok(n == N),
+        Err(embedded_sdmmc::Error::NotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_side<S: embassy_rp::spi::Instance, const N: usize>(
+    storage: &mut SdStorage<'_, S>,
+    file: &str,
+    slot: u16,
+    record: &[u8; N],
+) -> Result<(), SdError> {
+    // Hosts without a record leave gaps; write_at can only append, so fill
+    // them with empty records first.
+    let mut len = 0;
+    storage.list_files(|name, size| {
+        if name == file {
+            len = size;
+        }
+    })?;
+    // A torn write can leave part of a record at the end.
+    len -= len % N as u32;
+    let offset = slot as u32 * N as u32;
+    while len < offset {
+        storage.write_at(file, len, &[0; N])?;
+        len += N as u32;
+    }
+    storage.write_at(file, offset, record)
+}
+
+fn read_banners<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    slot: u16,
+) -> Result<Banners, SdError> {
+    let mut record = [0u8; BANNER_RECORD_LEN];
+    Ok(if read_side(storage, BANNERS_FILE, slot, &mut record)? {
+        decode_banners(&record)
+    } else {
+        Vec::new()
+    })
+}
+
+fn encode_key(key: &SshKey) -> [u8; KEY_RECORD_LEN] {
+    let mut buf = [0u8; KEY_RECORD_LEN];
+    buf[0] = MAGIC_KEY;
+    buf[2..4].copy_from_slice(&key.port.to_be_bytes());
+    put_str(&mut buf[4..28], &key.kind);
+    buf[28..60].copy_from_slice(&key.sha256);
+    buf
+}
+
+fn decode_key(buf: &[u8; KEY_RECORD_LEN]) -> Option<SshKey> {
+    (buf[0] == MAGIC_KEY).then(|| SshKey {
+        port: u16::from_be_bytes([buf[2], buf[3]]),
+        kind: get_str(&buf[4..28]),
+        sha256: buf[28..60].try_into().unwrap(),
+    })
+}
+
+fn encode_banners(banners: &Banners) -> [u8; BANNER_RECORD_LEN] {
+    let mut buf = [0u8; BANNER_RECORD_LEN];
+    for (entry, (port, banner)) in buf.chunks_exact_mut(2 + BANNER_LEN).zip(banners) {
+        entry[..2].copy_from_slice(&port.to_be_bytes());
+        put_str(&mut entry[2..], banner);
+    }
+    buf
+}
+
+fn decode_banners(buf: &[u8; BANNER_RECORD_LEN]) -> Banners {
+    let mut banners = Vec::new();
+    for entry in buf.chunks_exact(2 + BANNER_LEN) {
+        let port = u16::from_be_bytes([entry[0], entry[1]]);
+        if port != 0 {
+            banners.push((port, get_str(&entry[2..]))).ok();
+        }
+    }
+    banners
+}
+
+fn truncated<const N: usize>(s: &str) -> String<N> {
+    let mut out = String::new();
+    for c in s.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+fn put_str(buf: &mut [u8], s: &str) {
+    let n = s.len().min(buf.len());
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+}
+
+fn get_str<const N: usize>(buf: &[u8]) -> String<N> {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    truncated(core::str::from_utf8(&buf[..end]).unwrap_or(""))
+}
+
+#[derive(Clone, Copy)]
+struct IndexEntry {
+    key: HostKey,
+    slot: u16,
+}
+
+pub struct Inventory {
+    // Up to three entries per host, MAC, IPv4 and IPv6.
+    index: Vec<IndexEntry, { MAX_HOSTS * 3 }>,
+    // Records in the file, which is also the number of hosts.
+    slots: u16,
+    // Bit per slot, set for hosts seen since boot.
+    seen: [u32; MAX_HOSTS / 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Change {
+    New,
+    Updated,
+}
+
+impl Inventory {
+    // Reads the whole DB once to build the index.
+    pub fn load<S: embassy_rp::spi::Instance>(
+        storage: &mut SdStorage<'_, S>,
+    ) -> Result<Self, SdError> {
+        let mut inv = Self {
+            index: Vec::new(),
+            slots: 0,
+            seen: [0; MAX_HOSTS / 32],
+        };
+        storage.read_records::<RECORD_LEN>(DB_FILE, |slot, record| {
+            if let Some(host) = Host::decode(record) {
+                inv.slots = slot as u16 + 1;
+                inv.index_host(&host, slot as u16);
+            }
+        })?;
+        info!("inventory: {} hosts", inv.len());
+        Ok(inv)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots == 0
+    }
+
+    fn find(&self, key: HostKey) -> Option<u16> {
+        self.index.iter().find(|e| e.key == key).map(|e| e.slot)
+    }
+
+    fn index_host(&mut self, host: &Host, slot: u16) {
+        if let Some(mac) = host.mac {
+            self.set_key(HostKey::Mac(mac), slot);
+        }
+        for ip in host.addrs() {
+            self.set_key(HostKey::Ip(ip), slot);
+        }
+    }
+
+    // Drops `key` if it still points at `slot`, once that host has let go
+    // of the address.
+    fn remove_key(&mut self, key: HostKey, slot: u16) {
+        self.index.retain(|e| e.key != key || e.slot != slot);
+    }
+
+    // Later records win, so an IP that moved points at its newest owner.
+    fn set_key(&mut self, key: HostKey, slot: u16) {
+        if let Some(entry) = self.index.iter_mut().find(|e| e.key == key) {
+            entry.slot = slot;
+        } else if self.index.push(IndexEntry { key, slot }).is_err() {
+            warn!("inventory: index full, {} not indexed", key);
+        }
+    }
+
+    pub fn get<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        key: HostKey,
+    ) -> Result<Option<Host>, SdError> {
+        match self.find(key) {
+            Some(slot) => self.read_slot(storage, slot),
+            None => Ok(None),
+        }
+    }
+
+    fn read_slot<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        slot: u16,
+    ) -> Result<Option<Host>, SdError> {
+        let mut record = [0u8; RECORD_LEN];
+        let n = storage.read_at(DB_FILE, slot as u32 * RECORD_LEN as u32, &mut record)?;
+        Ok(if n == RECORD_LEN {
+            Host::decode(&record)
+        } else {
+            None
+        })
+    }
+
+    // Merges what we learned into the host's record, adding it if it's new.
+    pub fn upsert<S: embassy_rp::spi::Instance>(
+        &mut self,
+        storage: &mut SdStorage<'_, S>,
+        obs: &Observation<'_>,
+    ) -> Result<(Change, Host), SdError> {
+        let now = clock::now_unix() as u32;
+
+        // By MAC first. An IP-only match is the same host if it has no MAC
+        // yet; if it has a different MAC the IP has moved to a new host.
+        let mut slot = obs.mac.and_then(|mac| self.find(HostKey::Mac(mac)));
+        let mut existing = match slot {
+            Some(s) => self.read_slot(storage, s)?,
+            None => None,
+        };
+        if existing.is_none()
+            && let Some(ip) = obs.ip
+            && let Some(s) = self.find(HostKey::Ip(ip))
+            && let Some(host) = self.read_slot(storage, s)?
+            && (host.mac.is_none() || obs.mac.is_none() || host.mac == obs.mac)
+        {
+            slot = Some(s);
+            existing = Some(host);
+        }
+
+        let (change, slot, mut host) = match (slot, existing) {
+            (Some(slot), Some(host)) => (Change::Updated, slot, host),
+            _ => {
+                if self.slots as usize >= MAX_HOSTS {
+                    warn!("inventory: full, not adding {:?}", obs.ip);
+                    return Err(embedded_sdmmc::Error::DiskFull);
+                }
+                let slot = self.slots;
+                self.slots += 1;
+                (Change::New, slot, Host::new(now))
+            }
+        };
+
+        // The IP has moved here from another host, which doesn't have it
+        // any more.
+        if let Some(ip) = obs.ip
+            && let Some(prev) = self.find(HostKey::Ip(ip))
+            && prev != slot
+            && let Some(mut old) = self.read_slot(storage, prev)?
+        {
+            // Only if it's still that host's address; the index entry may
+            // be all that's left of it.
+            let had = match ip {
+                IpAddress::Ipv4(v4) => old.ip.take_if(|old| *old == v4).is_some(),
+                IpAddress::Ipv6(v6) => old.ip6.take_if(|old| *old == v6).is_some(),
+            };
+            if had {
+                storage.write_at(DB_FILE, prev as u32 * RECORD_LEN as u32, &old.encode())?;
+            }
+            debug!("inventory: {} moved from slot {} to {}", ip, prev, slot);
+        }
+
+        // A host that's been renumbered doesn't keep its old address.
+        let before = (host.ip, host.ip6);
+        host.merge(obs, now);
+        if let Some(ip) = before.0
+            && host.ip != before.0
+        {
+            self.remove_key(HostKey::Ip(ip.into()), slot);
+        }
+        if let Some(ip) = before.1
+            && host.ip6 != before.1
+        {
+            self.remove_key(HostKey::Ip(ip.into()), slot);
+        }
+        storage.write_at(DB_FILE, slot as u32 * RECORD_LEN as u32, &host.encode())?;
+        self.index_host(&host, slot);
+        self.seen[slot as usize / 32] |= 1 << (slot % 32);
+        if !obs.banners.is_empty() {
+            self.merge_banners(storage, slot, obs.banners)?;
+        }
+        debug!("inventory: {} slot {} ({:?})", change, slot, host.ip);
+        Ok((change, host))
+    }
+
+    pub fn ssh_key<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        key: HostKey,
+    ) -> Result<Option<SshKey>, SdError> {
+        let Some(slot) = self.find(key) else {
+            return Ok(None);
+        };
+        let mut record = [0u8; KEY_RECORD_LEN];
+        Ok(if read_side(storage, KEYS_FILE, slot, &mut record)? {
+            decode_key(&record)
+        } else {
+            None
+        })
+    }
+
+    // Stores the host's SSH key. Returns the key it had before if that was
+    // a different one.
+    pub fn set_ssh_key<S: embassy_rp::spi::Instance>(
+        &mut self,
+        storage: &mut SdStorage<'_, S>,
+        key: HostKey,
+        ssh: &SshKey,
+    ) -> Result<Option<SshKey>, SdError> {
+        let Some(slot) = self.find(key) else {
+            return Ok(None);
+        };
+        let old = self.ssh_key(storage, key)?;
+        if old.as_ref().is_some_and(|old| old.same_key(ssh)) {
+            return Ok(None);
+        }
+        write_side(storage, KEYS_FILE, slot, &encode_key(ssh))?;
+        Ok(old)
+    }
+
+    // What answered on each of the host's ports, as far as we know.
+    pub fn banners<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        key: HostKey,
+    ) -> Result<Banners, SdError> {
+        match self.find(key) {
+            Some(slot) => read_banners(storage, slot),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Newer banners replace older ones for the same port. Full means we
+    // keep the first twelve ports, like the host record.
+    fn merge_banners<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        slot: u16,
+        new: &[(u16, &str)],
+    ) -> Result<(), SdError> {
+        let mut banners = read_banners(storage, slot)?;
+        let before = banners.clone();
+        for &(port, banner) in new.iter().filter(|(_, b)| !b.is_empty()) {
+            let banner = truncated(banner);
+            match banners.iter_mut().find(|(p, _)| *p == port) {
+                Some(entry) => entry.1 = banner,
+                None => {
+                    banners.push((port, banner)).ok();
+                }
+            }
+        }
+        if banners != before {
+            write_side(storage, BANNERS_FILE, slot, &encode_banners(&banners))?;
+        }
+        Ok(())
+    }
+
+    // Forgets the host's open ports and their banners ahead of a rescan,
+    // so the ports that have closed since don't linger in the record.
+    pub fn clear_ports<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        key: HostKey,
+    ) -> Result<(), SdError> {
+        let Some(slot) = self.find(key) else {
+            return Ok(());
+        };
+        let Some(mut host) = self.read_slot(storage, slot)? else {
+            return Ok(());
+        };
+        if !host.ports.is_empty() {
+            host.ports.clear();
+            storage.write_at(DB_FILE, slot as u32 * RECORD_LEN as u32, &host.encode())?;
+        }
+        if !read_banners(storage, slot)?.is_empty() {
+            write_side(storage, BANNERS_FILE, slot, &encode_banners(&Vec::new()))?;
+        }
+        Ok(())
+    }
+
+    // Hosts seen since boot, by MAC if they have one.
+    pub fn seen_keys(&self) -> Vec<HostKey, MAX_HOSTS> {
+        let mut keys = Vec::new();
+        for slot in 0..self.slots {
+            if self.seen[slot as usize / 32] & (1 << (slot % 32)) == 0 {
+                continue;
+            }
+            let mut entries = self.index.iter().filter(|e| e.slot == slot);
+            let key = entries
+                .clone()
+                .find(|e| matches!(e.key, HostKey::Mac(_)))
+                .or_else(|| entries.next())
+                .map(|e| e.key);
+            if let Some(key) = key {
+                keys.push(key).ok();
+            }
+        }
+        keys
+    }
+
+    // IPs of hosts seen since boot, for scanning. Dual-stack hosts are in
+    // twice, once per address.
+    pub fn seen_ips(&self) -> Vec<IpAddress, MAX_HOSTS> {
+        let mut ips = Vec::new();
+        for entry in &self.index {
+            if let HostKey::Ip(ip) = entry.key
+                && self.seen[entry.slot as usize / 32] & (1 << (entry.slot % 32)) != 0
+            {
+                ips.push(ip).ok();
+            }
+        }
+        ips
+    }
+
+    // The host after `after` that we have a MAC for, in the order they were
+    // first seen, wrapping round to the first. For stepping through hosts
+    // to wake (see `wol`).
+    pub fn next_mac(&self, after: Option<[u8; 6]>) -> Option<[u8; 6]> {
+        let from = after.and_then(|mac| self.find(HostKey::Mac(mac)));
+        let macs = self.index.iter().filter_map(|e| match e.key {
+            HostKey::Mac(mac) => Some((e.slot, mac)),
+            HostKey::Ip(_) => None,
+        });
+        macs.clone()
+            .filter(|&(slot, _)| from.is_none_or(|from| slot > from))
+            .min_by_key(|&(slot, _)| slot)
+            .or_else(|| macs.min_by_key(|&(slot, _)| slot))
+            .map(|(_, mac)| mac)
+    }
+
+    // Every stored host, in the order they were first seen.
+    pub fn for_each<S: embassy_rp::spi::Instance>(
+        &self,
+        storage: &mut SdStorage<'_, S>,
+        mut f: impl FnMut(&Host),
+    ) -> Result<(), SdError> {
+        storage.read_records::<RECORD_LEN>(DB_FILE, |_, record| {
+            if let Some(host) = Host::decode(record) {
+                f(&host);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sd_storage::NoSpi;
+    use crate::smb::{Ntlm, OsVersion};
+
+    fn mac(last: u8) -> [u8; 6] {
+        [0x00, 0x11, 0x22, 0x33, 0x44, last]
+    }
+
+    fn v4(last: u8) -> IpAddress {
+        Ipv4Address::new(10, 0, 0, last).into()
+    }
+
+    fn see(
+        inv: &mut Inventory,
+        sd: &mut SdStorage<'_, NoSpi>,
+        mac: Option<[u8; 6]>,
+        ip: Option<IpAddress>,
+    ) -> Change {
+        let obs = Observation {
+            mac,
+            ip,
+            ..Default::default()
+        };
+        inv.upsert(sd, &obs).unwrap().0
+    }
+
+    // Everything set, every field as long as it can be.
+    fn full_host() -> Host {
+        let mac = [0xb8, 0x27, 0xeb, 0x01, 0x02, 0x03];
+        let mut host = Host::new(1_700_000_000);
+        host.mac = Some(mac);
+        host.vendor = truncated(oui::vendor(&mac));
+        host.ip = Some(Ipv4Address::new(192, 168, 1, 20));
+        host.ip6 = Some(Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x20));
+        host.last_seen = 1_700_086_400;
+        host.times_seen = 70_000;
+        for port in [22, 80, 443, 445, 3389, 8080, 8443, 502, 554, 21, 23, 65535] {
+            host.ports.push(port).unwrap();
+        }
+        host.hostname = truncated("build-server-01.lab.example.test");
+        host.services = truncated("ssh,http,https,smb,modbus");
+        host.last_boot = Some(1_699_000_000);
+        host.tick_hz = 1000;
+        host.smb1 = true;
+        host.smb_unsigned = true;
+        host.os_build = 19045;
+        host
+    }
+
+    #[test]
+    fn full_record_round_trip() {
+        let host = full_host();
+        assert_eq!(host.ports.len(), MAX_PORTS);
+        assert_eq!(host.hostname.len(), 32);
+        assert_eq!(host.services.len(), 24);
+
+        let buf = host.encode();
+        assert_eq!(buf[0], MAGIC);
+        assert_eq!(
+            buf[1],
+            FLAG_MAC | FLAG_IP | FLAG_IP6 | FLAG_SMB1 | FLAG_SMB_UNSIGNED
+        );
+        assert_eq!(&buf[24..26], &22u16.to_be_bytes());
+        assert_eq!(&buf[46..48], &65535u16.to_be_bytes());
+        assert_eq!(Host::decode(&buf), Some(host));
+    }
+
+    #[test]
+    fn sparse_record_round_trip() {
+        let mut host = Host::new(5);
+        host.ip6 = Some(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        host.ports.push(443).unwrap();
+        let buf = host.encode();
+        assert_eq!(buf[1], FLAG_IP6);
+        assert_eq!(&buf[2..12], &[0; 10]);
+        assert_eq!(&buf[120..124], &[0; 4]);
+
+        let back = Host::decode(&buf).unwrap();
+        assert_eq!(back, host);
+        assert_eq!(back.mac, None);
+        assert_eq!(back.ip, None);
+        assert_eq!(back.last_boot, None);
+        assert_eq!(back.key(), Some(HostKey::Ip(host.ip6.unwrap().into())));
+    }
+
+    #[test]
+    fn unused_slots_decode_to_nothing() {
+        assert_eq!(Host::decode(&[0; RECORD_LEN]), None);
+        let mut buf = full_host().encode();
+        buf[0] = 0xFF;
+        assert_eq!(Host::decode(&buf), None);
+    }
+
+    #[test]
+    fn merge_keeps_what_it_has() {
+        let mut host = Host::new(100);
+        let ports: Vec<(u16, Option<&str>), 16> = (1..=14)
+            .map(|p| (p, None))
+            .chain([(22, Some("ssh")), (80, Some("http"))])
+            .collect();
+        host.merge(
+            &Observation {
+                mac: Some(mac(1)),
+                ip: Some(v4(1)),
+                hostname: Some("nas"),
+                ports: &ports,
+                services: &["mdns", "mdns", "a-service-name-too-long-to-fit"],
+                ..Default::default()
+            },
+            200,
+        );
+        // The first twelve ports, no repeats; later services are still
+        // recorded even once the ports are full.
+        assert_eq!(
+            host.ports.as_slice(),
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+        );
+        assert_eq!(host.services.as_str(), "ssh,http,mdns");
+        assert_eq!(host.hostname.as_str(), "nas");
+        assert_eq!(host.vendor.as_str(), oui::vendor(&mac(1)));
+        assert_eq!(
+            (host.first_seen, host.last_seen, host.times_seen),
+            (100, 200, 1)
+        );
+
+        // An empty hostname or no address leaves the old ones alone.
+        host.merge(
+            &Observation {
+                hostname: Some(""),
+                uptime: Some(Uptime { hz: 250, secs: 50 }),
+                ..Default::default()
+            },
+            300,
+        );
+        assert_eq!(host.hostname.as_str(), "nas");
+        assert_eq!(host.ip, Some(Ipv4Address::new(10, 0, 0, 1)));
+        assert_eq!(host.last_boot, Some(250));
+        assert_eq!(host.tick_hz, 250);
+        assert_eq!(host.times_seen, 2);
+    }
+
+    #[test]
+    fn merge_smb() {
+        let smb = SmbInfo {
+            smb1: true,
+            signing_required: Some(false),
+            ntlm: Ntlm {
+                netbios_name: truncated("FILESRV"),
+                os: Some(OsVersion {
+                    major: 10,
+                    minor: 0,
+                    build: 17763,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut host = Host::new(0);
+        let obs = Observation {
+            smb: Some(&smb),
+            ..Default::default()
+        };
+        host.merge(&obs, 1);
+        assert!(host.smb1 && host.smb_unsigned);
+        assert_eq!(host.os_build, 17763);
+        assert_eq!(host.hostname.as_str(), "FILESRV");
+        assert!(host.has_service("smb"));
+
+        let back = Host::decode(&host.encode()).unwrap();
+        assert!(back.smb1 && back.smb_unsigned);
+        assert_eq!(back.os_build, 17763);
+    }
+
+    #[test]
+    fn parses_scan_hosts() {
+        let hosts: Vec<IpAddress, 4> =
+            parse_hosts(" 10.0.0.1, ,fe80::1,nonsense,10.0.0.300").collect();
+        assert_eq!(
+            hosts.as_slice(),
+            &[v4(1), Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).into()]
+        );
+        assert_eq!(parse_hosts("").count(), 0);
+        // Nothing in SCAN_HOSTS for the host build.
+        assert_eq!(configured_hosts().count(), 0);
+    }
+
+    #[test]
+    fn upsert_joins_ip_and_mac() {
+        let mut sd = SdStorage::default();
+        let mut inv = Inventory::load(&mut sd).unwrap();
+
+        // Seen by IP first, then ARP ties a MAC to it.
+        assert_eq!(see(&mut inv, &mut sd, None, Some(v4(5))), Change::New);
+        assert_eq!(
+            see(&mut inv, &mut sd, Some(mac(5)), Some(v4(5))),
+            Change::Updated
+        );
+        assert_eq!(inv.len(), 1);
+        let host = inv.get(&mut sd, HostKey::Ip(v4(5))).unwrap().unwrap();
+        assert_eq!(host.mac, Some(mac(5)));
+        assert_eq!(host.times_seen, 2);
+
+        // The index comes back the same from the card.
+        let inv = Inventory::load(&mut sd).unwrap();
+        assert_eq!(inv.len(), 1);
+        let again = inv.get(&mut sd, HostKey::Mac(mac(5))).unwrap();
+        assert_eq!(again, Some(host));
+    }
+
+    #[test]
+    fn upsert_moves_ip_to_new_owner() {
+        let mut sd = SdStorage::default();
+        let mut inv = Inventory::load(&mut sd).unwrap();
+        see(&mut inv, &mut sd, Some(mac(1)), Some(v4(7)));
+        // The lease goes to another machine.
+        assert_eq!(
+            see(&mut inv, &mut sd, Some(mac(2)), Some(v4(7))),
+            Change::New
+        );
+        assert_eq!(inv.len(), 2);
+
+        let old = inv.get(&mut sd, HostKey::Mac(mac(1))).unwrap().unwrap();
+        assert_eq!(old.ip, None);
+        let new = inv.get(&mut sd, HostKey::Ip(v4(7))).unwrap().unwrap();
+        assert_eq!(new.mac, Some(mac(2)));
+
+        // Only the new owner has it after a reload too.
+        let inv = Inventory::load(&mut sd).unwrap();
+        let mut owners = 0;
+        inv.for_each(&mut sd, |h| {
+            owners += (h.ip == Some(Ipv4Address::new(10, 0, 0, 7))) as u32
+        })
+        .unwrap();
+        assert_eq!(owners, 1);
+        assert_eq!(inv.seen_ips().len(), 0);
+    }
+
+    #[test]
+    fn upsert_renumbers_then_old_ip_moves() {
+        let mut sd = SdStorage::default();
+        let mut inv = Inventory::load(&mut sd).unwrap();
+        see(&mut inv, &mut sd, Some(mac(1)), Some(v4(7)));
+        // A gets a new lease; the old address isn't its any more.
+        assert_eq!(
+            see(&mut inv, &mut sd, Some(mac(1)), Some(v4(8))),
+            Change::Updated
+        );
+        assert_eq!(inv.seen_ips().as_slice(), &[v4(8)]);
+        assert!(inv.get(&mut sd, HostKey::Ip(v4(7))).unwrap().is_none());
+
+        // B picks up A's old address, which mustn't cost A its new one.
+        assert_eq!(
+            see(&mut inv, &mut sd, Some(mac(2)), Some(v4(7))),
+            Change::New
+        );
+        let a = inv.get(&mut sd, HostKey::Mac(mac(1))).unwrap().unwrap();
+        assert_eq!(a.ip, Some(Ipv4Address::new(10, 0, 0, 8)));
+        let b = inv.get(&mut sd, HostKey::Ip(v4(7))).unwrap().unwrap();
+        assert_eq!(b.mac, Some(mac(2)));
+        let ips = inv.seen_ips();
+        assert_eq!(ips.len(), 2);
+        assert!(ips.contains(&v4(7)) && ips.contains(&v4(8)));
+    }
+
+    #[test]
+    fn banners_per_port() {
+        let mut sd = SdStorage::default();
+        let mut inv = Inventory::load(&mut sd).unwrap();
+        // Only the second host has banners, so the file has a gap to fill.
+        see(&mut inv, &mut sd, Some(mac(1)), Some(v4(1)));
+        let long = "SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u3 and then some";
+        let obs = Observation {
+            ip: Some(v4(2)),
+            banners: &[(22, long), (21, "vsFTPd 3.0.3"), (80, "")],
+            ..Default::default()
+        };
+        inv.upsert(&mut sd, &obs).unwrap();
+        let obs = Observation {
+            ip: Some(v4(2)),
+            banners: &[(21, "ProFTPD 1.3.8")],
+            ..Default::default()
+        };
+        inv.upsert(&mut sd, &obs).unwrap();
+
+        let banners = inv.banners(&mut sd, HostKey::Ip(v4(2))).unwrap();
+        assert_eq!(banners.len(), 2);
+        assert_eq!(banners[0].0, 22);
+        assert_eq!(banners[0].1.as_str(), &long[..BANNER_LEN]);
+        assert_eq!(banners[1], (21, truncated("ProFTPD 1.3.8")));
+        assert_eq!(
+            inv.banners(&mut sd, HostKey::Ip(v4(1))).unwrap(),
+            Banners::new()
+        );
+        assert_eq!(
+            sd.files[BANNERS_FILE].len(),
+            2 * BANNER_RECORD_LEN,
+            "slot 0 padded, slot 1 written"
+        );
+    }
+
+    #[test]
+    fn steps_through_hosts_with_macs() {
+        let mut sd = SdStorage::default();
+        let mut inv = Inventory::load(&mut sd).unwrap();
+        assert_eq!(inv.next_mac(None), None);
+
+        see(&mut inv, &mut sd, Some(mac(1)), None);
+        see(&mut inv, &mut sd, None, Some(v4(9)));
+        see(&mut inv, &mut sd, Some(mac(2)), None);
+
+        assert_eq!(inv.next_mac(None), Some(mac(1)));
+        assert_eq!(inv.next_mac(Some(mac(1))), Some(mac(2)));
+        assert_eq!(inv.next_mac(Some(mac(2))), Some(mac(1)));
+        // A MAC we've never seen starts from the top.
+        assert_eq!(inv.next_mac(Some(mac(9))), Some(mac(1)));
+    }
+}
@@ -2,6 +2,43 @@
 
 use defmt::*;
 
+pub mod arp_guard;
+pub mod arp_tap;
+pub mod ble;
+pub mod ble_adv;
+pub mod camera;
+pub mod channels;
+pub mod clock;
+pub mod dns_audit;
+pub mod dns_wire;
+pub mod exposure;
 pub mod fat_utils;
+pub mod health;
+pub mod industrial;
+pub mod inventory;
+pub mod ndp;
+pub mod netif;
+pub mod oled;
+pub mod oui;
+pub mod perf;
+pub mod pmtu;
+pub mod portmap;
+pub mod rogue_ap;
+pub mod scan_diff;
 pub mod sd_spi;
 pub mod sd_storage;
+pub mod services;
+pub mod session;
+pub mod sha256;
+pub mod site_survey;
+pub mod smb;
+pub mod sntp;
+pub mod ssh;
+pub mod survey;
+pub mod syslog;
+pub mod uptime;
+pub mod util;
+pub mod vulns;
+pub mod wifi_scan;
+pub mod wiznet;
+pub mod wol;
@@ -1,19 +1,21 @@
 #![no_std]
 #![no_main]
 
+use bt_hci::controller::ExternalController;
 use core::fmt::Write;
 use cyw43::Control;
 use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
 use defmt::{debug, error, info, warn};
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either4, select4};
 use embassy_net::DhcpConfig;
-use embassy_net::{self, Config, Runner, Stack, StackResources};
+use embassy_net::{self, Config, ConfigV6, IpAddress, Runner, Stack, StackResources};
 use embassy_rp::bind_interrupts;
-use embassy_rp::gpio::{Level, Output};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
 use embassy_rp::i2c::{self, I2c};
 use embassy_rp::peripherals::{DMA_CH0, PIO0};
 use embassy_rp::pio::{InterruptHandler, Pio};
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     mono_font::{MonoTextStyle, ascii::FONT_6X10},
     pixelcolor::BinaryColor,
@@ -21,6 +23,37 @@ use embedded_graphics::{
     text::Text,
 };
 use heapless::String;
+use picomap::arp_guard;
+use picomap::arp_tap::{ArpTap, ArpTapState};
+use picomap::ble::{self, BleController};
+use picomap::ble_adv::BleInventory;
+use picomap::camera;
+use picomap::clock;
+use picomap::dns_audit::{self, DnsAuditConfig};
+use picomap::exposure;
+use picomap::health::{self, HealthConfig};
+use picomap::industrial;
+use picomap::inventory::{self, HostKey, Inventory, Observation};
+use picomap::ndp;
+use picomap::netif::NetInterfaces;
+use picomap::oled;
+use picomap::perf;
+use picomap::portmap;
+use picomap::rogue_ap::{self, RogueWatch};
+use picomap::sd_storage::SdStorage;
+use picomap::services::{self, Proto};
+use picomap::session;
+use picomap::site_survey;
+use picomap::smb;
+use picomap::sntp;
+use picomap::ssh;
+use picomap::survey;
+use picomap::syslog;
+use picomap::uptime;
+use picomap::wifi_scan;
+#[cfg(feature = "wiznet")]
+use picomap::wiznet;
+use picomap::wol;
 use ssd1306::mode::BufferedGraphicsMode;
 use ssd1306::{I2CDisplayInterface, Ssd1306, prelude::*};
 use static_cell::StaticCell;
@@ -30,9 +63,32 @@ bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => InterruptHandler<PIO0>;
 });
 
-static NET_STACK: StaticCell<Stack> = StaticCell::new();
-static NET_RESOURCES: StaticCell<StackResources<2>> = StaticCell::new();
-static NET_RUNNER: StaticCell<Runner<cyw43::NetDriver<'static>>> = StaticCell::new();
+// One set of stack resources per interface. WiFi is used unless built with
+// `wiznet` alone; the W5500 is used with `wiznet` or `dual`.
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+static WIFI_RESOURCES: StaticCell<StackResources<8>> = StaticCell::new();
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+static WIFI_RUNNER: StaticCell<Runner<ArpTap<cyw43::NetDriver<'static>>>> = StaticCell::new();
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+static WIFI_ARP: ArpTapState = ArpTapState::new();
+
+#[cfg(feature = "wiznet")]
+static WIRED_RESOURCES: StaticCell<StackResources<8>> = StaticCell::new();
+#[cfg(feature = "wiznet")]
+static WIRED_RUNNER: StaticCell<Runner<ArpTap<wiznet::WiznetDevice>>> = StaticCell::new();
+#[cfg(feature = "wiznet")]
+static WIRED_ARP: ArpTapState = ArpTapState::new();
+
+static BLE_CONTROLLER: StaticCell<BleController> = StaticCell::new();
+
+const BLE_SCAN_TIME: Duration = Duration::from_secs(10);
+const NTP_WAIT: Duration = Duration::from_secs(5);
+// How long ARP gets to fill the inventory before we check those hosts for
+// exposed services and uptime, save this boot's scan session and diff it
+// against the last one.
+const SESSION_TIME: Duration = Duration::from_secs(120);
+// How often to scan for rogue APs, when KNOWN_APS is set.
+const ROGUE_SCAN_TIME: Duration = Duration::from_secs(30);
 
 #[embassy_executor::task]
 async fn cyw43_task(
@@ -42,12 +98,106 @@ async fn cyw43_task(
     runner.run().await
 }
 
+#[cfg(feature = "wiznet")]
 #[embassy_executor::task]
-async fn network_task(runner: &'static mut Runner<'static, cyw43::NetDriver<'static>>) -> ! {
-    debug!("network_task running");
+async fn wiznet_task(runner: wiznet::WiznetRunner) -> ! {
+    debug!("wiznet_task running");
     runner.run().await
 }
 
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+#[embassy_executor::task]
+async fn wifi_network_task(
+    runner: &'static mut Runner<'static, ArpTap<cyw43::NetDriver<'static>>>,
+) -> ! {
+    debug!("wifi_network_task running");
+    runner.run().await
+}
+
+#[cfg(feature = "wiznet")]
+#[embassy_executor::task]
+async fn wired_network_task(
+    runner: &'static mut Runner<'static, ArpTap<wiznet::WiznetDevice>>,
+) -> ! {
+    debug!("wired_network_task running");
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn arp_watch_task(tap: &'static ArpTapState, own_ip: embassy_net::Ipv4Address) -> ! {
+    debug!("arp_watch_task running");
+    arp_guard::watch(tap, own_ip).await
+}
+
+#[embassy_executor::task]
+async fn ndp_watch_task(stack: Stack<'static>, tap: &'static ArpTapState) -> ! {
+    debug!("ndp_watch_task running");
+    ndp::watch(stack, tap).await
+}
+
+// Throughput tests measure our own WiFi, which is the mgmt interface.
+#[embassy_executor::task]
+async fn perf_server_task(stack: Stack<'static>) -> ! {
+    debug!("perf_server_task running");
+    perf::server(stack).await
+}
+
+#[embassy_executor::task]
+async fn waypoint_button_task(button: Input<'static>) -> ! {
+    debug!("waypoint_button_task running");
+    site_survey::watch_button(button).await
+}
+
+#[embassy_executor::task]
+async fn wol_button_task(button: Input<'static>) -> ! {
+    debug!("wol_button_task running");
+    wol::watch_button(button).await
+}
+
+// Time sync is management traffic, so it runs on the mgmt stack.
+#[embassy_executor::task]
+async fn sntp_task(stack: Stack<'static>) -> ! {
+    debug!("sntp_task running");
+    sntp::run(stack).await
+}
+
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+fn wifi_stack(spawner: &Spawner, device: cyw43::NetDriver<'static>) -> Stack<'static> {
+    let device = ArpTap::new(device, &WIFI_ARP);
+    let mut config = Config::dhcpv4(DhcpConfig::default());
+    config.ipv6 = ConfigV6::Static(ndp::static_config(WIFI_ARP.mac()));
+    let resources = WIFI_RESOURCES.init(StackResources::<8>::new());
+    let (stack, runner) = embassy_net::new(
+        device,
+        config,
+        resources,
+        embassy_time::Instant::now().as_ticks(),
+    );
+    spawner
+        .spawn(wifi_network_task(WIFI_RUNNER.init(runner)))
+        .unwrap();
+    stack
+}
+
+#[cfg(feature = "wiznet")]
+fn wired_stack(spawner: &Spawner, device: wiznet::WiznetDevice) -> Stack<'static> {
+    let device = ArpTap::new(device, &WIRED_ARP);
+    let mut config = Config::dhcpv4(DhcpConfig::default());
+    config.ipv6 = ConfigV6::Static(ndp::static_config(WIRED_ARP.mac()));
+    let resources = WIRED_RESOURCES.init(StackResources::<8>::new());
+    // Different seed from the WiFi stack so the two don't pick the same ports.
+    let (stack, runner) = embassy_net::new(
+        device,
+        config,
+        resources,
+        embassy_time::Instant::now().as_ticks() ^ 0x5a5a_5a5a,
+    );
+    spawner
+        .spawn(wired_network_task(WIRED_RUNNER.init(runner)))
+        .unwrap();
+    stack
+}
+
 async fn blink(control: &mut Control<'_>, num_blinks: usize, delay_ms: u64) {
     debug!("blink running: {} with {}", num_blinks, delay_ms);
     let delay = Duration::from_millis(delay_ms);
@@ -60,6 +210,7 @@ async fn blink(control: &mut Control<'_>, num_blinks: usize, delay_ms: u64) {
     }
 }
 
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
 async fn wifi_join_with_retries<'a, IFACE: ssd1306::prelude::WriteOnlyDataCommand>(
     control: &mut cyw43::Control<'_>,
     ssid: &str,
@@ -107,6 +258,574 @@ async fn wifi_join_with_retries<'a, IFACE: ssd1306::prelude::WriteOnlyDataComman
     }
 }
 
+#[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+async fn join_wifi<'a, IFACE: ssd1306::prelude::WriteOnlyDataCommand>(
+    control: &mut cyw43::Control<'_>,
+    display: &mut Ssd1306<IFACE, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>,
+    style: MonoTextStyle<'a, BinaryColor>,
+) {
+    const SSID: &str = env!("WIFISSID");
+    const PASSWORD: &str = env!("WIFIPASS");
+
+    let mut out: String<64> = String::new();
+    write!(&mut out, "SSID: {}", SSID).unwrap();
+    Text::new(&out, Point::new(0, 10), style)
+        .draw(display)
+        .unwrap();
+    Text::new("Connecting...", Point::new(0, 20), style)
+        .draw(display)
+        .unwrap();
+    display.flush().unwrap();
+
+    // blink 3 times in 1 second (333ms)
+    blink(control, 3, 333).await;
+
+    wifi_join_with_retries(control, SSID, PASSWORD, display, style).await;
+
+    // Otherwise, success!
+    // blink 10 times in 1 second (100ms)
+    blink(control, 10, 100).await;
+    info!("wifi connected!");
+
+    Text::new("Connected!", Point::new(0, 30), style)
+        .draw(display)
+        .unwrap();
+    display.flush().unwrap();
+}
+
+fn show_conflict<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+    conflict: &arp_guard::Conflict,
+) {
+    let mut ip: String<21> = String::new();
+    write!(&mut ip, "{}", conflict.ip).unwrap();
+    let mut a: String<21> = String::new();
+    write!(&mut a, "{}", arp_guard::Mac(&conflict.mac_a)).unwrap();
+    let mut b: String<21> = String::new();
+    write!(&mut b, "{}", arp_guard::Mac(&conflict.mac_b)).unwrap();
+    oled::show_lines(
+        display,
+        &[
+            "!! IP CONFLICT !!",
+            &ip,
+            &a,
+            conflict.vendor_a(),
+            &b,
+            conflict.vendor_b(),
+        ],
+    );
+
+    if let Some(sd) = storage.as_mut() {
+        let mut line: String<128> = String::new();
+        write!(&mut line, "{}", conflict).ok();
+        sd.log_event(&line).ok();
+    }
+}
+
+fn show_rogue<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+    alert: &rogue_ap::Alert,
+) {
+    let mut bssid: String<21> = String::new();
+    write!(&mut bssid, "{}", arp_guard::Mac(&alert.bssid)).unwrap();
+    let mut signal: String<21> = String::new();
+    write!(&mut signal, "ch {} {}dBm", alert.channel, alert.rssi).unwrap();
+    oled::show_lines(
+        display,
+        &[
+            "!! ROGUE AP !!",
+            &alert.ssid,
+            &bssid,
+            alert.reason.as_str(),
+            &signal,
+        ],
+    );
+
+    let mut line: String<128> = String::new();
+    write!(&mut line, "{}", alert).ok();
+    warn!("{}", line.as_str());
+    if let Some(sd) = storage.as_mut() {
+        sd.log_event(&line).ok();
+    }
+}
+
+// A short press selects the next inventory host with a MAC, a long one
+// wakes the selected host.
+async fn wol_press<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+    inventory: &Option<Inventory>,
+    stack: Stack<'_>,
+    selected: &mut Option<[u8; 6]>,
+    press: wol::Press,
+) {
+    let (Some(sd), Some(inv)) = (storage.as_mut(), inventory.as_ref()) else {
+        oled::show_lines(display, &["Wake-on-LAN", "no inventory"]);
+        return;
+    };
+    if press == wol::Press::Short || selected.is_none() {
+        *selected = inv.next_mac(*selected);
+    }
+    let Some(target) = *selected else {
+        oled::show_lines(display, &["Wake-on-LAN", "no hosts with MAC"]);
+        return;
+    };
+    let host = inv.get(sd, HostKey::Mac(target)).ok().flatten();
+    let mut mac: String<21> = String::new();
+    write!(&mut mac, "{}", arp_guard::Mac(&target)).unwrap();
+    let mut ip: String<40> = String::new();
+    if let Some(addr) = host.as_ref().and_then(|h| h.addrs().next()) {
+        write!(&mut ip, "{}", addr).ok();
+    }
+    let name = host.as_ref().map_or("", |h| h.hostname.as_str());
+
+    if press == wol::Press::Short {
+        oled::show_lines(display, &["Wake-on-LAN", &mac, &ip, name, "hold to wake"]);
+        return;
+    }
+    let status = match wol::send(stack, target, wol::password()).await {
+        Ok(()) => "sent",
+        Err(e) => {
+            warn!("wol: send failed: {}", e);
+            "send failed"
+        }
+    };
+    oled::show_lines(display, &["Wake-on-LAN", &mac, &ip, name, status]);
+    let mut line: String<128> = String::new();
+    write!(&mut line, "WoL {} {} {}", mac, ip, status).ok();
+    sd.log_event(&line).ok();
+}
+
+// Checks the hosts seen so far for risky services. Needs the inventory for
+// the host list. `findings` is kept for the later checks to add to.
+async fn check_exposure<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+    findings: &mut exposure::Findings,
+) {
+    let hosts = inv.seen_ips();
+    info!("Checking {} hosts for exposed services...", hosts.len());
+    // This is the first check to look at ports, so start each host from
+    // nothing and the session only has what's open now. The later checks
+    // add back the ports they find.
+    for ip in &hosts {
+        if let Err(e) = inv.clear_ports(sd, HostKey::Ip(*ip)) {
+            warn!(
+                "inventory: clearing ports of {} failed: {:?}",
+                ip,
+                defmt::Debug2Format(&e)
+            );
+        }
+    }
+    let top = exposure::top_ports_from_env();
+    *findings = exposure::run(stack, &hosts, top, |ip, port, banner| {
+        let obs = Observation {
+            ip: Some(ip),
+            ports: &[(port, services::name(Proto::Tcp, port))],
+            banners: &[(port, banner)],
+            ..Default::default()
+        };
+        update(sd, inv, &obs);
+    })
+    .await;
+    export_failed("findings", exposure::export(sd, findings));
+    for finding in &findings.list {
+        log_finding(sd, finding);
+    }
+
+    let lines = findings.lines();
+    let lines: heapless::Vec<&str, 6> = lines.iter().map(|l| l.as_str()).collect();
+    oled::show_lines(display, &lines);
+    // Leave it up before the session diff replaces it.
+    Timer::after_secs(5).await;
+}
+
+// Adds what a check found about a host to the inventory.
+fn update<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    obs: &Observation,
+) {
+    if let Err(e) = inv.upsert(sd, obs) {
+        warn!("inventory update failed: {:?}", defmt::Debug2Format(&e));
+    }
+}
+
+// `update`, plus a line in the event log.
+fn record<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    obs: &Observation,
+    event: core::fmt::Arguments,
+) {
+    update(sd, inv, obs);
+    let mut line: String<160> = String::new();
+    line.write_fmt(event).ok();
+    sd.log_event(&line).ok();
+}
+
+// Warns if a CSV export failed; the checks carry on either way.
+fn export_failed<E: core::fmt::Debug>(what: &str, result: Result<(), E>) {
+    if let Err(e) = result {
+        warn!("{} export failed: {:?}", what, defmt::Debug2Format(&e));
+    }
+}
+
+fn log_finding<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    finding: &exposure::Finding,
+) {
+    let mut line: String<160> = String::new();
+    write!(&mut line, "Finding: {}", finding).ok();
+    sd.log_event(&line).ok();
+}
+
+// Adds a finding for a banner with known CVEs, and re-exports. For
+// banners found after `check_exposure`.
+fn add_outdated<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    findings: &mut exposure::Findings,
+    host: IpAddress,
+    port: u16,
+    banner: &str,
+) {
+    let Some(finding) = exposure::outdated(host, port, banner) else {
+        return;
+    };
+    warn!("{}", defmt::Display2Format(&finding));
+    log_finding(sd, &finding);
+    findings.add(finding);
+    export_failed("findings", exposure::export(sd, findings));
+}
+
+// Estimates how long hosts with an open port have been up, from their TCP
+// timestamps. Runs after `check_exposure` so there are ports to try.
+async fn check_uptime<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+) {
+    let mut targets: heapless::Vec<(IpAddress, u16), { uptime::MAX_TARGETS }> =
+        heapless::Vec::new();
+    for ip in inv.seen_ips() {
+        if let Ok(Some(host)) = inv.get(sd, HostKey::Ip(ip))
+            && let Some(&port) = host.ports.first()
+            && targets.push((ip, port)).is_err()
+        {
+            break;
+        }
+    }
+    if targets.is_empty() {
+        return;
+    }
+    info!("Estimating uptime of {} hosts...", targets.len());
+    let now = clock::now_unix() as u32;
+    for (ip, up) in uptime::probe(stack, &targets).await {
+        let obs = Observation {
+            ip: Some(ip),
+            uptime: Some(up),
+            ..Default::default()
+        };
+        let boot = up.boot(now) as i64 + clock::tz_offset_minutes() as i64 * 60;
+        let booted = clock::DateTime::from_unix(boot);
+        record(
+            sd,
+            inv,
+            &obs,
+            format_args!("Uptime {} {}, booted {}", ip, up, booted),
+        );
+    }
+}
+
+// Asks hosts with 445 open what SMB they speak and what they call
+// themselves. Runs after `check_exposure`, which finds the open ports.
+async fn check_smb<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+) {
+    let mut targets: heapless::Vec<IpAddress, { smb::MAX_HOSTS }> = heapless::Vec::new();
+    for ip in inv.seen_ips() {
+        if let Ok(Some(host)) = inv.get(sd, HostKey::Ip(ip))
+            && host.ports.contains(&smb::PORT)
+            && targets.push(ip).is_err()
+        {
+            break;
+        }
+    }
+    if targets.is_empty() {
+        return;
+    }
+    info!("Probing SMB on {} hosts...", targets.len());
+    let mut results: heapless::Vec<(IpAddress, smb::SmbInfo), { smb::MAX_HOSTS }> =
+        heapless::Vec::new();
+    for &ip in &targets {
+        let Some(info) = smb::probe(stack, ip).await else {
+            continue;
+        };
+        let mut banner: String<64> = String::new();
+        write!(&mut banner, "{}", info).ok();
+        let obs = Observation {
+            ip: Some(ip),
+            smb: Some(&info),
+            banners: &[(smb::PORT, &banner)],
+            ..Default::default()
+        };
+        record(sd, inv, &obs, format_args!("SMB {} {}", ip, info));
+        results.push((ip, info)).ok();
+    }
+    export_failed("SMB", smb::export(sd, &results));
+}
+
+// Collects SSH host keys from hosts with an SSH port open, and flags any
+// that changed since the last time we saw them, and outdated versions.
+async fn check_ssh<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+    findings: &mut exposure::Findings,
+) {
+    let mut targets: heapless::Vec<(HostKey, IpAddress, u16), { ssh::MAX_HOSTS }> =
+        heapless::Vec::new();
+    for ip in inv.seen_ips() {
+        if let Ok(Some(host)) = inv.get(sd, HostKey::Ip(ip))
+            && let Some(key) = host.key()
+            && !targets.iter().any(|t| t.0 == key)
+            && let Some(&port) = ssh::PORTS.iter().find(|p| host.ports.contains(p))
+            && targets.push((key, ip, port)).is_err()
+        {
+            break;
+        }
+    }
+    if targets.is_empty() {
+        return;
+    }
+    info!("Collecting SSH host keys from {} hosts...", targets.len());
+    for &(key, ip, port) in &targets {
+        let Some(server) = ssh::probe(stack, ip, port).await else {
+            continue;
+        };
+        let obs = Observation {
+            ip: Some(ip),
+            banners: &[(port, &server.version)],
+            ..Default::default()
+        };
+        update(sd, inv, &obs);
+        add_outdated(sd, findings, ip, port, &server.version);
+        let Some(ssh_key) = server.key else {
+            continue;
+        };
+        debug!("ssh: {} {}", ip, defmt::Display2Format(&ssh_key));
+        match inv.set_ssh_key(sd, key, &ssh_key) {
+            Ok(Some(old)) => {
+                let mut line: String<160> = String::new();
+                write!(
+                    &mut line,
+                    "SSH host key changed {} ({}): {} -> {}",
+                    key, ip, old, ssh_key
+                )
+                .ok();
+                warn!("{}", line.as_str());
+                sd.log_event(&line).ok();
+            }
+            Ok(None) => {}
+            Err(e) => warn!("SSH key update failed: {:?}", defmt::Debug2Format(&e)),
+        }
+    }
+}
+
+// Asks Modbus, EtherNet/IP and BACnet devices who they are. Only with
+// the `ot` scan profile.
+async fn check_industrial<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+) {
+    let hosts = inv.seen_ips();
+    info!("Identifying OT devices on {} hosts...", hosts.len());
+    let devices = industrial::run(stack, &hosts).await;
+    for device in &devices {
+        let name = device.protocol.as_str();
+        let port = device.protocol.tcp_port().map(|port| (port, Some(name)));
+        let mut identity: String<96> = String::new();
+        write!(&mut identity, "{}", device.id).ok();
+        let banner = device
+            .protocol
+            .tcp_port()
+            .map(|port| (port, identity.as_str()));
+        let obs = Observation {
+            ip: Some(device.host),
+            ports: port.as_slice(),
+            services: &[name],
+            banners: banner.as_slice(),
+            ..Default::default()
+        };
+        record(sd, inv, &obs, format_args!("OT {}", device));
+    }
+    export_failed("OT", industrial::export(sd, &devices));
+}
+
+// Finds IP cameras by ONVIF discovery and RTSP, and tags them in the
+// inventory. RTSP servers with known CVEs are added to `findings`.
+async fn check_cameras<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+    findings: &mut exposure::Findings,
+) {
+    let hosts = inv.seen_ips();
+    info!("Looking for cameras on {} hosts...", hosts.len());
+    let cameras = camera::run(stack, &hosts).await;
+    for cam in &cameras {
+        let port = cam.rtsp.as_ref().map(|r| (r.port, Some("rtsp")));
+        let server = cam.rtsp.as_ref().map(|r| (r.port, r.server.as_str()));
+        let services: &[&str] = if cam.onvif.is_some() {
+            &["camera", "onvif"]
+        } else {
+            &["camera"]
+        };
+        let obs = Observation {
+            ip: Some(cam.host),
+            ports: port.as_slice(),
+            services,
+            banners: server.as_slice(),
+            ..Default::default()
+        };
+        record(sd, inv, &obs, format_args!("Camera {}", cam));
+        if let Some(rtsp) = &cam.rtsp {
+            add_outdated(sd, findings, cam.host, rtsp.port, &rtsp.server);
+        }
+    }
+    export_failed("camera", camera::export(sd, &cameras));
+}
+
+// Asks the gateway for its external address over NAT-PMP/PCP and lists
+// the port mappings UPnP has opened on it.
+async fn check_gateway<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+) {
+    info!("Auditing gateway port mappings...");
+    let Some(audit) = portmap::run(stack).await else {
+        return;
+    };
+    let mut services: heapless::Vec<&str, 3> = heapless::Vec::new();
+    for (found, name) in [
+        (audit.natpmp, "nat-pmp"),
+        (audit.pcp, "pcp"),
+        (audit.igd.is_some(), "upnp-igd"),
+    ] {
+        if found {
+            services.push(name).ok();
+        }
+    }
+    let obs = Observation {
+        ip: Some(IpAddress::Ipv4(audit.gateway)),
+        services: &services,
+        ..Default::default()
+    };
+    record(sd, inv, &obs, format_args!("Gateway {}", audit));
+    let mut line: String<160> = String::new();
+    for mapping in &audit.mappings {
+        line.clear();
+        write!(&mut line, "UPnP mapping {}", mapping).ok();
+        sd.log_event(&line).ok();
+    }
+    export_failed("port mapping", portmap::export(sd, &audit.mappings));
+}
+
+// Exports the DNS audit and marks the servers in the inventory.
+fn save_dns_audit<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inventory: Option<&mut Inventory>,
+    results: &dns_audit::DnsAuditResults,
+) {
+    export_failed("DNS audit", dns_audit::export(sd, results));
+    let Some(inv) = inventory else {
+        return;
+    };
+    for audit in results.iter().filter(|a| a.is_dns_server()) {
+        let ports: &[(u16, Option<&str>)] = if audit.tcp_open {
+            &[(53, Some("dns"))]
+        } else {
+            &[]
+        };
+        let obs = Observation {
+            ip: Some(audit.host),
+            ports,
+            services: &["dns"],
+            ..Default::default()
+        };
+        update(sd, inv, &obs);
+    }
+}
+
+// The boot-time DNS audit only knows the DHCP servers and the gateway;
+// this adds any other host the inventory has seen that answers on 53.
+async fn check_dns<S: embassy_rp::spi::Instance>(
+    sd: &mut SdStorage<'_, S>,
+    inv: &mut Inventory,
+    stack: Stack<'_>,
+    results: &mut dns_audit::DnsAuditResults,
+) {
+    let hosts = inv.seen_ips();
+    info!("Looking for DNS servers on {} hosts...", hosts.len());
+    let added = dns_audit::run_hosts(stack, &DnsAuditConfig::from_env(), &hosts, results).await;
+    if added > 0 {
+        save_dns_audit(sd, Some(inv), results);
+    }
+}
+
+fn show_session_diff<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+    inventory: &Option<Inventory>,
+) {
+    let (Some(sd), Some(inv)) = (storage.as_mut(), inventory.as_ref()) else {
+        return;
+    };
+    match session::finish(sd, inv) {
+        Ok(Some(summary)) => {
+            let lines = summary.lines();
+            oled::show_lines(
+                display,
+                &["Since last scan:", &lines[0], &lines[1], &lines[2]],
+            );
+            let mut line: String<128> = String::new();
+            write!(
+                &mut line,
+                "Scan diff: {} {} {}",
+                lines[0], lines[1], lines[2]
+            )
+            .ok();
+            sd.log_event(&line).ok();
+        }
+        Ok(None) => {}
+        Err(e) => warn!("scan session failed: {:?}", defmt::Debug2Format(&e)),
+    }
+}
+
+fn show_perf<I: ssd1306::prelude::WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+    result: &perf::PerfResult,
+) {
+    let lines = result.lines();
+    let lines: [&str; 4] = core::array::from_fn(|i| lines[i].as_str());
+    oled::show_lines(display, &lines);
+
+    if let Some(sd) = storage.as_mut() {
+        let mut line: String<128> = String::new();
+        write!(&mut line, "{}", result).ok();
+        sd.log_event(&line).ok();
+    }
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
@@ -129,6 +848,7 @@ async fn main(spawner: Spawner) {
 
     let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
+    let btfw = include_bytes!("../cyw43-firmware/43439A0_btfw.bin");
 
     // To make flashing faster for development, you may want to flash the firmwares independently
     // at hardcoded addresses, instead of baking them into the program with `include_bytes!`:
@@ -153,57 +873,123 @@ async fn main(spawner: Spawner) {
 
     static STATE: StaticCell<cyw43::State> = StaticCell::new();
     let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    let (wifi_device, bt_device, mut control, runner) =
+        cyw43::new_with_bluetooth(state, pwr, spi, fw, btfw).await;
     spawner.spawn(cyw43_task(runner)).unwrap();
+    let ble_controller = BLE_CONTROLLER.init(ExternalController::new(bt_device));
 
     control.init(clm).await;
 
     // blink 5 times in 0.5 seconds (100ms) just to show it started
     blink(&mut control, 5, 100).await;
 
-    let config = Config::dhcpv4(DhcpConfig::default());
+    // SD card is optional, everything still works without it, we just don't
+    // get any exports.
+    let mut sd_cfg = embassy_rp::spi::Config::default();
+    sd_cfg.frequency = 100_000;
+    let sd_spi = embassy_rp::spi::Spi::new_blocking(p.SPI0, p.PIN_18, p.PIN_19, p.PIN_16, sd_cfg);
+    let sd_cs = Output::new(p.PIN_17, Level::High);
+    let mut storage = match SdStorage::new(sd_spi, sd_cs) {
+        Ok(storage) => Some(storage),
+        Err(e) => {
+            warn!("no SD card: {:?}", defmt::Debug2Format(&e));
+            None
+        }
+    };
+    let mut inventory = storage.as_mut().and_then(|sd| match Inventory::load(sd) {
+        Ok(inv) => Some(inv),
+        Err(e) => {
+            warn!("inventory load failed: {:?}", defmt::Debug2Format(&e));
+            None
+        }
+    });
 
-    let resources = NET_RESOURCES.init(StackResources::<2>::new());
-    let (stack, runner) = embassy_net::new(
-        net_device,
-        config,
-        resources,
-        embassy_time::Instant::now().as_ticks(),
-    );
+    #[cfg(feature = "wiznet")]
+    let wired_device = {
+        let pins = wiznet::WiznetPins {
+            spi: p.SPI1,
+            sck: p.PIN_10,
+            mosi: p.PIN_11,
+            miso: p.PIN_12,
+            cs: p.PIN_13,
+            int: p.PIN_14,
+            rst: p.PIN_15,
+            tx_dma: p.DMA_CH1,
+            rx_dma: p.DMA_CH2,
+        };
+        let (device, runner) = wiznet::init(pins, wiznet::DEFAULT_MAC).await;
+        spawner.spawn(wiznet_task(runner)).unwrap();
+        device
+    };
 
-    let stack = NET_STACK.init(stack);
-    let runner = NET_RUNNER.init(runner);
+    #[cfg(not(feature = "wiznet"))]
+    let net = NetInterfaces::single(wifi_stack(&spawner, wifi_device), &WIFI_ARP);
 
-    spawner.spawn(network_task(runner)).unwrap();
+    #[cfg(all(feature = "wiznet", not(feature = "dual")))]
+    let net = {
+        // WiFi radio stays up for the LED, but the W5500 carries the traffic.
+        let _ = wifi_device;
+        NetInterfaces::single(wired_stack(&spawner, wired_device), &WIRED_ARP)
+    };
 
-    const SSID: &str = env!("WIFISSID");
-    const PASSWORD: &str = env!("WIFIPASS");
+    #[cfg(feature = "dual")]
+    let net = NetInterfaces::dual(
+        wired_stack(&spawner, wired_device),
+        &WIRED_ARP,
+        wifi_stack(&spawner, wifi_device),
+    );
 
     let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
-    let mut out: String<64> = String::new();
-    write!(&mut out, "SSID: {}", SSID).unwrap();
-    Text::new(&out, Point::new(0, 10), style)
-        .draw(&mut display)
-        .unwrap();
-    Text::new("Connecting...", Point::new(0, 20), style)
-        .draw(&mut display)
-        .unwrap();
-    display.flush().unwrap();
 
-    // blink 3 times in 1 second (333ms)
-    blink(&mut control, 3, 333).await;
-
-    wifi_join_with_retries(&mut control, SSID, PASSWORD, &mut display, style).await;
+    #[cfg(any(not(feature = "wiznet"), feature = "dual"))]
+    {
+        join_wifi(&mut control, &mut display, style).await;
+        // The CYW43439 drops multicast it hasn't been told about, and IPv6
+        // neighbor discovery needs these two groups. WiFi is always mgmt.
+        if let Some(v6) = net.mgmt.config_v6() {
+            for group in [ndp::ALL_NODES, ndp::solicited_node(v6.address.address())] {
+                if let Err(e) = control
+                    .add_multicast_address(ndp::multicast_mac(group))
+                    .await
+                {
+                    warn!("multicast filter: {:?}", defmt::Debug2Format(&e));
+                }
+            }
+        }
+        // WS-Discovery, for a camera's Hello while we listen.
+        if let Err(e) = control.add_multicast_address(camera::WSD_MAC).await {
+            warn!("multicast filter: {:?}", defmt::Debug2Format(&e));
+        }
+    }
 
-    // Otherwise, success!
-    // blink 10 times in 1 second (100ms)
-    blink(&mut control, 10, 100).await;
-    info!("wifi connected!");
+    #[cfg(feature = "wiznet")]
+    {
+        display.clear(BinaryColor::Off).unwrap();
+        Text::new("Ethernet (W5500)", Point::new(0, 10), style)
+            .draw(&mut display)
+            .unwrap();
+        Text::new("Waiting for link...", Point::new(0, 20), style)
+            .draw(&mut display)
+            .unwrap();
+        display.flush().unwrap();
+        net.scan.wait_link_up().await;
+        info!("ethernet link up!");
+    }
 
-    Text::new("Connected!", Point::new(0, 30), style)
+    Text::new("Waiting for DHCP...", Point::new(0, 40), style)
         .draw(&mut display)
         .unwrap();
     display.flush().unwrap();
+    net.scan.wait_config_up().await;
+    net.mgmt.wait_config_up().await;
+    let stack = net.scan;
+
+    spawner.spawn(sntp_task(net.mgmt)).unwrap();
+    if clock::wait_synced(NTP_WAIT).await {
+        info!("clock synced: {} local", clock::now_local());
+    } else {
+        warn!("no NTP yet, timestamps are uptime until it syncs");
+    }
 
     if let Some(cfg) = stack.config_v4() {
         info!("IPv4 address: {}", cfg.address);
@@ -225,23 +1011,277 @@ async fn main(spawner: Spawner) {
         Text::new(&msg2, Point::new(0, 20), style)
             .draw(&mut display)
             .unwrap();
+        if net.is_dual()
+            && let Some(mgmt) = net.mgmt.config_v4()
+        {
+            info!("Management IPv4 address: {}", mgmt.address);
+            let mut msg3: String<64> = String::new();
+            write!(&mut msg3, "MG: {}", mgmt.address).unwrap();
+            Text::new(&msg3, Point::new(0, 30), style)
+                .draw(&mut display)
+                .unwrap();
+        }
         display.flush().unwrap();
+
+        if let Some(sd) = storage.as_mut() {
+            sd.log_ip(&msg).ok();
+        }
+    }
+
+    if let Some(cfg) = stack.config_v4() {
+        let own_ip = cfg.address.address();
+        if let Some(conflict) = arp_guard::probe_own(net.scan_arp, own_ip).await {
+            show_conflict(&mut display, &mut storage, &conflict);
+            Timer::after_secs(5).await;
+        }
+        spawner.spawn(arp_watch_task(net.scan_arp, own_ip)).unwrap();
+    }
+    if let Some(cfg) = stack.config_v6() {
+        info!("IPv6 address: {}", cfg.address);
+        spawner.spawn(ndp_watch_task(stack, net.scan_arp)).unwrap();
+    }
+    // Hosts from SCAN_HOSTS get checked along with everything we find.
+    if let (Some(sd), Some(inv)) = (storage.as_mut(), inventory.as_mut()) {
+        for ip in inventory::configured_hosts() {
+            let obs = Observation {
+                ip: Some(ip),
+                ..Default::default()
+            };
+            if let Err(e) = inv.upsert(sd, &obs) {
+                warn!("inventory update failed: {:?}", defmt::Debug2Format(&e));
+            }
+        }
+    }
+
+    let woken = wol::wake_configured(stack).await;
+    if woken > 0 {
+        info!("sent {} wake-on-lan packets", woken);
     }
 
-    debug!("Setting wifi power management to PowerSave");
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
+    info!("Running connectivity health check...");
+    let report = health::run(stack, &HealthConfig::from_env()).await;
+    let lines = report.lines();
+    let lines: [&str; 6] = core::array::from_fn(|i| lines[i].as_str());
+    oled::show_lines(&mut display, &lines);
+    if report.all_passed() {
+        info!("health check passed");
+    } else {
+        warn!("health check has failures");
+    }
+    if let Some(sd) = storage.as_mut() {
+        for result in [
+            &report.gateway_pmtu,
+            &report.remote_pmtu,
+            &report.gateway6_pmtu,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let mut line: String<128> = String::new();
+            write!(&mut line, "PMTU {}", result).ok();
+            sd.log_event(&line).ok();
+        }
+    }
+
+    info!("Scanning for BLE advertisers...");
+    let mut ble_inventory = BleInventory::new();
+    if let Err(e) = ble::scan(ble_controller, BLE_SCAN_TIME, &mut ble_inventory).await {
+        warn!("BLE scan failed: {}", e);
+    }
+    if let Some(sd) = storage.as_mut()
+        && let Err(e) = ble::export(sd, &ble_inventory)
+    {
+        warn!("BLE export failed: {:?}", defmt::Debug2Format(&e));
+    }
+
+    info!("Auditing DNS servers...");
+    let mut dns_results = dns_audit::run(stack, &DnsAuditConfig::from_env()).await;
+    if let Some(sd) = storage.as_mut() {
+        save_dns_audit(sd, inventory.as_mut(), &dns_results);
+    }
+
+    let passed = report
+        .checks()
+        .iter()
+        .filter(|(_, s)| *s == health::Status::Pass)
+        .count();
+    let mut health_line: String<21> = String::new();
+    write!(&mut health_line, "Health: {}/6 OK", passed).unwrap();
+    let mut ble_line: String<21> = String::new();
+    write!(&mut ble_line, "BLE: {} devices", ble_inventory.len()).unwrap();
+    let mut dns_line: String<21> = String::new();
+    write!(
+        &mut dns_line,
+        "DNS: {} srv {} flag",
+        dns_results.iter().filter(|a| a.is_dns_server()).count(),
+        dns_results.iter().filter(|a| a.has_findings()).count()
+    )
+    .unwrap();
+    let mtu_line = report.pmtu_line();
+    oled::show_lines(
+        &mut display,
+        &[&health_line, &mtu_line, &ble_line, &dns_line],
+    );
+
+    let perf_mode = perf::mode_from_env();
+    match perf_mode {
+        Some(perf::Mode::Server) => {
+            info!("perf server listening on port {}", perf::PORT);
+            spawner.spawn(perf_server_task(net.mgmt)).unwrap();
+        }
+        Some(perf::Mode::Client { host, request }) => {
+            let addr = match host.parse::<core::net::IpAddr>() {
+                Ok(ip) => Some(ip.into()),
+                Err(_) => net
+                    .mgmt
+                    .dns_query(host, embassy_net::dns::DnsQueryType::A)
+                    .await
+                    .ok()
+                    .and_then(|addrs| addrs.first().copied()),
+            };
+            match addr {
+                Some(addr) => match perf::client(net.mgmt, addr, request).await {
+                    Ok(result) => show_perf(&mut display, &mut storage, &result),
+                    Err(e) => warn!("perf test against {} failed: {}", host, e),
+                },
+                None => warn!("perf: could not resolve {}", host),
+            }
+        }
+        None => {}
+    }
+
+    // Power save would cap the perf server's throughput, so leave it off.
+    if !matches!(perf_mode, Some(perf::Mode::Server)) {
+        debug!("Setting wifi power management to PowerSave");
+        control
+            .set_power_management(cyw43::PowerManagementMode::PowerSave)
+            .await;
+    }
+
+    if site_survey::enabled() {
+        // Waypoint button between GPIO 22 and ground.
+        let button = Input::new(p.PIN_22, Pull::Up);
+        spawner.spawn(waypoint_button_task(button)).unwrap();
+        site_survey::run(&mut control, &mut display, &mut storage).await;
+    }
+    // Otherwise the button picks inventory hosts to wake.
+    let button = Input::new(p.PIN_22, Pull::Up);
+    spawner.spawn(wol_button_task(button)).unwrap();
+    let mut wol_target: Option<[u8; 6]> = None;
 
     let on_delay = Duration::from_millis(500);
     let off_delay = Duration::from_millis(3000);
+    let session_at = Instant::now() + SESSION_TIME;
+    let mut session_saved = false;
+    let survey_every = survey::interval_from_env();
+    let mut next_survey = Instant::now();
+    let mut rogue_watch = RogueWatch::from_env();
+    if let Some(watch) = rogue_watch.as_ref() {
+        info!("watching for rogue APs, {} known", watch.known().len());
+    }
+    // Alerts also go out to syslog on the mgmt interface, if set.
+    let syslog_server = syslog::server_from_env();
+    let mut next_rogue_scan = Instant::now();
     info!("Starting blinking infinite loop...");
     loop {
+        if !session_saved && Instant::now() >= session_at {
+            session_saved = true;
+            // The checks all work from the inventory and write to the SD card.
+            if let (Some(sd), Some(inv)) = (storage.as_mut(), inventory.as_mut()) {
+                let mut findings = exposure::Findings::new();
+                check_exposure(&mut display, sd, inv, stack, &mut findings).await;
+                check_dns(sd, inv, stack, &mut dns_results).await;
+                check_smb(sd, inv, stack).await;
+                check_ssh(sd, inv, stack, &mut findings).await;
+                if industrial::enabled() {
+                    check_industrial(sd, inv, stack).await;
+                }
+                check_cameras(sd, inv, stack, &mut findings).await;
+                check_gateway(sd, inv, stack).await;
+                check_uptime(sd, inv, stack).await;
+            }
+            show_session_diff(&mut display, &mut storage, &inventory);
+        }
+        if let Some(every) = survey_every
+            && Instant::now() >= next_survey
+        {
+            next_survey = Instant::now() + every;
+            let results = survey::scan(&mut control).await;
+            survey::show(&mut display, &results);
+            if let Some(sd) = storage.as_mut()
+                && let Err(e) = survey::export(sd, &results)
+            {
+                warn!("survey export failed: {:?}", defmt::Debug2Format(&e));
+            }
+        }
+        if let Some(watch) = rogue_watch.as_mut()
+            && Instant::now() >= next_rogue_scan
+        {
+            next_rogue_scan = Instant::now() + ROGUE_SCAN_TIME;
+            let mut alerts: heapless::Vec<rogue_ap::Alert, 4> = heapless::Vec::new();
+            wifi_scan::scan(&mut control, |bss| {
+                if let Some(alert) =
+                    watch.check(&bss.ssid, bss.bssid, bss.channel, bss.rssi, bss.encrypted)
+                    && !alerts
+                        .iter()
+                        .any(|a| a.bssid == alert.bssid && a.reason == alert.reason)
+                {
+                    // Only marked reported once shown, so any that don't
+                    // fit come up again on the next scan.
+                    alerts.push(alert).ok();
+                }
+            })
+            .await;
+            for alert in &alerts {
+                show_rogue(&mut display, &mut storage, alert);
+                if let Some(server) = syslog_server {
+                    syslog::send(net.mgmt, server, "rogue_ap", alert).await;
+                }
+                watch.reported(alert);
+            }
+        }
+
         control.gpio_set(0, true).await;
         Timer::after(on_delay).await;
 
         control.gpio_set(0, false).await;
-        Timer::after(off_delay).await;
+        match select4(
+            arp_guard::next_conflict(off_delay),
+            perf::RESULTS.receive(),
+            arp_guard::SIGHTINGS.receive(),
+            wol::PRESSES.receive(),
+        )
+        .await
+        {
+            Either4::First(Some(conflict)) => show_conflict(&mut display, &mut storage, &conflict),
+            Either4::First(None) => {}
+            Either4::Second(result) => show_perf(&mut display, &mut storage, &result),
+            Either4::Third((ip, mac)) => {
+                if let (Some(sd), Some(inv)) = (storage.as_mut(), inventory.as_mut()) {
+                    let obs = Observation {
+                        mac: Some(mac),
+                        ip: Some(ip),
+                        ..Default::default()
+                    };
+                    match inv.upsert(sd, &obs) {
+                        Ok((inventory::Change::New, _)) => info!("inventory: new host {}", ip),
+                        Ok(_) => {}
+                        Err(e) => warn!("inventory update failed: {:?}", defmt::Debug2Format(&e)),
+                    }
+                }
+            }
+            Either4::Fourth(press) => {
+                wol_press(
+                    &mut display,
+                    &mut storage,
+                    &inventory,
+                    stack,
+                    &mut wol_target,
+                    press,
+                )
+                .await
+            }
+        }
     }
 }
 
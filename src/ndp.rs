@@ -0,0 +1,303 @@
+//! ndp
+//! ---
+//!
+//! IPv6 neighbors, from neighbor discovery.
+//!
+//! smoltcp doesn't do SLAAC, so each stack gets one static IPv6 address:
+//! `IPV6_ADDRESS` (with prefix length) and `IPV6_GATEWAY` from `.env` if
+//! set, otherwise the EUI-64 link-local address for its MAC.
+//!
+//!     IPV6_ADDRESS="2001:db8:10::50/64"
+//!     IPV6_GATEWAY="2001:db8:10::1"
+//!
+//! `ArpTap` hands us every neighbor solicitation, advertisement and router
+//! message off the wire, and echo replies from link-local addresses. Each
+//! one ties an IPv6 address to a MAC. To hear from quiet hosts too, we
+//! ping the all-nodes group every few minutes; most non-Windows hosts
+//! answer. New pairs go out on `arp_guard::SIGHTINGS` with the ARP ones,
+//! so a dual-stack host ends up as one inventory entry with both
+//! addresses.
+use defmt::{Display2Format, debug, warn};
+use embassy_futures::select::{Either, select};
+use embassy_net::icmp::{IcmpEndpoint, IcmpSocket, PacketMetadata};
+use embassy_net::{IpAddress, Ipv6Address, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+use crate::arp_guard::{Mac, SIGHTINGS};
+use crate::arp_tap::ArpTapState;
+
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+pub const ALL_NODES: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+const ETH_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+const ECHO_REQUEST: u8 = 128;
+const ECHO_REPLY: u8 = 129;
+const ROUTER_SOLICIT: u8 = 133;
+const ROUTER_ADVERT: u8 = 134;
+const NEIGHBOR_SOLICIT: u8 = 135;
+const NEIGHBOR_ADVERT: u8 = 136;
+const OPT_TARGET_LL_ADDR: u8 = 2;
+// "ND", so our sweeps are easy to spot in a capture.
+const IDENT: u16 = 0x4e44;
+const SWEEP_EVERY: Duration = Duration::from_secs(300);
+const MAX_NEIGHBORS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Neighbor {
+    pub mac: [u8; 6],
+    pub ip: Ipv6Address,
+}
+
+impl Neighbor {
+    // An Ethernet frame, if it shows a neighbor's address. ND messages
+    // only count with a hop limit of 255, which means they weren't routed.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN + IPV6_HEADER_LEN + 4
+            || u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_IPV6
+        {
+            return None;
+        }
+        let ip = &frame[ETH_HEADER_LEN..];
+        if ip[0] >> 4 != 6 || ip[6] != NEXT_HEADER_ICMPV6 {
+            return None;
+        }
+        let src = ipv6_at(ip, 8);
+        let icmp = &ip[IPV6_HEADER_LEN..];
+        let eth_src: [u8; 6] = frame[6..12].try_into().unwrap();
+        let on_link = ip[7] == 255;
+
+        let (mac, ip) = match icmp[0] {
+            ROUTER_SOLICIT | ROUTER_ADVERT | NEIGHBOR_SOLICIT if on_link => (eth_src, src),
+            NEIGHBOR_ADVERT if on_link && icmp.len() >= 24 => {
+                let mac = target_ll_addr(&icmp[24..]).unwrap_or(eth_src);
+                (mac, ipv6_at(icmp, 8))
+            }
+            ECHO_REPLY if is_link_local(&src) => (eth_src, src),
+            _ => return None,
+        };
+        if ip.is_unspecified() || ip.is_multicast() {
+            return None;
+        }
+        Some(Self { mac, ip })
+    }
+}
+
+fn ipv6_at(buf: &[u8], at: usize) -> Ipv6Address {
+    let octets: [u8; 16] = buf[at..at + 16].try_into().unwrap();
+    octets.into()
+}
+
+// The target link-layer address option of a neighbor advertisement.
+fn target_ll_addr(mut options: &[u8]) -> Option<[u8; 6]> {
+    while options.len() >= 8 {
+        let len = options[1] as usize * 8;
+        if len == 0 || options.len() < len {
+            return None;
+        }
+        if options[0] == OPT_TARGET_LL_ADDR {
+            return options[2..8].try_into().ok();
+        }
+        options = &options[len..];
+    }
+    None
+}
+
+pub fn is_link_local(ip: &Ipv6Address) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+// fe80::/64 with the MAC as modified EUI-64.
+pub fn link_local(mac: [u8; 6]) -> Ipv6Address {
+    let mut o = [0u8; 16];
+    o[..2].copy_from_slice(&[0xfe, 0x80]);
+    o[8..11].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2]]);
+    o[11..13].copy_from_slice(&[0xff, 0xfe]);
+    o[13..].copy_from_slice(&mac[3..]);
+    Ipv6Address::from(o)
+}
+
+// ff02::1:ffXX:XXXX, where neighbor solicitations for `ip` get sent.
+pub fn solicited_node(ip: Ipv6Address) -> Ipv6Address {
+    let mut o = [0u8; 16];
+    o[..2].copy_from_slice(&[0xff, 0x02]);
+    o[11..13].copy_from_slice(&[0x01, 0xff]);
+    o[13..].copy_from_slice(&ip.octets()[13..]);
+    Ipv6Address::from(o)
+}
+
+// The Ethernet address an IPv6 multicast group is sent to.
+pub fn multicast_mac(group: Ipv6Address) -> [u8; 6] {
+    let o = group.octets();
+    [0x33, 0x33, o[12], o[13], o[14], o[15]]
+}
+
+// From `.env`, falling back to link-local for `mac`.
+pub fn static_config(mac: [u8; 6]) -> StaticConfigV6 {
+    let mut address = Ipv6Cidr::new(link_local(mac), 64);
+    if let Some(s) = option_env!("IPV6_ADDRESS") {
+        match s.trim().parse() {
+            Ok(cidr) => address = cidr,
+            Err(_) => warn!("ndp: bad IPV6_ADDRESS {}, using link-local", s),
+        }
+    }
+    StaticConfigV6 {
+        address,
+        gateway: option_env!("IPV6_GATEWAY").and_then(|s| s.trim().parse().ok()),
+        dns_servers: Vec::new(),
+    }
+}
+
+// An echo request to all-nodes. Whoever answers shows up through the tap,
+// so the replies themselves aren't read.
+pub async fn sweep(stack: Stack<'_>) {
+    let mut rx_meta = [PacketMetadata::EMPTY];
+    let mut rx_buf = [0u8; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY];
+    let mut tx_buf = [0u8; 64];
+    let mut socket = IcmpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if socket.bind(IcmpEndpoint::Ident(IDENT)).is_err() {
+        return;
+    }
+    // smoltcp fills in the checksum.
+    let mut echo = [ECHO_REQUEST, 0, 0, 0, 0, 0, 0, 1];
+    echo[4..6].copy_from_slice(&IDENT.to_be_bytes());
+    if socket
+        .send_to(&echo, IpAddress::Ipv6(ALL_NODES))
+        .await
+        .is_err()
+    {
+        warn!("ndp: all-nodes ping failed");
+    }
+    socket.flush().await;
+}
+
+pub async fn watch(stack: Stack<'_>, tap: &ArpTapState) -> ! {
+    let our_mac = tap.mac();
+    let mut known: Vec<Neighbor, MAX_NEIGHBORS> = Vec::new();
+    let mut next_sweep = Instant::now();
+    tap.clear_neighbors();
+
+    loop {
+        let neighbor = match select(tap.receive_neighbor(), Timer::at(next_sweep)).await {
+            Either::First(neighbor) => neighbor,
+            Either::Second(()) => {
+                next_sweep = Instant::now() + SWEEP_EVERY;
+                debug!("ndp: pinging all-nodes");
+                sweep(stack).await;
+                continue;
+            }
+        };
+        if neighbor.mac == our_mac || known.contains(&neighbor) {
+            continue;
+        }
+        debug!(
+            "ndp: {} is {}",
+            neighbor.ip,
+            Display2Format(&Mac(&neighbor.mac))
+        );
+        if known.is_full() {
+            known.remove(0);
+        }
+        known.push(neighbor).ok();
+        SIGHTINGS
+            .try_send((IpAddress::Ipv6(neighbor.ip), neighbor.mac))
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0xb8, 0x27, 0xeb, 0x01, 0x02, 0x03];
+
+    // Ethernet + IPv6 + ICMPv6, checksum left at zero.
+    fn frame(src: Ipv6Address, hop_limit: u8, icmp: &[u8]) -> std::vec::Vec<u8> {
+        let mut f = std::vec![0u8; ETH_HEADER_LEN + IPV6_HEADER_LEN];
+        f[6..12].copy_from_slice(&MAC);
+        f[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        let ip = &mut f[ETH_HEADER_LEN..];
+        ip[0] = 0x60;
+        ip[4..6].copy_from_slice(&(icmp.len() as u16).to_be_bytes());
+        ip[6] = NEXT_HEADER_ICMPV6;
+        ip[7] = hop_limit;
+        ip[8..24].copy_from_slice(&src.octets());
+        ip[24..40].copy_from_slice(&ALL_NODES.octets());
+        f.extend_from_slice(icmp);
+        f
+    }
+
+    #[test]
+    fn addresses() {
+        let ll = link_local(MAC);
+        assert_eq!(
+            ll,
+            "fe80::ba27:ebff:fe01:203".parse::<Ipv6Address>().unwrap()
+        );
+        assert!(is_link_local(&ll));
+        assert!(!is_link_local(&ALL_NODES));
+        assert_eq!(
+            solicited_node(ll),
+            "ff02::1:ff01:203".parse::<Ipv6Address>().unwrap()
+        );
+        assert_eq!(
+            multicast_mac(solicited_node(ll)),
+            [0x33, 0x33, 0xff, 0x01, 0x02, 0x03]
+        );
+    }
+
+    #[test]
+    fn parses_neighbors() {
+        let ll = link_local(MAC);
+        let global: Ipv6Address = "2001:db8::5".parse().unwrap();
+
+        let mut ns = [0u8; 24];
+        ns[0] = NEIGHBOR_SOLICIT;
+        assert_eq!(
+            Neighbor::parse(&frame(ll, 255, &ns)),
+            Some(Neighbor { mac: MAC, ip: ll })
+        );
+        // Routed, or duplicate address detection from ::.
+        assert_eq!(Neighbor::parse(&frame(ll, 64, &ns)), None);
+        assert_eq!(
+            Neighbor::parse(&frame(Ipv6Address::UNSPECIFIED, 255, &ns)),
+            None
+        );
+
+        // Advertising a global address, with the MAC in an option.
+        let mut na = [0u8; 32];
+        na[0] = NEIGHBOR_ADVERT;
+        na[8..24].copy_from_slice(&global.octets());
+        na[24..32].copy_from_slice(&[OPT_TARGET_LL_ADDR, 1, 0, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert_eq!(
+            Neighbor::parse(&frame(ll, 255, &na)),
+            Some(Neighbor {
+                mac: [0, 0x11, 0x22, 0x33, 0x44, 0x55],
+                ip: global
+            })
+        );
+        // Without the option, from the Ethernet source.
+        assert_eq!(
+            Neighbor::parse(&frame(ll, 255, &na[..24])),
+            Some(Neighbor {
+                mac: MAC,
+                ip: global
+            })
+        );
+
+        // Echo replies count from link-local sources only.
+        let reply = [ECHO_REPLY, 0, 0, 0, 0x4e, 0x44, 0, 1];
+        assert_eq!(
+            Neighbor::parse(&frame(ll, 64, &reply)),
+            Some(Neighbor { mac: MAC, ip: ll })
+        );
+        assert_eq!(Neighbor::parse(&frame(global, 64, &reply)), None);
+
+        let mut arp = frame(ll, 255, &ns);
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert_eq!(Neighbor::parse(&arp), None);
+    }
+}
@@ -0,0 +1,48 @@
+//! netif
+//! -----
+//!
+//! Which network stacks are up, and what each one is for.
+//!
+//! Normally there's one interface and it does everything. Built with the
+//! `dual` feature, the W5500 is the scan interface (the wired LAN under
+//! test) and WiFi is the management interface (our own network, where
+//! results get pushed out), so scan and reporting traffic never mix.
+use embassy_net::Stack;
+
+use crate::arp_tap::ArpTapState;
+
+#[derive(Clone, Copy)]
+pub struct NetInterfaces {
+    // LAN under test. Scanners and the health check run here.
+    pub scan: Stack<'static>,
+    // ARP frames seen on (and sent from) the scan interface.
+    pub scan_arp: &'static ArpTapState,
+    // Where results and alerts get sent. Same as `scan` unless dual.
+    pub mgmt: Stack<'static>,
+}
+
+impl NetInterfaces {
+    pub fn single(stack: Stack<'static>, arp: &'static ArpTapState) -> Self {
+        Self {
+            scan: stack,
+            scan_arp: arp,
+            mgmt: stack,
+        }
+    }
+
+    pub fn dual(
+        scan: Stack<'static>,
+        scan_arp: &'static ArpTapState,
+        mgmt: Stack<'static>,
+    ) -> Self {
+        Self {
+            scan,
+            scan_arp,
+            mgmt,
+        }
+    }
+
+    pub fn is_dual(&self) -> bool {
+        cfg!(feature = "dual")
+    }
+}
@@ -0,0 +1,83 @@
+//! oled
+//! ----
+//!
+//! Helpers for drawing simple text screens on the 128x64 SSD1306.
+//!
+//! With FONT_6X10 we get 6 lines of 21 characters each.
+//! `show_bars` draws a bar chart above one line of text instead.
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::{Ssd1306, prelude::*};
+
+pub type Display<I> = Ssd1306<I, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+
+pub const LINE_HEIGHT: i32 = 10;
+pub const MAX_LINES: usize = 6;
+
+pub fn style() -> MonoTextStyle<'static, BinaryColor> {
+    MonoTextStyle::new(&FONT_6X10, BinaryColor::On)
+}
+
+// Clears the screen and draws each line top to bottom, then flushes.
+// Anything past MAX_LINES is dropped.
+pub fn show_lines<I: WriteOnlyDataCommand>(display: &mut Display<I>, lines: &[&str]) {
+    display.clear(BinaryColor::Off).unwrap();
+    for (i, line) in lines.iter().take(MAX_LINES).enumerate() {
+        Text::new(line, Point::new(0, LINE_HEIGHT * (i as i32 + 1)), style())
+            .draw(display)
+            .unwrap();
+    }
+    display.flush().unwrap();
+}
+
+// Bar chart of percentages (0-100) side by side across the screen, with
+// `caption` on the bottom line. The `mark`ed bar gets a tick under it.
+pub fn show_bars<I: WriteOnlyDataCommand>(
+    display: &mut Display<I>,
+    bars: &[u8],
+    mark: Option<usize>,
+    caption: &str,
+) {
+    const WIDTH: i32 = 128;
+    const CHART_BOTTOM: i32 = 50;
+    const CHART_HEIGHT: u32 = 48;
+
+    display.clear(BinaryColor::Off).unwrap();
+    let fill = PrimitiveStyle::with_fill(BinaryColor::On);
+    let slot = WIDTH / bars.len().max(1) as i32;
+    let bar_width = (slot - 2).max(1) as u32;
+
+    for (i, &pct) in bars.iter().enumerate() {
+        let x = i as i32 * slot;
+        // Keep anything non-zero visible.
+        let height = match pct.min(100) as u32 * CHART_HEIGHT / 100 {
+            0 if pct > 0 => 1,
+            h => h,
+        };
+        if height > 0 {
+            Rectangle::new(
+                Point::new(x, CHART_BOTTOM - height as i32),
+                Size::new(bar_width, height),
+            )
+            .into_styled(fill)
+            .draw(display)
+            .unwrap();
+        }
+        if mark == Some(i) {
+            Rectangle::new(Point::new(x, CHART_BOTTOM + 2), Size::new(bar_width, 2))
+                .into_styled(fill)
+                .draw(display)
+                .unwrap();
+        }
+    }
+    Text::new(caption, Point::new(0, 62), style())
+        .draw(display)
+        .unwrap();
+    display.flush().unwrap();
+}
@@ -0,0 +1,72 @@
+//! oui
+//! ---
+//!
+//! MAC vendor lookup. The full IEEE registry is far too big for flash, so
+//! this is a short list of vendors we actually run into on lab and office
+//! networks. Anything else comes back as "unknown", and randomized/virtual
+//! MACs (locally administered bit set) as "local".
+
+// Sorted by prefix so we can binary search.
+static VENDORS: &[([u8; 3], &str)] = &[
+    ([0x00, 0x00, 0x0C], "Cisco"),
+    ([0x00, 0x03, 0x93], "Apple"),
+    ([0x00, 0x04, 0x4B], "NVIDIA"),
+    ([0x00, 0x04, 0xF2], "Polycom"),
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x08, 0xDC], "WIZnet"),
+    ([0x00, 0x09, 0x0F], "Fortinet"),
+    ([0x00, 0x0B, 0x82], "Grandstream"),
+    ([0x00, 0x0C, 0x29], "VMware"),
+    ([0x00, 0x0D, 0xB9], "PC Engines"),
+    ([0x00, 0x0E, 0x58], "Sonos"),
+    ([0x00, 0x11, 0x32], "Synology"),
+    ([0x00, 0x15, 0x5D], "Microsoft"),
+    ([0x00, 0x17, 0x88], "Philips Hue"),
+    ([0x00, 0x17, 0xF2], "Apple"),
+    ([0x00, 0x1A, 0x11], "Google"),
+    ([0x00, 0x1B, 0x17], "Palo Alto"),
+    ([0x00, 0x1B, 0x21], "Intel"),
+    ([0x00, 0x1B, 0x54], "Cisco"),
+    ([0x00, 0x1B, 0x63], "Apple"),
+    ([0x00, 0x1E, 0x67], "Intel"),
+    ([0x00, 0x1E, 0xC0], "Microchip"),
+    ([0x00, 0x21, 0x9B], "Dell"),
+    ([0x00, 0x25, 0x90], "Supermicro"),
+    ([0x00, 0x40, 0x8C], "Axis"),
+    ([0x00, 0x40, 0x96], "Cisco"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x00, 0xE0, 0x4C], "Realtek"),
+    ([0x08, 0x00, 0x27], "VirtualBox"),
+    ([0x18, 0xB4, 0x30], "Nest Labs"),
+    ([0x24, 0x0A, 0xC4], "Espressif"),
+    ([0x28, 0xCD, 0xC1], "Raspberry Pi"),
+    ([0x2C, 0xCF, 0x67], "Raspberry Pi"),
+    ([0x30, 0xAE, 0xA4], "Espressif"),
+    ([0x3C, 0x5A, 0xB4], "Google"),
+    ([0x84, 0xF3, 0xEB], "Espressif"),
+    ([0xA0, 0x36, 0x9F], "Intel"),
+    ([0xAC, 0xCC, 0x8E], "Axis"),
+    ([0xB8, 0x27, 0xEB], "Raspberry Pi"),
+    ([0xB8, 0xA4, 0x4F], "Axis"),
+    ([0xD8, 0x3A, 0xDD], "Raspberry Pi"),
+    ([0xDC, 0xA6, 0x32], "Raspberry Pi"),
+    ([0xE4, 0x5F, 0x01], "Raspberry Pi"),
+    ([0xF0, 0x18, 0x98], "Apple"),
+    ([0xF4, 0xF5, 0xD8], "Google"),
+    ([0xF8, 0xBC, 0x12], "Dell"),
+];
+
+pub fn is_locally_administered(mac: &[u8; 6]) -> bool {
+    mac[0] & 0x02 != 0
+}
+
+pub fn vendor(mac: &[u8; 6]) -> &'static str {
+    if is_locally_administered(mac) {
+        return "local";
+    }
+    let prefix = [mac[0], mac[1], mac[2]];
+    match VENDORS.binary_search_by(|(p, _)| p.cmp(&prefix)) {
+        Ok(idx) => VENDORS[idx].1,
+        Err(_) => "unknown",
+    }
+}
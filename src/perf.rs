@@ -0,0 +1,751 @@
+//! perf
+//! ----
+//!
+//! iperf-ish throughput tester, so the Pico can be one end of a WiFi
+//! speed test. It can be the server (a laptop runs `scripts/picoperf.py`
+//! against it) or the client (it connects to `picoperf.py -s`).
+//!
+//! Wire protocol, everything big-endian, all on one port (PORT, 5301).
+//!
+//! The client opens TCP and sends a 16 byte request:
+//!
+//!     0   "PMPF"
+//!     4   version (1)
+//!     5   proto: 0 = TCP, 1 = UDP
+//!     6   direction: 0 = up (client sends), 1 = down (server sends)
+//!     7   reserved, 0
+//!     8   duration in seconds, u16
+//!     10  UDP payload length, u16 (>= 8)
+//!     12  UDP rate in kbit/s, u16
+//!     14  client's UDP port for down tests, u16
+//!
+//! The server answers "PMPA" once it's ready (UDP socket bound and so on),
+//! and the client doesn't start until it sees that. Then the sender sends
+//! for `duration`. Over TCP that's any bytes on the same connection,
+//! followed by shutting down its write side. Over UDP it's datagrams to
+//! the server's PORT (up) or the client's UDP port (down), each starting
+//! with seq u32 and the sender's send time in microseconds, u32
+//! (wrapping). Three datagrams with seq 0xFFFFFFFF mark the end.
+//!
+//! For up tests the server then answers on the TCP connection with a 28
+//! byte report of what it received. For down tests the client measured it
+//! itself, so there's no report.
+//!
+//!     0   "PMPR"
+//!     4   bytes received, u64
+//!     12  elapsed milliseconds, u32
+//!     16  datagrams received, u32 (0 for TCP)
+//!     20  datagrams lost, u32
+//!     24  jitter in microseconds, u32 (RFC 3550 style)
+//!
+//! Set up from `.env`, all optional. Without `PERF_MODE` nothing runs.
+//!
+//!     PERF_MODE="server"      # or "client"
+//!     PERF_HOST="192.168.1.50"
+//!     PERF_PROTO="tcp"        # or "udp"
+//!     PERF_DIR="up"           # or "down", from the client's side
+//!     PERF_SECS="10"
+//!     PERF_RATE_KBPS="5000"
+use core::fmt::{self, Write};
+use defmt::{info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use heapless::String;
+
+use crate::util::write_all;
+
+pub const PORT: u16 = 5301;
+pub const REQUEST_LEN: usize = 16;
+pub const REPORT_LEN: usize = 28;
+const ACK: &[u8; 4] = b"PMPA";
+const VERSION: u8 = 1;
+const END_SEQ: u32 = 0xFFFF_FFFF;
+const MAX_PAYLOAD: usize = 1400;
+const MAX_SECS: u16 = 120;
+// UDP receiver gives up this long after the last datagram.
+const UDP_IDLE: Duration = Duration::from_secs(2);
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Finished tests from the server task, for main to display and log.
+pub static RESULTS: Channel<CriticalSectionRawMutex, PerfResult, 2> = Channel::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PerfError {
+    Connect,
+    Socket,
+    Protocol,
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Proto {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Request {
+    pub proto: Proto,
+    pub direction: Direction,
+    pub secs: u16,
+    pub payload_len: u16,
+    pub rate_kbps: u16,
+    pub udp_port: u16,
+}
+
+impl Request {
+    pub fn encode(&self) -> [u8; REQUEST_LEN] {
+        let mut buf = [0u8; REQUEST_LEN];
+        buf[..4].copy_from_slice(b"PMPF");
+        buf[4] = VERSION;
+        buf[5] = match self.proto {
+            Proto::Tcp => 0,
+            Proto::Udp => 1,
+        };
+        buf[6] = match self.direction {
+            Direction::Up => 0,
+            Direction::Down => 1,
+        };
+        buf[8..10].copy_from_slice(&self.secs.to_be_bytes());
+        buf[10..12].copy_from_slice(&self.payload_len.to_be_bytes());
+        buf[12..14].copy_from_slice(&self.rate_kbps.to_be_bytes());
+        buf[14..16].copy_from_slice(&self.udp_port.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < REQUEST_LEN || &buf[..4] != b"PMPF" || buf[4] != VERSION {
+            return None;
+        }
+        let word = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+        let req = Self {
+            proto: match buf[5] {
+                0 => Proto::Tcp,
+                1 => Proto::Udp,
+                _ => return None,
+            },
+            direction: match buf[6] {
+                0 => Direction::Up,
+                1 => Direction::Down,
+                _ => return None,
+            },
+            secs: word(8).clamp(1, MAX_SECS),
+            payload_len: word(10).clamp(8, MAX_PAYLOAD as u16),
+            rate_kbps: word(12).max(1),
+            udp_port: word(14),
+        };
+        Some(req)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct UdpStats {
+    pub received: u32,
+    pub lost: u32,
+    pub jitter_us: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PerfResult {
+    pub proto: Proto,
+    pub direction: Direction,
+    // What the receiving end counted.
+    pub bytes: u64,
+    pub elapsed_ms: u32,
+    pub udp: Option<UdpStats>,
+}
+
+impl PerfResult {
+    pub fn kbps(&self) -> u64 {
+        self.bytes * 8 / self.elapsed_ms.max(1) as u64
+    }
+
+    // Loss in tenths of a percent.
+    pub fn loss_permille(&self) -> Option<u32> {
+        let udp = self.udp?;
+        let total = udp.received + udp.lost;
+        Some(if total == 0 {
+            0
+        } else {
+            (udp.lost as u64 * 1000 / total as u64) as u32
+        })
+    }
+
+    fn encode_report(&self) -> [u8; REPORT_LEN] {
+        let udp = self.udp.unwrap_or_default();
+        let mut buf = [0u8; REPORT_LEN];
+        buf[..4].copy_from_slice(b"PMPR");
+        buf[4..12].copy_from_slice(&self.bytes.to_be_bytes());
+        buf[12..16].copy_from_slice(&self.elapsed_ms.to_be_bytes());
+        buf[16..20].copy_from_slice(&udp.received.to_be_bytes());
+        buf[20..24].copy_from_slice(&udp.lost.to_be_bytes());
+        buf[24..28].copy_from_slice(&udp.jitter_us.to_be_bytes());
+        buf
+    }
+
+    fn decode_report(req: &Request, buf: &[u8]) -> Option<Self> {
+        if buf.len() < REPORT_LEN || &buf[..4] != b"PMPR" {
+            return None;
+        }
+        let word = |i: usize| u32::from_be_bytes(buf[i..i + 4].try_into().unwrap());
+        Some(Self {
+            proto: req.proto,
+            direction: req.direction,
+            bytes: u64::from_be_bytes(buf[4..12].try_into().unwrap()),
+            elapsed_ms: word(12),
+            udp: (req.proto == Proto::Udp).then(|| UdpStats {
+                received: word(16),
+                lost: word(20),
+                jitter_us: word(24),
+            }),
+        })
+    }
+
+    // For the OLED.
+    pub fn lines(&self) -> [String<21>; 4] {
+        let mut out: [String<21>; 4] = Default::default();
+        write!(
+            out[0],
+            "Perf {} {}",
+            match self.proto {
+                Proto::Tcp => "TCP",
+                Proto::Udp => "UDP",
+            },
+            match self.direction {
+                Direction::Up => "up",
+                Direction::Down => "down",
+            }
+        )
+        .ok();
+        let kbps = self.kbps();
+        write!(out[1], "{}.{:02} Mbit/s", kbps / 1000, kbps % 1000 / 10).ok();
+        if let (Some(udp), Some(loss)) = (self.udp, self.loss_permille()) {
+            write!(out[2], "loss {}.{}%", loss / 10, loss % 10).ok();
+            write!(
+                out[3],
+                "jitter {}.{:02}ms",
+                udp.jitter_us / 1000,
+                udp.jitter_us % 1000 / 10
+            )
+            .ok();
+        } else {
+            write!(out[2], "{} KiB", self.bytes / 1024).ok();
+        }
+        out
+    }
+}
+
+// One line for the SD log.
+impl fmt::Display for PerfResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kbps = self.kbps();
+        write!(
+            f,
+            "perf {:?} {:?} {}.{:02} Mbit/s {} bytes {} ms",
+            self.proto,
+            self.direction,
+            kbps / 1000,
+            kbps % 1000 / 10,
+            self.bytes,
+            self.elapsed_ms
+        )?;
+        if let (Some(udp), Some(loss)) = (self.udp, self.loss_permille()) {
+            write!(
+                f,
+                " loss {}/{} ({}.{}%) jitter {}us",
+                udp.lost,
+                udp.received + udp.lost,
+                loss / 10,
+                loss % 10,
+                udp.jitter_us
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub enum Mode {
+    Server,
+    Client {
+        host: &'static str,
+        request: Request,
+    },
+}
+
+pub fn mode_from_env() -> Option<Mode> {
+    match option_env!("PERF_MODE")? {
+        "server" => Some(Mode::Server),
+        "client" => {
+            let Some(host) = option_env!("PERF_HOST") else {
+                warn!("perf: PERF_MODE=client needs PERF_HOST");
+                return None;
+            };
+            let parse =
+                |v: Option<&str>, default: u16| v.and_then(|s| s.parse().ok()).unwrap_or(default);
+            let request = Request {
+                proto: match option_env!("PERF_PROTO") {
+                    Some("udp") => Proto::Udp,
+                    _ => Proto::Tcp,
+                },
+                direction: match option_env!("PERF_DIR") {
+                    Some("down") => Direction::Down,
+                    _ => Direction::Up,
+                },
+                secs: parse(option_env!("PERF_SECS"), 10).clamp(1, MAX_SECS),
+                payload_len: 1024,
+                rate_kbps: parse(option_env!("PERF_RATE_KBPS"), 5000).max(1),
+                udp_port: PORT,
+            };
+            Some(Mode::Client { host, request })
+        }
+        other => {
+            warn!("perf: unknown PERF_MODE {}", other);
+            None
+        }
+    }
+}
+
+// Runs one test against a `picoperf.py -s` server.
+pub async fn client(
+    stack: Stack<'_>,
+    host: IpAddress,
+    req: Request,
+) -> Result<PerfResult, PerfError> {
+    let mut rx_buf = [0u8; 4096];
+    let mut tx_buf = [0u8; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    socket.set_timeout(Some(CONTROL_TIMEOUT + Duration::from_secs(req.secs as u64)));
+    match with_timeout(CONTROL_TIMEOUT, socket.connect((host, PORT))).await {
+        Ok(Ok(())) => {}
+        _ => return Err(PerfError::Connect),
+    }
+    info!("perf: client {} to {}", req, host);
+
+    let result = match req.proto {
+        Proto::Tcp => {
+            start(&mut socket, &req).await?;
+            match req.direction {
+                Direction::Up => {
+                    tcp_send(&mut socket, req.secs).await?;
+                    read_report(&mut socket, &req).await
+                }
+                Direction::Down => Ok(tcp_receive(&mut socket, req).await),
+            }
+        }
+        // Bind first so nothing the server sends is lost.
+        Proto::Udp => {
+            with_udp(stack, req.udp_port, async |udp| {
+                start(&mut socket, &req).await?;
+                match req.direction {
+                    Direction::Up => {
+                        udp_send(udp, (host, PORT).into(), &req).await?;
+                        read_report(&mut socket, &req).await
+                    }
+                    Direction::Down => Ok(udp_receive(udp, req).await),
+                }
+            })
+            .await
+        }
+    };
+    socket.close();
+    result
+}
+
+// Serves tests forever, one at a time. Results go to RESULTS.
+pub async fn server(stack: Stack<'_>) -> ! {
+    let mut rx_buf = [0u8; 4096];
+    let mut tx_buf = [0u8; 4096];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("perf: accept failed: {:?}", e);
+            Timer::after_secs(1).await;
+            continue;
+        }
+        let Some(peer) = socket.remote_endpoint() else {
+            continue;
+        };
+        match serve_one(stack, &mut socket, peer).await {
+            Ok(result) => {
+                info!("perf: {} from {}", result, peer);
+                RESULTS.try_send(result).ok();
+            }
+            Err(e) => warn!("perf: test from {} failed: {}", peer, e),
+        }
+        socket.close();
+        socket.flush().await.ok();
+    }
+}
+
+async fn serve_one(
+    stack: Stack<'_>,
+    socket: &mut TcpSocket<'_>,
+    peer: IpEndpoint,
+) -> Result<PerfResult, PerfError> {
+    let mut buf = [0u8; REQUEST_LEN];
+    with_timeout(CONTROL_TIMEOUT, read_exact(socket, &mut buf))
+        .await
+        .map_err(|_| PerfError::Timeout)??;
+    let req = Request::decode(&buf).ok_or(PerfError::Protocol)?;
+    socket.set_timeout(Some(CONTROL_TIMEOUT + Duration::from_secs(req.secs as u64)));
+    info!("perf: serving {} for {}", req, peer);
+
+    match (req.proto, req.direction) {
+        (Proto::Tcp, Direction::Up) => {
+            write_ack(socket).await?;
+            let result = tcp_receive(socket, req).await;
+            write_all(socket, &result.encode_report())
+                .await
+                .map_err(|_| PerfError::Socket)?;
+            Ok(result)
+        }
+        (Proto::Tcp, Direction::Down) => {
+            write_ack(socket).await?;
+            let start = Instant::now();
+            let bytes = tcp_send(socket, req.secs).await?;
+            Ok(PerfResult {
+                proto: req.proto,
+                direction: req.direction,
+                bytes,
+                elapsed_ms: start.elapsed().as_millis() as u32,
+                udp: None,
+            })
+        }
+        (Proto::Udp, Direction::Up) => {
+            let result = with_udp(stack, PORT, async |udp| {
+                write_ack(socket).await?;
+                Ok(udp_receive(udp, req).await)
+            })
+            .await?;
+            write_all(socket, &result.encode_report())
+                .await
+                .map_err(|_| PerfError::Socket)?;
+            Ok(result)
+        }
+        (Proto::Udp, Direction::Down) => {
+            let remote = IpEndpoint::new(peer.addr, req.udp_port);
+            with_udp(stack, PORT, async |udp| {
+                write_ack(socket).await?;
+                let start = Instant::now();
+                let bytes = udp_send(udp, remote, &req).await?;
+                Ok(PerfResult {
+                    proto: req.proto,
+                    direction: req.direction,
+                    bytes,
+                    elapsed_ms: start.elapsed().as_millis() as u32,
+                    udp: None,
+                })
+            })
+            .await
+        }
+    }
+}
+
+async fn with_udp<R>(
+    stack: Stack<'_>,
+    port: u16,
+    f: impl AsyncFnOnce(&mut UdpSocket<'_>) -> Result<R, PerfError>,
+) -> Result<R, PerfError> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buf = [0u8; 4096];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buf = [0u8; 4096];
+    let mut udp = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    udp.bind(port).map_err(|_| PerfError::Socket)?;
+    f(&mut udp).await
+}
+
+async fn tcp_send(socket: &mut TcpSocket<'_>, secs: u16) -> Result<u64, PerfError> {
+    let chunk = [0x5au8; 1024];
+    let deadline = Instant::now() + Duration::from_secs(secs as u64);
+    let mut sent = 0u64;
+    while Instant::now() < deadline {
+        sent += socket.write(&chunk).await.map_err(|_| PerfError::Socket)? as u64;
+    }
+    socket.flush().await.map_err(|_| PerfError::Socket)?;
+    // Half close so the receiver sees EOF but we can still read its report.
+    socket.close();
+    Ok(sent)
+}
+
+async fn tcp_receive(socket: &mut TcpSocket<'_>, req: Request) -> PerfResult {
+    let mut buf = [0u8; 1024];
+    let mut bytes = 0u64;
+    let mut start = None;
+    loop {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                start.get_or_insert_with(Instant::now);
+                bytes += n as u64;
+            }
+        }
+    }
+    PerfResult {
+        proto: req.proto,
+        direction: req.direction,
+        bytes,
+        elapsed_ms: start.map_or(0, |s: Instant| s.elapsed().as_millis() as u32),
+        udp: None,
+    }
+}
+
+async fn udp_send(
+    udp: &mut UdpSocket<'_>,
+    remote: IpEndpoint,
+    req: &Request,
+) -> Result<u64, PerfError> {
+    let len = req.payload_len as usize;
+    let mut packet = [0u8; MAX_PAYLOAD];
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(req.secs as u64);
+    let mut sent = 0u64;
+    let mut seq = 0u32;
+
+    while Instant::now() < deadline {
+        packet[..4].copy_from_slice(&seq.to_be_bytes());
+        packet[4..8].copy_from_slice(&(Instant::now().as_micros() as u32).to_be_bytes());
+        udp.send_to(&packet[..len], remote)
+            .await
+            .map_err(|_| PerfError::Socket)?;
+        sent += len as u64;
+        seq = seq.wrapping_add(1);
+        // kbit/s is bits per millisecond, pace to where we should be.
+        Timer::at(start + Duration::from_micros(sent * 8 * 1000 / req.rate_kbps as u64)).await;
+    }
+
+    packet[..4].copy_from_slice(&END_SEQ.to_be_bytes());
+    for _ in 0..3 {
+        udp.send_to(&packet[..8], remote)
+            .await
+            .map_err(|_| PerfError::Socket)?;
+        Timer::after_millis(10).await;
+    }
+    Ok(sent)
+}
+
+async fn udp_receive(udp: &mut UdpSocket<'_>, req: Request) -> PerfResult {
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let mut stats = UdpStats::default();
+    let mut bytes = 0u64;
+    let mut first: Option<Instant> = None;
+    let mut last = Instant::now();
+    let mut highest: Option<u32> = None;
+    let mut prev_transit: Option<i64> = None;
+    let mut jitter: i64 = 0;
+
+    // Wait for the whole test to start, then stop when the sender goes quiet.
+    let mut idle = Duration::from_secs(req.secs as u64) + CONTROL_TIMEOUT;
+    loop {
+        let Ok(Ok((n, _))) = with_timeout(idle, udp.recv_from(&mut buf)).await else {
+            break;
+        };
+        idle = UDP_IDLE;
+        if n < 8 {
+            continue;
+        }
+        let seq = u32::from_be_bytes(buf[..4].try_into().unwrap());
+        if seq == END_SEQ {
+            break;
+        }
+        let now = Instant::now();
+        first.get_or_insert(now);
+        last = now;
+        stats.received += 1;
+        bytes += n as u64;
+        highest = Some(highest.map_or(seq, |h| h.max(seq)));
+
+        // RFC 3550 jitter. Clocks differ, but only changes in transit time
+        // matter, so the offset cancels out.
+        let sent_us = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+        let transit = (now.as_micros() as u32).wrapping_sub(sent_us) as i32 as i64;
+        if let Some(prev) = prev_transit {
+            let d = (transit - prev).abs();
+            jitter += (d - jitter) / 16;
+        }
+        prev_transit = Some(transit);
+    }
+
+    let expected = highest.map_or(0, |h| h + 1);
+    stats.lost = expected.saturating_sub(stats.received);
+    stats.jitter_us = jitter as u32;
+    PerfResult {
+        proto: req.proto,
+        direction: req.direction,
+        bytes,
+        elapsed_ms: first.map_or(0, |f| (last - f).as_millis() as u32),
+        udp: Some(stats),
+    }
+}
+
+async fn write_ack(socket: &mut TcpSocket<'_>) -> Result<(), PerfError> {
+    write_all(socket, ACK).await.map_err(|_| PerfError::Socket)
+}
+
+// Client side: send the request and wait for the server to be ready.
+async fn start(socket: &mut TcpSocket<'_>, req: &Request) -> Result<(), PerfError> {
+    write_all(socket, &req.encode())
+        .await
+        .map_err(|_| PerfError::Socket)?;
+    let mut ack = [0u8; 4];
+    with_timeout(CONTROL_TIMEOUT, read_exact(socket, &mut ack))
+        .await
+        .map_err(|_| PerfError::Timeout)??;
+    if &ack != ACK {
+        return Err(PerfError::Protocol);
+    }
+    Ok(())
+}
+
+async fn read_report(socket: &mut TcpSocket<'_>, req: &Request) -> Result<PerfResult, PerfError> {
+    let mut buf = [0u8; REPORT_LEN];
+    with_timeout(CONTROL_TIMEOUT, read_exact(socket, &mut buf))
+        .await
+        .map_err(|_| PerfError::Timeout)??;
+    PerfResult::decode_report(req, &buf).ok_or(PerfError::Protocol)
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Result<(), PerfError> {
+    let mut got = 0;
+    while got < buf.len() {
+        match socket.read(&mut buf[got..]).await {
+            Ok(0) | Err(_) => return Err(PerfError::Socket),
+            Ok(n) => got += n,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UDP_DOWN: Request = Request {
+        proto: Proto::Udp,
+        direction: Direction::Down,
+        secs: 30,
+        payload_len: 1200,
+        rate_kbps: 5000,
+        udp_port: 40123,
+    };
+
+    #[test]
+    fn request_round_trip() {
+        let buf = UDP_DOWN.encode();
+        assert_eq!(&buf[..8], b"PMPF\x01\x01\x01\x00");
+        assert_eq!(Request::decode(&buf), Some(UDP_DOWN));
+
+        let tcp_up = Request {
+            proto: Proto::Tcp,
+            direction: Direction::Up,
+            ..UDP_DOWN
+        };
+        assert_eq!(Request::decode(&tcp_up.encode()), Some(tcp_up));
+    }
+
+    #[test]
+    fn request_clamps_limits() {
+        let wild = Request {
+            secs: 0,
+            payload_len: 9000,
+            rate_kbps: 0,
+            ..UDP_DOWN
+        };
+        let req = Request::decode(&wild.encode()).unwrap();
+        assert_eq!(req.secs, 1);
+        assert_eq!(req.payload_len, MAX_PAYLOAD as u16);
+        assert_eq!(req.rate_kbps, 1);
+
+        let long = Request {
+            secs: 1000,
+            payload_len: 2,
+            ..UDP_DOWN
+        };
+        let req = Request::decode(&long.encode()).unwrap();
+        assert_eq!(req.secs, MAX_SECS);
+        assert_eq!(req.payload_len, 8);
+    }
+
+    #[test]
+    fn request_rejects_garbage() {
+        let buf = UDP_DOWN.encode();
+        assert_eq!(Request::decode(&buf[..REQUEST_LEN - 1]), None);
+        let mut bad = buf;
+        bad[0] = b'X';
+        assert_eq!(Request::decode(&bad), None);
+        let mut bad = buf;
+        bad[4] = 2;
+        assert_eq!(Request::decode(&bad), None);
+        let mut bad = buf;
+        bad[5] = 2;
+        assert_eq!(Request::decode(&bad), None);
+        let mut bad = buf;
+        bad[6] = 2;
+        assert_eq!(Request::decode(&bad), None);
+    }
+
+    #[test]
+    fn report_round_trip() {
+        let udp = PerfResult {
+            proto: Proto::Udp,
+            direction: Direction::Down,
+            bytes: 5_000_000_000,
+            elapsed_ms: 30_012,
+            udp: Some(UdpStats {
+                received: 104_000,
+                lost: 17,
+                jitter_us: 1_250,
+            }),
+        };
+        let buf = udp.encode_report();
+        assert_eq!(&buf[..4], b"PMPR");
+        assert_eq!(PerfResult::decode_report(&UDP_DOWN, &buf), Some(udp));
+        assert_eq!(
+            PerfResult::decode_report(&UDP_DOWN, &buf[..REPORT_LEN - 1]),
+            None
+        );
+
+        // Over TCP the datagram counters are zero and not reported.
+        let tcp_req = Request {
+            proto: Proto::Tcp,
+            direction: Direction::Up,
+            ..UDP_DOWN
+        };
+        let tcp = PerfResult {
+            proto: Proto::Tcp,
+            direction: Direction::Up,
+            udp: None,
+            ..udp
+        };
+        let buf = tcp.encode_report();
+        assert_eq!(&buf[16..], &[0; 12]);
+        assert_eq!(PerfResult::decode_report(&tcp_req, &buf), Some(tcp));
+    }
+
+    #[test]
+    fn loss_and_rate() {
+        let result = PerfResult {
+            proto: Proto::Udp,
+            direction: Direction::Up,
+            bytes: 1_250_000,
+            elapsed_ms: 1_000,
+            udp: Some(UdpStats {
+                received: 990,
+                lost: 10,
+                jitter_us: 0,
+            }),
+        };
+        assert_eq!(result.kbps(), 10_000);
+        assert_eq!(result.loss_permille(), Some(10));
+    }
+}
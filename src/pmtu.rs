@@ -0,0 +1,593 @@
+//! pmtu
+//! ----
+//!
+//! Path MTU discovery with don't-fragment ICMP echoes.
+//!
+//! Finds the biggest IP packet that makes it to a target and back by
+//! binary search on echo request size. A reply means that size fits. An
+//! ICMP Fragmentation Needed (ICMPv6 Packet Too Big) means it doesn't, and
+//! the next-hop MTU the router puts in it is tried next. Silence, after a
+//! retry, also counts as too big: that's what an MTU black hole looks
+//! like, and it shows up as a path MTU below ours with no Fragmentation
+//! Needed to explain it.
+//!
+//! smoltcp sets DF on everything it sends and never fragments, so the
+//! biggest size we can test is our own interface MTU. IPv6 routers never
+//! fragment either, and every IPv6 link carries 1280 bytes, so an IPv6
+//! search starts from there.
+use core::fmt;
+use defmt::{debug, info};
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{IpAddress, Ipv4Address, Ipv6Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline};
+use heapless::Vec;
+
+// Ethernet, for both the CYW43439 and the W5500.
+pub const LOCAL_MTU: u16 = 1500;
+// Every IPv4 link has to carry this much.
+pub const MIN_SIZE: u16 = 68;
+// And every IPv6 link this much.
+pub const MIN_SIZE6: u16 = 1280;
+
+const IPV4_HEADER: usize = 20;
+const IPV6_HEADER: usize = 40;
+const ICMP_HEADER: usize = 8;
+const PROTO_ICMP: u8 = 1;
+const PROTO_ICMPV6: u8 = 58;
+const ECHO_REPLY: u8 = 0;
+const DEST_UNREACHABLE: u8 = 3;
+const FRAG_NEEDED: u8 = 4;
+const ECHO_REQUEST: u8 = 8;
+const PACKET_TOO_BIG: u8 = 2;
+const ECHO_REQUEST6: u8 = 128;
+const ECHO_REPLY6: u8 = 129;
+// "PM", so our echoes are easy to spot in a capture.
+const IDENT: u16 = 0x504d;
+const REPLY_TIMEOUT: Duration = Duration::from_millis(800);
+const TRIES: usize = 2;
+
+// One's complement sum of 16 bit words, as IPv4 and ICMP use.
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(add_words(0, data))
+}
+
+// The same over IPv6, where ICMPv6 and TCP also cover a pseudo header of
+// the addresses, length and next header.
+pub fn checksum6(src: [u8; 16], dst: [u8; 16], next_header: u8, data: &[u8]) -> u16 {
+    let mut sum = add_words(0, &src);
+    sum = add_words(sum, &dst);
+    sum = add_words(sum, &(data.len() as u32).to_be_bytes());
+    sum = add_words(sum, &[0, 0, 0, next_header]);
+    fold(add_words(sum, data))
+}
+
+// Only the last chunk summed may have an odd length.
+fn add_words(mut sum: u32, data: &[u8]) -> u32 {
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// An echo request `size` bytes long, IPv4 header included, with DF set.
+// `buf` must be at least `size` bytes.
+pub fn build_echo(buf: &mut [u8], src: [u8; 4], dst: [u8; 4], seq: u16, size: u16) -> &[u8] {
+    let packet = &mut buf[..size as usize];
+    let (ip, icmp) = packet.split_at_mut(IPV4_HEADER);
+
+    ip.copy_from_slice(&[0; IPV4_HEADER]);
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&size.to_be_bytes());
+    ip[4..6].copy_from_slice(&seq.to_be_bytes());
+    // Don't fragment.
+    ip[6] = 0x40;
+    ip[8] = 64;
+    ip[9] = PROTO_ICMP;
+    ip[12..16].copy_from_slice(&src);
+    ip[16..20].copy_from_slice(&dst);
+    let sum = checksum(ip);
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    fill_echo(icmp, ECHO_REQUEST, seq);
+    let sum = checksum(icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+// The same over IPv6, with an ICMPv6 echo. There's no DF bit, IPv6
+// routers never fragment.
+pub fn build_echo6(buf: &mut [u8], src: [u8; 16], dst: [u8; 16], seq: u16, size: u16) -> &[u8] {
+    let packet = &mut buf[..size as usize];
+    let (ip, icmp) = packet.split_at_mut(IPV6_HEADER);
+
+    ip.copy_from_slice(&[0; IPV6_HEADER]);
+    ip[0] = 0x60;
+    ip[4..6].copy_from_slice(&(size - IPV6_HEADER as u16).to_be_bytes());
+    ip[6] = PROTO_ICMPV6;
+    ip[7] = 64;
+    ip[8..24].copy_from_slice(&src);
+    ip[24..40].copy_from_slice(&dst);
+
+    fill_echo(icmp, ECHO_REQUEST6, seq);
+    let sum = checksum6(src, dst, PROTO_ICMPV6, icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+// Echo header with our ident, checksum left at zero, then a counting
+// pattern.
+fn fill_echo(icmp: &mut [u8], kind: u8, seq: u16) {
+    icmp[..ICMP_HEADER].copy_from_slice(&[kind, 0, 0, 0, 0, 0, 0, 0]);
+    icmp[4..6].copy_from_slice(&IDENT.to_be_bytes());
+    icmp[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, b) in icmp[ICMP_HEADER..].iter_mut().enumerate() {
+        *b = i as u8;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Echo { seq: u16 },
+    // Or Packet Too Big, for IPv6. `mtu` is 0 from routers too old to
+    // say.
+    FragNeeded { seq: u16, from: IpAddress, mtu: u16 },
+}
+
+impl Reply {
+    pub fn seq(&self) -> u16 {
+        match self {
+            Reply::Echo { seq } | Reply::FragNeeded { seq, .. } => *seq,
+        }
+    }
+}
+
+// The ICMP message in an IPv4 packet, at least its 8 byte header.
+fn icmp_of(packet: &[u8]) -> Option<&[u8]> {
+    let ihl = (*packet.first()? & 0x0f) as usize * 4;
+    if packet[0] >> 4 != 4 || ihl < IPV4_HEADER || packet.len() < ihl + ICMP_HEADER {
+        return None;
+    }
+    (packet[9] == PROTO_ICMP).then(|| &packet[ihl..])
+}
+
+// An IPv4 packet from the raw socket, if it answers one of our echoes to
+// `target`.
+pub fn parse_reply(packet: &[u8], target: [u8; 4]) -> Option<Reply> {
+    let icmp = icmp_of(packet)?;
+    let ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+    match (icmp[0], icmp[1]) {
+        (ECHO_REPLY, 0) if packet[12..16] == target && ident == IDENT => Some(Reply::Echo {
+            seq: u16::from_be_bytes([icmp[6], icmp[7]]),
+        }),
+        (DEST_UNREACHABLE, FRAG_NEEDED) => {
+            // The router quotes our IPv4 header and the start of the echo.
+            let quoted = &icmp[ICMP_HEADER..];
+            let echo = icmp_of(quoted)?;
+            if quoted[16..20] != target
+                || echo[0] != ECHO_REQUEST
+                || echo[4..6] != IDENT.to_be_bytes()
+            {
+                return None;
+            }
+            let from: [u8; 4] = packet[12..16].try_into().unwrap();
+            Some(Reply::FragNeeded {
+                seq: u16::from_be_bytes([echo[6], echo[7]]),
+                from: Ipv4Address::from(from).into(),
+                mtu: u16::from_be_bytes([icmp[6], icmp[7]]),
+            })
+        }
+        _ => None,
+    }
+}
+
+// The ICMPv6 message in an IPv6 packet with no extension headers, at
+// least its 8 byte header.
+fn icmp6_of(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < IPV6_HEADER + ICMP_HEADER || packet[0] >> 4 != 6 || packet[6] != PROTO_ICMPV6
+    {
+        return None;
+    }
+    Some(&packet[IPV6_HEADER..])
+}
+
+// `parse_reply` for IPv6.
+pub fn parse_reply6(packet: &[u8], target: [u8; 16]) -> Option<Reply> {
+    let icmp = icmp6_of(packet)?;
+    let ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+    match (icmp[0], icmp[1]) {
+        (ECHO_REPLY6, 0) if packet[8..24] == target && ident == IDENT => Some(Reply::Echo {
+            seq: u16::from_be_bytes([icmp[6], icmp[7]]),
+        }),
+        (PACKET_TOO_BIG, 0) => {
+            // The router quotes as much of our echo as fits in 1280 bytes.
+            let quoted = &icmp[ICMP_HEADER..];
+            let echo = icmp6_of(quoted)?;
+            if quoted[24..40] != target
+                || echo[0] != ECHO_REQUEST6
+                || echo[4..6] != IDENT.to_be_bytes()
+            {
+                return None;
+            }
+            let from: [u8; 16] = packet[8..24].try_into().unwrap();
+            let mtu = u32::from_be_bytes(icmp[4..8].try_into().unwrap());
+            Some(Reply::FragNeeded {
+                seq: u16::from_be_bytes([echo[6], echo[7]]),
+                from: Ipv6Address::from(from).into(),
+                mtu: mtu.min(u16::MAX as u32) as u16,
+            })
+        }
+        _ => None,
+    }
+}
+
+// Binary search between the biggest size known to fit and the smallest
+// known not to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Search {
+    fits: u16,
+    too_big: u16,
+    // Try the biggest candidate next rather than the middle one.
+    try_top: bool,
+}
+
+impl Search {
+    // Assumes `min` fits; check that first.
+    pub fn new(min: u16, max: u16) -> Self {
+        Self {
+            fits: min,
+            too_big: max + 1,
+            try_top: true,
+        }
+    }
+
+    // The next size to try, None once it's pinned down.
+    pub fn next(&self) -> Option<u16> {
+        let gap = self.too_big - self.fits;
+        if gap <= 1 {
+            None
+        } else if self.try_top {
+            Some(self.too_big - 1)
+        } else {
+            Some(self.fits + gap / 2)
+        }
+    }
+
+    pub fn fits(&mut self, size: u16) {
+        self.fits = self.fits.max(size);
+        self.try_top = false;
+    }
+
+    // With the next-hop MTU from a Fragmentation Needed, if there was one.
+    pub fn too_big(&mut self, size: u16, hint: Option<u16>) {
+        self.too_big = self.too_big.min(size);
+        self.try_top = false;
+        if let Some(mtu) = hint
+            && mtu > self.fits
+            && mtu < self.too_big
+        {
+            self.too_big = mtu + 1;
+            self.try_top = true;
+        }
+    }
+
+    pub fn result(&self) -> u16 {
+        self.fits
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct FragNeeded {
+    pub from: IpAddress,
+    // 0 when the router didn't say.
+    pub mtu: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PmtuResult {
+    pub target: IpAddress,
+    // None when even the smallest echo got no reply.
+    pub mtu: Option<u16>,
+    // Distinct ones seen, up to 4.
+    pub frag_needed: Vec<FragNeeded, 4>,
+    pub probes: u16,
+}
+
+impl PmtuResult {
+    // Smaller than ours with no Fragmentation Needed to say why.
+    pub fn black_hole(&self) -> bool {
+        self.mtu.is_some_and(|mtu| mtu < LOCAL_MTU) && self.frag_needed.is_empty()
+    }
+}
+
+// "8.8.8.8 path MTU 1400 (frag needed from 10.0.0.1, mtu 1400)"
+impl fmt::Display for PmtuResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mtu) = self.mtu else {
+            return write!(f, "{} path MTU unknown, no echo replies", self.target);
+        };
+        write!(f, "{} path MTU {}", self.target, mtu)?;
+        let what = match self.target {
+            IpAddress::Ipv4(_) => "frag needed",
+            IpAddress::Ipv6(_) => "too big",
+        };
+        for frag in &self.frag_needed {
+            write!(f, " ({} from {}, mtu {})", what, frag.from, frag.mtu)?;
+        }
+        if self.black_hole() {
+            write!(f, " (no frag needed seen, black hole?)")?;
+        }
+        Ok(())
+    }
+}
+
+struct Prober<'a> {
+    socket: RawSocket<'a>,
+    buf: [u8; LOCAL_MTU as usize],
+    src: IpAddress,
+    target: IpAddress,
+    seq: u16,
+    probes: u16,
+}
+
+impl Prober<'_> {
+    // One echo of `size` bytes, retried once if nothing comes back.
+    async fn try_size(&mut self, size: u16) -> Option<Reply> {
+        for _ in 0..TRIES {
+            self.seq = self.seq.wrapping_add(1);
+            self.probes += 1;
+            if let Some(reply) = self.exchange(size).await {
+                return Some(reply);
+            }
+        }
+        None
+    }
+
+    async fn exchange(&mut self, size: u16) -> Option<Reply> {
+        let packet = match (self.src, self.target) {
+            (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) => {
+                build_echo(&mut self.buf, src.octets(), dst.octets(), self.seq, size)
+            }
+            (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => {
+                build_echo6(&mut self.buf, src.octets(), dst.octets(), self.seq, size)
+            }
+            _ => return None,
+        };
+        self.socket.send(packet).await;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let Ok(Ok(n)) = with_deadline(deadline, self.socket.recv(&mut self.buf)).await else {
+                return None;
+            };
+            // Anything else ICMP comes through here too, including late
+            // replies to earlier tries.
+            let reply = match self.target {
+                IpAddress::Ipv4(target) => parse_reply(&self.buf[..n], target.octets()),
+                IpAddress::Ipv6(target) => parse_reply6(&self.buf[..n], target.octets()),
+            };
+            if let Some(reply) = reply
+                && reply.seq() == self.seq
+            {
+                return Some(reply);
+            }
+        }
+    }
+}
+
+pub async fn probe(stack: Stack<'_>, target: IpAddress) -> PmtuResult {
+    let mut result = PmtuResult {
+        target,
+        mtu: None,
+        frag_needed: Vec::new(),
+        probes: 0,
+    };
+    let (src, version, protocol, min) = match target {
+        IpAddress::Ipv4(_) => (
+            stack
+                .config_v4()
+                .map(|c| IpAddress::Ipv4(c.address.address())),
+            IpVersion::Ipv4,
+            IpProtocol::Icmp,
+            MIN_SIZE,
+        ),
+        IpAddress::Ipv6(_) => (
+            stack
+                .config_v6()
+                .map(|c| IpAddress::Ipv6(c.address.address())),
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            MIN_SIZE6,
+        ),
+    };
+    let Some(src) = src else {
+        return result;
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buf = [0u8; LOCAL_MTU as usize + 100];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; LOCAL_MTU as usize];
+    // The driver type isn't used for anything, any driver will do.
+    let socket = RawSocket::new::<cyw43::NetDriver<'static>>(
+        stack,
+        version,
+        protocol,
+        &mut rx_meta,
+        &mut rx_buf,
+        &mut tx_meta,
+        &mut tx_buf,
+    );
+    let mut prober = Prober {
+        socket,
+        buf: [0; LOCAL_MTU as usize],
+        src,
+        target,
+        seq: 0,
+        probes: 0,
+    };
+
+    // Nothing to learn from a host that doesn't answer pings.
+    if !matches!(prober.try_size(min).await, Some(Reply::Echo { .. })) {
+        info!("pmtu: no echo reply from {}", target);
+        result.probes = prober.probes;
+        return result;
+    }
+
+    let mut search = Search::new(min, LOCAL_MTU);
+    while let Some(size) = search.next() {
+        match prober.try_size(size).await {
+            Some(Reply::Echo { .. }) => search.fits(size),
+            Some(Reply::FragNeeded { from, mtu, .. }) => {
+                let frag = FragNeeded { from, mtu };
+                debug!("pmtu: {} too big, {}", size, frag);
+                if !result.frag_needed.contains(&frag) {
+                    result.frag_needed.push(frag).ok();
+                }
+                search.too_big(size, (mtu > 0).then_some(mtu));
+            }
+            None => search.too_big(size, None),
+        }
+    }
+
+    result.mtu = Some(search.result());
+    result.probes = prober.probes;
+    info!("pmtu: {}", defmt::Display2Format(&result));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: [u8; 4] = [192, 168, 1, 77];
+    const DST: [u8; 4] = [8, 8, 8, 8];
+    const ROUTER: [u8; 4] = [10, 0, 0, 1];
+    const SRC6: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x77];
+    const DST6: [u8; 16] = [
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x20,
+    ];
+    const ROUTER6: [u8; 16] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+    #[test]
+    fn builds_echo() {
+        let mut buf = [0u8; 128];
+        let packet = build_echo(&mut buf, SRC, DST, 7, 100);
+        assert_eq!(packet.len(), 100);
+        assert_eq!(&packet[..4], &[0x45, 0, 0, 100]);
+        assert_eq!(packet[6] & 0x40, 0x40);
+        assert_eq!(checksum(&packet[..IPV4_HEADER]), 0);
+        assert_eq!(checksum(&packet[IPV4_HEADER..]), 0);
+        assert_eq!(&packet[20..22], &[ECHO_REQUEST, 0]);
+        assert_eq!(&packet[24..28], &[0x50, 0x4d, 0, 7]);
+    }
+
+    #[test]
+    fn parses_replies() {
+        let mut buf = [0u8; 128];
+        let mut reply = build_echo(&mut buf, DST, SRC, 7, 100).to_vec();
+        reply[IPV4_HEADER] = ECHO_REPLY;
+        assert_eq!(parse_reply(&reply, DST), Some(Reply::Echo { seq: 7 }));
+        assert_eq!(parse_reply(&reply, ROUTER), None);
+
+        // Router's IPv4 header, then type 3 code 4 with the next-hop MTU,
+        // then our echo's header and first 8 bytes.
+        let mut big = [0u8; LOCAL_MTU as usize];
+        let echo = build_echo(&mut big, SRC, DST, 9, 1500);
+        let mut frag = [0u8; 20 + 8 + 28];
+        frag[..20].copy_from_slice(&build_echo(&mut [0u8; 64], ROUTER, SRC, 0, 56)[..20]);
+        frag[20..28].copy_from_slice(&[DEST_UNREACHABLE, FRAG_NEEDED, 0, 0, 0, 0, 0x05, 0x78]);
+        frag[28..].copy_from_slice(&echo[..28]);
+        assert_eq!(
+            parse_reply(&frag, DST),
+            Some(Reply::FragNeeded {
+                seq: 9,
+                from: Ipv4Address::from(ROUTER).into(),
+                mtu: 1400
+            })
+        );
+        assert_eq!(parse_reply(&frag, ROUTER), None);
+        assert_eq!(parse_reply(&frag[..30], DST), None);
+    }
+
+    #[test]
+    fn builds_echo6() {
+        let mut buf = [0u8; 128];
+        let packet = build_echo6(&mut buf, SRC6, DST6, 7, 100);
+        assert_eq!(packet.len(), 100);
+        assert_eq!(&packet[..8], &[0x60, 0, 0, 0, 0, 60, PROTO_ICMPV6, 64]);
+        assert_eq!(&packet[24..40], &DST6);
+        let icmp = &packet[IPV6_HEADER..];
+        assert_eq!(checksum6(SRC6, DST6, PROTO_ICMPV6, icmp), 0);
+        assert_eq!(&icmp[..2], &[ECHO_REQUEST6, 0]);
+        assert_eq!(&icmp[4..8], &[0x50, 0x4d, 0, 7]);
+    }
+
+    #[test]
+    fn parses_replies6() {
+        let mut buf = [0u8; 128];
+        let mut reply = build_echo6(&mut buf, DST6, SRC6, 7, 100).to_vec();
+        reply[IPV6_HEADER] = ECHO_REPLY6;
+        assert_eq!(parse_reply6(&reply, DST6), Some(Reply::Echo { seq: 7 }));
+        assert_eq!(parse_reply6(&reply, ROUTER6), None);
+        assert_eq!(parse_reply(&reply, DST), None);
+
+        // Router's IPv6 header, then type 2 with the MTU, then the start
+        // of our echo.
+        let mut big = [0u8; LOCAL_MTU as usize];
+        let echo = build_echo6(&mut big, SRC6, DST6, 9, 1500);
+        let mut too_big = [0u8; 40 + 8 + 64];
+        too_big[..40].copy_from_slice(&build_echo6(&mut [0u8; 112], ROUTER6, SRC6, 0, 112)[..40]);
+        too_big[40..48].copy_from_slice(&[PACKET_TOO_BIG, 0, 0, 0, 0, 0, 0x05, 0x78]);
+        too_big[48..].copy_from_slice(&echo[..64]);
+        assert_eq!(
+            parse_reply6(&too_big, DST6),
+            Some(Reply::FragNeeded {
+                seq: 9,
+                from: Ipv6Address::from(ROUTER6).into(),
+                mtu: 1400
+            })
+        );
+        assert_eq!(parse_reply6(&too_big, ROUTER6), None);
+        assert_eq!(parse_reply6(&too_big[..80], DST6), None);
+    }
+
+    fn run(min: u16, path_mtu: u16, hint: bool) -> (u16, usize) {
+        let mut search = Search::new(min, LOCAL_MTU);
+        let mut probes = 0;
+        while let Some(size) = search.next() {
+            probes += 1;
+            if size <= path_mtu {
+                search.fits(size);
+            } else {
+                search.too_big(size, hint.then_some(path_mtu));
+            }
+        }
+        (search.result(), probes)
+    }
+
+    #[test]
+    fn searches() {
+        assert_eq!(run(MIN_SIZE, 1500, false), (1500, 1));
+        assert_eq!(run(MIN_SIZE, 1400, true), (1400, 2));
+        let (mtu, probes) = run(MIN_SIZE, 1436, false);
+        assert_eq!(mtu, 1436);
+        assert!(probes <= 12);
+        assert_eq!(run(MIN_SIZE, MIN_SIZE, false).0, MIN_SIZE);
+        // IPv6 starts at 1280, so fewer sizes to go through.
+        let (mtu, probes) = run(MIN_SIZE6, 1436, false);
+        assert_eq!(mtu, 1436);
+        assert!(probes <= 9);
+        assert_eq!(run(MIN_SIZE6, MIN_SIZE6, false).0, MIN_SIZE6);
+    }
+}
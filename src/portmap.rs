@@ -0,0 +1,740 @@
+//! portmap
+//! -------
+//!
+//! Audit of what the gateway lets hosts open through it on their own.
+//!
+//!   - NAT-PMP and PCP on UDP 5351 of the DHCP gateway. A PCP `ANNOUNCE`
+//!     tells us whether PCP is there; the NAT-PMP external address request
+//!     gives the WAN address, and PCP servers answer it too.
+//!   - UPnP IGD: an SSDP M-SEARCH for InternetGatewayDevice, the device
+//!     description at the LOCATION the gateway gives, and from it the
+//!     WANIPConnection (or WANPPPConnection) control URL. Then
+//!     `GetGenericPortMappingEntry` from index 0 until the gateway says
+//!     there are no more, which lists every mapping whoever made it.
+//!
+//! Only answers from the gateway itself count; a UPnP media server on some
+//! other host isn't a router. Nothing here adds or removes a mapping.
+//!
+//! Mappings are logged and go in `PORTMAP.CSV`. Only the first
+//! `MAX_MAPPINGS` are kept; if the gateway has more, the summary line says
+//! so.
+use core::fmt::{self, Write};
+use defmt::{debug, info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use heapless::{String, Vec};
+
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::util::{element, http_body, http_status_code, text, write_all};
+
+pub const PMP_PORT: u16 = 5351;
+pub const SSDP_GROUP: Ipv4Address = Ipv4Address::new(239, 255, 255, 250);
+pub const SSDP_PORT: u16 = 1900;
+pub const MAX_MAPPINGS: usize = 32;
+pub const EXPORT_FILE: &str = "PORTMAP.CSV";
+pub const CSV_HEADER: &str =
+    "protocol,external_port,remote_host,internal_host,internal_port,enabled,lease,description";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+// RFC 6886 starts at 250ms and doubles; two tries of a second is plenty
+// on the LAN side of the router.
+const PMP_WAIT: Duration = Duration::from_secs(1);
+const SSDP_WAIT: Duration = Duration::from_secs(3);
+// Of a description too big for the buffer, what's kept when it slides.
+// Enough for a whole <service> element.
+const KEEP: usize = 1024;
+
+// Version 0, opcode 0: external address request.
+pub const NATPMP_REQUEST: [u8; 2] = [0, 0];
+
+pub const MSEARCH: &str = concat!(
+    "M-SEARCH * HTTP/1.1\r\n",
+    "HOST: 239.255.255.250:1900\r\n",
+    "MAN: \"ssdp:discover\"\r\n",
+    "MX: 2\r\n",
+    "ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n",
+    "\r\n",
+);
+
+// Where an HTTP request goes. IGDs give their addresses as IP literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: Ipv4Address,
+    pub port: u16,
+    pub path: String<96>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mapping {
+    pub protocol: String<4>,
+    pub external_port: u16,
+    // Empty when any remote host may connect, which is the usual.
+    pub remote_host: String<40>,
+    pub internal_host: String<40>,
+    pub internal_port: u16,
+    pub enabled: bool,
+    // Seconds left, 0 for permanent.
+    pub lease: u32,
+    pub description: String<48>,
+}
+
+// "TCP 8080 -> 192.168.1.23:80 Plex Media Server"
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} -> {}:{}",
+            self.protocol, self.external_port, self.internal_host, self.internal_port
+        )?;
+        if !self.description.is_empty() {
+            write!(f, " {}", self.description)?;
+        }
+        if !self.remote_host.is_empty() {
+            write!(f, " from {}", self.remote_host)?;
+        }
+        if !self.enabled {
+            f.write_str(" (disabled)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Mapping {
+    pub fn csv_row(&self) -> String<192> {
+        let mut row = String::new();
+        write!(
+            row,
+            "{},{},{},{},{},{},{},{}",
+            self.protocol,
+            self.external_port,
+            self.remote_host,
+            self.internal_host,
+            self.internal_port,
+            self.enabled as u8,
+            self.lease,
+            self.description
+        )
+        .ok();
+        row
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Audit {
+    pub gateway: Ipv4Address,
+    pub natpmp: bool,
+    pub pcp: bool,
+    // WAN address, if NAT-PMP gave one.
+    pub external: Option<Ipv4Address>,
+    // The IGD's WAN connection service type, if UPnP answered.
+    pub igd: Option<String<64>>,
+    pub mappings: Vec<Mapping, MAX_MAPPINGS>,
+    // The gateway has more mappings than `mappings` holds.
+    pub truncated: bool,
+}
+
+// "192.168.1.1 NAT-PMP PCP external 203.0.113.7 UPnP WANIPConnection:1 3 mappings",
+// "32+ mappings" when there are more than we kept.
+impl fmt::Display for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.gateway)?;
+        if self.natpmp {
+            f.write_str(" NAT-PMP")?;
+        }
+        if self.pcp {
+            f.write_str(" PCP")?;
+        }
+        if let Some(external) = self.external {
+            write!(f, " external {}", external)?;
+        }
+        if let Some(igd) = &self.igd {
+            let service = igd.split("service:").nth(1).unwrap_or(igd);
+            let more = if self.truncated { "+" } else { "" };
+            write!(
+                f,
+                " UPnP {} {}{} mappings",
+                service,
+                self.mappings.len(),
+                more
+            )?;
+        }
+        if !self.natpmp && !self.pcp && self.igd.is_none() {
+            f.write_str(" no NAT-PMP, PCP or UPnP")?;
+        }
+        Ok(())
+    }
+}
+
+// Some(Ok(address)) for a NAT-PMP external address reply, Some(Err(result))
+// when it answered with an error, such as 3 while the WAN is down.
+pub fn parse_natpmp(reply: &[u8]) -> Option<Result<Ipv4Address, u16>> {
+    if reply.len() < 12 || reply[0] != 0 || reply[1] != 128 {
+        return None;
+    }
+    match u16::from_be_bytes([reply[2], reply[3]]) {
+        0 => Some(Ok(Ipv4Address::new(
+            reply[8], reply[9], reply[10], reply[11],
+        ))),
+        result => Some(Err(result)),
+    }
+}
+
+// PCP ANNOUNCE from `client`: version 2, opcode 0, lifetime 0, and our
+// address IPv4-mapped.
+pub fn pcp_announce(client: Ipv4Address) -> [u8; 24] {
+    let mut req = [0u8; 24];
+    req[0] = 2;
+    req[18] = 0xff;
+    req[19] = 0xff;
+    req[20..24].copy_from_slice(&client.octets());
+    req
+}
+
+// Whether a reply to our ANNOUNCE came from PCP. A NAT-PMP only gateway
+// answers it too, with version 0 and "unsupported version".
+pub fn parse_pcp(reply: &[u8]) -> Option<bool> {
+    match reply {
+        [2, 0x80, ..] if reply.len() >= 24 => Some(true),
+        [0, 0x80, ..] => Some(false),
+        _ => None,
+    }
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.split("\r\n")
+        .filter_map(|l| l.split_once(':'))
+        .find(|(n, _)| n.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+// LOCATION of a 200 reply to our M-SEARCH.
+pub fn parse_ssdp(reply: &[u8]) -> Option<&str> {
+    if http_status_code(reply)? != 200 {
+        return None;
+    }
+    header(core::str::from_utf8(reply).ok()?, "location")
+}
+
+// "http://192.168.1.1:5000/rootDesc.xml"
+pub fn parse_url(url: &str) -> Option<Endpoint> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    Some(Endpoint {
+        host: host.parse().ok()?,
+        port,
+        path: String::try_from(if path.is_empty() { "/" } else { path }).ok()?,
+    })
+}
+
+// A control URL as found in the description, relative to where it came
+// from unless it's a whole URL.
+pub fn resolve(base: &Endpoint, url: &str) -> Option<Endpoint> {
+    if url.starts_with("http://") {
+        return parse_url(url);
+    }
+    let mut endpoint = Endpoint {
+        path: String::new(),
+        ..base.clone()
+    };
+    if !url.starts_with('/') {
+        endpoint.path.push('/').ok()?;
+    }
+    endpoint.path.push_str(url).ok()?;
+    Some(endpoint)
+}
+
+// Service type and control URL of the first WAN connection service whose
+// <service> element is all there.
+pub fn find_service(xml: &str) -> Option<(&str, &str)> {
+    let mut rest = xml;
+    while let Some(start) = rest.find("<service>") {
+        let block = &rest[start..];
+        let end = block.find("</service>")?;
+        rest = &block[end..];
+        let service = &block[..end];
+        let (Some(kind), Some(url)) = (
+            element(service, "serviceType"),
+            element(service, "controlURL"),
+        ) else {
+            continue;
+        };
+        let kind = kind.trim();
+        if kind.contains(":WANIPConnection:") || kind.contains(":WANPPPConnection:") {
+            return Some((kind, url.trim()));
+        }
+    }
+    None
+}
+
+pub fn describe_request(location: &Endpoint) -> String<160> {
+    let mut req = String::new();
+    write!(
+        req,
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
+        location.path, location.host, location.port
+    )
+    .ok();
+    req
+}
+
+pub fn entry_request(control: &Endpoint, service: &str, index: usize) -> String<1024> {
+    let mut body: String<512> = String::new();
+    write!(
+        body,
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/""#,
+            r#" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:GetGenericPortMappingEntry xmlns:u="{}">"#,
+            "<NewPortMappingIndex>{}</NewPortMappingIndex>",
+            "</u:GetGenericPortMappingEntry></s:Body></s:Envelope>",
+        ),
+        service, index
+    )
+    .ok();
+    let mut req = String::new();
+    write!(
+        req,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\n\
+         Content-Type: text/xml; charset=\"utf-8\"\r\n\
+         SOAPAction: \"{}#GetGenericPortMappingEntry\"\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        control.path,
+        control.host,
+        control.port,
+        service,
+        body.len(),
+        body
+    )
+    .ok();
+    req
+}
+
+// One mapping out of a GetGenericPortMappingEntry response. None for a
+// fault, which is how the gateway says the index is past the end.
+pub fn parse_entry(resp: &[u8]) -> Option<Mapping> {
+    if http_status_code(resp)? != 200 {
+        return None;
+    }
+    let xml = core::str::from_utf8(http_body(resp)).ok()?;
+    element(xml, "GetGenericPortMappingEntryResponse")?;
+    let field = |name| element(xml, name).unwrap_or("").trim();
+    Some(Mapping {
+        protocol: text(field("NewProtocol").as_bytes()),
+        external_port: field("NewExternalPort").parse().ok()?,
+        remote_host: text(field("NewRemoteHost").as_bytes()),
+        internal_host: text(field("NewInternalClient").as_bytes()),
+        internal_port: field("NewInternalPort").parse().unwrap_or(0),
+        enabled: matches!(field("NewEnabled"), "1" | "true"),
+        lease: field("NewLeaseDuration").parse().unwrap_or(0),
+        description: text(field("NewPortMappingDescription").as_bytes()),
+    })
+}
+
+// The longest valid UTF-8 prefix, so a read that splits a character
+// doesn't lose the whole window.
+fn utf8_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+// Sends `req` to the gateway on 5351 and waits for a reply `parse` makes
+// sense of, sending it once more if none comes.
+async fn ask<T>(
+    socket: &mut UdpSocket<'_>,
+    gateway: Ipv4Address,
+    req: &[u8],
+    parse: impl Fn(&[u8]) -> Option<T>,
+) -> Option<T> {
+    for _ in 0..2 {
+        socket
+            .send_to(req, (IpAddress::Ipv4(gateway), PMP_PORT))
+            .await
+            .ok()?;
+        let deadline = Instant::now() + PMP_WAIT;
+        while let Ok((from, got)) = with_deadline(
+            deadline,
+            socket.recv_from_with(|reply, meta| (meta.endpoint.addr, parse(reply))),
+        )
+        .await
+        {
+            if from == IpAddress::Ipv4(gateway)
+                && let Some(got) = got
+            {
+                return Some(got);
+            }
+        }
+    }
+    None
+}
+
+// PCP, then NAT-PMP.
+async fn pmp(stack: Stack<'_>, audit: &mut Audit, client: Ipv4Address) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buf = [0u8; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 64];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if socket.bind(0).is_err() {
+        return;
+    }
+    audit.pcp =
+        ask(&mut socket, audit.gateway, &pcp_announce(client), parse_pcp).await == Some(true);
+    match ask(&mut socket, audit.gateway, &NATPMP_REQUEST, parse_natpmp).await {
+        Some(Ok(external)) => {
+            audit.natpmp = true;
+            audit.external = Some(external);
+        }
+        Some(Err(result)) => {
+            debug!("portmap: NAT-PMP result {}", result);
+            audit.natpmp = true;
+        }
+        None => {}
+    }
+}
+
+// The gateway's description URL, from SSDP.
+async fn ssdp(stack: Stack<'_>, gateway: Ipv4Address) -> Option<Endpoint> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buf = [0u8; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    socket.bind(0).ok()?;
+    if let Err(e) = socket
+        .send_to(MSEARCH.as_bytes(), (IpAddress::Ipv4(SSDP_GROUP), SSDP_PORT))
+        .await
+    {
+        warn!("portmap: M-SEARCH not sent: {:?}", e);
+        return None;
+    }
+    let deadline = Instant::now() + SSDP_WAIT;
+    while let Ok((from, location)) = with_deadline(
+        deadline,
+        socket.recv_from_with(|reply, meta| {
+            (meta.endpoint.addr, parse_ssdp(reply).and_then(parse_url))
+        }),
+    )
+    .await
+    {
+        if from == IpAddress::Ipv4(gateway)
+            && let Some(location) = location
+        {
+            return Some(location);
+        }
+    }
+    None
+}
+
+async fn connect<'a>(socket: &mut TcpSocket<'a>, to: &Endpoint) -> bool {
+    let ok = matches!(
+        with_timeout(
+            CONNECT_TIMEOUT,
+            socket.connect((IpAddress::Ipv4(to.host), to.port))
+        )
+        .await,
+        Ok(Ok(()))
+    );
+    if !ok {
+        socket.abort();
+    }
+    ok
+}
+
+// Control URL and service type of the WAN connection service. The
+// description can be bigger than the buffer, so it slides, keeping the
+// last KEEP bytes each time it fills.
+async fn describe(stack: Stack<'_>, location: &Endpoint) -> Option<(Endpoint, String<64>)> {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !connect(&mut socket, location).await {
+        return None;
+    }
+    let mut found = None;
+    if write_all(&mut socket, describe_request(location).as_bytes())
+        .await
+        .is_ok()
+    {
+        let mut buf = [0u8; 2048];
+        let mut got = 0;
+        let read = async {
+            loop {
+                match socket.read(&mut buf[got..]).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => got += n,
+                }
+                if let Some((kind, url)) = find_service(utf8_prefix(&buf[..got])) {
+                    found = resolve(location, url).zip(String::try_from(kind).ok());
+                    break;
+                }
+                if got == buf.len() {
+                    // Start on a character, not in the middle of one.
+                    let mut from = got - KEEP;
+                    while from < got && buf[from] & 0xc0 == 0x80 {
+                        from += 1;
+                    }
+                    buf.copy_within(from..got, 0);
+                    got -= from;
+                }
+            }
+        };
+        with_timeout(READ_TIMEOUT, read).await.ok();
+    }
+    socket.abort();
+    found
+}
+
+// GetGenericPortMappingEntry for one index.
+async fn entry(
+    stack: Stack<'_>,
+    control: &Endpoint,
+    service: &str,
+    index: usize,
+) -> Option<Mapping> {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 1024];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !connect(&mut socket, control).await {
+        return None;
+    }
+    let req = entry_request(control, service, index);
+    let mut buf = [0u8; 2048];
+    let mut got = 0;
+    if write_all(&mut socket, req.as_bytes()).await.is_ok() {
+        let read = async {
+            while got < buf.len() {
+                match socket.read(&mut buf[got..]).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => got += n,
+                }
+            }
+        };
+        with_timeout(READ_TIMEOUT, read).await.ok();
+    }
+    socket.abort();
+    parse_entry(&buf[..got])
+}
+
+// Audits the IPv4 gateway. None without a DHCP lease and gateway.
+pub async fn run(stack: Stack<'_>) -> Option<Audit> {
+    let config = stack.config_v4()?;
+    let mut audit = Audit {
+        gateway: config.gateway?,
+        natpmp: false,
+        pcp: false,
+        external: None,
+        igd: None,
+        mappings: Vec::new(),
+        truncated: false,
+    };
+    pmp(stack, &mut audit, config.address.address()).await;
+    if let Some(location) = ssdp(stack, audit.gateway).await {
+        debug!("portmap: IGD description at {}", location.path.as_str());
+        if let Some((control, service)) = describe(stack, &location).await {
+            for index in 0..MAX_MAPPINGS {
+                let Some(mapping) = entry(stack, &control, &service, index).await else {
+                    break;
+                };
+                audit.mappings.push(mapping).ok();
+            }
+            // One more tells us whether the list was cut short.
+            if audit.mappings.is_full() {
+                audit.truncated = entry(stack, &control, &service, MAX_MAPPINGS)
+                    .await
+                    .is_some();
+            }
+            if audit.truncated {
+                warn!(
+                    "portmap: gateway has more than {} UPnP mappings, only those are listed",
+                    MAX_MAPPINGS
+                );
+            }
+            audit.igd = Some(service);
+        }
+    }
+    info!(
+        "portmap: gateway {}, {} UPnP mappings",
+        audit.gateway,
+        audit.mappings.len()
+    );
+    Some(audit)
+}
+
+// Writes the mappings out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    mappings: &[Mapping],
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::<192>::try_from(CSV_HEADER).unwrap())
+            .chain(mappings.iter().map(Mapping::csv_row)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = concat!(
+        r#"<?xml version="1.0"?><root xmlns="urn:schemas-upnp-org:device-1-0">"#,
+        "<device><deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>",
+        "<serviceList><service>",
+        "<serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>",
+        "<controlURL>/ctl/L3F</controlURL></service></serviceList>",
+        "<deviceList><device><deviceList><device><serviceList><service>",
+        "<serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>",
+        "<serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>",
+        "<controlURL>/ctl/IPConn</controlURL>",
+        "<eventSubURL>/evt/IPConn</eventSubURL></service></serviceList>",
+        "</device></deviceList></device></deviceList></device></root>",
+    );
+
+    #[test]
+    fn natpmp_and_pcp() {
+        let reply = [0, 128, 0, 0, 0, 0, 0x1c, 0x20, 203, 0, 113, 7];
+        assert_eq!(
+            parse_natpmp(&reply),
+            Some(Ok(Ipv4Address::new(203, 0, 113, 7)))
+        );
+        let reply = [0, 128, 0, 3, 0, 0, 0x1c, 0x20, 0, 0, 0, 0];
+        assert_eq!(parse_natpmp(&reply), Some(Err(3)));
+        assert_eq!(parse_natpmp(&NATPMP_REQUEST), None);
+
+        let req = pcp_announce(Ipv4Address::new(192, 168, 1, 50));
+        assert_eq!(req[..4], [2, 0, 0, 0]);
+        assert_eq!(req[16..], [0, 0, 0xff, 0xff, 192, 168, 1, 50]);
+        assert_eq!(parse_pcp(&req), None);
+        let mut reply = [0u8; 24];
+        reply[..2].copy_from_slice(&[2, 0x80]);
+        assert_eq!(parse_pcp(&reply), Some(true));
+        // NAT-PMP only: unsupported version.
+        assert_eq!(parse_pcp(&[0, 0x80, 0, 1, 0, 0, 0, 9]), Some(false));
+    }
+
+    #[test]
+    fn ssdp_and_urls() {
+        let reply = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\n\
+                      ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                      Location: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        let location = parse_url(parse_ssdp(reply).unwrap()).unwrap();
+        assert_eq!(location.host, Ipv4Address::new(192, 168, 1, 1));
+        assert_eq!(location.port, 5000);
+        assert_eq!(location.path, "/rootDesc.xml");
+        assert_eq!(parse_ssdp(MSEARCH.as_bytes()), None);
+
+        let bare = parse_url("http://10.0.0.1").unwrap();
+        assert_eq!((bare.port, bare.path.as_str()), (80, "/"));
+        assert_eq!(parse_url("https://10.0.0.1/"), None);
+        assert_eq!(parse_url("http://router.lan/"), None);
+
+        assert_eq!(
+            resolve(&location, "ctl/IPConn").unwrap().path,
+            "/ctl/IPConn"
+        );
+        let absolute = resolve(&location, "http://192.168.1.1:49152/upnp/control").unwrap();
+        assert_eq!(
+            (absolute.port, absolute.path.as_str()),
+            (49152, "/upnp/control")
+        );
+    }
+
+    #[test]
+    fn description() {
+        assert_eq!(
+            find_service(DESCRIPTION),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "/ctl/IPConn"
+            ))
+        );
+        // Cut off inside the service we want: not found yet.
+        let cut = DESCRIPTION.find("<eventSubURL>").unwrap();
+        assert_eq!(find_service(&DESCRIPTION[..cut]), None);
+        // And the window having slid past the start of the document.
+        let slid = DESCRIPTION.find("<deviceList>").unwrap();
+        assert!(find_service(&DESCRIPTION[slid..]).is_some());
+    }
+
+    #[test]
+    fn entries() {
+        let control = parse_url("http://192.168.1.1:5000/ctl/IPConn").unwrap();
+        let service = "urn:schemas-upnp-org:service:WANIPConnection:1";
+        let req = entry_request(&control, service, 3);
+        assert!(req.len() < req.capacity());
+        assert!(req.starts_with("POST /ctl/IPConn HTTP/1.1\r\nHost: 192.168.1.1:5000\r\n"));
+        assert!(req.contains(
+            "SOAPAction: \"urn:schemas-upnp-org:service:WANIPConnection:1\
+             #GetGenericPortMappingEntry\"\r\n"
+        ));
+        let body = core::str::from_utf8(http_body(req.as_bytes())).unwrap();
+        let mut length: String<32> = String::new();
+        write!(length, "Content-Length: {}\r\n", body.len()).unwrap();
+        assert!(req.contains(length.as_str()));
+        assert_eq!(element(body, "NewPortMappingIndex"), Some("3"));
+
+        let resp = concat!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml; charset=\"utf-8\"\r\n\r\n",
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"<s:Body><u:GetGenericPortMappingEntryResponse xmlns:u="urn:x">"#,
+            "<NewRemoteHost></NewRemoteHost><NewExternalPort>32400</NewExternalPort>",
+            "<NewProtocol>TCP</NewProtocol><NewInternalPort>32400</NewInternalPort>",
+            "<NewInternalClient>192.168.1.23</NewInternalClient><NewEnabled>1</NewEnabled>",
+            "<NewPortMappingDescription>Plex Media Server, TV</NewPortMappingDescription>",
+            "<NewLeaseDuration>0</NewLeaseDuration>",
+            "</u:GetGenericPortMappingEntryResponse></s:Body></s:Envelope>",
+        );
+        let mapping = parse_entry(resp.as_bytes()).unwrap();
+        let mut line: String<96> = String::new();
+        write!(line, "{}", mapping).unwrap();
+        assert_eq!(
+            line,
+            "TCP 32400 -> 192.168.1.23:32400 Plex Media Server  TV"
+        );
+        assert_eq!(
+            mapping.csv_row(),
+            "TCP,32400,,192.168.1.23,32400,1,0,Plex Media Server  TV"
+        );
+
+        let fault = b"HTTP/1.1 500 Internal Server Error\r\n\r\n<s:Envelope><s:Body>\
+                      <s:Fault><detail><UPnPError><errorCode>713</errorCode>\
+                      <errorDescription>SpecifiedArrayIndexInvalid</errorDescription>\
+                      </UPnPError></detail></s:Fault></s:Body></s:Envelope>";
+        assert_eq!(parse_entry(fault), None);
+    }
+
+    #[test]
+    fn summary() {
+        let mut audit = Audit {
+            gateway: Ipv4Address::new(192, 168, 1, 1),
+            natpmp: true,
+            pcp: false,
+            external: Some(Ipv4Address::new(203, 0, 113, 7)),
+            igd: Some(String::try_from("urn:schemas-upnp-org:service:WANIPConnection:1").unwrap()),
+            mappings: Vec::new(),
+            truncated: false,
+        };
+        while audit.mappings.push(Mapping::default()).is_ok() {}
+        let mut line: String<96> = String::new();
+        write!(line, "{}", audit).unwrap();
+        assert_eq!(
+            line,
+            "192.168.1.1 NAT-PMP external 203.0.113.7 UPnP WANIPConnection:1 32 mappings"
+        );
+        audit.truncated = true;
+        line.clear();
+        write!(line, "{}", audit).unwrap();
+        assert!(line.ends_with(" 32+ mappings"));
+    }
+}
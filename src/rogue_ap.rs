@@ -0,0 +1,282 @@
+//! rogue_ap
+//! --------
+//!
+//! Evil twin / rogue AP detection for our own SSIDs.
+//!
+//! `KNOWN_APS` lists every legitimate AP as `ssid,bssid,security,channel`,
+//! separated by `;`. Security and channel can be left empty or `*` to not
+//! check them:
+//!
+//!     KNOWN_APS="Corp,00:11:22:33:44:01,wpa2,1;Corp,00:11:22:33:44:02,wpa2,11"
+//!
+//! Any scan result using one of those SSIDs is checked against its list:
+//! an unknown BSSID, an open network where we expect encryption, or a
+//! known BSSID on a channel it shouldn't be on are all alerts. Each AP
+//! and reason is only alerted once per boot.
+//!
+//! Scan results only tell us open vs encrypted (see `wifi_scan`), so a
+//! twin running WPA where we expect WPA2/3 isn't caught by the security
+//! check, only by its BSSID.
+use core::fmt;
+use defmt::warn;
+use heapless::{String, Vec};
+
+use crate::arp_guard::{Mac, parse_mac};
+
+const MAX_KNOWN: usize = 16;
+const MAX_REPORTED: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Security {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    Wpa3,
+}
+
+impl Security {
+    pub fn parse(s: &str) -> Option<Self> {
+        const NAMES: [(&str, Security); 5] = [
+            ("open", Security::Open),
+            ("wep", Security::Wep),
+            ("wpa", Security::Wpa),
+            ("wpa2", Security::Wpa2),
+            ("wpa3", Security::Wpa3),
+        ];
+        NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s.trim()))
+            .map(|&(_, security)| security)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownAp<'a> {
+    pub ssid: &'a str,
+    pub bssid: [u8; 6],
+    pub security: Option<Security>,
+    pub channel: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Reason {
+    UnknownBssid,
+    // Open, where we expect encryption.
+    WeakerSecurity,
+    UnexpectedChannel { expected: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i16,
+    pub encrypted: bool,
+    pub reason: Reason,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::UnknownBssid => "unknown BSSID",
+            Reason::WeakerSecurity => "open, expected encryption",
+            Reason::UnexpectedChannel { .. } => "unexpected channel",
+        }
+    }
+}
+
+// "Rogue AP Corp 66:77:88:99:aa:bb ch 6 -48dBm open: unknown BSSID"
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rogue AP {} {} ch {} {}dBm {}: {}",
+            self.ssid,
+            Mac(&self.bssid),
+            self.channel,
+            self.rssi,
+            if self.encrypted { "encrypted" } else { "open" },
+            self.reason.as_str()
+        )?;
+        if let Reason::UnexpectedChannel { expected } = self.reason {
+            write!(f, " (expected {})", expected)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct RogueWatch<'a> {
+    known: Vec<KnownAp<'a>, MAX_KNOWN>,
+    reported: Vec<([u8; 6], Reason), MAX_REPORTED>,
+}
+
+impl<'a> RogueWatch<'a> {
+    // Bad entries are skipped with a warning.
+    pub fn parse(config: &'a str) -> Self {
+        let mut known = Vec::new();
+        for entry in config.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut fields = entry.split(',');
+            let ssid = fields.next().unwrap_or("");
+            let Some(bssid) = fields.next().and_then(parse_mac) else {
+                warn!("rogue_ap: bad KNOWN_APS entry {}", entry);
+                continue;
+            };
+            let security = fields.next().and_then(Security::parse);
+            let channel = fields.next().and_then(|c| c.trim().parse().ok());
+            let ap = KnownAp {
+                ssid,
+                bssid,
+                security,
+                channel,
+            };
+            if known.push(ap).is_err() {
+                warn!("rogue_ap: more than {} known APs", MAX_KNOWN);
+                break;
+            }
+        }
+        Self {
+            known,
+            reported: Vec::new(),
+        }
+    }
+
+    pub fn from_env() -> Option<RogueWatch<'static>> {
+        let watch = RogueWatch::parse(option_env!("KNOWN_APS")?);
+        (!watch.known.is_empty()).then_some(watch)
+    }
+
+    pub fn known(&self) -> &[KnownAp<'a>] {
+        &self.known
+    }
+
+    // What's wrong with this scan result, if anything, ignoring ones
+    // already reported. It keeps coming back until `reported` is called.
+    pub fn check(
+        &self,
+        ssid: &str,
+        bssid: [u8; 6],
+        channel: u8,
+        rssi: i16,
+        encrypted: bool,
+    ) -> Option<Alert> {
+        let mut ours = self.known.iter().filter(|k| k.ssid == ssid).peekable();
+        ours.peek()?;
+        let reason = match ours.find(|k| k.bssid == bssid) {
+            None => Reason::UnknownBssid,
+            Some(k) if !encrypted && k.security.is_some_and(|s| s > Security::Open) => {
+                Reason::WeakerSecurity
+            }
+            Some(k) => match k.channel {
+                Some(expected) if expected != channel => Reason::UnexpectedChannel { expected },
+                _ => return None,
+            },
+        };
+
+        if self.reported.contains(&(bssid, reason)) {
+            return None;
+        }
+        let mut name = String::new();
+        name.push_str(ssid).ok();
+        Some(Alert {
+            ssid: name,
+            bssid,
+            channel,
+            rssi,
+            encrypted,
+            reason,
+        })
+    }
+
+    // Call once the alert has been shown and logged.
+    pub fn reported(&mut self, alert: &Alert) {
+        let key = (alert.bssid, alert.reason);
+        if self.reported.contains(&key) {
+            return;
+        }
+        // Full just means we may alert on old ones again.
+        if self.reported.is_full() {
+            self.reported.remove(0);
+        }
+        self.reported.push(key).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "Corp,00:11:22:33:44:01,wpa2,1; Corp,00:11:22:33:44:02,WPA3,* ;\
+                          Guest,00:11:22:33:44:03,,6;bad,entry";
+    const AP1: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x01];
+    const AP2: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x02];
+    const AP3: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x03];
+    const EVIL: [u8; 6] = [0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb];
+
+    #[test]
+    fn parses_config() {
+        let watch = RogueWatch::parse(CONFIG);
+        assert_eq!(
+            watch.known(),
+            &[
+                KnownAp {
+                    ssid: "Corp",
+                    bssid: AP1,
+                    security: Some(Security::Wpa2),
+                    channel: Some(1),
+                },
+                KnownAp {
+                    ssid: "Corp",
+                    bssid: AP2,
+                    security: Some(Security::Wpa3),
+                    channel: None,
+                },
+                KnownAp {
+                    ssid: "Guest",
+                    bssid: AP3,
+                    security: None,
+                    channel: Some(6),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn legit_aps_pass() {
+        let watch = RogueWatch::parse(CONFIG);
+        assert_eq!(watch.check("Corp", AP1, 1, -50, true), None);
+        assert_eq!(watch.check("Corp", AP2, 11, -50, true), None);
+        assert_eq!(watch.check("Guest", AP3, 6, -50, false), None);
+        assert_eq!(watch.check("Neighbour", EVIL, 3, -50, false), None);
+    }
+
+    #[test]
+    fn flags_rogues_once() {
+        let mut watch = RogueWatch::parse(CONFIG);
+        let alert = watch.check("Corp", EVIL, 6, -48, false).unwrap();
+        assert_eq!(alert.reason, Reason::UnknownBssid);
+        assert_eq!(
+            alert.to_string(),
+            "Rogue AP Corp 66:77:88:99:aa:bb ch 6 -48dBm open: unknown BSSID"
+        );
+        // Not shown yet, so it comes back.
+        assert_eq!(
+            watch.check("Corp", EVIL, 6, -48, false),
+            Some(alert.clone())
+        );
+        watch.reported(&alert);
+        assert_eq!(watch.check("Corp", EVIL, 6, -48, false), None);
+
+        let alert = watch.check("Corp", AP1, 1, -50, false).unwrap();
+        assert_eq!(alert.reason, Reason::WeakerSecurity);
+
+        let alert = watch.check("Guest", AP3, 11, -50, true).unwrap();
+        assert_eq!(alert.reason, Reason::UnexpectedChannel { expected: 6 });
+        assert!(
+            alert
+                .to_string()
+                .ends_with("unexpected channel (expected 6)")
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! scan_diff
+//! ---------
+//!
+//! Compares two scan sessions and reports what changed.
+//!
+//! A session is a text file with one line per host and open port:
+//!
+//!     host,port,ip,detail
+//!
+//! `host` is the MAC, or the IP for hosts we only know by IP. Every host
+//! has a port 0 line for the host itself, whose detail is its name and
+//! services. Other lines are open ports, with whatever we learned about
+//! them as the detail: the banner or version it answered with (see
+//! `inventory`'s `BANNERS.DB`), then the SSH host key fingerprint on SSH
+//! ports. Ports are TCP. `ip` is only there to make the report readable,
+//! a host changing IP isn't a change.
+//! Dual-stack hosts have both addresses there, space separated.
+//!
+//! Lines must be sorted by host, then port number. Then diffing is one
+//! pass over both files at once, without holding either in RAM.
+use core::cmp::Ordering;
+use core::fmt::{self, Write};
+
+use crate::services::{Port, Proto};
+
+pub const HEADER: &str = "host,port,ip,detail";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub ip: &'a str,
+    pub detail: &'a str,
+}
+
+impl<'a> Entry<'a> {
+    // None for the header and anything else that isn't an entry.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.trim_end_matches(['\r', '\n']).splitn(4, ',');
+        let host = fields.next().filter(|h| !h.is_empty())?;
+        let port = fields.next()?.parse().ok()?;
+        Some(Self {
+            host,
+            port,
+            ip: fields.next().unwrap_or(""),
+            detail: fields.next().unwrap_or(""),
+        })
+    }
+
+    // The order session files are sorted in.
+    pub fn cmp_key(&self, other: &Entry<'_>) -> Ordering {
+        self.host.cmp(other.host).then(self.port.cmp(&other.port))
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.host, self.port, self.ip, self.detail)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    HostAdded(Entry<'a>),
+    HostRemoved(Entry<'a>),
+    // `new_host` when it's one of a just added host's ports.
+    PortOpened { entry: Entry<'a>, new_host: bool },
+    PortClosed { entry: Entry<'a>, gone_host: bool },
+    Changed { old: Entry<'a>, new: Entry<'a> },
+}
+
+// One report line per change:
+//
+//     + host b8:27:eb:01:02:03 (192.168.1.20) pi-hole dns
+//         port 53/domain open
+//     - host 192.168.1.9
+//     + b8:27:eb:01:02:03 port 22/ssh open OpenSSH_9.2
+//     - b8:27:eb:01:02:03 port 80/http closed
+//     ~ b8:27:eb:01:02:03 port 22/ssh: OpenSSH_9.2 -> OpenSSH_9.6
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::HostAdded(e) | Change::HostRemoved(e) => {
+                let sign = if matches!(self, Change::HostAdded(_)) {
+                    '+'
+                } else {
+                    '-'
+                };
+                write!(f, "{} host {}", sign, e.host)?;
+                if !e.ip.is_empty() && e.ip != e.host {
+                    write!(f, " ({})", e.ip)?;
+                }
+                if !e.detail.is_empty() {
+                    write!(f, " {}", e.detail)?;
+                }
+                Ok(())
+            }
+            Change::PortOpened {
+                entry,
+                new_host: true,
+            } => {
+                write!(f, "    port {} open", Port(Proto::Tcp, entry.port))?;
+                detail(f, entry.detail)
+            }
+            Change::PortClosed {
+                entry,
+                gone_host: true,
+            } => {
+                write!(f, "    port {} was open", Port(Proto::Tcp, entry.port))?;
+                detail(f, entry.detail)
+            }
+            Change::PortOpened { entry, .. } => {
+                write!(
+                    f,
+                    "+ {} port {} open",
+                    entry.host,
+                    Port(Proto::Tcp, entry.port)
+                )?;
+                detail(f, entry.detail)
+            }
+            Change::PortClosed { entry, .. } => {
+                write!(
+                    f,
+                    "- {} port {} closed",
+                    entry.host,
+                    Port(Proto::Tcp, entry.port)
+                )
+            }
+            Change::Changed { old, new } if new.port == 0 => {
+                write!(
+                    f,
+                    "~ {}: {} -> {}",
+                    new.host,
+                    or_none(old.detail),
+                    or_none(new.detail)
+                )
+            }
+            Change::Changed { old, new } => write!(
+                f,
+                "~ {} port {}: {} -> {}",
+                new.host,
+                Port(Proto::Tcp, new.port),
+                or_none(old.detail),
+                or_none(new.detail)
+            ),
+        }
+    }
+}
+
+fn detail(f: &mut fmt::Formatter<'_>, detail: &str) -> fmt::Result {
+    if detail.is_empty() {
+        Ok(())
+    } else {
+        write!(f, " {}", detail)
+    }
+}
+
+fn or_none(detail: &str) -> &str {
+    if detail.is_empty() { "(none)" } else { detail }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct Summary {
+    pub hosts_added: u16,
+    pub hosts_removed: u16,
+    // Ports on hosts that were in both sessions.
+    pub ports_opened: u16,
+    pub ports_closed: u16,
+    pub changed: u16,
+}
+
+impl Summary {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Three OLED lines.
+    pub fn lines(&self) -> [heapless::String<21>; 3] {
+        let mut lines: [heapless::String<21>; 3] = Default::default();
+        write!(
+            &mut lines[0],
+            "Hosts: +{} -{}",
+            self.hosts_added, self.hosts_removed
+        )
+        .ok();
+        write!(
+            &mut lines[1],
+            "Ports: +{} -{}",
+            self.ports_opened, self.ports_closed
+        )
+        .ok();
+        write!(&mut lines[2], "Changed: {}", self.changed).ok();
+        lines
+    }
+
+    fn count(&mut self, change: &Change<'_>) {
+        let n = match change {
+            Change::HostAdded(_) => &mut self.hosts_added,
+            Change::HostRemoved(_) => &mut self.hosts_removed,
+            Change::PortOpened {
+                new_host: false, ..
+            } => &mut self.ports_opened,
+            Change::PortClosed {
+                gone_host: false, ..
+            } => &mut self.ports_closed,
+            Change::Changed { .. } => &mut self.changed,
+            _ => return,
+        };
+        *n = n.saturating_add(1);
+    }
+}
+
+// Walks two sorted sessions, calling `f` with each change in order.
+// Lines that don't parse (like the header) are skipped.
+pub fn diff<O, N>(old: O, new: N, mut f: impl FnMut(&Change<'_>)) -> Summary
+where
+    O: IntoIterator,
+    O::Item: AsRef<str>,
+    N: IntoIterator,
+    N::Item: AsRef<str>,
+{
+    let mut old = old
+        .into_iter()
+        .filter(|l| Entry::parse(l.as_ref()).is_some())
+        .peekable();
+    let mut new = new
+        .into_iter()
+        .filter(|l| Entry::parse(l.as_ref()).is_some())
+        .peekable();
+    let mut summary = Summary::default();
+    // Host whose port 0 line only one side had, so its ports are part of
+    // that host coming or going rather than changes of their own.
+    let mut added: heapless::String<39> = heapless::String::new();
+    let mut removed: heapless::String<39> = heapless::String::new();
+
+    let mut emit = |summary: &mut Summary, change: Change<'_>| {
+        summary.count(&change);
+        f(&change);
+    };
+
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(o), Some(n)) => {
+                let (o, n) = (Entry::parse(o.as_ref()), Entry::parse(n.as_ref()));
+                o.zip(n).map_or(Ordering::Equal, |(o, n)| o.cmp_key(&n))
+            }
+        };
+        match order {
+            Ordering::Less => {
+                let line = old.next().unwrap();
+                let Some(entry) = Entry::parse(line.as_ref()) else {
+                    continue;
+                };
+                let change = if entry.port == 0 {
+                    set(&mut removed, entry.host);
+                    Change::HostRemoved(entry)
+                } else {
+                    Change::PortClosed {
+                        entry,
+                        gone_host: removed == entry.host,
+                    }
+                };
+                emit(&mut summary, change);
+            }
+            Ordering::Greater => {
+                let line = new.next().unwrap();
+                let Some(entry) = Entry::parse(line.as_ref()) else {
+                    continue;
+                };
+                let change = if entry.port == 0 {
+                    set(&mut added, entry.host);
+                    Change::HostAdded(entry)
+                } else {
+                    Change::PortOpened {
+                        entry,
+                        new_host: added == entry.host,
+                    }
+                };
+                emit(&mut summary, change);
+            }
+            Ordering::Equal => {
+                let (o, n) = (old.next().unwrap(), new.next().unwrap());
+                if let (Some(o), Some(n)) = (Entry::parse(o.as_ref()), Entry::parse(n.as_ref()))
+                    && o.detail != n.detail
+                {
+                    emit(&mut summary, Change::Changed { old: o, new: n });
+                }
+            }
+        }
+    }
+    summary
+}
+
+fn set(s: &mut heapless::String<39>, host: &str) {
+    s.clear();
+    s.push_str(host).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(old: &[&str], new: &[&str]) -> (Summary, Vec<String>) {
+        let mut report = Vec::new();
+        let summary = diff(old.iter(), new.iter(), |c| report.push(c.to_string()));
+        (summary, report)
+    }
+
+    const OLD: &[&str] = &[
+        HEADER,
+        "192.168.1.9,0,192.168.1.9,",
+        "192.168.1.9,80,192.168.1.9,",
+        "b8:27:eb:01:02:03,0,192.168.1.20,pi-hole dns",
+        "b8:27:eb:01:02:03,22,192.168.1.20,OpenSSH_9.2",
+        "b8:27:eb:01:02:03,53,192.168.1.20,",
+        "b8:27:eb:01:02:03,80,192.168.1.20,",
+    ];
+
+    #[test]
+    fn parses_entries() {
+        assert_eq!(Entry::parse(HEADER), None);
+        assert_eq!(Entry::parse(""), None);
+        assert_eq!(
+            Entry::parse("aa:bb:cc:dd:ee:ff,443,10.0.0.1,CN=x, O=y\r\n"),
+            Some(Entry {
+                host: "aa:bb:cc:dd:ee:ff",
+                port: 443,
+                ip: "10.0.0.1",
+                detail: "CN=x, O=y",
+            })
+        );
+        let e = Entry::parse("10.0.0.1,0,10.0.0.1,").unwrap();
+        assert_eq!(e.to_string(), "10.0.0.1,0,10.0.0.1,");
+    }
+
+    #[test]
+    fn same_session_is_empty() {
+        let (summary, report) = run(OLD, OLD);
+        assert!(summary.is_empty());
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn ip_move_is_not_a_change() {
+        let new: Vec<String> = OLD
+            .iter()
+            .map(|l| l.replace("192.168.1.20", "192.168.1.21"))
+            .collect();
+        let summary = diff(OLD.iter(), new.iter(), |_| {});
+        assert!(summary.is_empty());
+    }
+
+    #[test]
+    fn reports_changes() {
+        let new = &[
+            HEADER,
+            "10.0.0.5,0,10.0.0.5,",
+            "10.0.0.5,443,10.0.0.5,sha256:ab12",
+            "b8:27:eb:01:02:03,0,192.168.1.20,pi-hole dns,ssh",
+            "b8:27:eb:01:02:03,22,192.168.1.20,OpenSSH_9.6",
+            "b8:27:eb:01:02:03,53,192.168.1.20,",
+            "b8:27:eb:01:02:03,8080,192.168.1.20,",
+        ];
+        let (summary, report) = run(OLD, new);
+        assert_eq!(
+            report,
+            [
+                "+ host 10.0.0.5",
+                "    port 443/https open sha256:ab12",
+                "- host 192.168.1.9",
+                "    port 80/http was open",
+                "~ b8:27:eb:01:02:03: pi-hole dns -> pi-hole dns,ssh",
+                "~ b8:27:eb:01:02:03 port 22/ssh: OpenSSH_9.2 -> OpenSSH_9.6",
+                "- b8:27:eb:01:02:03 port 80/http closed",
+                "+ b8:27:eb:01:02:03 port 8080/http-proxy open",
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                hosts_added: 1,
+                hosts_removed: 1,
+                ports_opened: 1,
+                ports_closed: 1,
+                changed: 2,
+            }
+        );
+        assert_eq!(summary.lines()[0].as_str(), "Hosts: +1 -1");
+    }
+
+    #[test]
+    fn empty_sessions() {
+        let (summary, report) = run(&[], OLD);
+        assert_eq!(summary.hosts_added, 2);
+        assert_eq!(summary.ports_opened, 0);
+        assert_eq!(report.len(), 6);
+        assert_eq!(
+            report[2],
+            "+ host b8:27:eb:01:02:03 (192.168.1.20) pi-hole dns"
+        );
+
+        let (summary, _) = run(OLD, &[HEADER]);
+        assert_eq!(summary.hosts_removed, 2);
+        assert_eq!(summary.ports_closed, 0);
+    }
+}
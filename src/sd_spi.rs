@@ -9,7 +9,7 @@ use core::cell::UnsafeCell;
 use defmt::{debug, error, info, warn};
 use embassy_rp::gpio::Output;
 use embassy_rp::spi::Spi;
-use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx, TimeSource, Timestamp};
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
 
 // SD command constants
 
@@ -47,21 +47,6 @@ pub enum SdSpiError {
     BadToken,
 }
 
-pub struct DummyTime;
-
-impl TimeSource for DummyTime {
-    fn get_timestamp(&self) -> Timestamp {
-        Timestamp {
-            year_since_1970: 0,
-            zero_indexed_month: 0,
-            zero_indexed_day: 0,
-            hours: 0,
-            minutes: 0,
-            seconds: 0,
-        }
-    }
-}
-
 // Owned SPI + CS block-device
 
 pub struct EmbassySpiDevice<'d, S: embassy_rp::spi::Instance> {
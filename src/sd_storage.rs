@@ -1,12 +1,14 @@
-use crate::sd_spi::{DummyTime, EmbassySpiDevice};
+use crate::clock::{self, WallClock};
+use crate::sd_spi::EmbassySpiDevice;
+use core::fmt::Write;
 use embassy_rp::gpio::Output;
 use embedded_sdmmc::{Mode, VolumeIdx, VolumeManager};
 
-use crate::fat_utils::str_to_sfn;
+use crate::fat_utils::{sfn_to_str, str_to_sfn};
 use crate::sd_spi::SdSpiError;
 
 pub struct SdStorage<'d, S: embassy_rp::spi::Instance> {
-    volman: VolumeManager<EmbassySpiDevice<'d, S>, DummyTime>,
+    volman: VolumeManager<EmbassySpiDevice<'d, S>, WallClock>,
 }
 
 fn flatten_err<E: core::fmt::Debug>(
@@ -56,24 +58,81 @@ where
     ) -> Result<Self, SdSpiError> {
         let dev = EmbassySpiDevice::new(spi_dev, cs);
         dev.init()?;
-        let ts = DummyTime;
+        let ts = WallClock;
         let volman = VolumeManager::new(dev, ts);
 
         Ok(SdStorage { volman })
     }
 
     pub fn log_ip(&mut self, ip: &str) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        self.log_event(ip)
+    }
+
+    // Timestamped line in NETWORK.LOG.
+    pub fn log_event(&mut self, event: &str) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        let mut line: heapless::String<160> = heapless::String::new();
+        write!(&mut line, "{} {}", clock::now_local(), event).ok();
+        self.append_line("NETWORK.LOG", &line)
+    }
+
+    // Opens (or creates) `name` in the root dir and appends `line` plus a newline.
+    pub fn append_line(
+        &mut self,
+        name: &str,
+        line: &str,
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        self.append_lines(name, core::iter::once(line))
+    }
+
+    // Same as append_line but only opens the file once for a batch of lines.
+    pub fn append_lines<I, L>(
+        &mut self,
+        name: &str,
+        lines: I,
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
+        self.write_lines_with_mode(name, lines, Mode::ReadWriteCreateOrAppend)
+    }
+
+    // Replaces the contents of `name` with `lines`, creating it if needed.
+    pub fn write_lines<I, L>(
+        &mut self,
+        name: &str,
+        lines: I,
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
+        self.write_lines_with_mode(name, lines, Mode::ReadWriteCreateOrTruncate)
+    }
+
+    fn write_lines_with_mode<I, L>(
+        &mut self,
+        name: &str,
+        lines: I,
+        mode: Mode,
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<str>,
+    {
         let volume = self.volman.open_volume(VolumeIdx(0)).map_err(flatten_err)?;
         let root = volume.open_root_dir().map_err(flatten_err)?;
         let raw = root.to_raw_directory();
         let handle = self
             .volman
-            .open_file_in_dir(raw, str_to_sfn("NETWORK.LOG"), Mode::ReadWriteAppend)
+            .open_file_in_dir(raw, str_to_sfn(name), mode)
             .map_err(flatten_err)?;
-        self.volman
-            .write(handle, ip.as_bytes())
-            .map_err(flatten_err)?;
-        self.volman.write(handle, b"\n").map_err(flatten_err)?;
+        for line in lines {
+            self.volman
+                .write(handle, line.as_ref().as_bytes())
+                .map_err(flatten_err)?;
+            self.volman.write(handle, b"\n").map_err(flatten_err)?;
+        }
         self.volman.flush_file(handle).ok();
         self.volman.close_dir(raw).ok();
         self.volman.close_file(handle).ok();
@@ -81,6 +140,133 @@ where
         Ok(())
     }
 
+    // Overwrites `data` at `offset` in `name`, creating the file if needed.
+    // `offset` can be at most the current length, so this can append too.
+    pub fn write_at(
+        &mut self,
+        name: &str,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        let volume = self.volman.open_volume(VolumeIdx(0)).map_err(flatten_err)?;
+        let root = volume.open_root_dir().map_err(flatten_err)?;
+        let raw = root.to_raw_directory();
+        let handle = self
+            .volman
+            .open_file_in_dir(raw, str_to_sfn(name), Mode::ReadWriteCreateOrAppend)
+            .map_err(flatten_err)?;
+        let res = self
+            .volman
+            .file_seek_from_start(handle, offset)
+            .and_then(|_| self.volman.write(handle, data))
+            .map_err(flatten_err);
+        self.volman.close_file(handle).ok();
+        self.volman.close_dir(raw).ok();
+        volume.close().map_err(flatten_err)?;
+        res
+    }
+
+    // Reads up to `buf.len()` bytes from `offset`, returns how many we got.
+    pub fn read_at(
+        &mut self,
+        name: &str,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, embedded_sdmmc::Error<SdSpiError>> {
+        let volume = self.volman.open_volume(VolumeIdx(0)).map_err(flatten_err)?;
+        let root = volume.open_root_dir().map_err(flatten_err)?;
+        let raw = root.to_raw_directory();
+        let handle = self
+            .volman
+            .open_file_in_dir(raw, str_to_sfn(name), Mode::ReadOnly)
+            .map_err(flatten_err)?;
+        let res = self
+            .volman
+            .file_seek_from_start(handle, offset)
+            .and_then(|_| {
+                let mut got = 0;
+                while got < buf.len() {
+                    let n = self.volman.read(handle, &mut buf[got..])?;
+                    if n == 0 {
+                        break;
+                    }
+                    got += n;
+                }
+                Ok(got)
+            })
+            .map_err(flatten_err);
+        self.volman.close_file(handle).ok();
+        self.volman.close_dir(raw).ok();
+        volume.close().map_err(flatten_err)?;
+        res
+    }
+
+    // Calls `f` with each whole `N` byte record in `name` and its index, in
+    // one pass. A missing file is just no records.
+    pub fn read_records<const N: usize>(
+        &mut self,
+        name: &str,
+        mut f: impl FnMut(u32, &[u8; N]),
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        let volume = self.volman.open_volume(VolumeIdx(0)).map_err(flatten_err)?;
+        let root = volume.open_root_dir().map_err(flatten_err)?;
+        let raw = root.to_raw_directory();
+        let handle = match self
+            .volman
+            .open_file_in_dir(raw, str_to_sfn(name), Mode::ReadOnly)
+        {
+            Ok(handle) => handle,
+            Err(embedded_sdmmc::Error::NotFound) => {
+                self.volman.close_dir(raw).ok();
+                volume.close().map_err(flatten_err)?;
+                return Ok(());
+            }
+            Err(e) => return Err(flatten_err(e)),
+        };
+
+        let mut record = [0u8; N];
+        let mut got = 0;
+        let mut index = 0;
+        let res = loop {
+            match self.volman.read(handle, &mut record[got..]) {
+                Ok(0) => break Ok(()),
+                Ok(n) => got += n,
+                Err(e) => break Err(flatten_err(e)),
+            }
+            if got == N {
+                f(index, &record);
+                index += 1;
+                got = 0;
+            }
+        };
+
+        self.volman.close_file(handle).ok();
+        self.volman.close_dir(raw).ok();
+        volume.close().map_err(flatten_err)?;
+        res
+    }
+
+    // Calls `f` with the name and size of every file in the root dir.
+    pub fn list_files(
+        &mut self,
+        mut f: impl FnMut(&str, u32),
+    ) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+        let volume = self.volman.open_volume(VolumeIdx(0)).map_err(flatten_err)?;
+        let root = volume.open_root_dir().map_err(flatten_err)?;
+        let raw = root.to_raw_directory();
+        let res = self
+            .volman
+            .iterate_dir(raw, |entry| {
+                if !entry.attributes.is_directory() && !entry.attributes.is_volume() {
+                    f(&sfn_to_str(&entry.name), entry.size);
+                }
+            })
+            .map_err(flatten_err);
+        self.volman.close_dir(raw).ok();
+        volume.close().map_err(flatten_err)?;
+        res
+    }
+
     pub fn read_file<const N: usize>(
         &mut self,
         name: &str,
@@ -115,3 +301,73 @@ where
         Ok(out)
     }
 }
+
+// Reads a file a line at a time, without holding the file open between
+// lines, so two can be walked side by side. Lines longer than the buffer
+// get split.
+pub struct LineReader {
+    name: heapless::String<12>,
+    offset: u32,
+    buf: [u8; 256],
+    start: usize,
+    end: usize,
+}
+
+impl LineReader {
+    pub fn new(name: &str) -> Self {
+        let mut n = heapless::String::new();
+        n.push_str(name).ok();
+        Self {
+            name: n,
+            offset: 0,
+            buf: [0; 256],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn next_line<S: embassy_rp::spi::Instance, const N: usize>(
+        &mut self,
+        storage: &mut SdStorage<'_, S>,
+    ) -> Result<Option<heapless::String<N>>, embedded_sdmmc::Error<SdSpiError>> {
+        loop {
+            let pending = &self.buf[self.start..self.end];
+            if let Some(nl) = pending.iter().position(|&b| b == b'\n') {
+                let line = to_string(&pending[..nl]);
+                self.start += nl + 1;
+                return Ok(Some(line));
+            }
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+
+            let n = if self.end < self.buf.len() {
+                storage.read_at(&self.name, self.offset, &mut self.buf[self.end..])?
+            } else {
+                0
+            };
+            if n == 0 {
+                // Last line without a newline, or one that filled the buffer.
+                if self.end == 0 {
+                    return Ok(None);
+                }
+                let line = to_string(&self.buf[..self.end]);
+                self.end = 0;
+                return Ok(Some(line));
+            }
+            self.offset += n as u32;
+            self.end += n;
+        }
+    }
+}
+
+fn to_string<const N: usize>(bytes: &[u8]) -> heapless::String<N> {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    let mut out = heapless::String::new();
+    for c in core::str::from_utf8(bytes).unwrap_or("").chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
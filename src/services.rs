@@ -0,0 +1,120 @@
+//! services
+//! --------
+//!
+//! Port number -> service name, like nmap-services.
+//!
+//! The table is `data/services.txt`, compiled into flash by `build.rs`.
+//! Each protocol gets a table sorted by port for lookups, and its ports
+//! sorted by how often they're found open, for "top N ports" lists.
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Proto {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Service {
+    pub port: u16,
+    pub name: &'static str,
+    // How often it's found open, in parts per million.
+    pub freq: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/services.rs"));
+
+fn table(proto: Proto) -> &'static [Service] {
+    match proto {
+        Proto::Tcp => &TCP,
+        Proto::Udp => &UDP,
+    }
+}
+
+fn ranked(proto: Proto) -> &'static [u16] {
+    match proto {
+        Proto::Tcp => &TCP_RANKED,
+        Proto::Udp => &UDP_RANKED,
+    }
+}
+
+pub fn lookup(proto: Proto, port: u16) -> Option<&'static Service> {
+    let table = table(proto);
+    table
+        .binary_search_by_key(&port, |s| s.port)
+        .ok()
+        .map(|i| &table[i])
+}
+
+pub fn name(proto: Proto, port: u16) -> Option<&'static str> {
+    lookup(proto, port).map(|s| s.name)
+}
+
+// The `n` most often open ports, most likely first. Fewer if the table
+// doesn't have that many.
+pub fn top_ports(proto: Proto, n: usize) -> &'static [u16] {
+    let ranked = ranked(proto);
+    &ranked[..n.min(ranked.len())]
+}
+
+// 1 for the most often open port, None if it's not in the table.
+pub fn rank(proto: Proto, port: u16) -> Option<usize> {
+    ranked(proto).iter().position(|&p| p == port).map(|i| i + 1)
+}
+
+// "22/ssh", or just "40000" for ports not in the table.
+pub struct Port(pub Proto, pub u16);
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match name(self.0, self.1) {
+            Some(name) => write!(f, "{}/{}", self.1, name),
+            None => write!(f, "{}", self.1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_sorted() {
+        for proto in [Proto::Tcp, Proto::Udp] {
+            assert!(table(proto).windows(2).all(|w| w[0].port < w[1].port));
+            assert_eq!(ranked(proto).len(), table(proto).len());
+            let freqs: Vec<u32> = ranked(proto)
+                .iter()
+                .map(|&p| lookup(proto, p).unwrap().freq)
+                .collect();
+            assert!(freqs.windows(2).all(|w| w[0] >= w[1]));
+        }
+    }
+
+    #[test]
+    fn looks_up_names() {
+        assert_eq!(name(Proto::Tcp, 22), Some("ssh"));
+        assert_eq!(name(Proto::Tcp, 443), Some("https"));
+        assert_eq!(name(Proto::Udp, 161), Some("snmp"));
+        assert_eq!(name(Proto::Udp, 22), None);
+        assert_eq!(name(Proto::Tcp, 40000), None);
+        assert_eq!(lookup(Proto::Tcp, 80).unwrap().freq, 484_000);
+    }
+
+    #[test]
+    fn ranks_ports() {
+        assert_eq!(top_ports(Proto::Tcp, 5), &[80, 23, 443, 21, 22]);
+        assert_eq!(top_ports(Proto::Udp, 2), &[631, 161]);
+        assert_eq!(top_ports(Proto::Tcp, 100_000).len(), TCP.len());
+        assert_eq!(top_ports(Proto::Udp, 0), &[] as &[u16]);
+        assert_eq!(rank(Proto::Tcp, 80), Some(1));
+        assert_eq!(rank(Proto::Tcp, 22), Some(5));
+        assert_eq!(rank(Proto::Tcp, 40000), None);
+    }
+
+    #[test]
+    fn formats_ports() {
+        assert_eq!(Port(Proto::Tcp, 3389).to_string(), "3389/ms-wbt-server");
+        assert_eq!(Port(Proto::Udp, 40000).to_string(), "40000");
+    }
+}
@@ -0,0 +1,251 @@
+//! session
+//! -------
+//!
+//! Scan sessions on the SD card, and the diff between the last two.
+//!
+//! Each boot snapshots the hosts the inventory saw into the next
+//! `SCANnnnn.CSV` (format in `scan_diff`), then diffs it against the one
+//! before into `DIFFnnnn.TXT`.
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use defmt::{info, warn};
+use heapless::String;
+
+use crate::inventory::{HostKey, Inventory};
+use crate::scan_diff::{self, Summary};
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::{LineReader, SdStorage};
+
+type SdError = embedded_sdmmc::Error<SdSpiError>;
+// Longest is a dual-stack host's SSH port: MAC or IPv6 key, both
+// addresses, a full banner and the key fingerprint.
+type Line = String<224>;
+
+fn scan_file(n: u16) -> String<12> {
+    let mut name = String::new();
+    write!(&mut name, "SCAN{:04}.CSV", n).ok();
+    name
+}
+
+fn diff_file(n: u16) -> String<12> {
+    let mut name = String::new();
+    write!(&mut name, "DIFF{:04}.TXT", n).ok();
+    name
+}
+
+// Highest session number on the card, 0 if there are none.
+pub fn latest<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+) -> Result<u16, SdError> {
+    let mut latest = 0;
+    storage.list_files(|name, _| {
+        if let Some(n) = name
+            .strip_prefix("SCAN")
+            .and_then(|rest| rest.strip_suffix(".CSV"))
+            .filter(|digits| digits.len() == 4)
+            .and_then(|digits| digits.parse::<u16>().ok())
+        {
+            latest = latest.max(n);
+        }
+    })?;
+    Ok(latest)
+}
+
+// An IPv6 address is the longest key.
+fn key_string(key: &HostKey) -> String<39> {
+    let mut s = String::new();
+    write!(&mut s, "{}", key).ok();
+    s
+}
+
+// Writes the hosts seen since boot to session `n`, sorted the way
+// `scan_diff` needs. Returns how many hosts went in.
+pub fn write<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    inventory: &Inventory,
+    n: u16,
+) -> Result<usize, SdError> {
+    let name = scan_file(n);
+    let mut keys = inventory.seen_keys();
+    if keys.is_empty() {
+        return Ok(0);
+    }
+    keys.sort_unstable_by_key(key_string);
+
+    storage.write_lines(&name, [scan_diff::HEADER])?;
+    for key in &keys {
+        let Some(host) = inventory.get(storage, *key)? else {
+            continue;
+        };
+        let id = key_string(key);
+        // "192.168.1.20 2001:db8::20" for dual-stack hosts.
+        let mut ip: String<55> = String::new();
+        for (i, addr) in host.addrs().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(&mut ip, "{}{}", sep, addr).ok();
+        }
+
+        let mut lines: heapless::Vec<Line, 13> = heapless::Vec::new();
+        let mut line = Line::new();
+        write!(&mut line, "{},0,{},", id, ip).ok();
+        if !host.hostname.is_empty() {
+            write!(&mut line, "{} ", host.hostname).ok();
+        }
+        line.push_str(&host.services).ok();
+        lines.push(line).ok();
+
+        let mut ports = host.ports.clone();
+        ports.sort_unstable();
+        let ssh = inventory.ssh_key(storage, *key)?;
+        let banners = inventory.banners(storage, *key)?;
+        for port in ports {
+            let mut line = Line::new();
+            write!(&mut line, "{},{},{},", id, port, ip).ok();
+            // "SSH-2.0-OpenSSH_9.6 ssh-ed25519 SHA256:uNiVz..."
+            let banner = banners.iter().find(|(p, _)| *p == port);
+            let ssh = ssh.as_ref().filter(|k| k.port == port);
+            if let Some((_, banner)) = banner {
+                line.push_str(banner).ok();
+            }
+            if let Some(ssh) = ssh {
+                let sep = if banner.is_some() { " " } else { "" };
+                write!(&mut line, "{}{}", sep, ssh).ok();
+            }
+            lines.push(line).ok();
+        }
+        storage.append_lines(&name, &lines)?;
+    }
+    info!("session: wrote {} hosts to {}", keys.len(), name.as_str());
+    Ok(keys.len())
+}
+
+// Diffs session `old` against `new` into `new`'s report.
+pub fn diff<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    old: u16,
+    new: u16,
+) -> Result<Summary, SdError> {
+    let report = diff_file(new);
+    let mut header = Line::new();
+    write!(
+        &mut header,
+        "Changes from {} to {}",
+        scan_file(old),
+        scan_file(new)
+    )
+    .ok();
+    storage.write_lines(&report, [header])?;
+
+    // Both sessions and the report are read and written in turns.
+    let storage = RefCell::new(storage);
+    let error: Cell<Option<SdError>> = Cell::new(None);
+    let mut old_lines = LineReader::new(&scan_file(old));
+    let mut new_lines = LineReader::new(&scan_file(new));
+    let read = |reader: &mut LineReader| -> Option<Line> {
+        match reader.next_line(&mut storage.borrow_mut()) {
+            Ok(line) => line,
+            Err(e) => {
+                error.set(Some(e));
+                None
+            }
+        }
+    };
+
+    let summary = scan_diff::diff(
+        core::iter::from_fn(|| read(&mut old_lines)),
+        core::iter::from_fn(|| read(&mut new_lines)),
+        |change| {
+            let mut line = Line::new();
+            write!(&mut line, "{}", change).ok();
+            if let Err(e) = storage.borrow_mut().append_line(&report, &line) {
+                error.set(Some(e));
+            }
+        },
+    );
+    if let Some(e) = error.take() {
+        return Err(e);
+    }
+    if summary.is_empty() {
+        storage.borrow_mut().append_line(&report, "No changes")?;
+    }
+    info!("session: {} -> {}: {}", old, new, summary);
+    Ok(summary)
+}
+
+// Saves this boot's session and diffs it against the last one. None if
+// there was nothing to diff against.
+pub fn finish<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    inventory: &Inventory,
+) -> Result<Option<Summary>, SdError> {
+    let n = latest(storage)?.saturating_add(1);
+    if write(storage, inventory, n)? == 0 {
+        warn!("session: no hosts seen, not saving");
+        return Ok(None);
+    }
+    if n == 1 {
+        return Ok(None);
+    }
+    diff(storage, n - 1, n).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Observation;
+    use crate::sd_storage::NoSpi;
+    use embassy_net::{IpAddress, Ipv4Address};
+
+    const MAC: [u8; 6] = [0xb8, 0x27, 0xeb, 0x01, 0x02, 0x03];
+
+    // One boot: load the inventory, rescan the host with `ports` open,
+    // and save the session.
+    fn boot(sd: &mut SdStorage<'_, NoSpi>, ports: &[(u16, &str)]) -> Option<Summary> {
+        let mut inv = Inventory::load(sd).unwrap();
+        let ip = IpAddress::from(Ipv4Address::new(192, 168, 1, 20));
+        let obs = Observation {
+            mac: Some(MAC),
+            ip: Some(ip),
+            ..Default::default()
+        };
+        inv.upsert(sd, &obs).unwrap();
+        inv.clear_ports(sd, HostKey::Ip(ip)).unwrap();
+        for &(port, banner) in ports {
+            let obs = Observation {
+                ip: Some(ip),
+                ports: &[(port, None)],
+                banners: &[(port, banner)],
+                ..Default::default()
+            };
+            inv.upsert(sd, &obs).unwrap();
+        }
+        finish(sd, &inv).unwrap()
+    }
+
+    #[test]
+    fn diff_shows_closed_port() {
+        let mut sd = SdStorage::default();
+        assert_eq!(
+            boot(&mut sd, &[(22, "SSH-2.0-OpenSSH_9.6"), (80, "nginx")]),
+            None
+        );
+        assert!(sd.text("SCAN0001.CSV").contains(",80,192.168.1.20,nginx"));
+
+        let summary = boot(&mut sd, &[(22, "SSH-2.0-OpenSSH_9.6")]).unwrap();
+        assert_eq!(summary.ports_closed, 1);
+        assert_eq!(summary.ports_opened, 0);
+        assert!(!sd.text("SCAN0002.CSV").contains(",80,"));
+        let report = sd.text("DIFF0002.TXT");
+        assert!(report.contains("- b8:27:eb:01:02:03 port 80"), "{}", report);
+        assert!(report.contains("closed"));
+    }
+
+    #[test]
+    fn unchanged_session_says_so() {
+        let mut sd = SdStorage::default();
+        boot(&mut sd, &[(22, "SSH-2.0-OpenSSH_9.6")]);
+        let summary = boot(&mut sd, &[(22, "SSH-2.0-OpenSSH_9.6")]).unwrap();
+        assert!(summary.is_empty());
+        assert!(sd.text("DIFF0002.TXT").ends_with("No changes\n"));
+    }
+}
@@ -0,0 +1,169 @@
+//! site_survey
+//! -----------
+//!
+//! Walk-around WiFi site survey with numbered waypoints.
+//!
+//! Built with `SITE_SURVEY="1"`, the Pico skips its normal loop and does a
+//! quick scan of every channel once a second instead. Every AP it hears
+//! is appended to `SITESURV.CSV` with the current waypoint number:
+//!
+//!     time,waypoint,kind,bssid,ssid,channel,rssi
+//!     2025-06-01T14:03:09,3,ours,00:11:22:33:44:01,Corp,6,-52
+//!     2025-06-01T14:03:09,3,ap,66:77:88:99:aa:bb,Neighbour,11,-80
+//!     2025-06-01T14:03:10,4,mark,,,,
+//!
+//! `ours` is any AP with the SSID we joined, `ap` is everything else, and
+//! `mark` is written when the waypoint button (GPIO 22 to ground) is
+//! pressed. Waypoints start at 0 and count up one per press, so readings
+//! can be matched to spots marked on a floor plan.
+//!
+//! cyw43 doesn't tell us which BSSID we're associated with, or its RSSI
+//! outside a scan, so the OLED's "joined AP" figure is the strongest AP
+//! with our SSID.
+use core::fmt::{self, Write};
+use cyw43::{Control, ScanOptions, ScanType};
+use defmt::{info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_rp::gpio::Input;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{String, Vec};
+use ssd1306::prelude::WriteOnlyDataCommand;
+
+use crate::arp_guard::Mac;
+use crate::clock;
+use crate::oled;
+use crate::sd_storage::SdStorage;
+use crate::wifi_scan::{self, Bss};
+
+pub const EXPORT_FILE: &str = "SITESURV.CSV";
+pub const CSV_HEADER: &str = "time,waypoint,kind,bssid,ssid,channel,rssi";
+
+const TICK: Duration = Duration::from_secs(1);
+// Per channel, short enough for a full pass to fit in a tick.
+const DWELL: Duration = Duration::from_millis(40);
+// Presses and releases closer together than this are switch bounce.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+const MAX_ROWS: usize = 64;
+
+// Waypoint numbers from the button, for `run`.
+pub static WAYPOINTS: Channel<CriticalSectionRawMutex, u16, 4> = Channel::new();
+
+pub fn enabled() -> bool {
+    matches!(option_env!("SITE_SURVEY"), Some("1" | "true" | "yes"))
+}
+
+// Counts presses of an active-low button. Runs as its own task so presses
+// during a scan aren't missed.
+pub async fn watch_button(mut button: Input<'static>) -> ! {
+    let mut waypoint: u16 = 0;
+    loop {
+        button.wait_for_falling_edge().await;
+        waypoint = waypoint.wrapping_add(1);
+        WAYPOINTS.send(waypoint).await;
+        Timer::after(DEBOUNCE).await;
+        button.wait_for_high().await;
+        Timer::after(DEBOUNCE).await;
+    }
+}
+
+// "time,waypoint,kind,bssid,ssid,channel,rssi", with the AP fields empty
+// for a mark.
+fn row(time: &dyn fmt::Display, waypoint: u16, kind: &str, bss: Option<&Bss>) -> String<96> {
+    let mut row = String::new();
+    write!(&mut row, "{},{},{},", time, waypoint, kind).ok();
+    match bss {
+        Some(bss) => {
+            write!(&mut row, "{},", Mac(&bss.bssid)).ok();
+            // Keep the CSV parseable.
+            for c in bss.ssid.chars() {
+                row.push(if c == ',' || c == '"' { '_' } else { c }).ok();
+            }
+            write!(&mut row, ",{},{}", bss.channel, bss.rssi).ok();
+        }
+        None => {
+            row.push_str(",,,").ok();
+        }
+    }
+    row
+}
+
+fn show<I: WriteOnlyDataCommand>(
+    display: &mut oled::Display<I>,
+    waypoint: u16,
+    joined: Option<i16>,
+    aps: usize,
+) {
+    let mut wp: String<21> = String::new();
+    write!(&mut wp, "Waypoint {}", waypoint).ok();
+    let mut rssi: String<21> = String::new();
+    match joined {
+        Some(dbm) => write!(&mut rssi, "Joined AP {}dBm", dbm).ok(),
+        None => write!(&mut rssi, "Joined AP not heard").ok(),
+    };
+    let mut count: String<21> = String::new();
+    write!(&mut count, "{} APs", aps).ok();
+    oled::show_lines(display, &["SITE SURVEY", &wp, &rssi, &count]);
+}
+
+fn append<S: embassy_rp::spi::Instance>(
+    storage: &mut Option<SdStorage<'_, S>>,
+    rows: impl IntoIterator<Item = impl AsRef<str>>,
+) {
+    if let Some(sd) = storage.as_mut()
+        && let Err(e) = sd.append_lines(EXPORT_FILE, rows)
+    {
+        warn!("site survey export failed: {:?}", defmt::Debug2Format(&e));
+    }
+}
+
+// Scans and logs once a second, forever.
+pub async fn run<I: WriteOnlyDataCommand, S: embassy_rp::spi::Instance>(
+    control: &mut Control<'_>,
+    display: &mut oled::Display<I>,
+    storage: &mut Option<SdStorage<'_, S>>,
+) -> ! {
+    let ours = option_env!("WIFISSID").unwrap_or("");
+    let mut options = ScanOptions::default();
+    options.scan_type = ScanType::Active;
+    options.dwell_time = Some(DWELL);
+
+    if let Some(sd) = storage.as_mut()
+        && sd.read_at(EXPORT_FILE, 0, &mut [0u8; 1]).is_err()
+    {
+        sd.write_lines(EXPORT_FILE, [CSV_HEADER]).ok();
+    }
+
+    let mut waypoint: u16 = 0;
+    let mut next = Instant::now();
+    info!("site survey running, SSID {}", ours);
+    loop {
+        let now = clock::now_local();
+        let mut rows: Vec<String<96>, MAX_ROWS> = Vec::new();
+        let mut joined: Option<i16> = None;
+        let mut aps = 0;
+        wifi_scan::scan_with(control, options.clone(), |bss| {
+            let kind = if !ours.is_empty() && bss.ssid == ours {
+                joined = Some(joined.map_or(bss.rssi, |best| best.max(bss.rssi)));
+                "ours"
+            } else {
+                "ap"
+            };
+            aps += 1;
+            rows.push(row(&now, waypoint, kind, Some(bss))).ok();
+        })
+        .await;
+        append(storage, &rows);
+        show(display, waypoint, joined, aps);
+
+        // Wait out the rest of the second, taking presses as they come.
+        next = (next + TICK).max(Instant::now());
+        while let Either::Second(n) = select(Timer::at(next), WAYPOINTS.receive()).await {
+            waypoint = n;
+            info!("site survey: waypoint {}", waypoint);
+            append(storage, [row(&clock::now_local(), waypoint, "mark", None)]);
+            show(display, waypoint, joined, aps);
+        }
+    }
+}
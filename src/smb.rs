@@ -0,0 +1,686 @@
+//! smb
+//! ---
+//!
+//! What a Windows (or Samba) host says about itself on TCP 445, before
+//! anyone logs in.
+//!
+//! An SMB2 NEGOTIATE offering every dialect gets the best one the server
+//! speaks and whether it insists on signing. On the same connection, a
+//! SESSION_SETUP carrying an NTLMSSP NEGOTIATE gets the server's CHALLENGE
+//! back, which is where the interesting bits are: its NetBIOS and DNS
+//! names, its domain, and (from Windows) the OS version and build. We
+//! never answer the challenge, so there's no login attempt in the server's
+//! logs, just a dropped session.
+//!
+//! Then one connection per older dialect to see which of those it still
+//! accepts, and the SMB1 negotiate from `exposure` to see if SMBv1 is on.
+//! A host that only speaks SMB1 gets its signing requirement from that
+//! reply; names and version would need an SMB1 session setup, which we
+//! don't do.
+//!
+//! Results go in the inventory (SMBv1 and unsigned flags, hostname, build)
+//! and in `SMB.CSV`.
+use core::fmt::{self, Write};
+use defmt::{debug, info};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, with_timeout};
+use heapless::{String, Vec};
+
+use crate::exposure::{SMB1_NEGOTIATE, smb1_accepted};
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::util::write_all;
+
+pub const PORT: u16 = 445;
+pub const MAX_HOSTS: usize = 16;
+pub const EXPORT_FILE: &str = "SMB.CSV";
+pub const CSV_HEADER: &str =
+    "host,dialects,smb1,signing,netbios_name,netbios_domain,dns_name,dns_domain,os";
+
+// 2.0.2, 2.1, 3.0, 3.0.2, 3.1.1, oldest first.
+pub const DIALECTS: [u16; 5] = [0x0202, 0x0210, 0x0300, 0x0302, 0x0311];
+const SMB_3_1_1: u16 = 0x0311;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+const NETBIOS_HEADER: usize = 4;
+const SMB2_HEADER: usize = 64;
+const SMB2_MAGIC: [u8; 4] = *b"\xfeSMB";
+const CMD_NEGOTIATE: u16 = 0;
+const CMD_SESSION_SETUP: u16 = 1;
+const SIGNING_ENABLED: u16 = 0x01;
+const SIGNING_REQUIRED: u16 = 0x02;
+// SMB1 SecurityMode bit for "signatures required".
+const SMB1_SIGNING_REQUIRED: u8 = 0x08;
+// Fixed, so the server sees the same client every time we come back.
+const CLIENT_GUID: [u8; 16] = *b"picomap-smbprobe";
+
+const NTLMSSP: [u8; 8] = *b"NTLMSSP\0";
+// UNICODE | OEM | REQUEST_TARGET | NTLM | ALWAYS_SIGN |
+// EXTENDED_SESSIONSECURITY | VERSION | 128 | KEY_EXCH | 56
+const NTLM_FLAGS: u32 = 0xe208_8207;
+const NTLM_FLAG_VERSION: u32 = 0x0200_0000;
+const AV_EOL: u16 = 0;
+const AV_NB_COMPUTER: u16 = 1;
+const AV_NB_DOMAIN: u16 = 2;
+const AV_DNS_COMPUTER: u16 = 3;
+const AV_DNS_DOMAIN: u16 = 4;
+
+// SPNEGO and NTLMSSP object identifiers, DER encoded.
+const OID_SPNEGO: [u8; 8] = [0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+const OID_NTLMSSP: [u8; 12] = [
+    0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a,
+];
+
+pub type Message = Vec<u8, 256>;
+
+// "3.1.1" for 0x0311.
+pub struct Dialect(pub u16);
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor, patch) = (self.0 >> 8, (self.0 >> 4) & 0xf, self.0 & 0xf);
+        write!(f, "{}.{}", major, minor)?;
+        if patch != 0 {
+            write!(f, ".{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct OsVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u16,
+}
+
+impl fmt::Display for OsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub dialect: u16,
+    pub signing_required: bool,
+}
+
+// What the NTLMSSP CHALLENGE gave away. Samba leaves the version out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ntlm {
+    pub netbios_name: String<16>,
+    pub netbios_domain: String<16>,
+    pub dns_name: String<64>,
+    pub dns_domain: String<64>,
+    pub os: Option<OsVersion>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmbInfo {
+    // SMB2 and later dialects accepted, oldest first.
+    pub dialects: Vec<u16, 5>,
+    pub smb1: bool,
+    // None if neither negotiate got far enough to say.
+    pub signing_required: Option<bool>,
+    pub ntlm: Ntlm,
+}
+
+impl SmbInfo {
+    // DNS name if it gave one, else the NetBIOS name.
+    pub fn name(&self) -> &str {
+        if self.ntlm.dns_name.is_empty() {
+            &self.ntlm.netbios_name
+        } else {
+            &self.ntlm.dns_name
+        }
+    }
+
+    pub fn csv_row(&self, host: IpAddress) -> String<256> {
+        let mut row = String::new();
+        write!(row, "{},", host).ok();
+        for (i, &d) in self.dialects.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(row, "{}{}", sep, Dialect(d)).ok();
+        }
+        let signing = match self.signing_required {
+            Some(true) => "required",
+            Some(false) => "optional",
+            None => "",
+        };
+        let n = &self.ntlm;
+        write!(
+            row,
+            ",{},{},{},{},{},{},",
+            if self.smb1 { "yes" } else { "no" },
+            signing,
+            n.netbios_name,
+            n.netbios_domain,
+            n.dns_name,
+            n.dns_domain
+        )
+        .ok();
+        if let Some(os) = n.os {
+            write!(row, "{}", os).ok();
+        }
+        row
+    }
+}
+
+// "SMB 3.1.1, signing optional, SMBv1 on, FILESRV1 in CORP, 10.0.20348"
+impl fmt::Display for SmbInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dialects.last() {
+            Some(&d) => write!(f, "SMB {}", Dialect(d))?,
+            None => f.write_str("SMB1 only")?,
+        }
+        match self.signing_required {
+            Some(true) => f.write_str(", signing required")?,
+            Some(false) => f.write_str(", signing optional")?,
+            None => {}
+        }
+        if self.smb1 && !self.dialects.is_empty() {
+            f.write_str(", SMBv1 on")?;
+        }
+        if !self.name().is_empty() {
+            write!(f, ", {}", self.name())?;
+            if !self.ntlm.netbios_domain.is_empty() {
+                write!(f, " in {}", self.ntlm.netbios_domain)?;
+            }
+        }
+        if let Some(os) = self.ntlm.os {
+            write!(f, ", {}", os)?;
+        }
+        Ok(())
+    }
+}
+
+fn put_u16(msg: &mut Message, v: u16) {
+    msg.extend_from_slice(&v.to_le_bytes()).ok();
+}
+
+fn put_u32(msg: &mut Message, v: u32) {
+    msg.extend_from_slice(&v.to_le_bytes()).ok();
+}
+
+fn get_u16(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn get_u32(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+// NetBIOS session header placeholder, then the 64 byte SMB2 header.
+fn start(command: u16, message_id: u64) -> Message {
+    let mut msg = Message::new();
+    msg.extend_from_slice(&[0; NETBIOS_HEADER]).ok();
+    msg.extend_from_slice(&SMB2_MAGIC).ok();
+    put_u16(&mut msg, SMB2_HEADER as u16);
+    put_u16(&mut msg, 0); // credit charge
+    put_u32(&mut msg, 0); // status
+    put_u16(&mut msg, command);
+    put_u16(&mut msg, 1); // credits requested
+    put_u32(&mut msg, 0); // flags
+    put_u32(&mut msg, 0); // next command
+    msg.extend_from_slice(&message_id.to_le_bytes()).ok();
+    put_u32(&mut msg, 0); // process id
+    put_u32(&mut msg, 0); // tree id
+    msg.extend_from_slice(&[0; 8]).ok(); // session id
+    msg.extend_from_slice(&[0; 16]).ok(); // signature
+    msg
+}
+
+// Fills in the NetBIOS length now the message is complete.
+fn finish(mut msg: Message) -> Message {
+    let len = (msg.len() - NETBIOS_HEADER) as u32;
+    msg[1..4].copy_from_slice(&len.to_be_bytes()[1..]);
+    msg
+}
+
+// SMB2 NEGOTIATE offering `dialects`. 3.1.1 needs a preauth integrity
+// context; the salt should be random, but we never get as far as using it.
+pub fn negotiate(dialects: &[u16]) -> Message {
+    let mut msg = start(CMD_NEGOTIATE, 0);
+    put_u16(&mut msg, 36); // structure size
+    put_u16(&mut msg, dialects.len() as u16);
+    put_u16(&mut msg, SIGNING_ENABLED);
+    put_u16(&mut msg, 0); // reserved
+    put_u32(&mut msg, 0); // capabilities
+    msg.extend_from_slice(&CLIENT_GUID).ok();
+    let contexts_at = msg.len();
+    msg.extend_from_slice(&[0; 8]).ok(); // context offset and count
+    for &d in dialects {
+        put_u16(&mut msg, d);
+    }
+    if dialects.contains(&SMB_3_1_1) {
+        while !(msg.len() - NETBIOS_HEADER).is_multiple_of(8) {
+            msg.push(0).ok();
+        }
+        let offset = (msg.len() - NETBIOS_HEADER) as u32;
+        msg[contexts_at..contexts_at + 4].copy_from_slice(&offset.to_le_bytes());
+        msg[contexts_at + 4..contexts_at + 6].copy_from_slice(&1u16.to_le_bytes());
+        put_u16(&mut msg, 1); // SMB2_PREAUTH_INTEGRITY_CAPABILITIES
+        put_u16(&mut msg, 38); // data length
+        put_u32(&mut msg, 0); // reserved
+        put_u16(&mut msg, 1); // one hash algorithm
+        put_u16(&mut msg, 32); // salt length
+        put_u16(&mut msg, 1); // SHA-512
+        msg.extend_from_slice(&[0x5a; 32]).ok();
+    }
+    finish(msg)
+}
+
+// The SMB2 payload of a NetBIOS message, if it's a successful reply to
+// `command`.
+fn smb2_reply(resp: &[u8], command: u16, ok_status: u32) -> Option<&[u8]> {
+    let smb = resp.get(NETBIOS_HEADER..)?;
+    if smb.len() < SMB2_HEADER
+        || smb[..4] != SMB2_MAGIC
+        || get_u32(smb, 8)? != ok_status
+        || get_u16(smb, 12)? != command
+    {
+        return None;
+    }
+    Some(smb)
+}
+
+pub fn parse_negotiate(resp: &[u8]) -> Option<Negotiated> {
+    let smb = smb2_reply(resp, CMD_NEGOTIATE, 0)?;
+    let body = &smb[SMB2_HEADER..];
+    if get_u16(body, 0)? != 65 {
+        return None;
+    }
+    Some(Negotiated {
+        dialect: get_u16(body, 4)?,
+        signing_required: get_u16(body, 2)? & SIGNING_REQUIRED != 0,
+    })
+}
+
+// Some(signing required) if the SMB1 negotiate was accepted.
+pub fn parse_smb1(resp: &[u8]) -> Option<bool> {
+    if !smb1_accepted(resp) || resp.len() < 40 {
+        return None;
+    }
+    Some(resp[39] & SMB1_SIGNING_REQUIRED != 0)
+}
+
+// 40 byte NTLMSSP NEGOTIATE, no domain or workstation.
+fn ntlm_negotiate() -> [u8; 40] {
+    let mut msg = [0u8; 40];
+    msg[..8].copy_from_slice(&NTLMSSP);
+    msg[8..12].copy_from_slice(&1u32.to_le_bytes());
+    msg[12..16].copy_from_slice(&NTLM_FLAGS.to_le_bytes());
+    msg[39] = 15; // NTLMSSP revision
+    msg
+}
+
+// Wraps an NTLMSSP token in a SPNEGO NegTokenInit that offers only NTLM.
+// Short DER lengths only, which is plenty for a 40 byte token.
+pub fn spnego_init(token: &[u8]) -> Vec<u8, 128> {
+    let mech_token = token.len() + 2; // OCTET STRING
+    let mech_types = OID_NTLMSSP.len() + 2; // SEQUENCE
+    let init = (mech_types + 2) + (mech_token + 2); // [0], [2]
+    let outer = OID_SPNEGO.len() + init + 4; // [0] SEQUENCE
+    let mut out = Vec::new();
+    out.extend_from_slice(&[0x60, outer as u8]).ok();
+    out.extend_from_slice(&OID_SPNEGO).ok();
+    out.extend_from_slice(&[0xa0, (init + 2) as u8, 0x30, init as u8])
+        .ok();
+    out.extend_from_slice(&[0xa0, mech_types as u8, 0x30, OID_NTLMSSP.len() as u8])
+        .ok();
+    out.extend_from_slice(&OID_NTLMSSP).ok();
+    out.extend_from_slice(&[0xa2, mech_token as u8, 0x04, token.len() as u8])
+        .ok();
+    out.extend_from_slice(token).ok();
+    out
+}
+
+// SMB2 SESSION_SETUP carrying the NTLMSSP NEGOTIATE.
+pub fn session_setup() -> Message {
+    let blob = spnego_init(&ntlm_negotiate());
+    let mut msg = start(CMD_SESSION_SETUP, 1);
+    put_u16(&mut msg, 25); // structure size
+    msg.push(0).ok(); // flags
+    msg.push(SIGNING_ENABLED as u8).ok();
+    put_u32(&mut msg, 0); // capabilities
+    put_u32(&mut msg, 0); // channel
+    put_u16(&mut msg, (SMB2_HEADER + 24) as u16);
+    put_u16(&mut msg, blob.len() as u16);
+    msg.extend_from_slice(&[0; 8]).ok(); // previous session id
+    msg.extend_from_slice(&blob).ok();
+    finish(msg)
+}
+
+fn utf16<const N: usize>(buf: &[u8]) -> String<N> {
+    let units = buf
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+    let mut out = String::new();
+    for c in char::decode_utf16(units) {
+        if out.push(c.unwrap_or('?')).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+// Finds the NTLMSSP CHALLENGE in a SESSION_SETUP reply. It's inside a
+// SPNEGO NegTokenResp, but looking for the signature is simpler than
+// walking the DER and works for raw NTLMSSP too.
+pub fn parse_challenge(resp: &[u8]) -> Option<Ntlm> {
+    const MORE_PROCESSING_REQUIRED: u32 = 0xc000_0016;
+    let smb = smb2_reply(resp, CMD_SESSION_SETUP, MORE_PROCESSING_REQUIRED)?;
+    let at = smb.windows(NTLMSSP.len()).position(|w| w == NTLMSSP)?;
+    let msg = &smb[at..];
+    if get_u32(msg, 8)? != 2 {
+        return None;
+    }
+    let mut ntlm = Ntlm::default();
+    if get_u32(msg, 20)? & NTLM_FLAG_VERSION != 0
+        && let Some(v) = msg.get(48..52)
+    {
+        ntlm.os = Some(OsVersion {
+            major: v[0],
+            minor: v[1],
+            build: u16::from_le_bytes([v[2], v[3]]),
+        });
+    }
+
+    let info_len = get_u16(msg, 40)? as usize;
+    let info_at = get_u32(msg, 44)? as usize;
+    let mut info = msg.get(info_at..info_at + info_len)?;
+    while let (Some(id), Some(len)) = (get_u16(info, 0), get_u16(info, 2)) {
+        let Some(value) = info.get(4..4 + len as usize) else {
+            break;
+        };
+        match id {
+            AV_EOL => break,
+            AV_NB_COMPUTER => ntlm.netbios_name = utf16(value),
+            AV_NB_DOMAIN => ntlm.netbios_domain = utf16(value),
+            AV_DNS_COMPUTER => ntlm.dns_name = utf16(value),
+            AV_DNS_DOMAIN => ntlm.dns_domain = utf16(value),
+            _ => {}
+        }
+        info = &info[4 + len as usize..];
+    }
+    Some(ntlm)
+}
+
+// Total length of the NetBIOS message at the start of `buf`, once the
+// header's in.
+fn framed_len(buf: &[u8]) -> Option<usize> {
+    let h = buf.get(..NETBIOS_HEADER)?;
+    Some(NETBIOS_HEADER + u32::from_be_bytes([0, h[1], h[2], h[3]]) as usize)
+}
+
+// Sends `req` and reads one NetBIOS message back, or as much of it as fits.
+async fn exchange<'b>(
+    socket: &mut TcpSocket<'_>,
+    req: &[u8],
+    buf: &'b mut [u8],
+) -> Option<&'b [u8]> {
+    write_all(socket, req).await.ok()?;
+    let mut got = 0;
+    let read = async {
+        while got < buf.len() && framed_len(&buf[..got]).is_none_or(|n| got < n) {
+            match socket.read(&mut buf[got..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => got += n,
+            }
+        }
+    };
+    with_timeout(READ_TIMEOUT, read).await.ok();
+    (got > 0).then_some(&buf[..got])
+}
+
+// One connection: NEGOTIATE with `dialects`, then the NTLMSSP challenge
+// if `ntlm` and the negotiate worked.
+async fn session(
+    stack: Stack<'_>,
+    host: IpAddress,
+    dialects: &[u16],
+    ntlm: bool,
+) -> Option<(Negotiated, Option<Ntlm>)> {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, PORT))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    let mut buf = [0u8; 1024];
+    let result = match exchange(&mut socket, &negotiate(dialects), &mut buf)
+        .await
+        .and_then(parse_negotiate)
+    {
+        Some(neg) if ntlm => {
+            let challenge = exchange(&mut socket, &session_setup(), &mut buf)
+                .await
+                .and_then(parse_challenge);
+            Some((neg, challenge))
+        }
+        Some(neg) => Some((neg, None)),
+        None => None,
+    };
+    socket.abort();
+    result
+}
+
+// Some(signing required) if the host still takes SMB1.
+async fn smb1(stack: Stack<'_>, host: IpAddress) -> Option<bool> {
+    let mut rx_buf = [0u8; 512];
+    let mut tx_buf = [0u8; 128];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, PORT))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    let mut buf = [0u8; 512];
+    let result = exchange(&mut socket, &SMB1_NEGOTIATE, &mut buf)
+        .await
+        .and_then(parse_smb1);
+    socket.abort();
+    result
+}
+
+// None if nothing on 445 would negotiate at all.
+pub async fn probe(stack: Stack<'_>, host: IpAddress) -> Option<SmbInfo> {
+    let mut info = SmbInfo::default();
+    if let Some((neg, ntlm)) = session(stack, host, &DIALECTS, true).await {
+        info.signing_required = Some(neg.signing_required);
+        info.ntlm = ntlm.unwrap_or_default();
+        // The server picked its best, so only older ones are in question.
+        for &d in DIALECTS.iter().filter(|&&d| d < neg.dialect) {
+            if let Some((older, _)) = session(stack, host, &[d], false).await
+                && older.dialect == d
+            {
+                info.dialects.push(d).ok();
+            }
+        }
+        info.dialects.push(neg.dialect).ok();
+    }
+    let smb1_signing = smb1(stack, host).await;
+    info.smb1 = smb1_signing.is_some();
+    if info.dialects.is_empty() {
+        info.signing_required = smb1_signing;
+        if !info.smb1 {
+            debug!("smb: {} didn't negotiate", host);
+            return None;
+        }
+    }
+    info!("smb: {} {}", host, defmt::Display2Format(&info));
+    Some(info)
+}
+
+// Writes the results out as CSV, replacing the previous export.
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    results: &[(IpAddress, SmbInfo)],
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    storage.write_lines(
+        EXPORT_FILE,
+        core::iter::once(String::<256>::try_from(CSV_HEADER).unwrap())
+            .chain(results.iter().map(|(host, info)| info.csv_row(*host))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(command: u16, status: u32, body: &[u8]) -> Vec<u8, 512> {
+        let mut smb = start(command, 0);
+        smb[NETBIOS_HEADER + 8..NETBIOS_HEADER + 12].copy_from_slice(&status.to_le_bytes());
+        let mut out: Vec<u8, 512> = Vec::from_slice(&smb).unwrap();
+        out.extend_from_slice(body).unwrap();
+        let len = (out.len() - NETBIOS_HEADER) as u32;
+        out[1..4].copy_from_slice(&len.to_be_bytes()[1..]);
+        out
+    }
+
+    #[test]
+    fn negotiate_request() {
+        let msg = negotiate(&DIALECTS);
+        assert_eq!(framed_len(&msg), Some(msg.len()));
+        let smb = &msg[NETBIOS_HEADER..];
+        assert_eq!(smb[..4], SMB2_MAGIC);
+        assert_eq!(get_u16(smb, 64 + 2), Some(5));
+        // Dialects at 100, context at the next multiple of 8.
+        assert_eq!(get_u16(smb, 100 + 8), Some(0x0311));
+        assert_eq!(get_u32(smb, 64 + 28), Some(112));
+        assert_eq!(get_u16(smb, 112), Some(1));
+        assert_eq!(smb.len(), 112 + 8 + 38);
+
+        let msg = negotiate(&[0x0210]);
+        assert_eq!(msg.len(), NETBIOS_HEADER + 64 + 36 + 2);
+        assert_eq!(get_u32(&msg, NETBIOS_HEADER + 64 + 28), Some(0));
+    }
+
+    #[test]
+    fn negotiate_reply() {
+        let mut body = [0u8; 65];
+        body[0] = 65;
+        body[2] = (SIGNING_ENABLED | SIGNING_REQUIRED) as u8;
+        body[4..6].copy_from_slice(&0x0311u16.to_le_bytes());
+        let resp = reply(CMD_NEGOTIATE, 0, &body);
+        assert_eq!(
+            parse_negotiate(&resp),
+            Some(Negotiated {
+                dialect: 0x0311,
+                signing_required: true
+            })
+        );
+        body[2] = SIGNING_ENABLED as u8;
+        let resp = reply(CMD_NEGOTIATE, 0, &body);
+        assert_eq!(
+            parse_negotiate(&resp).map(|n| n.signing_required),
+            Some(false)
+        );
+        // STATUS_NOT_SUPPORTED
+        let resp = reply(CMD_NEGOTIATE, 0xc000_00bb, &body);
+        assert_eq!(parse_negotiate(&resp), None);
+        assert_eq!(parse_negotiate(&resp[..40]), None);
+    }
+
+    #[test]
+    fn spnego() {
+        let blob = spnego_init(&ntlm_negotiate());
+        assert_eq!(blob.len(), 74);
+        assert_eq!(blob[..4], [0x60, 0x48, 0x06, 0x06]);
+        assert_eq!(blob[10..14], [0xa0, 0x3e, 0x30, 0x3c]);
+        assert_eq!(blob[30..34], [0xa2, 0x2a, 0x04, 0x28]);
+        assert_eq!(blob[34..42], NTLMSSP);
+
+        let msg = session_setup();
+        assert_eq!(framed_len(&msg), Some(msg.len()));
+        assert_eq!(msg.len(), NETBIOS_HEADER + 88 + 74);
+    }
+
+    #[test]
+    fn challenge() {
+        let mut info: Vec<u8, 128> = Vec::new();
+        for (id, value) in [
+            (AV_NB_DOMAIN, "CORP"),
+            (AV_NB_COMPUTER, "FILESRV1"),
+            (AV_DNS_DOMAIN, "corp.example"),
+            (AV_DNS_COMPUTER, "filesrv1.corp.example"),
+        ] {
+            info.extend_from_slice(&id.to_le_bytes()).unwrap();
+            info.extend_from_slice(&(value.len() as u16 * 2).to_le_bytes())
+                .unwrap();
+            for c in value.encode_utf16() {
+                info.extend_from_slice(&c.to_le_bytes()).unwrap();
+            }
+        }
+        info.extend_from_slice(&[0; 4]).unwrap();
+
+        let mut ntlm = [0u8; 56];
+        ntlm[..8].copy_from_slice(&NTLMSSP);
+        ntlm[8] = 2;
+        ntlm[20..24].copy_from_slice(&NTLM_FLAGS.to_le_bytes());
+        ntlm[40..42].copy_from_slice(&(info.len() as u16).to_le_bytes());
+        ntlm[44] = 56;
+        ntlm[48..52].copy_from_slice(&[10, 0, 0x5c, 0x4f]);
+        let mut body: Vec<u8, 256> = Vec::new();
+        // Structure size etc, then some SPNEGO wrapping before the token.
+        body.extend_from_slice(&[9, 0, 0, 0, 72, 0, 0, 0, 0xa1, 0x81])
+            .unwrap();
+        body.extend_from_slice(&ntlm).unwrap();
+        body.extend_from_slice(&info).unwrap();
+        let resp = reply(CMD_SESSION_SETUP, 0xc000_0016, &body);
+
+        let ntlm = parse_challenge(&resp).unwrap();
+        assert_eq!(ntlm.netbios_name, "FILESRV1");
+        assert_eq!(ntlm.netbios_domain, "CORP");
+        assert_eq!(ntlm.dns_name, "filesrv1.corp.example");
+        assert_eq!(ntlm.dns_domain, "corp.example");
+        assert_eq!(
+            ntlm.os,
+            Some(OsVersion {
+                major: 10,
+                minor: 0,
+                build: 20316
+            })
+        );
+
+        let smb = SmbInfo {
+            dialects: Vec::from_slice(&[0x0202, 0x0210, 0x0300, 0x0302, 0x0311]).unwrap(),
+            smb1: true,
+            signing_required: Some(false),
+            ntlm,
+        };
+        let mut line: String<128> = String::new();
+        write!(line, "{}", smb).unwrap();
+        assert_eq!(
+            line,
+            "SMB 3.1.1, signing optional, SMBv1 on, filesrv1.corp.example in CORP, 10.0.20316"
+        );
+        assert_eq!(
+            smb.csv_row(IpAddress::v4(10, 0, 0, 5)),
+            "10.0.0.5,2.0.2 2.1 3.0 3.0.2 3.1.1,yes,optional,FILESRV1,CORP,\
+             filesrv1.corp.example,corp.example,10.0.20316"
+        );
+    }
+
+    #[test]
+    fn smb1_signing() {
+        let mut resp = [0u8; 40];
+        resp[4..9].copy_from_slice(b"\xffSMB\x72");
+        resp[36] = 17;
+        resp[39] = 0x03;
+        assert_eq!(parse_smb1(&resp), Some(false));
+        resp[39] = 0x0f;
+        assert_eq!(parse_smb1(&resp), Some(true));
+        assert_eq!(parse_smb1(&resp[..39]), None);
+    }
+}
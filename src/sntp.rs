@@ -0,0 +1,266 @@
+//! sntp
+//! ----
+//!
+//! Minimal SNTPv4 client (RFC 4330) that keeps `clock` in sync.
+//!
+//! Server, in order of preference:
+//!   1. `NTP_SERVER` from `.env`, an IP or a hostname
+//!   2. the DHCP gateway, most routers answer NTP
+//!   3. pool.ntp.org, also tried whenever the gateway doesn't answer
+//!
+//! embassy-net doesn't hand us DHCP option 42, so "the DHCP-provided server"
+//! is the gateway in practice. Set `NTP_SERVER` if your network has its own.
+use defmt::{debug, info, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+
+use crate::clock;
+
+pub const PORT: u16 = 123;
+pub const PACKET_LEN: usize = 48;
+const FALLBACK_SERVER: &str = "pool.ntp.org";
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(3);
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Seconds from 1900-01-01 (NTP era 0) to 1970-01-01.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+// Seconds in an NTP era, the 32-bit seconds field wraps after that.
+const NTP_ERA: u64 = 1 << 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SntpError {
+    NoServer,
+    Socket,
+    Timeout,
+    BadReply,
+    // Stratum 0, the server is telling us to go away.
+    KissOfDeath,
+}
+
+// Client request: LI 0, VN 4, mode 3. We put our uptime in the transmit
+// timestamp so we can match the server's originate timestamp against it.
+pub fn build_request(buf: &mut [u8; PACKET_LEN], cookie: u64) {
+    buf.fill(0);
+    buf[0] = (4 << 3) | 3;
+    buf[40..48].copy_from_slice(&cookie.to_be_bytes());
+}
+
+// Returns the server's transmit time as unix microseconds.
+pub fn parse_reply(reply: &[u8], cookie: u64) -> Result<u64, SntpError> {
+    if reply.len() < PACKET_LEN {
+        return Err(SntpError::BadReply);
+    }
+    let mode = reply[0] & 0x07;
+    let leap = reply[0] >> 6;
+    if mode != 4 || leap == 3 {
+        return Err(SntpError::BadReply);
+    }
+    if reply[1] == 0 {
+        return Err(SntpError::KissOfDeath);
+    }
+    if reply[24..32] != cookie.to_be_bytes() {
+        return Err(SntpError::BadReply);
+    }
+
+    let mut secs = u32::from_be_bytes([reply[40], reply[41], reply[42], reply[43]]) as u64;
+    let frac = u32::from_be_bytes([reply[44], reply[45], reply[46], reply[47]]) as u64;
+    // Era 0 runs out in 2036. RFC 4330: with the top bit clear, it's era 1.
+    if secs & 0x8000_0000 == 0 {
+        secs += NTP_ERA;
+    }
+    if secs < NTP_UNIX_OFFSET {
+        return Err(SntpError::BadReply);
+    }
+    Ok((secs - NTP_UNIX_OFFSET) * 1_000_000 + ((frac * 1_000_000) >> 32))
+}
+
+// The server to try first: `NTP_SERVER` if set, else the gateway.
+pub async fn resolve_server(stack: Stack<'_>) -> Option<IpAddress> {
+    if let Some(name) = option_env!("NTP_SERVER") {
+        return resolve(stack, name).await;
+    }
+    if let Some(gw) = stack.config_v4().and_then(|c| c.gateway) {
+        return Some(gw.into());
+    }
+    resolve(stack, FALLBACK_SERVER).await
+}
+
+async fn resolve(stack: Stack<'_>, name: &str) -> Option<IpAddress> {
+    if let Ok(addr) = name.parse::<core::net::IpAddr>() {
+        return Some(addr.into());
+    }
+    match stack.dns_query(name, DnsQueryType::A).await {
+        Ok(addrs) => addrs.first().copied(),
+        Err(e) => {
+            warn!("sntp: could not resolve {}: {:?}", name, e);
+            None
+        }
+    }
+}
+
+// One request/reply against `server`. Returns unix microseconds, corrected
+// by half the round trip.
+pub async fn query(stack: Stack<'_>, server: IpAddress) -> Result<u64, SntpError> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    socket.bind(0).map_err(|_| SntpError::Socket)?;
+
+    let sent = Instant::now();
+    let cookie = sent.as_micros();
+    let mut req = [0u8; PACKET_LEN];
+    build_request(&mut req, cookie);
+    socket
+        .send_to(&req, (server, PORT))
+        .await
+        .map_err(|_| SntpError::Socket)?;
+
+    let mut reply = [0u8; 64];
+    let recv = async {
+        loop {
+            match socket.recv_from(&mut reply).await {
+                Ok((n, meta)) if meta.endpoint.addr == server => return Ok(n),
+                Ok(_) => continue,
+                Err(_) => return Err(SntpError::Socket),
+            }
+        }
+    };
+    let n = with_timeout(REPLY_TIMEOUT, recv)
+        .await
+        .map_err(|_| SntpError::Timeout)??;
+    let rtt = sent.elapsed().as_micros();
+    Ok(parse_reply(&reply[..n], cookie)? + rtt / 2)
+}
+
+pub async fn sync(stack: Stack<'_>) -> Result<(), SntpError> {
+    let mut server = resolve_server(stack).await.ok_or(SntpError::NoServer)?;
+    let now = match query(stack, server).await {
+        Ok(now) => now,
+        // Plenty of routers don't serve NTP. Without NTP_SERVER set, the
+        // gateway is only a guess, so try the pool before giving up.
+        Err(e) if option_env!("NTP_SERVER").is_none() => {
+            let Some(fallback) = resolve(stack, FALLBACK_SERVER).await else {
+                return Err(e);
+            };
+            if fallback == server {
+                return Err(e);
+            }
+            warn!(
+                "sntp: {} failed ({}), trying {}",
+                server, e, FALLBACK_SERVER
+            );
+            server = fallback;
+            query(stack, server).await?
+        }
+        Err(e) => return Err(e),
+    };
+    let drift = clock::set_unix_micros(now);
+    info!(
+        "sntp: synced to {}, now {} UTC (adjusted {}ms)",
+        server,
+        clock::now_utc(),
+        drift / 1000
+    );
+    Ok(())
+}
+
+// Syncs now, then every hour. Runs forever, spawn it from a task.
+pub async fn run(stack: Stack<'_>) -> ! {
+    stack.wait_config_up().await;
+    loop {
+        match sync(stack).await {
+            Ok(()) => Timer::after(RESYNC_INTERVAL).await,
+            Err(e) => {
+                warn!("sntp: sync failed: {}", e);
+                Timer::after(RETRY_INTERVAL).await;
+            }
+        }
+        debug!("sntp: resyncing");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::DateTime;
+
+    const COOKIE: u64 = 0x0123_4567_89ab_cdef;
+
+    // A server reply to `COOKIE` sent at `secs` + `frac` NTP time.
+    fn reply(secs: u32, frac: u32) -> [u8; PACKET_LEN] {
+        let mut buf = [0u8; PACKET_LEN];
+        buf[0] = (4 << 3) | 4; // LI 0, VN 4, mode 4
+        buf[1] = 2; // stratum
+        buf[24..32].copy_from_slice(&COOKIE.to_be_bytes());
+        buf[40..44].copy_from_slice(&secs.to_be_bytes());
+        buf[44..48].copy_from_slice(&frac.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn request() {
+        let mut buf = [0xffu8; PACKET_LEN];
+        build_request(&mut buf, COOKIE);
+        assert_eq!(buf[0], 0x23);
+        assert!(buf[1..40].iter().all(|&b| b == 0));
+        assert_eq!(buf[40..48], COOKIE.to_be_bytes());
+    }
+
+    #[test]
+    fn known_time() {
+        // 2025-06-01T14:03:09.5Z
+        let micros = parse_reply(&reply(0xebe6_dc1d, 0x8000_0000), COOKIE).unwrap();
+        assert_eq!(micros, 1_748_786_589_500_000);
+        assert_eq!(
+            DateTime::from_unix((micros / 1_000_000) as i64).to_string(),
+            "2025-06-01T14:03:09"
+        );
+        // The unix epoch is the offset itself.
+        assert_eq!(parse_reply(&reply(2_208_988_800, 0), COOKIE), Ok(0));
+    }
+
+    #[test]
+    fn era_one() {
+        // The seconds wrap on 2036-02-07T06:28:16Z.
+        let micros = parse_reply(&reply(0, 0), COOKIE).unwrap();
+        assert_eq!(micros / 1_000_000, NTP_ERA - NTP_UNIX_OFFSET);
+        assert_eq!(
+            DateTime::from_unix((micros / 1_000_000) as i64).to_string(),
+            "2036-02-07T06:28:16"
+        );
+        let micros = parse_reply(&reply(0x0756_79a5, 0), COOKIE).unwrap();
+        assert_eq!(
+            DateTime::from_unix((micros / 1_000_000) as i64).to_string(),
+            "2040-01-02T03:04:05"
+        );
+        // Top bit set but before 1970 is neither era.
+        assert_eq!(
+            parse_reply(&reply(0x8000_0000, 0), COOKIE),
+            Err(SntpError::BadReply)
+        );
+    }
+
+    #[test]
+    fn bad_replies() {
+        let good = reply(0xebe6_dc1d, 0);
+        assert_eq!(parse_reply(&good[..47], COOKIE), Err(SntpError::BadReply));
+        assert_eq!(parse_reply(&good, COOKIE + 1), Err(SntpError::BadReply));
+
+        let mut client = good;
+        client[0] = (4 << 3) | 3;
+        assert_eq!(parse_reply(&client, COOKIE), Err(SntpError::BadReply));
+        // Leap indicator 3 is an unsynchronised server.
+        let mut unsynced = good;
+        unsynced[0] |= 0xc0;
+        assert_eq!(parse_reply(&unsynced, COOKIE), Err(SntpError::BadReply));
+        let mut kod = good;
+        kod[1] = 0;
+        assert_eq!(parse_reply(&kod, COOKIE), Err(SntpError::KissOfDeath));
+    }
+}
@@ -0,0 +1,390 @@
+//! ssh
+//! ---
+//!
+//! SSH host key fingerprints, to catch machines that were re-imaged or
+//! cloned, or something answering in their place.
+//!
+//! The host key only shows up in the server's half of the key exchange,
+//! so this goes a little further than a banner grab: version exchange,
+//! KEXINIT both ways, then a curve25519 KEX_ECDH_INIT. The server's
+//! KEX_ECDH_REPLY carries its host key blob, and the fingerprint is the
+//! SHA256 of that blob, same as `ssh-keygen -l` prints. We hang up there,
+//! before NEWKEYS, so nothing is ever authenticated.
+//!
+//! Our public value is the curve25519 base point rather than a real
+//! ephemeral key; we never compute the shared secret, and the server
+//! doesn't care.
+//!
+//! The host key type the server picks depends on what we offer, so the
+//! offer is fixed (ed25519 first) to keep fingerprints comparable between
+//! scans. Keys are kept per host in the inventory (see `inventory`).
+use core::fmt::{self, Write};
+use core::ops::Range;
+use defmt::debug;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, with_timeout};
+use heapless::{String, Vec};
+
+use crate::sha256;
+use crate::util::write_all;
+
+pub const PORTS: [u16; 2] = [22, 2222];
+pub const MAX_HOSTS: usize = 32;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+// Big enough for a server KEXINIT or a reply with a 4096 bit RSA key.
+const BUF_LEN: usize = 2048;
+
+const VERSION: &[u8] = b"SSH-2.0-picomap\r\n";
+const MSG_DISCONNECT: u8 = 1;
+const MSG_KEXINIT: u8 = 20;
+const MSG_KEX_ECDH_INIT: u8 = 30;
+const MSG_KEX_ECDH_REPLY: u8 = 31;
+
+const KEX: &str = "curve25519-sha256,curve25519-sha256@libssh.org";
+const HOST_KEYS: &str = "ssh-ed25519,ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,\
+                         ecdsa-sha2-nistp521,rsa-sha2-512,rsa-sha2-256,ssh-rsa";
+const CIPHERS: &str = "chacha20-poly1305@openssh.com,aes128-ctr,aes256-ctr,\
+                       aes128-gcm@openssh.com,aes256-gcm@openssh.com";
+const MACS: &str = "hmac-sha2-256-etm@openssh.com,hmac-sha2-256,hmac-sha1";
+const COMPRESSION: &str = "none";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshKey {
+    pub port: u16,
+    // Key type from the blob, "ssh-ed25519", "ssh-rsa"...
+    pub kind: String<24>,
+    pub sha256: [u8; 32],
+}
+
+impl SshKey {
+    // Same key, whatever port it was found on.
+    pub fn same_key(&self, other: &SshKey) -> bool {
+        self.kind == other.kind && self.sha256 == other.sha256
+    }
+}
+
+// What answered on an SSH port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshServer {
+    // "SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u3"
+    pub version: String<64>,
+    // None if it wouldn't do curve25519.
+    pub key: Option<SshKey>,
+}
+
+// "ssh-ed25519 SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"
+impl fmt::Display for SshKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} SHA256:", self.kind)?;
+        base64(f, &self.sha256)
+    }
+}
+
+// Unpadded, like OpenSSH prints fingerprints.
+fn base64(f: &mut impl Write, data: &[u8]) -> fmt::Result {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            f.write_char(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char)?;
+        }
+    }
+    Ok(())
+}
+
+pub type Packet = Vec<u8, 768>;
+
+fn put_string(out: &mut Packet, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u32).to_be_bytes()).ok();
+    out.extend_from_slice(s).ok();
+}
+
+// Wraps a payload in the unencrypted binary packet format: length,
+// padding length, payload, at least 4 bytes of padding to a multiple of 8.
+fn packet(payload: &[u8]) -> Packet {
+    let mut pad = 8 - (5 + payload.len()) % 8;
+    if pad < 4 {
+        pad += 8;
+    }
+    let mut out = Packet::new();
+    let len = (1 + payload.len() + pad) as u32;
+    out.extend_from_slice(&len.to_be_bytes()).ok();
+    out.push(pad as u8).ok();
+    out.extend_from_slice(payload).ok();
+    out.extend_from_slice(&[0; 16][..pad]).ok();
+    out
+}
+
+pub fn kexinit() -> Packet {
+    let mut payload = Packet::new();
+    payload.push(MSG_KEXINIT).ok();
+    payload.extend_from_slice(b"picomap-hostkey!").ok(); // cookie
+    for list in [
+        KEX,
+        HOST_KEYS,
+        CIPHERS,
+        CIPHERS,
+        MACS,
+        MACS,
+        COMPRESSION,
+        COMPRESSION,
+        "",
+        "",
+    ] {
+        put_string(&mut payload, list.as_bytes());
+    }
+    payload.push(0).ok(); // no guessed packet follows
+    payload.extend_from_slice(&[0; 4]).ok();
+    packet(&payload)
+}
+
+pub fn ecdh_init() -> Packet {
+    let mut base_point = [0u8; 32];
+    base_point[0] = 9;
+    let mut payload = Packet::new();
+    payload.push(MSG_KEX_ECDH_INIT).ok();
+    put_string(&mut payload, &base_point);
+    packet(&payload)
+}
+
+// The server's version line, and how many bytes it and anything before
+// it took. Servers may send other lines first.
+pub fn version_line(buf: &[u8]) -> Option<(&str, usize)> {
+    let mut at = 0;
+    while let Some(end) = buf[at..].iter().position(|&b| b == b'\n') {
+        let line = &buf[at..at + end];
+        at += end + 1;
+        if line.starts_with(b"SSH-") {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            return Some((core::str::from_utf8(line).ok()?, at));
+        }
+    }
+    None
+}
+
+// The payload of the packet at the start of `buf` and the packet's total
+// length, once it's all there.
+pub fn split_packet(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    let total = 4 + len;
+    let pad = *buf.get(4)? as usize;
+    if len < pad + 2 || buf.len() < total {
+        return None;
+    }
+    Some((&buf[5..total - pad], total))
+}
+
+fn get_string<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    let s = buf.get(4..4 + len)?;
+    *buf = &buf[4 + len..];
+    Some(s)
+}
+
+// Whether the server's KEXINIT offers one of our key exchanges.
+pub fn kex_supported(payload: &[u8]) -> bool {
+    let Some(mut rest) = payload.get(17..).filter(|_| payload[0] == MSG_KEXINIT) else {
+        return false;
+    };
+    let Some(theirs) = get_string(&mut rest) else {
+        return false;
+    };
+    theirs
+        .split(|&b| b == b',')
+        .any(|name| KEX.split(',').any(|ours| ours.as_bytes() == name))
+}
+
+pub fn parse_ecdh_reply(payload: &[u8], port: u16) -> Option<SshKey> {
+    let (&msg, mut rest) = payload.split_first()?;
+    if msg != MSG_KEX_ECDH_REPLY {
+        return None;
+    }
+    let blob = get_string(&mut rest)?;
+    let mut fields = blob;
+    let kind = core::str::from_utf8(get_string(&mut fields)?).ok()?;
+    Some(SshKey {
+        port,
+        kind: String::try_from(kind).ok()?,
+        sha256: sha256::digest(blob),
+    })
+}
+
+struct Reader {
+    buf: [u8; BUF_LEN],
+    len: usize,
+    // Bytes at the front already handed out.
+    used: usize,
+}
+
+impl Reader {
+    // False if the buffer's full or the peer's done.
+    async fn fill(&mut self, socket: &mut TcpSocket<'_>) -> bool {
+        self.buf.copy_within(self.used..self.len, 0);
+        self.len -= self.used;
+        self.used = 0;
+        if self.len == BUF_LEN {
+            return false;
+        }
+        match with_timeout(READ_TIMEOUT, socket.read(&mut self.buf[self.len..])).await {
+            Ok(Ok(n)) if n > 0 => {
+                self.len += n;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The server's version line, as much of it as fits.
+    async fn version(&mut self, socket: &mut TcpSocket<'_>) -> Option<String<64>> {
+        loop {
+            if let Some((line, used)) = version_line(&self.buf[..self.len]) {
+                debug!("ssh: {}", line);
+                self.used = used;
+                return Some(String::try_from(line.get(..64).unwrap_or(line)).unwrap_or_default());
+            }
+            if !self.fill(socket).await {
+                return None;
+            }
+        }
+    }
+
+    // Where the next packet's payload is in `buf`.
+    async fn packet(&mut self, socket: &mut TcpSocket<'_>) -> Option<Range<usize>> {
+        loop {
+            if let Some((payload, total)) = split_packet(&self.buf[self.used..self.len]) {
+                let start = self.used + 5;
+                let range = start..start + payload.len();
+                self.used += total;
+                return Some(range);
+            }
+            if !self.fill(socket).await {
+                return None;
+            }
+        }
+    }
+
+    // The next packet of type `msg`, skipping IGNORE, DEBUG and the like.
+    async fn expect(&mut self, socket: &mut TcpSocket<'_>, msg: u8) -> Option<&[u8]> {
+        loop {
+            let range = self.packet(socket).await?;
+            match self.buf[range.clone()].first() {
+                Some(&m) if m == msg => return Some(&self.buf[range]),
+                Some(&MSG_DISCONNECT) | None => return None,
+                _ => {}
+            }
+        }
+    }
+}
+
+// None if there's no SSH server on `port`.
+pub async fn probe(stack: Stack<'_>, host: IpAddress, port: u16) -> Option<SshServer> {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 512];
+    let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+    if !matches!(
+        with_timeout(CONNECT_TIMEOUT, socket.connect((host, port))).await,
+        Ok(Ok(()))
+    ) {
+        socket.abort();
+        return None;
+    }
+    let mut reader = Reader {
+        buf: [0; BUF_LEN],
+        len: 0,
+        used: 0,
+    };
+    let Some(version) = reader.version(&mut socket).await else {
+        socket.abort();
+        return None;
+    };
+    let key = async {
+        write_all(&mut socket, VERSION).await.ok()?;
+        write_all(&mut socket, &kexinit()).await.ok()?;
+        let theirs = reader.expect(&mut socket, MSG_KEXINIT).await?;
+        if !kex_supported(theirs) {
+            debug!("ssh: {}:{} has no curve25519", host, port);
+            return None;
+        }
+        write_all(&mut socket, &ecdh_init()).await.ok()?;
+        let reply = reader.expect(&mut socket, MSG_KEX_ECDH_REPLY).await?;
+        parse_ecdh_reply(reply, port)
+    }
+    .await;
+    socket.abort();
+    Some(SshServer { version, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        let init = kexinit();
+        let (payload, total) = split_packet(&init).unwrap();
+        assert_eq!(total, init.len());
+        assert_eq!(total % 8, 0);
+        assert_eq!(payload[0], MSG_KEXINIT);
+        assert!(kex_supported(payload));
+
+        let init = ecdh_init();
+        let (payload, _) = split_packet(&init).unwrap();
+        assert_eq!(payload.len(), 1 + 4 + 32);
+        assert!(init[4] >= 4);
+        assert_eq!(split_packet(&init[..init.len() - 1]), None);
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(
+            version_line(b"SSH-2.0-OpenSSH_9.6\r\n\0\0\0\x0c"),
+            Some(("SSH-2.0-OpenSSH_9.6", 21))
+        );
+        assert_eq!(
+            version_line(b"hello\r\nSSH-2.0-dropbear\n"),
+            Some(("SSH-2.0-dropbear", 24))
+        );
+        assert_eq!(version_line(b"SSH-2.0-Open"), None);
+    }
+
+    #[test]
+    fn host_key() {
+        // An ssh-ed25519 blob: type, then the 32 byte key.
+        let mut blob = Packet::new();
+        put_string(&mut blob, b"ssh-ed25519");
+        put_string(&mut blob, &[0x42; 32]);
+        let mut payload = Packet::new();
+        payload.push(MSG_KEX_ECDH_REPLY).unwrap();
+        put_string(&mut payload, &blob);
+        put_string(&mut payload, &[7; 32]); // server's public value
+        put_string(&mut payload, b"signature");
+
+        let key = parse_ecdh_reply(&payload, 22).unwrap();
+        assert_eq!(key.kind, "ssh-ed25519");
+        assert_eq!(key.sha256, sha256::digest(&blob));
+        let mut s: String<64> = String::new();
+        write!(s, "{}", key).unwrap();
+        assert!(s.starts_with("ssh-ed25519 SHA256:"));
+        assert_eq!(s.len(), "ssh-ed25519 SHA256:".len() + 43);
+        assert_eq!(parse_ecdh_reply(&payload[..20], 22), None);
+    }
+
+    #[test]
+    fn base64_unpadded() {
+        let mut s: String<16> = String::new();
+        base64(&mut s, b"foobar").unwrap();
+        assert_eq!(s, "Zm9vYmFy");
+        s.clear();
+        base64(&mut s, b"fooba").unwrap();
+        assert_eq!(s, "Zm9vYmE");
+        s.clear();
+        base64(&mut s, b"foob").unwrap();
+        assert_eq!(s, "Zm9vYg");
+    }
+}
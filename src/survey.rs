@@ -0,0 +1,61 @@
+//! survey
+//! ------
+//!
+//! WiFi channel survey over time, for deciding where to put APs.
+//!
+//! Built with `SURVEY_SECS` set, it scans every that many seconds. Each
+//! scan is scored per channel (see `channels`), drawn as a bar chart on
+//! the OLED with the recommended channel marked, and appended to
+//! `SURVEY.CSV`, 13 rows per scan.
+use core::fmt::Write;
+use cyw43::Control;
+use defmt::info;
+use embassy_time::Duration;
+use heapless::String;
+use ssd1306::prelude::WriteOnlyDataCommand;
+
+use crate::channels::{self, Survey};
+use crate::clock;
+use crate::oled;
+use crate::sd_spi::SdSpiError;
+use crate::sd_storage::SdStorage;
+use crate::wifi_scan;
+
+pub const EXPORT_FILE: &str = "SURVEY.CSV";
+// Don't let a tiny SURVEY_SECS turn into back to back scans.
+const MIN_INTERVAL_SECS: u64 = 10;
+
+pub fn interval_from_env() -> Option<Duration> {
+    let secs: u64 = option_env!("SURVEY_SECS")?.parse().ok()?;
+    Some(Duration::from_secs(secs.max(MIN_INTERVAL_SECS)))
+}
+
+// One pass over every channel.
+pub async fn scan(control: &mut Control<'_>) -> Survey {
+    let mut survey = Survey::new();
+    wifi_scan::scan(control, |bss| survey.add(bss.channel, bss.rssi)).await;
+    info!(
+        "survey: {} APs, recommend channel {}",
+        survey.total_aps(),
+        survey.recommend()
+    );
+    survey
+}
+
+pub fn show<I: WriteOnlyDataCommand>(display: &mut oled::Display<I>, survey: &Survey) {
+    let best = survey.recommend();
+    let mut caption: String<21> = String::new();
+    write!(&mut caption, "Best ch {}  APs {}", best, survey.total_aps()).ok();
+    oled::show_bars(display, &survey.bars(), Some(best as usize - 1), &caption);
+}
+
+pub fn export<S: embassy_rp::spi::Instance>(
+    storage: &mut SdStorage<'_, S>,
+    survey: &Survey,
+) -> Result<(), embedded_sdmmc::Error<SdSpiError>> {
+    if storage.read_at(EXPORT_FILE, 0, &mut [0u8; 1]).is_err() {
+        storage.write_lines(EXPORT_FILE, [channels::CSV_HEADER])?;
+    }
+    let now = clock::now_local();
+    storage.append_lines(EXPORT_FILE, survey.csv_rows(&now))
+}
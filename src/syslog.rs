@@ -0,0 +1,136 @@
+//! syslog
+//! ------
+//!
+//! Alerts out over the management interface as RFC 5424 syslog lines on
+//! UDP, so they reach someone who isn't looking at the OLED. Only sent
+//! when a server is set, as an IP with an optional port (514 if not):
+//!
+//!     SYSLOG_SERVER="192.168.1.10"
+//!     SYSLOG_SERVER="[2001:db8::10]:5514"
+//!
+//! UDP syslog is fire and forget; a line that doesn't arrive is only in
+//! `NETWORK.LOG`.
+use core::fmt::{self, Write};
+use defmt::{debug, warn};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Stack};
+use heapless::String;
+
+use crate::clock::{self, DateTime};
+
+pub const PORT: u16 = 514;
+const HOSTNAME: &str = "picomap";
+// Facility 4 (security/authorization), severity 1 (alert).
+const PRI: u8 = 4 * 8 + 1;
+
+type Line = String<256>;
+
+pub fn server_from_env() -> Option<(IpAddress, u16)> {
+    let server = option_env!("SYSLOG_SERVER")?;
+    let parsed = parse_server(server);
+    if parsed.is_none() {
+        warn!("syslog: bad SYSLOG_SERVER {}", server);
+    }
+    parsed
+}
+
+// "10.0.0.1", "10.0.0.1:5514", "2001:db8::1" or "[2001:db8::1]:5514".
+pub fn parse_server(s: &str) -> Option<(IpAddress, u16)> {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        let port = match port {
+            "" => PORT,
+            port => port.strip_prefix(':')?.parse().ok()?,
+        };
+        return Some((ip.parse().ok()?, port));
+    }
+    if let Ok(ip) = s.parse() {
+        return Some((ip, PORT));
+    }
+    let (ip, port) = s.split_once(':')?;
+    Some((ip.parse().ok()?, port.parse().ok()?))
+}
+
+// "<33>1 2025-06-01T14:03:09Z picomap picomap - rogue_ap - Rogue AP ..."
+// The time is left out (as "-") until SNTP has set the clock.
+pub fn format<W: Write>(
+    w: &mut W,
+    time: Option<DateTime>,
+    msg_id: &str,
+    msg: impl fmt::Display,
+) -> fmt::Result {
+    write!(w, "<{}>1 ", PRI)?;
+    match time {
+        Some(time) => write!(w, "{}Z", time)?,
+        None => w.write_char('-')?,
+    }
+    write!(w, " {} picomap - {} - {}", HOSTNAME, msg_id, msg)
+}
+
+pub async fn send(
+    stack: Stack<'_>,
+    server: (IpAddress, u16),
+    msg_id: &str,
+    msg: impl fmt::Display,
+) {
+    let mut line = Line::new();
+    let time = clock::is_synced().then(clock::now_utc);
+    // Too long just gets cut short.
+    format(&mut line, time, msg_id, msg).ok();
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 256];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if socket.bind(0).is_err() {
+        warn!("syslog: no socket");
+        return;
+    }
+    match socket.send_to(line.as_bytes(), server).await {
+        Ok(()) => {
+            socket.flush().await;
+            debug!("syslog: sent {} to {}", msg_id, server.0);
+        }
+        Err(e) => warn!("syslog: send to {} failed: {:?}", server.0, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_net::{Ipv4Address, Ipv6Address};
+
+    #[test]
+    fn servers() {
+        let v4: IpAddress = Ipv4Address::new(192, 168, 1, 10).into();
+        let v6: IpAddress = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10).into();
+        assert_eq!(parse_server("192.168.1.10"), Some((v4, 514)));
+        assert_eq!(parse_server(" 192.168.1.10:5514 "), Some((v4, 5514)));
+        assert_eq!(parse_server("2001:db8::10"), Some((v6, 514)));
+        assert_eq!(parse_server("[2001:db8::10]"), Some((v6, 514)));
+        assert_eq!(parse_server("[2001:db8::10]:5514"), Some((v6, 5514)));
+
+        assert_eq!(parse_server(""), None);
+        assert_eq!(parse_server("syslog.lan"), None);
+        assert_eq!(parse_server("192.168.1.10:syslog"), None);
+        assert_eq!(parse_server("192.168.1.10:70000"), None);
+        assert_eq!(parse_server("[2001:db8::10]5514"), None);
+    }
+
+    #[test]
+    fn lines() {
+        let mut line = Line::new();
+        let time = DateTime::from_unix(1_748_786_589);
+        format(&mut line, Some(time), "rogue_ap", "Rogue AP Corp").unwrap();
+        assert_eq!(
+            line,
+            "<33>1 2025-06-01T14:03:09Z picomap picomap - rogue_ap - Rogue AP Corp"
+        );
+
+        line.clear();
+        format(&mut line, None, "rogue_ap", "Rogue AP Corp").unwrap();
+        assert_eq!(line, "<33>1 - picomap picomap - rogue_ap - Rogue AP Corp");
+    }
+}
@@ -0,0 +1,540 @@
+//! uptime
+//! ------
+//!
+//! Host uptime from TCP timestamps, the way nmap guesses it.
+//!
+//! Most stacks put a timestamp option (RFC 7323) on their SYN-ACK if the
+//! SYN had one, and the TSval in it is a tick counter that started near
+//! zero at boot. Two SYNs a few seconds apart give the tick rate, and the
+//! second TSval divided by that rate is roughly how long the host has
+//! been up.
+//!
+//! smoltcp doesn't put timestamps on its SYNs and embassy-net has no way
+//! to ask it to, so the SYNs are built by hand and sent through a raw
+//! socket, which also gets a copy of every TCP packet coming in. smoltcp
+//! has no socket for the SYN-ACK and answers it with a RST, so no
+//! connection is left half open on the target.
+//!
+//! IPv6 hosts get the same SYN behind an IPv6 header, through a second
+//! raw socket. Each family is probed in its own pass.
+//!
+//! Caveats, all shared with nmap:
+//!   - Linux since 4.10 adds a random offset per peer, so its "uptime" is
+//!     noise. The tick rate is still right.
+//!   - The counter is 32 bits, so at 1000 Hz it wraps every 49.7 days and
+//!     the uptime is only correct modulo that.
+use core::fmt;
+use defmt::{debug, info};
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, Instant, Timer, with_deadline};
+use heapless::Vec;
+
+use crate::pmtu::{checksum, checksum6};
+
+pub const MAX_TARGETS: usize = 32;
+// Tick rates seen in the wild: old Windows 10, BSD 100, older Linux 250,
+// and 1000 for Linux and Windows since. OpenBSD's 2 Hz is too slow to
+// measure in a few seconds, and it randomizes timestamps anyway.
+pub const RATES: [u16; 4] = [10, 100, 250, 1000];
+
+const IPV4_HEADER: usize = 20;
+const IPV6_HEADER: usize = 40;
+const TCP_HEADER: usize = 20;
+const SYN_OPTIONS: usize = 20;
+pub const SYN_LEN: usize = IPV4_HEADER + TCP_HEADER + SYN_OPTIONS;
+pub const SYN6_LEN: usize = IPV6_HEADER + TCP_HEADER + SYN_OPTIONS;
+const PROTO_TCP: u8 = 6;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_ACK: u8 = 0x10;
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_TIMESTAMP: u8 = 8;
+// Our SYNs come from PORT_BASE up. smoltcp picks its own ports anywhere
+// above 1024, so a clash is possible but harmless: it still sees the
+// SYN-ACK first.
+const PORT_BASE: u16 = 40000;
+const PORT_SPAN: u16 = 1024;
+// Samples closer together than this give a rate too rough to snap.
+const MIN_GAP: Duration = Duration::from_secs(3);
+const REPLY_TIMEOUT: Duration = Duration::from_millis(800);
+const TRIES: usize = 2;
+
+// The TSval from a TCP header's options, if it has a timestamp.
+pub fn tsval(tcp: &[u8]) -> Option<u32> {
+    let offset = (*tcp.get(12)? >> 4) as usize * 4;
+    if offset < TCP_HEADER || tcp.len() < offset {
+        return None;
+    }
+    let mut options = &tcp[TCP_HEADER..offset];
+    loop {
+        match *options.first()? {
+            OPT_END => return None,
+            OPT_NOP => options = &options[1..],
+            kind => {
+                let len = *options.get(1)? as usize;
+                if len < 2 || options.len() < len {
+                    return None;
+                }
+                if kind == OPT_TIMESTAMP && len == 10 {
+                    return Some(u32::from_be_bytes(options[2..6].try_into().unwrap()));
+                }
+                options = &options[len..];
+            }
+        }
+    }
+}
+
+// A SYN from `src_port` with MSS, SACK, timestamp and window scale
+// options, like any desktop OS sends, IPv4 header included.
+pub fn build_syn(
+    buf: &mut [u8; SYN_LEN],
+    src: [u8; 4],
+    dst: [u8; 4],
+    src_port: u16,
+    dst_port: u16,
+    our_tsval: u32,
+) -> &[u8] {
+    let (ip, tcp) = buf.split_at_mut(IPV4_HEADER);
+
+    ip.copy_from_slice(&[0; IPV4_HEADER]);
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(SYN_LEN as u16).to_be_bytes());
+    ip[4..6].copy_from_slice(&src_port.to_be_bytes());
+    // Don't fragment.
+    ip[6] = 0x40;
+    ip[8] = 64;
+    ip[9] = PROTO_TCP;
+    ip[12..16].copy_from_slice(&src);
+    ip[16..20].copy_from_slice(&dst);
+    let sum = checksum(ip);
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    fill_syn(tcp, src_port, dst_port, our_tsval);
+    // TCP's checksum covers a pseudo header of the addresses and length.
+    let mut pseudo = [0u8; 12 + TCP_HEADER + SYN_OPTIONS];
+    pseudo[0..4].copy_from_slice(&src);
+    pseudo[4..8].copy_from_slice(&dst);
+    pseudo[9] = PROTO_TCP;
+    pseudo[10..12].copy_from_slice(&((TCP_HEADER + SYN_OPTIONS) as u16).to_be_bytes());
+    pseudo[12..].copy_from_slice(tcp);
+    let sum = checksum(&pseudo);
+    tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+    buf
+}
+
+// The same SYN behind an IPv6 header.
+pub fn build_syn6(
+    buf: &mut [u8; SYN6_LEN],
+    src: [u8; 16],
+    dst: [u8; 16],
+    src_port: u16,
+    dst_port: u16,
+    our_tsval: u32,
+) -> &[u8] {
+    let (ip, tcp) = buf.split_at_mut(IPV6_HEADER);
+
+    ip.copy_from_slice(&[0; IPV6_HEADER]);
+    ip[0] = 0x60;
+    ip[4..6].copy_from_slice(&((TCP_HEADER + SYN_OPTIONS) as u16).to_be_bytes());
+    ip[6] = PROTO_TCP;
+    ip[7] = 64;
+    ip[8..24].copy_from_slice(&src);
+    ip[24..40].copy_from_slice(&dst);
+
+    fill_syn(tcp, src_port, dst_port, our_tsval);
+    let sum = checksum6(src, dst, PROTO_TCP, tcp);
+    tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+    buf
+}
+
+// The TCP header and options of our SYN, checksum left at zero.
+fn fill_syn(tcp: &mut [u8], src_port: u16, dst_port: u16, our_tsval: u32) {
+    tcp.copy_from_slice(&[0; TCP_HEADER + SYN_OPTIONS]);
+    tcp[0..2].copy_from_slice(&src_port.to_be_bytes());
+    tcp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    // Any sequence number will do, nothing follows the SYN-ACK.
+    tcp[4..8].copy_from_slice(&our_tsval.to_be_bytes());
+    tcp[12] = (((TCP_HEADER + SYN_OPTIONS) / 4) as u8) << 4;
+    tcp[13] = FLAG_SYN;
+    tcp[14..16].copy_from_slice(&64240u16.to_be_bytes());
+    let options = &mut tcp[TCP_HEADER..];
+    // MSS 1460, SACK permitted, timestamp, NOP, window scale 7.
+    options[..6].copy_from_slice(&[2, 4, 0x05, 0xb4, 4, 2]);
+    options[6..8].copy_from_slice(&[OPT_TIMESTAMP, 10]);
+    options[8..12].copy_from_slice(&our_tsval.to_be_bytes());
+    options[16..20].copy_from_slice(&[OPT_NOP, 3, 3, 7]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SynAck {
+    pub dst_port: u16,
+    pub tsval: Option<u32>,
+}
+
+// An IPv4 packet from the raw socket, if it's a SYN-ACK from `from:port`.
+pub fn parse_syn_ack(packet: &[u8], from: [u8; 4], port: u16) -> Option<SynAck> {
+    let ihl = (*packet.first()? & 0x0f) as usize * 4;
+    if packet[0] >> 4 != 4
+        || ihl < IPV4_HEADER
+        || packet.len() < ihl + TCP_HEADER
+        || packet[9] != PROTO_TCP
+        || packet[12..16] != from
+    {
+        return None;
+    }
+    syn_ack_of(&packet[ihl..], port)
+}
+
+// `parse_syn_ack` for an IPv6 packet with no extension headers.
+pub fn parse_syn_ack6(packet: &[u8], from: [u8; 16], port: u16) -> Option<SynAck> {
+    if packet.len() < IPV6_HEADER + TCP_HEADER
+        || packet[0] >> 4 != 6
+        || packet[6] != PROTO_TCP
+        || packet[8..24] != from
+    {
+        return None;
+    }
+    syn_ack_of(&packet[IPV6_HEADER..], port)
+}
+
+fn syn_ack_of(tcp: &[u8], port: u16) -> Option<SynAck> {
+    let flags = tcp[13];
+    if u16::from_be_bytes([tcp[0], tcp[1]]) != port
+        || flags & (FLAG_SYN | FLAG_ACK) != FLAG_SYN | FLAG_ACK
+    {
+        return None;
+    }
+    Some(SynAck {
+        dst_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+        tsval: tsval(tcp),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    // When the SYN-ACK arrived, by our own clock.
+    pub at_ms: u64,
+    pub tsval: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Uptime {
+    pub hz: u16,
+    // As of the later sample.
+    pub secs: u32,
+}
+
+impl Uptime {
+    // Unix time the host booted, given the time now.
+    pub fn boot(&self, now: u32) -> u32 {
+        now.saturating_sub(self.secs)
+    }
+}
+
+// "up 412d 3h (1000 Hz)"
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hours = self.secs / 3600;
+        if hours >= 24 {
+            write!(f, "up {}d {}h", hours / 24, hours % 24)?;
+        } else {
+            write!(f, "up {}h {}m", hours, self.secs / 60 % 60)?;
+        }
+        write!(f, " ({} Hz)", self.hz)
+    }
+}
+
+// The tick rate from two samples, snapped to the nearest of `RATES` if
+// it's within 10%. None if the samples are less than `MIN_GAP` apart, or
+// the counter didn't move forward, or moved at no sensible rate, which is
+// what per-connection random offsets look like.
+pub fn estimate(first: Sample, second: Sample) -> Option<Uptime> {
+    let elapsed_ms = second.at_ms.checked_sub(first.at_ms)?;
+    let ticks = second.tsval.wrapping_sub(first.tsval);
+    if elapsed_ms < MIN_GAP.as_millis() || ticks == 0 || ticks > u32::MAX / 2 {
+        return None;
+    }
+    let measured = ticks as u64 * 1000 / elapsed_ms;
+    let hz = RATES
+        .into_iter()
+        .find(|&rate| measured.abs_diff(rate as u64) * 10 <= rate as u64)?;
+    Some(Uptime {
+        hz,
+        secs: second.tsval / hz as u32,
+    })
+}
+
+struct Prober<'a> {
+    socket: RawSocket<'a>,
+    buf: [u8; 256],
+    src: IpAddress,
+    sent: u16,
+}
+
+impl Prober<'_> {
+    // One TSval from `target:port`, retried once if nothing comes back.
+    async fn sample(&mut self, target: IpAddress, port: u16) -> Option<Sample> {
+        for _ in 0..TRIES {
+            let src_port = PORT_BASE + self.sent % PORT_SPAN;
+            self.sent = self.sent.wrapping_add(1);
+            let mut syn = [0u8; SYN_LEN];
+            let mut syn6 = [0u8; SYN6_LEN];
+            let our_tsval = Instant::now().as_millis() as u32;
+            let packet = match (self.src, target) {
+                (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) => build_syn(
+                    &mut syn,
+                    src.octets(),
+                    dst.octets(),
+                    src_port,
+                    port,
+                    our_tsval,
+                ),
+                (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => build_syn6(
+                    &mut syn6,
+                    src.octets(),
+                    dst.octets(),
+                    src_port,
+                    port,
+                    our_tsval,
+                ),
+                _ => return None,
+            };
+            self.socket.send(packet).await;
+
+            let deadline = Instant::now() + REPLY_TIMEOUT;
+            // Every other TCP packet for us comes through here too.
+            while let Ok(Ok(n)) = with_deadline(deadline, self.socket.recv(&mut self.buf)).await {
+                let packet = &self.buf[..n];
+                let reply = match target {
+                    IpAddress::Ipv4(ip) => parse_syn_ack(packet, ip.octets(), port),
+                    IpAddress::Ipv6(ip) => parse_syn_ack6(packet, ip.octets(), port),
+                };
+                if let Some(reply) = reply
+                    && reply.dst_port == src_port
+                {
+                    // No timestamp now means no timestamp on a retry.
+                    return reply.tsval.map(|tsval| Sample {
+                        at_ms: Instant::now().as_millis(),
+                        tsval,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+// Estimates the uptime of each host from an open TCP port on it. Every
+// host gets one SYN, then a second one at least `MIN_GAP` after its
+// first reply, so each family's list takes about as long as a single
+// host would.
+pub async fn probe(
+    stack: Stack<'_>,
+    targets: &[(IpAddress, u16)],
+) -> Vec<(IpAddress, Uptime), MAX_TARGETS> {
+    let targets = &targets[..targets.len().min(MAX_TARGETS)];
+    let mut results = Vec::new();
+    if let Some(v4) = stack.config_v4() {
+        let src = IpAddress::Ipv4(v4.address.address());
+        probe_family(stack, src, targets, &mut results).await;
+    }
+    if let Some(v6) = stack.config_v6() {
+        let src = IpAddress::Ipv6(v6.address.address());
+        probe_family(stack, src, targets, &mut results).await;
+    }
+    results
+}
+
+// The targets of the same family as `src`.
+async fn probe_family(
+    stack: Stack<'_>,
+    src: IpAddress,
+    targets: &[(IpAddress, u16)],
+    results: &mut Vec<(IpAddress, Uptime), MAX_TARGETS>,
+) {
+    let same_family = |ip: &IpAddress| ip.version() == src.version();
+    if !targets.iter().any(|(ip, _)| same_family(ip)) {
+        return;
+    }
+    let version = match src {
+        IpAddress::Ipv4(_) => IpVersion::Ipv4,
+        IpAddress::Ipv6(_) => IpVersion::Ipv6,
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut rx_buf = [0u8; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; SYN6_LEN];
+    // The driver type isn't used for anything, any driver will do.
+    let socket = RawSocket::new::<cyw43::NetDriver<'static>>(
+        stack,
+        version,
+        IpProtocol::Tcp,
+        &mut rx_meta,
+        &mut rx_buf,
+        &mut tx_meta,
+        &mut tx_buf,
+    );
+    let mut prober = Prober {
+        socket,
+        buf: [0; 256],
+        src,
+        sent: 0,
+    };
+
+    let mut first: Vec<Option<Sample>, MAX_TARGETS> = Vec::new();
+    for &(target, port) in targets {
+        let sample = if same_family(&target) {
+            prober.sample(target, port).await
+        } else {
+            None
+        };
+        first.push(sample).ok();
+    }
+
+    for (&(target, port), first) in targets.iter().zip(first) {
+        if !same_family(&target) {
+            continue;
+        }
+        let Some(first) = first else {
+            debug!("uptime: no timestamp from {}:{}", target, port);
+            continue;
+        };
+        // A slow first pass has usually waited this out already.
+        Timer::at(Instant::from_millis(first.at_ms) + MIN_GAP).await;
+        let Some(second) = prober.sample(target, port).await else {
+            continue;
+        };
+        match estimate(first, second) {
+            Some(uptime) => {
+                info!("uptime: {} {}", target, defmt::Display2Format(&uptime));
+                results.push((target, uptime)).ok();
+            }
+            None => debug!("uptime: {} timestamps don't tick sensibly", target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: [u8; 4] = [192, 168, 1, 77];
+    const DST: [u8; 4] = [192, 168, 1, 20];
+    const SRC6: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x77];
+    const DST6: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x20];
+
+    // Our SYN turned into the SYN-ACK a host would send back.
+    fn syn_ack(tsval: u32) -> [u8; SYN_LEN] {
+        let mut buf = [0u8; SYN_LEN];
+        build_syn(&mut buf, DST, SRC, 22, 40001, tsval);
+        buf[IPV4_HEADER + 13] = FLAG_SYN | FLAG_ACK;
+        buf
+    }
+
+    #[test]
+    fn builds_syn() {
+        let mut buf = [0u8; SYN_LEN];
+        let packet = build_syn(&mut buf, SRC, DST, 40001, 22, 1234);
+        assert_eq!(packet.len(), SYN_LEN);
+        assert_eq!(checksum(&packet[..IPV4_HEADER]), 0);
+        let tcp = &packet[IPV4_HEADER..];
+        assert_eq!(&tcp[..4], &[0x9c, 0x41, 0, 22]);
+        assert_eq!(tcp[13], FLAG_SYN);
+        assert_eq!(tsval(tcp), Some(1234));
+
+        let mut pseudo = [0u8; 12 + TCP_HEADER + SYN_OPTIONS];
+        pseudo[..4].copy_from_slice(&SRC);
+        pseudo[4..8].copy_from_slice(&DST);
+        pseudo[9] = PROTO_TCP;
+        pseudo[11] = (TCP_HEADER + SYN_OPTIONS) as u8;
+        pseudo[12..].copy_from_slice(tcp);
+        assert_eq!(checksum(&pseudo), 0);
+    }
+
+    #[test]
+    fn builds_syn6() {
+        let mut buf = [0u8; SYN6_LEN];
+        let packet = build_syn6(&mut buf, SRC6, DST6, 40001, 22, 1234);
+        assert_eq!(packet.len(), SYN6_LEN);
+        assert_eq!(&packet[..8], &[0x60, 0, 0, 0, 0, 40, PROTO_TCP, 64]);
+        let tcp = &packet[IPV6_HEADER..];
+        assert_eq!(&tcp[..4], &[0x9c, 0x41, 0, 22]);
+        assert_eq!(tsval(tcp), Some(1234));
+        assert_eq!(checksum6(SRC6, DST6, PROTO_TCP, tcp), 0);
+
+        let mut reply = [0u8; SYN6_LEN];
+        build_syn6(&mut reply, DST6, SRC6, 22, 40001, 99);
+        reply[IPV6_HEADER + 13] = FLAG_SYN | FLAG_ACK;
+        assert_eq!(
+            parse_syn_ack6(&reply, DST6, 22),
+            Some(SynAck {
+                dst_port: 40001,
+                tsval: Some(99)
+            })
+        );
+        assert_eq!(parse_syn_ack6(&reply, SRC6, 22), None);
+        assert_eq!(parse_syn_ack(&reply, DST, 22), None);
+    }
+
+    #[test]
+    fn parses_options() {
+        let mut tcp = [0u8; 32];
+        tcp[12] = 0x80;
+        // NOP, NOP, timestamp.
+        tcp[20..32].copy_from_slice(&[1, 1, 8, 10, 0, 0, 0x30, 0x39, 0, 0, 0, 0]);
+        assert_eq!(tsval(&tcp), Some(12345));
+        // MSS only, then end of options.
+        tcp[20..24].copy_from_slice(&[2, 4, 5, 0xb4]);
+        tcp[24] = OPT_END;
+        assert_eq!(tsval(&tcp), None);
+        // A length running off the end.
+        tcp[20..22].copy_from_slice(&[8, 40]);
+        assert_eq!(tsval(&tcp), None);
+        // Data offset past the end of the segment.
+        assert_eq!(tsval(&tcp[..24]), None);
+
+        let reply = syn_ack(99);
+        assert_eq!(
+            parse_syn_ack(&reply, DST, 22),
+            Some(SynAck {
+                dst_port: 40001,
+                tsval: Some(99)
+            })
+        );
+        assert_eq!(parse_syn_ack(&reply, SRC, 22), None);
+        assert_eq!(parse_syn_ack(&reply, DST, 80), None);
+        let mut rst = reply;
+        rst[IPV4_HEADER + 13] = 0x14;
+        assert_eq!(parse_syn_ack(&rst, DST, 22), None);
+    }
+
+    #[test]
+    fn estimates() {
+        let at = |at_ms, tsval| Sample { at_ms, tsval };
+        // Up 40 days at 1000 Hz, sampled 3.1 s apart.
+        let up = 40 * 86_400 * 1000;
+        let uptime = estimate(at(5_000, up), at(8_100, up + 3_093)).unwrap();
+        assert_eq!(
+            uptime,
+            Uptime {
+                hz: 1000,
+                secs: 40 * 86_400 + 3
+            }
+        );
+        assert_eq!(uptime.boot(1_750_000_000), 1_750_000_000 - uptime.secs);
+        // 100 Hz across a wrap of the counter.
+        let uptime = estimate(at(0, u32::MAX - 100), at(3_000, 199)).unwrap();
+        assert_eq!(uptime.hz, 100);
+        // 10 Hz, as coarse as it gets.
+        assert_eq!(estimate(at(0, 500), at(3_000, 530)).unwrap().hz, 10);
+        // Stuck, backwards, or random.
+        assert_eq!(estimate(at(0, 500), at(3_000, 500)), None);
+        assert_eq!(estimate(at(0, 500), at(3_000, 400)), None);
+        assert_eq!(estimate(at(0, 500), at(3_000, 3_000_500)), None);
+        assert_eq!(estimate(at(0, 500), at(3_000, 2_000)), None);
+        // 1000 Hz, but too close together to trust.
+        assert_eq!(estimate(at(0, 500), at(1_000, 1_500)), None);
+    }
+}
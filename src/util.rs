@@ -0,0 +1,132 @@
+//! util
+//! ----
+//!
+//! Small helpers the probes share: writing a whole request to a TCP
+//! socket, picking apart an HTTP response, pulling an element out of SOAP
+//! or UPnP XML, and cleaning up text for a CSV.
+use embassy_net::tcp::TcpSocket;
+use heapless::String;
+
+pub async fn write_all(
+    socket: &mut TcpSocket<'_>,
+    mut buf: &[u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    while !buf.is_empty() {
+        let n = socket.write(buf).await?;
+        buf = &buf[n..];
+    }
+    socket.flush().await
+}
+
+// "HTTP/1.1 204 No Content" -> 204
+pub fn http_status_code(resp: &[u8]) -> Option<u16> {
+    if !resp.starts_with(b"HTTP/1.") || resp.len() < 12 {
+        return None;
+    }
+    core::str::from_utf8(&resp[9..12]).ok()?.parse().ok()
+}
+
+pub fn http_body(resp: &[u8]) -> &[u8] {
+    match find(resp, b"\r\n\r\n") {
+        Some(idx) => &resp[idx + 4..],
+        None => &[],
+    }
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+pub fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find(haystack, needle).is_some()
+}
+
+// Text of the first element called `local`, whatever its namespace prefix.
+pub fn element<'a>(xml: &'a str, local: &str) -> Option<&'a str> {
+    let mut at = 0;
+    while let Some(i) = xml[at..].find(local) {
+        let start = at + i;
+        at = start + local.len();
+        // Right after '<' or after a namespace prefix, and not a closing tag.
+        let Some(lt) = xml[..start].rfind('<') else {
+            continue;
+        };
+        let prefix = &xml[lt + 1..start];
+        let opens = prefix.is_empty()
+            || prefix.strip_suffix(':').is_some_and(|p| {
+                p.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            });
+        if !opens || !xml[at..].starts_with(['>', ' ']) {
+            continue;
+        }
+        let body = &xml[at + xml[at..].find('>')? + 1..];
+        return Some(&body[..body.find("</")?]);
+    }
+    None
+}
+
+// Printable text only, commas turned into spaces so it can go in a CSV.
+pub fn text<const N: usize>(bytes: &[u8]) -> String<N> {
+    let mut out = String::new();
+    for &b in bytes {
+        let c = match b {
+            b',' => ' ',
+            0x20..=0x7e => b as char,
+            _ => continue,
+        };
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    let trimmed = out.trim();
+    if trimmed.len() == out.len() {
+        out
+    } else {
+        String::try_from(trimmed).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_responses() {
+        let resp = b"HTTP/1.1 204 No Content\r\nServer: x\r\n\r\nbody";
+        assert_eq!(http_status_code(resp), Some(204));
+        assert_eq!(http_body(resp), b"body");
+        assert_eq!(http_status_code(b"RTSP/1.0 200 OK\r\n\r\n"), None);
+        assert_eq!(http_body(b"HTTP/1.1 200 OK\r\n"), b"");
+        assert_eq!(find(b"abcabc", b"ca"), Some(2));
+        assert!(!contains(b"abc", b"abcd"));
+    }
+
+    #[test]
+    fn elements() {
+        assert_eq!(
+            element("<XAddrs>http://x/</XAddrs>", "XAddrs"),
+            Some("http://x/")
+        );
+        assert_eq!(element("<a:XAddrsExtra>x</a:XAddrsExtra>", "XAddrs"), None);
+        assert_eq!(
+            element(
+                "<u:Resp xmlns:u=\"x\"><NewPort>80</NewPort></u:Resp>",
+                "NewPort"
+            ),
+            Some("80")
+        );
+        assert_eq!(element("</NewPort>", "NewPort"), None);
+    }
+
+    #[test]
+    fn cleans_text() {
+        let s: String<16> = text(b" Acme, Inc.\0\x01 ");
+        assert_eq!(s, "Acme  Inc.");
+        let s: String<4> = text(b"PLC-1000");
+        assert_eq!(s, "PLC-");
+    }
+}
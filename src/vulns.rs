@@ -0,0 +1,294 @@
+//! vulns
+//! -----
+//!
+//! Offline "outdated; known CVEs" hints from service banners.
+//!
+//! The signatures are product/version ranges in `data/vulns.txt`, which
+//! `build.rs` compiles into `SIGNATURES` in flash, so the list can be
+//! updated without touching code. All it knows is names and versions, so
+//! a distro that backports fixes still gets flagged. It's a hint to go
+//! look, not a finding.
+use core::cmp::Ordering;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, defmt::Format)]
+pub struct Signature {
+    // Lowercase.
+    pub product: &'static str,
+    // Empty when everything before `fixed` is affected.
+    pub introduced: &'static str,
+    pub fixed: &'static str,
+    pub severity: Severity,
+    pub cve_year: u16,
+    pub cve_id: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/vulns.rs"));
+
+impl Signature {
+    pub fn affects(&self, version: &str) -> bool {
+        (self.introduced.is_empty() || compare_versions(version, self.introduced).is_ge())
+            && compare_versions(version, self.fixed).is_lt()
+    }
+}
+
+// "CVE-2016-6210 (medium)"
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CVE-{}-{:04} ({})",
+            self.cve_year,
+            self.cve_id,
+            self.severity.as_str()
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Piece<'a> {
+    Num(u32),
+    Word(&'a str),
+}
+
+// Splits "7.2p2" into 7, 2, "p", 2. Anything that isn't a digit or a
+// letter just separates pieces.
+fn pieces(version: &str) -> impl Iterator<Item = Piece<'_>> {
+    let mut rest = version;
+    core::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+        let first = rest.chars().next()?;
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (piece, tail) = rest.split_at(end);
+        rest = tail;
+        Some(if digits {
+            Piece::Num(piece.parse().unwrap_or(u32::MAX))
+        } else {
+            Piece::Word(piece)
+        })
+    })
+}
+
+// Numbers compare as numbers, so 1.4.35 < 1.4.100, and a trailing suffix
+// makes a version newer, so 7.2 < 7.2p2 < 7.3.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (pieces(a), pieces(b));
+    loop {
+        let order = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (Some(Piece::Num(x)), Some(Piece::Num(y))) => x.cmp(&y),
+            (Some(Piece::Word(x)), Some(Piece::Word(y))) => x
+                .bytes()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(y.bytes().map(|c| c.to_ascii_lowercase())),
+            (Some(Piece::Word(_)), Some(Piece::Num(_))) => Ordering::Less,
+            (Some(Piece::Num(_)), Some(Piece::Word(_))) => Ordering::Greater,
+        };
+        if order.is_ne() {
+            return order;
+        }
+    }
+}
+
+// Picks the product and version out of a banner or server header:
+//
+//     SSH-2.0-OpenSSH_7.2p2 Ubuntu-4ubuntu2.10    OpenSSH 7.2p2
+//     Server: lighttpd/1.4.35                     lighttpd 1.4.35
+//     220 (vsFTPd 2.3.4)                          vsFTPd 2.3.4
+//     dnsmasq-2.76                                dnsmasq 2.76
+pub fn parse_banner(banner: &str) -> Option<(&str, &str)> {
+    let banner = match banner.strip_prefix("SSH-") {
+        // Skip the protocol version, "SSH-2.0-".
+        Some(rest) => rest.split_once('-').map_or(rest, |(_, software)| software),
+        None => banner,
+    };
+    let mut words = banner
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ',' || c == ';')
+        .filter(|w| !w.is_empty())
+        .peekable();
+
+    while let Some(word) = words.next() {
+        if !word.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        // "OpenSSH_7.2p2", "lighttpd/1.4.35", "dnsmasq-2.76"
+        if let Some(at) = word.char_indices().find_map(|(i, c)| {
+            (matches!(c, '/' | '_' | '-')
+                && word[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+            .then_some(i)
+        }) {
+            return Some((&word[..at], &word[at + 1..]));
+        }
+        // "vsFTPd 2.3.4"
+        if let Some(next) = words.peek()
+            && next.starts_with(|c: char| c.is_ascii_digit())
+            && next.contains('.')
+        {
+            return Some((word, next));
+        }
+    }
+    None
+}
+
+// Every signature affecting this product and version.
+pub fn matches<'a>(
+    product: &'a str,
+    version: &'a str,
+) -> impl Iterator<Item = &'static Signature> + 'a {
+    SIGNATURES
+        .iter()
+        .filter(move |s| s.product.eq_ignore_ascii_case(product) && s.affects(version))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Hint {
+    pub count: usize,
+    // The most severe one, or the first listed of those.
+    pub worst: &'static Signature,
+}
+
+impl Hint {
+    pub fn severity(&self) -> Severity {
+        self.worst.severity
+    }
+}
+
+// "outdated; known CVEs: CVE-2016-10009 (high) +3 more"
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "outdated; known CVEs: {}", self.worst)?;
+        if self.count > 1 {
+            write!(f, " +{} more", self.count - 1)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn check(product: &str, version: &str) -> Option<Hint> {
+    let mut hint: Option<Hint> = None;
+    for sig in matches(product, version) {
+        hint = Some(match hint {
+            None => Hint {
+                count: 1,
+                worst: sig,
+            },
+            Some(h) => Hint {
+                count: h.count + 1,
+                worst: if sig.severity > h.worst.severity {
+                    sig
+                } else {
+                    h.worst
+                },
+            },
+        });
+    }
+    hint
+}
+
+pub fn check_banner(banner: &str) -> Option<Hint> {
+    let (product, version) = parse_banner(banner)?;
+    check(product, version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        use Ordering::*;
+        assert_eq!(compare_versions("1.4.35", "1.4.100"), Less);
+        assert_eq!(compare_versions("7.2", "7.2p2"), Less);
+        assert_eq!(compare_versions("7.2p2", "7.3"), Less);
+        assert_eq!(compare_versions("9.8p1", "9.8"), Greater);
+        assert_eq!(compare_versions("1.3.5", "1.3.5a"), Less);
+        assert_eq!(compare_versions("2.4.49", "2.4.49"), Equal);
+        assert_eq!(compare_versions("2016.74", "2016.74"), Equal);
+        assert_eq!(compare_versions("1.3.5A", "1.3.5a"), Equal);
+    }
+
+    #[test]
+    fn parses_banners() {
+        assert_eq!(
+            parse_banner("SSH-2.0-OpenSSH_7.2p2 Ubuntu-4ubuntu2.10"),
+            Some(("OpenSSH", "7.2p2"))
+        );
+        assert_eq!(
+            parse_banner("lighttpd/1.4.35"),
+            Some(("lighttpd", "1.4.35"))
+        );
+        assert_eq!(
+            parse_banner("Microsoft-IIS/6.0"),
+            Some(("Microsoft-IIS", "6.0"))
+        );
+        assert_eq!(
+            parse_banner("220 (vsFTPd 2.3.4)"),
+            Some(("vsFTPd", "2.3.4"))
+        );
+        assert_eq!(parse_banner("dnsmasq-2.76"), Some(("dnsmasq", "2.76")));
+        assert_eq!(
+            parse_banner("220 mx.example.com ESMTP Exim 4.89 Tue, 1 Jan 2019"),
+            Some(("Exim", "4.89"))
+        );
+        assert_eq!(parse_banner("9.18.24"), None);
+        assert_eq!(parse_banner("SSH-2.0-dropbear"), None);
+    }
+
+    #[test]
+    fn table_is_sorted_and_valid() {
+        assert!(SIGNATURES.windows(2).all(|w| w[0].product <= w[1].product));
+        for sig in &SIGNATURES {
+            assert_eq!(sig.product, sig.product.to_ascii_lowercase());
+            assert!(compare_versions(sig.introduced, sig.fixed).is_lt());
+        }
+    }
+
+    #[test]
+    fn flags_outdated_versions() {
+        let hint = check_banner("SSH-2.0-OpenSSH_7.2p2 Ubuntu-4ubuntu2.10").unwrap();
+        assert_eq!(hint.severity(), Severity::High);
+        assert!(hint.count >= 3);
+        assert!(matches("OpenSSH", "7.2p2").any(|s| s.cve_year == 2016 && s.cve_id == 6210));
+
+        let hint = check_banner("lighttpd/1.4.35").unwrap();
+        assert_eq!(hint.severity(), Severity::Medium);
+
+        assert_eq!(check("vsftpd", "2.3.4").map(|h| h.worst.cve_id), Some(2523));
+        assert!(check("vsftpd", "3.0.3").is_none());
+        assert!(check("OpenSSH", "9.8p1").is_none());
+        assert!(check("unknownd", "1.0").is_none());
+    }
+
+    #[test]
+    fn formats_hints() {
+        let hint = check("proftpd", "1.3.5").unwrap();
+        assert_eq!(
+            hint.to_string(),
+            "outdated; known CVEs: CVE-2015-3306 (critical)"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! wifi_scan
+//! ---------
+//!
+//! One pass of a cyw43 scan, boiled down to what the survey and the rogue
+//! AP watch need.
+//!
+//! cyw43 only hands back the fixed part of each scan result, not the
+//! information elements, so all we know about security is the privacy
+//! bit: open, or encrypted somehow (WEP, WPA, WPA2 and WPA3 look alike).
+use cyw43::{Control, ScanOptions};
+use heapless::{String, Vec};
+
+// BSSIDs we can dedupe per scan; past this they're reported every time
+// the scan returns them.
+const MAX_BSS: usize = 64;
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+pub struct Bss {
+    pub bssid: [u8; 6],
+    // Empty for hidden networks.
+    pub ssid: String<32>,
+    pub channel: u8,
+    pub rssi: i16,
+    pub encrypted: bool,
+}
+
+// Calls `f` once per AP found.
+pub async fn scan(control: &mut Control<'_>, f: impl FnMut(&Bss)) {
+    scan_with(control, ScanOptions::default(), f).await
+}
+
+// Same, with the caller's dwell time, scan type and so on.
+pub async fn scan_with(control: &mut Control<'_>, options: ScanOptions, mut f: impl FnMut(&Bss)) {
+    let mut seen: Vec<[u8; 6], MAX_BSS> = Vec::new();
+    let mut scanner = control.scan(options).await;
+
+    while let Some(info) = scanner.next().await {
+        let bssid = info.bssid;
+        if seen.contains(&bssid) {
+            continue;
+        }
+        seen.push(bssid).ok();
+
+        let ssid_len = (info.ssid_len as usize).min(32);
+        let ssid_bytes = info.ssid;
+        let mut ssid = String::new();
+        ssid.push_str(core::str::from_utf8(&ssid_bytes[..ssid_len]).unwrap_or(""))
+            .ok();
+        // The control channel; a 40 MHz AP also spills into the channels
+        // on one side, which we don't count.
+        let channel = match info.ctl_ch {
+            0 => (info.chanspec & 0xff) as u8,
+            ch => ch,
+        };
+        f(&Bss {
+            bssid,
+            ssid,
+            channel,
+            rssi: info.rssi,
+            encrypted: info.capability & CAPABILITY_PRIVACY != 0,
+        });
+    }
+}
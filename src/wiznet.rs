@@ -0,0 +1,94 @@
+//! wiznet
+//! ------
+//!
+//! Wired Ethernet through a WIZnet W5500 over SPI1, as an alternative to the
+//! cyw43 WiFi `NetDriver`. The W5500 runs in MACRAW mode, so embassy-net sees
+//! raw Ethernet frames exactly like it does from the cyw43, and every scanner
+//! that takes a `Stack` works the same over either interface.
+//!
+//! SPI0 (GP16-GP19) belongs to the SD card, so the W5500 gets SPI1:
+//!
+//!     W5500 module   ->    Pico W pin
+//!     ------------         ----------
+//!     SCLK           ->    GP10   (SPI1 SCK)
+//!     MOSI           ->    GP11   (SPI1 TX)
+//!     MISO           ->    GP12   (SPI1 RX)
+//!     CS             ->    GP13
+//!     INT            ->    GP14
+//!     RST            ->    GP15
+//!     3V3 / GND      ->    3V3 / GND
+use defmt::{error, info};
+use embassy_net_wiznet::chip::W5500;
+use embassy_net_wiznet::{Device, InitError, Runner, State};
+use embassy_rp::Peri;
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{
+    DMA_CH1, DMA_CH2, PIN_10, PIN_11, PIN_12, PIN_13, PIN_14, PIN_15, SPI1,
+};
+use embassy_rp::spi::{Async, Config, Spi};
+use embassy_time::Delay;
+use embedded_hal_bus::spi::ExclusiveDevice;
+use static_cell::StaticCell;
+
+// Locally administered, spells "PICO" in the middle.
+pub const DEFAULT_MAC: [u8; 6] = [0x02, 0x50, 0x49, 0x43, 0x4f, 0x01];
+
+const SPI_FREQUENCY: u32 = 50_000_000;
+
+pub type WiznetSpi = ExclusiveDevice<Spi<'static, SPI1, Async>, Output<'static>, Delay>;
+pub type WiznetRunner = Runner<'static, W5500, WiznetSpi, Input<'static>, Output<'static>>;
+pub type WiznetDevice = Device<'static>;
+
+static STATE: StaticCell<State<8, 8>> = StaticCell::new();
+
+pub struct WiznetPins {
+    pub spi: Peri<'static, SPI1>,
+    pub sck: Peri<'static, PIN_10>,
+    pub mosi: Peri<'static, PIN_11>,
+    pub miso: Peri<'static, PIN_12>,
+    pub cs: Peri<'static, PIN_13>,
+    pub int: Peri<'static, PIN_14>,
+    pub rst: Peri<'static, PIN_15>,
+    pub tx_dma: Peri<'static, DMA_CH1>,
+    pub rx_dma: Peri<'static, DMA_CH2>,
+}
+
+// Resets the W5500 and brings it up in MACRAW mode.
+// Spawn a task that calls `run()` on the returned runner, and hand the
+// device to `embassy_net::new` like you would the cyw43 `NetDriver`.
+pub async fn init(pins: WiznetPins, mac: [u8; 6]) -> (WiznetDevice, WiznetRunner) {
+    let mut cfg = Config::default();
+    cfg.frequency = SPI_FREQUENCY;
+    let spi = Spi::new(
+        pins.spi,
+        pins.sck,
+        pins.mosi,
+        pins.miso,
+        pins.tx_dma,
+        pins.rx_dma,
+        cfg,
+    );
+    let cs = Output::new(pins.cs, Level::High);
+    let int = Input::new(pins.int, Pull::Up);
+    let rst = Output::new(pins.rst, Level::High);
+
+    let state = STATE.init(State::<8, 8>::new());
+    match embassy_net_wiznet::new(mac, state, ExclusiveDevice::new(spi, cs, Delay), int, rst).await
+    {
+        Ok((device, runner)) => {
+            info!("W5500 up with MAC {:02x}", mac);
+            (device, runner)
+        }
+        Err(InitError::InvalidChipVersion { expected, actual }) => {
+            error!(
+                "W5500 version mismatch: expected {}, got {}",
+                expected, actual
+            );
+            panic!("W5500 init failed, is it wired up?");
+        }
+        Err(InitError::SpiError(_)) => {
+            error!("W5500 SPI error during init");
+            panic!("W5500 init failed, is it wired up?");
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! wol
+//! ---
+//!
+//! Wake-on-LAN sender. A magic packet is six 0xFF bytes followed by the
+//! target MAC sixteen times, optionally followed by a six byte SecureOn
+//! password. We send it as a UDP broadcast to port 9 on the scan network.
+//!
+//! Hosts to wake at boot can be listed in `.env`, with an optional password:
+//!
+//!     WOL_MACS="00:11:22:33:44:55,66:77:88:99:aa:bb"
+//!     WOL_PASSWORD="01:02:03:04:05:06"
+//!
+//! Any host in the inventory can be woken later with the button on GPIO 22
+//! (unless it's taken by site survey mode): a short press steps through
+//! the hosts we have a MAC for, holding it for a second wakes the one on
+//! the OLED.
+use defmt::{info, warn};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_rp::gpio::Input;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::arp_guard::parse_mac;
+
+pub const PORT: u16 = 9;
+pub const PACKET_LEN: usize = 6 + 16 * 6;
+pub const SECUREON_LEN: usize = PACKET_LEN + 6;
+
+// Held at least this long, a press wakes rather than selects.
+pub const LONG_PRESS: Duration = Duration::from_secs(1);
+// Presses and releases closer together than this are switch bounce.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Press {
+    // Select the next host.
+    Short,
+    // Wake the selected host.
+    Long,
+}
+
+// Presses from `watch_button`, for the main loop.
+pub static PRESSES: Channel<CriticalSectionRawMutex, Press, 4> = Channel::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WolError {
+    NoNetwork,
+    Socket,
+}
+
+// Builds the magic packet into `buf` and returns its length, which is
+// PACKET_LEN, or SECUREON_LEN with a password.
+pub fn magic_packet(
+    buf: &mut [u8; SECUREON_LEN],
+    mac: [u8; 6],
+    password: Option<[u8; 6]>,
+) -> usize {
+    buf[..6].fill(0xFF);
+    for chunk in buf[6..PACKET_LEN].chunks_exact_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    match password {
+        Some(pw) => {
+            buf[PACKET_LEN..].copy_from_slice(&pw);
+            SECUREON_LEN
+        }
+        None => PACKET_LEN,
+    }
+}
+
+// WOL_PASSWORD, if set and valid.
+pub fn password() -> Option<[u8; 6]> {
+    option_env!("WOL_PASSWORD").and_then(parse_mac)
+}
+
+// Times presses of an active-low button. Runs as its own task so a press
+// isn't missed while the main loop is busy.
+pub async fn watch_button(mut button: Input<'static>) -> ! {
+    loop {
+        button.wait_for_falling_edge().await;
+        let pressed = Instant::now();
+        Timer::after(DEBOUNCE).await;
+        button.wait_for_high().await;
+        let press = if pressed.elapsed() >= LONG_PRESS {
+            Press::Long
+        } else {
+            Press::Short
+        };
+        PRESSES.send(press).await;
+        Timer::after(DEBOUNCE).await;
+    }
+}
+
+pub async fn send(
+    stack: Stack<'_>,
+    mac: [u8; 6],
+    password: Option<[u8; 6]>,
+) -> Result<(), WolError> {
+    let v4 = stack.config_v4().ok_or(WolError::NoNetwork)?;
+    // Subnet broadcast rather than 255.255.255.255, some switches and
+    // routers only pass the directed one.
+    let target = v4.address.broadcast().unwrap_or(Ipv4Address::BROADCAST);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buf = [0u8; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    socket.bind(0).map_err(|_| WolError::Socket)?;
+
+    let mut packet = [0u8; SECUREON_LEN];
+    let len = magic_packet(&mut packet, mac, password);
+    socket
+        .send_to(&packet[..len], (IpAddress::Ipv4(target), PORT))
+        .await
+        .map_err(|_| WolError::Socket)?;
+    socket.flush().await;
+
+    info!("wol: sent magic packet for {:02x} to {}", mac, target);
+    Ok(())
+}
+
+// Wakes everything in WOL_MACS, if set.
+pub async fn wake_configured(stack: Stack<'_>) -> usize {
+    let Some(macs) = option_env!("WOL_MACS") else {
+        return 0;
+    };
+    let password = password();
+    let mut sent = 0;
+    for entry in macs.split(',').filter(|m| !m.trim().is_empty()) {
+        let Some(mac) = parse_mac(entry) else {
+            warn!("wol: bad MAC in WOL_MACS: {}", entry);
+            continue;
+        };
+        match send(stack, mac, password).await {
+            Ok(()) => sent += 1,
+            Err(e) => warn!("wol: send failed: {}", e),
+        }
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC];
+
+    #[test]
+    fn magic_packet_layout() {
+        let mut buf = [0u8; SECUREON_LEN];
+        let len = magic_packet(&mut buf, MAC, None);
+        assert_eq!(len, 102);
+        assert_eq!(&buf[..6], &[0xFF; 6]);
+        for i in 0..16 {
+            let at = 6 + i * 6;
+            assert_eq!(&buf[at..at + 6], &MAC);
+        }
+    }
+
+    #[test]
+    fn magic_packet_with_secureon() {
+        let mut buf = [0u8; SECUREON_LEN];
+        let pw = [1, 2, 3, 4, 5, 6];
+        let len = magic_packet(&mut buf, MAC, Some(pw));
+        assert_eq!(len, 108);
+        assert_eq!(&buf[96..102], &MAC);
+        assert_eq!(&buf[102..108], &pw);
+    }
+}